@@ -1,49 +1,25 @@
 #![no_std]
 #![allow(async_fn_in_trait)]
 
+//! Async version of [embedded_nand].
+//!
+//! The error types, addresses and iterators are shared with [embedded_nand], so one
+//! [NandFlashError] implementation serves both the blocking and async traits. The helpers
+//! depending on the flash trait are generated from the same definitions as the blocking ones.
+
+pub use embedded_nand::{
+    BlockFailure, ErrorType, NandFlashError, NandFlashErrorKind, NandOperation,
+};
 use embedded_nand::{BlockIndex, BlockStatus};
 
 mod address;
 mod fmt;
 pub mod iter;
+mod nor;
 
 pub use address::AddressConversions;
-
-pub trait NandFlashError: core::fmt::Debug {
-    /// Convert a specific NAND flash error into a generic error kind
-    fn kind(&self) -> NandFlashErrorKind;
-}
-
-/// A trait that NandFlash implementations can use to share an error type.
-pub trait ErrorType {
-    /// Errors returned by this NAND flash.
-    type Error: NandFlashError;
-}
-
-/// NAND flash error kinds.
-///
-/// NAND flash implementations must map their error to those generic error kinds through the
-/// [`NandFlashError`] trait.
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
-#[non_exhaustive]
-pub enum NandFlashErrorKind {
-    /// The arguments are not properly aligned.
-    NotAligned,
-
-    /// The arguments are out of bounds.
-    OutOfBounds,
-
-    /// Block has failed either during erase, write or read checksum.
-    /// Contains byte address of failed block, or [None] if specific block unknown
-    BlockFail(Option<u32>),
-
-    /// Block is failing but operation was successful i.e ECC corrected read.
-    /// Contains byte address of failing block, or [None] if specific block unknown
-    BlockFailing(Option<u32>),
-
-    /// Error specific to the implementation.
-    Other,
-}
+pub use embedded_nand::NorError;
+pub use nor::NorFlashAdapter;
 
 /// NAND flash trait.
 pub trait NandFlash: ErrorType {
@@ -73,10 +49,10 @@ pub trait NandFlash: ErrorType {
     /// Returns an error if the arguments are not aligned or out of bounds. The implementation
     /// can use the [`check_read`] helper function.
     ///
-    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error>;
+    async fn read(&mut self, offset: u64, bytes: &mut [u8]) -> Result<(), Self::Error>;
 
     /// The capacity of the peripheral in bytes.
-    fn capacity(&self) -> u32;
+    fn capacity(&self) -> u64;
 
     /// Mark the block as bad
     async fn mark_block_bad(&mut self, block: BlockIndex) -> Result<(), Self::Error>;
@@ -94,7 +70,7 @@ pub trait NandFlash: ErrorType {
     /// Returns an error if the arguments are not aligned or out of bounds (the case where `to >
     /// from` is considered out of bounds). The implementation can use the [`check_erase`]
     /// helper function.
-    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error>;
+    async fn erase(&mut self, from: u64, to: u64) -> Result<(), Self::Error>;
 
     /// Erase a block by block index.
     async fn erase_block(&mut self, block: BlockIndex) -> Result<(), Self::Error>;
@@ -107,7 +83,7 @@ pub trait NandFlash: ErrorType {
     ///
     /// Returns an error if the arguments are not aligned or out of bounds. The implementation
     /// can use the [`check_write`] helper function.
-    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error>;
+    async fn write(&mut self, offset: u64, bytes: &[u8]) -> Result<(), Self::Error>;
 
     /// Copy data from one location to another.
     ///
@@ -116,52 +92,10 @@ pub trait NandFlash: ErrorType {
     /// implement the copy command.
     async fn copy(
         &mut self,
-        src_offset: u32,
-        dest_offset: u32,
-        length: u32,
+        src_offset: u64,
+        dest_offset: u64,
+        length: u64,
     ) -> Result<(), Self::Error>;
 }
 
-/// Return whether a read operation is within bounds.
-pub fn check_read<T: NandFlash>(
-    flash: &T,
-    offset: u32,
-    length: usize,
-) -> Result<(), NandFlashErrorKind> {
-    check_slice(flash, T::READ_SIZE, offset, length)
-}
-
-/// Return whether an erase operation is aligned and within bounds.
-pub fn check_erase<T: NandFlash>(flash: &T, from: u32, to: u32) -> Result<(), NandFlashErrorKind> {
-    if from > to || to > flash.capacity() {
-        return Err(NandFlashErrorKind::OutOfBounds);
-    }
-    if from % T::ERASE_SIZE as u32 != 0 || to % T::ERASE_SIZE as u32 != 0 {
-        return Err(NandFlashErrorKind::NotAligned);
-    }
-    Ok(())
-}
-
-/// Return whether a write operation is aligned and within bounds.
-pub fn check_write<T: NandFlash>(
-    flash: &T,
-    offset: u32,
-    length: usize,
-) -> Result<(), NandFlashErrorKind> {
-    check_slice(flash, T::WRITE_SIZE, offset, length)
-}
-
-pub fn check_slice<T: NandFlash>(
-    flash: &T,
-    align: usize,
-    offset: u32,
-    length: usize,
-) -> Result<(), NandFlashErrorKind> {
-    if length as u32 > flash.capacity() || offset > (flash.capacity() - (length as u32)) {
-        return Err(NandFlashErrorKind::OutOfBounds);
-    }
-    if offset % align as u32 != 0 || length % align != 0 {
-        return Err(NandFlashErrorKind::NotAligned);
-    }
-    Ok(())
-}
+embedded_nand::__check_helpers!(NandFlash);
@@ -0,0 +1,99 @@
+use embedded_nand::{check_erase_range, check_range, BlockIndex};
+use embedded_storage_async::nor_flash::{self, NorFlash, ReadNorFlash};
+
+use crate::{NandFlash, NorError};
+
+/// Presents a [NandFlash] as an [embedded_storage_async] [NorFlash].
+///
+/// Async equivalent of [embedded_nand::NorFlashAdapter]. A NAND block is the erase unit
+/// and a page the write unit, and reads and writes are passed straight through, so each
+/// page must only be written once between erases.
+/// Only the first 4 GiB of larger devices is accessible.
+#[derive(Debug)]
+pub struct NorFlashAdapter<F> {
+    flash: F,
+}
+
+impl<F: NandFlash> NorFlashAdapter<F> {
+    /// Wrap a [NandFlash]
+    pub fn new(flash: F) -> Self {
+        NorFlashAdapter { flash }
+    }
+
+    /// Access the wrapped flash
+    pub fn inner(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Release the wrapped flash
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// Check a slice is aligned and within the NOR capacity
+    fn check_slice(
+        &self,
+        align: usize,
+        offset: u32,
+        length: usize,
+    ) -> Result<(), NorError<F::Error>> {
+        Ok(check_range(
+            self.capacity() as u64,
+            align,
+            offset as u64,
+            length as u64,
+        )?)
+    }
+}
+
+impl<F: NandFlash> nor_flash::ErrorType for NorFlashAdapter<F> {
+    type Error = NorError<F::Error>;
+}
+
+impl<F: NandFlash> ReadNorFlash for NorFlashAdapter<F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.check_slice(Self::READ_SIZE, offset, bytes.len())?;
+        self.flash
+            .read(offset as u64, bytes)
+            .await
+            .map_err(NorError::Flash)
+    }
+
+    fn capacity(&self) -> usize {
+        // Limit to what can be addressed with a u32, in whole blocks
+        let capacity = self.flash.capacity().min(u32::MAX as u64);
+        (capacity - capacity % F::ERASE_SIZE as u64) as usize
+    }
+}
+
+impl<F: NandFlash> NorFlash for NorFlashAdapter<F> {
+    const WRITE_SIZE: usize = F::PAGE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase_range(
+            self.capacity() as u64,
+            F::ERASE_SIZE,
+            from as u64,
+            to as u64,
+        )?;
+        let erase_size = F::ERASE_SIZE as u32;
+        for block in (from / erase_size)..(to / erase_size) {
+            self.flash
+                .erase_block(BlockIndex::new(block))
+                .await
+                .map_err(NorError::Flash)?;
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check_slice(Self::WRITE_SIZE, offset, bytes.len())?;
+        self.flash
+            .write(offset as u64, bytes)
+            .await
+            .map_err(NorError::Flash)
+    }
+}
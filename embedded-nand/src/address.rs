@@ -1,4 +1,4 @@
-use crate::NandFlash;
+use crate::NandFlashErrorKind;
 use core::{
     fmt::Display,
     ops::{Add, AddAssign},
@@ -18,24 +18,59 @@ impl PageIndex {
         self.0
     }
     pub fn inc(&mut self) {
-        self.0 += 1;
+        *self = *self + 1;
+    }
+
+    /// Add `rhs` pages, returning [NandFlashErrorKind::OutOfBounds] on overflow
+    pub fn checked_add(self, rhs: u32) -> Result<Self, NandFlashErrorKind> {
+        self.0
+            .checked_add(rhs)
+            .map(PageIndex)
+            .ok_or(NandFlashErrorKind::OutOfBounds)
     }
 
     pub fn as_block_index(&self, pages_per_block: u32) -> BlockIndex {
-        BlockIndex((self.0 / pages_per_block) as u16)
+        BlockIndex(self.0 / pages_per_block)
     }
 
     pub fn as_byte_address(&self, page_size: u32) -> ByteAddress {
-        ByteAddress(self.0 * page_size)
+        ByteAddress(self.0 as u64 * page_size as u64)
     }
 
+    /// Convert from a [ByteAddress].
+    ///
+    /// Panics if the page index does not fit in a [u32], see [PageIndex::try_from_byte_address].
     pub fn from_byte_address(ba: ByteAddress, page_size: u32) -> Self {
-        PageIndex(ba.0 / page_size)
+        Self::try_from_byte_address(ba, page_size).expect("page index overflow")
+    }
+
+    /// Convert from a [ByteAddress], returning [NandFlashErrorKind::OutOfBounds]
+    /// if the page index does not fit in a [u32]
+    pub fn try_from_byte_address(
+        ba: ByteAddress,
+        page_size: u32,
+    ) -> Result<Self, NandFlashErrorKind> {
+        u32::try_from(ba.0 / page_size as u64)
+            .map(PageIndex)
+            .map_err(|_| NandFlashErrorKind::OutOfBounds)
     }
 
     /// Convert from a [BlockIndex]
+    ///
+    /// Panics if the page index does not fit in a [u32], see [PageIndex::try_from_block_address].
     pub fn from_block_address(ba: BlockIndex, pages_per_block: u32) -> Self {
-        PageIndex(ba.0 as u32 * pages_per_block)
+        Self::try_from_block_address(ba, pages_per_block).expect("page index overflow")
+    }
+
+    /// Convert from a [BlockIndex], returning [NandFlashErrorKind::OutOfBounds]
+    /// if the page index does not fit in a [u32]
+    pub fn try_from_block_address(
+        ba: BlockIndex,
+        pages_per_block: u32,
+    ) -> Result<Self, NandFlashErrorKind> {
+        ba.0.checked_mul(pages_per_block)
+            .map(PageIndex)
+            .ok_or(NandFlashErrorKind::OutOfBounds)
     }
 }
 
@@ -47,12 +82,7 @@ impl From<PageIndex> for u32 {
 
 impl From<&[u8; 3]> for PageIndex {
     fn from(bytes: &[u8; 3]) -> Self {
-        let mut index = 0;
-        for i in 0..3 {
-            index <<= 8;
-            index |= bytes[i] as u32;
-        }
-        PageIndex(index)
+        PageIndex(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]))
     }
 }
 
@@ -62,11 +92,12 @@ impl Display for PageIndex {
     }
 }
 
+/// Panics on overflow, use [PageIndex::checked_add] to handle it
 impl Add<u32> for PageIndex {
     type Output = Self;
 
     fn add(self, rhs: u32) -> Self::Output {
-        PageIndex(self.0 + rhs)
+        self.checked_add(rhs).expect("page index overflow")
     }
 }
 
@@ -74,59 +105,86 @@ impl Add<u32> for PageIndex {
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-pub struct BlockIndex(pub(crate) u16);
+pub struct BlockIndex(pub(crate) u32);
 
 impl BlockIndex {
-    pub fn new(index: u16) -> Self {
+    pub fn new(index: u32) -> Self {
         BlockIndex(index)
     }
 
-    pub fn as_u16(&self) -> u16 {
+    pub fn as_u32(&self) -> u32 {
         self.0
     }
 
     pub fn inc(&mut self) {
-        self.0 += 1;
+        *self += 1;
+    }
+
+    /// Add `rhs` blocks, returning [NandFlashErrorKind::OutOfBounds] on overflow
+    pub fn checked_add(self, rhs: u32) -> Result<Self, NandFlashErrorKind> {
+        self.0
+            .checked_add(rhs)
+            .map(BlockIndex)
+            .ok_or(NandFlashErrorKind::OutOfBounds)
     }
 
+    /// Convert to a [PageIndex].
+    ///
+    /// Panics if the page index does not fit in a [u32], see [PageIndex::try_from_block_address].
     pub fn as_page_index(&self, pages_per_block: u32) -> PageIndex {
-        PageIndex((self.0 as u32) * pages_per_block)
+        PageIndex::from_block_address(*self, pages_per_block)
     }
 
     pub fn as_byte_address(&self, block_size: u32) -> ByteAddress {
-        ByteAddress((self.0 as u32) * block_size)
+        ByteAddress(self.0 as u64 * block_size as u64)
     }
 
     pub fn from_page_address(pa: PageIndex, pages_per_block: u32) -> Self {
-        BlockIndex((pa.0 / pages_per_block) as u16)
+        BlockIndex(pa.0 / pages_per_block)
     }
 
+    /// Convert from a [ByteAddress].
+    ///
+    /// Panics if the block index does not fit in a [u32], see [BlockIndex::try_from_byte_address].
     pub fn from_byte_address(ba: ByteAddress, block_size: u32) -> Self {
-        BlockIndex((ba.0 / (block_size)) as u16)
+        Self::try_from_byte_address(ba, block_size).expect("block index overflow")
     }
 
-    pub fn from_raw_byte_offset(offset: u32, block_size: u32) -> Self {
-        BlockIndex((offset / block_size) as u16)
+    /// Convert from a [ByteAddress], returning [NandFlashErrorKind::OutOfBounds]
+    /// if the block index does not fit in a [u32]
+    pub fn try_from_byte_address(
+        ba: ByteAddress,
+        block_size: u32,
+    ) -> Result<Self, NandFlashErrorKind> {
+        u32::try_from(ba.0 / block_size as u64)
+            .map(BlockIndex)
+            .map_err(|_| NandFlashErrorKind::OutOfBounds)
+    }
+
+    pub fn from_raw_byte_offset(offset: u64, block_size: u32) -> Self {
+        Self::from_byte_address(ByteAddress(offset), block_size)
     }
 }
 
-impl From<BlockIndex> for u16 {
+impl From<BlockIndex> for u32 {
     fn from(bi: BlockIndex) -> Self {
-        bi.as_u16()
+        bi.as_u32()
     }
 }
 
-impl Add<u16> for BlockIndex {
+/// Panics on overflow, use [BlockIndex::checked_add] to handle it
+impl Add<u32> for BlockIndex {
     type Output = Self;
 
-    fn add(self, rhs: u16) -> Self::Output {
-        BlockIndex(self.0 + rhs)
+    fn add(self, rhs: u32) -> Self::Output {
+        self.checked_add(rhs).expect("block index overflow")
     }
 }
 
-impl AddAssign<u16> for BlockIndex {
-    fn add_assign(&mut self, rhs: u16) {
-        self.0 += rhs;
+/// Panics on overflow, use [BlockIndex::checked_add] to handle it
+impl AddAssign<u32> for BlockIndex {
+    fn add_assign(&mut self, rhs: u32) {
+        *self = *self + rhs;
     }
 }
 
@@ -140,52 +198,68 @@ impl Display for BlockIndex {
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-pub struct ByteAddress(pub(crate) u32);
+pub struct ByteAddress(pub(crate) u64);
 
 impl ByteAddress {
-    pub fn new(address: u32) -> Self {
+    pub fn new(address: u64) -> Self {
         ByteAddress(address)
     }
 
-    pub fn as_u32(&self) -> u32 {
+    pub fn as_u64(&self) -> u64 {
         self.0
     }
 
+    /// Add `rhs` bytes, returning [NandFlashErrorKind::OutOfBounds] on overflow
+    pub fn checked_add(self, rhs: u64) -> Result<Self, NandFlashErrorKind> {
+        self.0
+            .checked_add(rhs)
+            .map(ByteAddress)
+            .ok_or(NandFlashErrorKind::OutOfBounds)
+    }
+
+    /// Convert to a [BlockIndex].
+    ///
+    /// Panics if the block index does not fit in a [u32], see [BlockIndex::try_from_byte_address].
     pub fn as_block_index(&self, block_size: u32) -> BlockIndex {
-        BlockIndex((self.0 / block_size) as u16)
+        BlockIndex::from_byte_address(*self, block_size)
     }
 
     /// Number of bytes into the block
     pub fn block_offset(&self, block_size: u32) -> u32 {
-        self.0 % block_size
+        (self.0 % block_size as u64) as u32
     }
 
+    /// Convert to a [PageIndex].
+    ///
+    /// Panics if the page index does not fit in a [u32], see [PageIndex::try_from_byte_address].
     pub fn as_page_index(&self, page_size: u32) -> PageIndex {
-        PageIndex(self.0 / page_size)
+        PageIndex::from_byte_address(*self, page_size)
     }
 
     pub fn as_column_address(&self, page_size: u32) -> ColumnAddress {
-        ColumnAddress((self.0 % page_size) as u16)
+        ColumnAddress::from_byte_address(*self, page_size)
     }
 }
 
-impl From<ByteAddress> for u32 {
+impl From<ByteAddress> for u64 {
     fn from(ba: ByteAddress) -> Self {
-        ba.as_u32()
+        ba.as_u64()
     }
 }
 
+/// Panics on overflow, use [ByteAddress::checked_add] to handle it
 impl Add<u32> for ByteAddress {
     type Output = Self;
 
     fn add(self, rhs: u32) -> Self::Output {
-        ByteAddress(self.0 + rhs)
+        self.checked_add(rhs as u64).expect("byte address overflow")
     }
 }
 
+/// Panics on overflow, use [ByteAddress::checked_add] to handle it
 impl AddAssign<u32> for ByteAddress {
     fn add_assign(&mut self, rhs: u32) {
-        self.0 += rhs;
+        *self = *self + rhs;
     }
 }
 
@@ -211,7 +285,7 @@ impl ColumnAddress {
     }
 
     pub fn from_byte_address(ba: ByteAddress, page_size: u32) -> Self {
-        ColumnAddress((ba.0 % page_size) as u16)
+        ColumnAddress((ba.0 % page_size as u64) as u16)
     }
 }
 
@@ -221,61 +295,122 @@ impl Display for ColumnAddress {
     }
 }
 
-/// Trait for converting between page and block indices and byte and column addresses
-pub trait AddressConversions {
-    fn page_to_byte_address(page: PageIndex) -> ByteAddress;
-    fn page_to_block_index(page: PageIndex) -> BlockIndex;
-    fn page_in_block(page: PageIndex) -> u32;
-    fn page_range_from_length(length: u32) -> u32;
-    fn block_to_page_index(block: BlockIndex) -> PageIndex;
-    fn block_to_byte_address(block: BlockIndex) -> ByteAddress;
-    fn byte_to_page_index(byte: ByteAddress) -> PageIndex;
-    fn byte_to_block_index(byte: ByteAddress) -> BlockIndex;
-    fn byte_to_column_address(byte: ByteAddress) -> ColumnAddress;
-    fn byte_in_block(byte: ByteAddress) -> u32;
-    fn raw_byte_to_block_index(offset: u32) -> BlockIndex;
-    fn is_block_aligned(byte: ByteAddress) -> bool;
-    fn is_page_aligned(byte: ByteAddress) -> bool;
+/// Declare [AddressConversions] and implement it for every flash implementing `$flash`.
+///
+/// The blocking and async `NandFlash` traits have the same geometry constants, so both
+/// crates generate their conversions from this one definition.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __address_conversions {
+    ($flash:path) => {
+        /// Trait for converting between page and block indices and byte and column addresses
+        pub trait AddressConversions {
+            fn page_to_byte_address(page: $crate::PageIndex) -> $crate::ByteAddress;
+            fn page_to_block_index(page: $crate::PageIndex) -> $crate::BlockIndex;
+            fn page_in_block(page: $crate::PageIndex) -> u32;
+            fn page_range_from_length(length: u64) -> u32;
+            fn block_to_page_index(block: $crate::BlockIndex) -> $crate::PageIndex;
+            fn block_to_byte_address(block: $crate::BlockIndex) -> $crate::ByteAddress;
+            fn byte_to_page_index(byte: $crate::ByteAddress) -> $crate::PageIndex;
+            fn byte_to_block_index(byte: $crate::ByteAddress) -> $crate::BlockIndex;
+            fn byte_to_column_address(byte: $crate::ByteAddress) -> $crate::ColumnAddress;
+            fn byte_in_block(byte: $crate::ByteAddress) -> u32;
+            fn raw_byte_to_block_index(offset: u64) -> $crate::BlockIndex;
+            fn is_block_aligned(byte: $crate::ByteAddress) -> bool;
+            fn is_page_aligned(byte: $crate::ByteAddress) -> bool;
+        }
+
+        impl<T: $flash> AddressConversions for T {
+            fn page_to_byte_address(page: $crate::PageIndex) -> $crate::ByteAddress {
+                page.as_byte_address(Self::PAGE_SIZE as u32)
+            }
+            fn page_to_block_index(page: $crate::PageIndex) -> $crate::BlockIndex {
+                page.as_block_index(Self::PAGES_PER_BLOCK as u32)
+            }
+            fn page_in_block(page: $crate::PageIndex) -> u32 {
+                page.as_u32() % Self::PAGES_PER_BLOCK as u32
+            }
+            fn page_range_from_length(length: u64) -> u32 {
+                (length / Self::PAGE_SIZE as u64) as u32
+            }
+            fn block_to_page_index(block: $crate::BlockIndex) -> $crate::PageIndex {
+                block.as_page_index(Self::PAGES_PER_BLOCK as u32)
+            }
+            fn block_to_byte_address(block: $crate::BlockIndex) -> $crate::ByteAddress {
+                block.as_byte_address(Self::ERASE_SIZE as u32)
+            }
+            fn byte_to_page_index(byte: $crate::ByteAddress) -> $crate::PageIndex {
+                byte.as_page_index(Self::PAGE_SIZE as u32)
+            }
+            fn byte_to_block_index(byte: $crate::ByteAddress) -> $crate::BlockIndex {
+                byte.as_block_index(Self::ERASE_SIZE as u32)
+            }
+            fn byte_to_column_address(byte: $crate::ByteAddress) -> $crate::ColumnAddress {
+                byte.as_column_address(Self::PAGE_SIZE as u32)
+            }
+            fn byte_in_block(byte: $crate::ByteAddress) -> u32 {
+                byte.block_offset(Self::ERASE_SIZE as u32)
+            }
+            fn raw_byte_to_block_index(offset: u64) -> $crate::BlockIndex {
+                $crate::BlockIndex::from_raw_byte_offset(offset, Self::ERASE_SIZE as u32)
+            }
+            fn is_block_aligned(byte: $crate::ByteAddress) -> bool {
+                byte.as_u64() % (Self::ERASE_SIZE as u64) == 0
+            }
+            fn is_page_aligned(byte: $crate::ByteAddress) -> bool {
+                byte.as_u64() % (Self::PAGE_SIZE as u64) == 0
+            }
+        }
+    };
 }
 
-impl<T: NandFlash> AddressConversions for T {
-    fn page_to_byte_address(page: PageIndex) -> ByteAddress {
-        page.as_byte_address(Self::PAGE_SIZE as u32)
-    }
-    fn page_to_block_index(page: PageIndex) -> BlockIndex {
-        page.as_block_index(Self::PAGES_PER_BLOCK as u32)
-    }
-    fn page_in_block(page: PageIndex) -> u32 {
-        page.0 % Self::PAGES_PER_BLOCK as u32
-    }
-    fn page_range_from_length(length: u32) -> u32 {
-        length / Self::PAGE_SIZE as u32
-    }
-    fn block_to_page_index(block: BlockIndex) -> PageIndex {
-        block.as_page_index(Self::PAGES_PER_BLOCK as u32)
-    }
-    fn block_to_byte_address(block: BlockIndex) -> ByteAddress {
-        block.as_byte_address(Self::ERASE_SIZE as u32)
-    }
-    fn byte_to_page_index(byte: ByteAddress) -> PageIndex {
-        byte.as_page_index(Self::PAGE_SIZE as u32)
-    }
-    fn byte_to_block_index(byte: ByteAddress) -> BlockIndex {
-        byte.as_block_index(Self::ERASE_SIZE as u32)
-    }
-    fn byte_to_column_address(byte: ByteAddress) -> ColumnAddress {
-        byte.as_column_address(Self::PAGE_SIZE as u32)
-    }
-    fn byte_in_block(byte: ByteAddress) -> u32 {
-        byte.block_offset(Self::ERASE_SIZE as u32)
-    }
-    fn raw_byte_to_block_index(offset: u32) -> BlockIndex {
-        BlockIndex::from_raw_byte_offset(offset, Self::ERASE_SIZE as u32)
-    }
-    fn is_block_aligned(byte: ByteAddress) -> bool {
-        byte.0 % Self::ERASE_SIZE as u32 == 0
-    }
-    fn is_page_aligned(byte: ByteAddress) -> bool {
-        byte.0 % Self::PAGE_SIZE as u32 == 0
+crate::__address_conversions!(crate::NandFlash);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wide_addresses() {
+        // 8 Gbit stacked die, 8192 blocks of 128 KiB
+        let block = BlockIndex::new(8191);
+        let ba = block.as_byte_address(128 * 1024);
+        assert_eq!(ba.as_u64(), 8191 * 128 * 1024);
+        assert_eq!(ba.as_block_index(128 * 1024), block);
+        // Past 4 GiB
+        let ba = BlockIndex::new(40_000).as_byte_address(128 * 1024);
+        assert!(ba.as_u64() > u32::MAX as u64);
+        assert_eq!(ba.as_page_index(2048), PageIndex::new(40_000 * 64));
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        assert_eq!(
+            PageIndex::new(u32::MAX).checked_add(1),
+            Err(NandFlashErrorKind::OutOfBounds)
+        );
+        assert_eq!(
+            BlockIndex::new(u32::MAX).checked_add(1),
+            Err(NandFlashErrorKind::OutOfBounds)
+        );
+        assert_eq!(
+            ByteAddress::new(u64::MAX).checked_add(1),
+            Err(NandFlashErrorKind::OutOfBounds)
+        );
+        assert_eq!(
+            PageIndex::try_from_block_address(BlockIndex::new(u32::MAX), 64),
+            Err(NandFlashErrorKind::OutOfBounds)
+        );
+        assert_eq!(
+            BlockIndex::try_from_byte_address(ByteAddress::new(u64::MAX), 1),
+            Err(NandFlashErrorKind::OutOfBounds)
+        );
+        assert_eq!(BlockIndex::new(1).checked_add(2), Ok(BlockIndex::new(3)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_add_overflow_panics() {
+        let _ = BlockIndex::new(u32::MAX) + 1;
     }
 }
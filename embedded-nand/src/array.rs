@@ -0,0 +1,641 @@
+use core::marker::PhantomData;
+
+use crate::{
+    check_erase, check_read, check_slice, check_write, BlockIndex, BlockStatus, ErrorType,
+    NandFlash, NandFlashError, NandFlashErrorKind, PageIndex, SplitProgram,
+};
+
+/// Largest page size supported by [NandArray::copy] between devices
+const COPY_BUFFER_SIZE: usize = 4096;
+
+/// How the devices `F` of a [NandArray] are combined
+pub trait ArrayLayout<F: NandFlash>: Sized {
+    /// Interleave pages across devices instead of concatenating them
+    const INTERLEAVED: bool;
+
+    /// Write `bytes` at `offset` in the array, already checked to be within bounds
+    fn write<const N: usize>(
+        array: &mut NandArray<F, N, Self>,
+        offset: u64,
+        bytes: &[u8],
+    ) -> Result<(), ArrayError<F::Error>>;
+}
+
+/// Devices are placed one after another.
+///
+/// Block `b` is on device `b / F::BLOCK_COUNT`, so `BLOCK_COUNT` is the sum of all devices.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Concatenated;
+
+impl<F: NandFlash> ArrayLayout<F> for Concatenated {
+    const INTERLEAVED: bool = false;
+
+    fn write<const N: usize>(
+        array: &mut NandArray<F, N, Self>,
+        offset: u64,
+        bytes: &[u8],
+    ) -> Result<(), ArrayError<F::Error>> {
+        let mut done = 0;
+        while done < bytes.len() {
+            let (device, local, contiguous) = NandArray::<F, N, Self>::locate(offset + done as u64);
+            let len = (bytes.len() - done).min(contiguous as usize);
+            array.devices[device]
+                .write(local, &bytes[done..done + len])
+                .map_err(NandArray::<F, N, Self>::flash_error(device))?;
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+/// Consecutive pages are spread across devices.
+///
+/// Page `p` is page `p / N` on device `p % N`, so sequential access alternates between
+/// devices. Whole pages are written with [SplitProgram]: the next page is loaded into the
+/// next device while the previous devices are still programming, and each device is only
+/// waited for before it is given its next page.
+/// A block of the array is made from the same block on every device, so `BLOCK_COUNT`
+/// matches a single device and `PAGES_PER_BLOCK` is `N` times larger.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Interleaved;
+
+impl<F: SplitProgram> ArrayLayout<F> for Interleaved {
+    const INTERLEAVED: bool = true;
+
+    fn write<const N: usize>(
+        array: &mut NandArray<F, N, Self>,
+        offset: u64,
+        bytes: &[u8],
+    ) -> Result<(), ArrayError<F::Error>> {
+        // Page being programmed on each device
+        let mut programming: [Option<PageIndex>; N] = [None; N];
+        let mut result = array.write_interleaved(offset, bytes, &mut programming);
+        // Finish every started program, even after an error
+        for (device, (flash, page)) in array.devices.iter_mut().zip(programming).enumerate() {
+            if let Some(page) = page {
+                if let Err(e) = flash.program_finish(page) {
+                    let error = NandArray::<F, N, Self>::flash_error(device)(e);
+                    result = result.and(Err(error));
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Error returned by a [NandArray]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ArrayError<E> {
+    /// Error from one of the devices
+    Flash {
+        /// Error from the device
+        error: E,
+        /// Index of the device in the array
+        device: u32,
+        /// Array page of the first page of the device
+        base_page: u32,
+        /// Number of devices pages are interleaved across, 1 if concatenated
+        interleave: u32,
+    },
+    /// The arguments are not properly aligned
+    NotAligned,
+    /// The arguments are out of bounds
+    OutOfBounds,
+}
+
+impl<E: NandFlashError> NandFlashError for ArrayError<E> {
+    fn kind(&self) -> NandFlashErrorKind {
+        match self {
+            // Failures are reported at the array page, not the device page
+            ArrayError::Flash {
+                error,
+                base_page,
+                interleave,
+                ..
+            } => error
+                .kind()
+                .map_page(|page| page.checked_mul(*interleave)?.checked_add(*base_page)),
+            ArrayError::NotAligned => NandFlashErrorKind::NotAligned,
+            ArrayError::OutOfBounds => NandFlashErrorKind::OutOfBounds,
+        }
+    }
+}
+
+// Conversion for the check_* helper functions
+impl<E> From<NandFlashErrorKind> for ArrayError<E> {
+    fn from(kind: NandFlashErrorKind) -> Self {
+        match kind {
+            NandFlashErrorKind::NotAligned => ArrayError::NotAligned,
+            _ => ArrayError::OutOfBounds,
+        }
+    }
+}
+
+/// Several identical NAND devices presented as a single [NandFlash].
+///
+/// Use this for multiple chips on separate chip selects. The devices are combined
+/// according to the [ArrayLayout] `L`, either [Concatenated] (default) or [Interleaved].
+///
+/// ```ignore
+/// let array: NandArray<_, 2> = NandArray::new([flash0, flash1]);
+/// let striped: NandArray<_, 2, Interleaved> = NandArray::new([flash0, flash1]);
+/// ```
+#[derive(Debug)]
+pub struct NandArray<F, const N: usize, L = Concatenated> {
+    devices: [F; N],
+    layout: PhantomData<L>,
+}
+
+impl<F: NandFlash, const N: usize, L: ArrayLayout<F>> NandArray<F, N, L> {
+    /// Create an array from the given devices, in order
+    pub fn new(devices: [F; N]) -> Self {
+        NandArray {
+            devices,
+            layout: PhantomData,
+        }
+    }
+
+    /// Access the underlying devices
+    pub fn devices(&mut self) -> &mut [F; N] {
+        &mut self.devices
+    }
+
+    /// Release the underlying devices
+    pub fn release(self) -> [F; N] {
+        self.devices
+    }
+
+    /// Size of a single device in bytes
+    const fn device_size() -> u64 {
+        F::ERASE_SIZE as u64 * F::BLOCK_COUNT as u64
+    }
+
+    /// Find the device and device offset of a byte offset in the array.
+    ///
+    /// Also returns the number of bytes that are contiguous on that device from the offset.
+    fn locate(offset: u64) -> (usize, u64, u64) {
+        if L::INTERLEAVED {
+            let page_size = F::PAGE_SIZE as u64;
+            let page = offset / page_size;
+            let column = offset % page_size;
+            let device = (page % N as u64) as usize;
+            let local = (page / N as u64) * page_size + column;
+            (device, local, page_size - column)
+        } else {
+            let device = (offset / Self::device_size()) as usize;
+            let local = offset % Self::device_size();
+            (device, local, Self::device_size() - local)
+        }
+    }
+
+    /// Find the device and device block of a block in a [Concatenated] array
+    fn locate_block(block: BlockIndex) -> (usize, BlockIndex) {
+        let block_count = F::BLOCK_COUNT as u32;
+        (
+            (block.as_u32() / block_count) as usize,
+            BlockIndex::new(block.as_u32() % block_count),
+        )
+    }
+
+    /// Wrap an error from `device`
+    fn flash_error(device: usize) -> impl FnOnce(F::Error) -> ArrayError<F::Error> {
+        let (base_page, interleave) = if L::INTERLEAVED {
+            (device as u32, N as u32)
+        } else {
+            let pages = F::BLOCK_COUNT as u32 * F::PAGES_PER_BLOCK as u32;
+            (device as u32 * pages, 1)
+        };
+        move |error| ArrayError::Flash {
+            error,
+            device: device as u32,
+            base_page,
+            interleave,
+        }
+    }
+
+    /// Check a block index is within the array
+    fn check_block(block: BlockIndex) -> Result<(), ArrayError<F::Error>> {
+        if block.as_u32() as usize >= Self::BLOCK_COUNT {
+            return Err(ArrayError::OutOfBounds);
+        }
+        Ok(())
+    }
+}
+
+impl<F: SplitProgram, const N: usize> NandArray<F, N, Interleaved> {
+    /// Write pages across the devices, leaving the last page started on each device in
+    /// `programming` to be finished
+    fn write_interleaved(
+        &mut self,
+        offset: u64,
+        bytes: &[u8],
+        programming: &mut [Option<PageIndex>; N],
+    ) -> Result<(), ArrayError<F::Error>> {
+        let mut done = 0;
+        while done < bytes.len() {
+            let (device, local, contiguous) = Self::locate(offset + done as u64);
+            let len = (bytes.len() - done).min(contiguous as usize);
+            let flash = &mut self.devices[device];
+            // The device must finish its last page before it is used again
+            if let Some(page) = programming[device].take() {
+                flash
+                    .program_finish(page)
+                    .map_err(Self::flash_error(device))?;
+            }
+            let data = &bytes[done..done + len];
+            if len == F::PAGE_SIZE {
+                let page = PageIndex::new((local / F::PAGE_SIZE as u64) as u32);
+                flash
+                    .program_start(page, data)
+                    .map_err(Self::flash_error(device))?;
+                programming[device] = Some(page);
+            } else {
+                flash
+                    .write(local, data)
+                    .map_err(Self::flash_error(device))?;
+            }
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+impl<F: NandFlash, const N: usize, L> ErrorType for NandArray<F, N, L> {
+    type Error = ArrayError<F::Error>;
+}
+
+impl<F: NandFlash, const N: usize, L: ArrayLayout<F>> NandFlash for NandArray<F, N, L> {
+    const READ_SIZE: usize = F::READ_SIZE;
+    const PAGE_SIZE: usize = F::PAGE_SIZE;
+    const PAGES_PER_BLOCK: usize = if L::INTERLEAVED {
+        F::PAGES_PER_BLOCK * N
+    } else {
+        F::PAGES_PER_BLOCK
+    };
+    const BLOCK_COUNT: usize = if L::INTERLEAVED {
+        F::BLOCK_COUNT
+    } else {
+        F::BLOCK_COUNT * N
+    };
+    const ERASE_SIZE: usize = Self::PAGE_SIZE * Self::PAGES_PER_BLOCK;
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+
+    fn read(&mut self, offset: u64, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let mut done = 0;
+        while done < bytes.len() {
+            let (device, local, contiguous) = Self::locate(offset + done as u64);
+            let len = (bytes.len() - done).min(contiguous as usize);
+            self.devices[device]
+                .read(local, &mut bytes[done..done + len])
+                .map_err(Self::flash_error(device))?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> u64 {
+        Self::device_size() * N as u64
+    }
+
+    /// For [Interleaved] arrays the worst status of the block on each device is returned,
+    /// and the block is only erased if it is erased on every device
+    fn block_status(&mut self, block: BlockIndex) -> Result<BlockStatus, Self::Error> {
+        Self::check_block(block)?;
+        if L::INTERLEAVED {
+            let mut erased = true;
+            for (device, flash) in self.devices.iter_mut().enumerate() {
+                let status = flash
+                    .block_status(block)
+                    .map_err(Self::flash_error(device))?;
+                if !status.is_ok() {
+                    return Ok(status);
+                }
+                erased &= status.is_erased();
+            }
+            Ok(if erased {
+                BlockStatus::Erased
+            } else {
+                BlockStatus::Ok
+            })
+        } else {
+            let (device, local) = Self::locate_block(block);
+            self.devices[device]
+                .block_status(local)
+                .map_err(Self::flash_error(device))
+        }
+    }
+
+    /// For [Interleaved] arrays the block is marked bad on every device
+    fn mark_block_bad(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+        Self::check_block(block)?;
+        if L::INTERLEAVED {
+            // Try every device before reporting the first error
+            let mut result = Ok(());
+            for (device, flash) in self.devices.iter_mut().enumerate() {
+                if let Err(e) = flash.mark_block_bad(block) {
+                    result = result.and(Err(Self::flash_error(device)(e)));
+                }
+            }
+            result
+        } else {
+            let (device, local) = Self::locate_block(block);
+            self.devices[device]
+                .mark_block_bad(local)
+                .map_err(Self::flash_error(device))
+        }
+    }
+
+    fn erase(&mut self, from: u64, to: u64) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        let first = (from / Self::ERASE_SIZE as u64) as u32;
+        let last = (to / Self::ERASE_SIZE as u64) as u32;
+        for block in first..last {
+            self.erase_block(BlockIndex::new(block))?;
+        }
+        Ok(())
+    }
+
+    fn erase_block(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+        Self::check_block(block)?;
+        if L::INTERLEAVED {
+            for (device, flash) in self.devices.iter_mut().enumerate() {
+                flash
+                    .erase_block(block)
+                    .map_err(Self::flash_error(device))?;
+            }
+            Ok(())
+        } else {
+            let (device, local) = Self::locate_block(block);
+            self.devices[device]
+                .erase_block(local)
+                .map_err(Self::flash_error(device))
+        }
+    }
+
+    fn write(&mut self, offset: u64, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        L::write(self, offset, bytes)
+    }
+
+    /// Copies a page at a time. Pages on the same device use the device copy,
+    /// otherwise the page is read and written through a buffer on the stack.
+    fn copy(&mut self, src_offset: u64, dest_offset: u64, length: u64) -> Result<(), Self::Error> {
+        const { ::core::assert!(F::PAGE_SIZE <= COPY_BUFFER_SIZE) };
+        check_slice(self, Self::PAGE_SIZE, src_offset, length)?;
+        check_slice(self, Self::PAGE_SIZE, dest_offset, length)?;
+
+        let page_size = Self::PAGE_SIZE as u64;
+        for page in 0..length / page_size {
+            let (src_device, src_local, _) = Self::locate(src_offset + page * page_size);
+            let (dest_device, dest_local, _) = Self::locate(dest_offset + page * page_size);
+            if src_device == dest_device {
+                self.devices[src_device]
+                    .copy(src_local, dest_local, page_size)
+                    .map_err(Self::flash_error(src_device))?;
+            } else {
+                let mut buf = [0; COPY_BUFFER_SIZE];
+                let buf = &mut buf[..Self::PAGE_SIZE];
+                self.devices[src_device]
+                    .read(src_local, buf)
+                    .map_err(Self::flash_error(src_device))?;
+                self.devices[dest_device]
+                    .write(dest_local, buf)
+                    .map_err(Self::flash_error(dest_device))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::RefCell;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::test::VirtualNandFlash;
+
+    use test_log::test;
+
+    const PAGE_SIZE: usize = 128;
+    const PAGES_PER_BLOCK: usize = 8;
+    const BLOCK_COUNT: usize = 16;
+    const DEVICE_SIZE: usize = PAGE_SIZE * PAGES_PER_BLOCK * BLOCK_COUNT;
+
+    type Flash = VirtualNandFlash<PAGE_SIZE, PAGES_PER_BLOCK, BLOCK_COUNT>;
+
+    /// Writes over the device boundary are split between devices
+    #[test]
+    fn test_concatenated_rw() {
+        let mut array: NandArray<Flash, 2> = NandArray::new([Flash::new(), Flash::new()]);
+        assert_eq!(<NandArray<Flash, 2>>::BLOCK_COUNT, BLOCK_COUNT * 2);
+        assert_eq!(array.capacity(), DEVICE_SIZE as u64 * 2);
+
+        let offset = (DEVICE_SIZE - PAGE_SIZE) as u64;
+        let buffer = [0x5A; PAGE_SIZE * 2];
+        array.write(offset, &buffer).unwrap();
+        let mut rbuffer = [0; PAGE_SIZE * 2];
+        array.read(offset, &mut rbuffer).unwrap();
+        assert_eq!(buffer, rbuffer);
+
+        let [mut first, mut second] = array.release();
+        let mut page = [0; PAGE_SIZE];
+        first.read(offset, &mut page).unwrap();
+        assert_eq!(page, [0x5A; PAGE_SIZE]);
+        second.read(0, &mut page).unwrap();
+        assert_eq!(page, [0x5A; PAGE_SIZE]);
+    }
+
+    /// Consecutive pages alternate between devices and blocks span all devices
+    #[test]
+    fn test_interleaved_rwe() {
+        let mut array: NandArray<Flash, 2, Interleaved> =
+            NandArray::new([Flash::new(), Flash::new()]);
+        assert_eq!(
+            <NandArray<Flash, 2, Interleaved>>::PAGES_PER_BLOCK,
+            PAGES_PER_BLOCK * 2
+        );
+
+        let mut buffer = [0; PAGE_SIZE * 4];
+        for (i, page) in buffer.chunks_mut(PAGE_SIZE).enumerate() {
+            page.fill(i as u8);
+        }
+        array.write(0, &buffer).unwrap();
+
+        let mut page = [0; PAGE_SIZE];
+        array.devices()[1]
+            .read(PAGE_SIZE as u64, &mut page)
+            .unwrap();
+        assert_eq!(page, [3; PAGE_SIZE]);
+
+        // Marking bad applies to every device
+        array.mark_block_bad(BlockIndex::new(1)).unwrap();
+        assert!(array.devices()[0]
+            .block_status(BlockIndex::new(1))
+            .unwrap()
+            .is_failed());
+        assert!(array.block_status(BlockIndex::new(1)).unwrap().is_failed());
+
+        array.erase_block(BlockIndex::new(0)).unwrap();
+        let mut rbuffer = [0; PAGE_SIZE * 4];
+        array.read(0, &mut rbuffer).unwrap();
+        assert_eq!(rbuffer, [0xFF; PAGE_SIZE * 4]);
+    }
+
+    /// Program events of a [Recorder]: (device, started, page)
+    type Events = RefCell<Vec<(usize, bool, u32)>>;
+
+    /// Records split programs of a device into a log shared by all devices
+    struct Recorder<'a> {
+        flash: Flash,
+        device: usize,
+        events: &'a Events,
+    }
+
+    impl ErrorType for Recorder<'_> {
+        type Error = <Flash as ErrorType>::Error;
+    }
+
+    impl NandFlash for Recorder<'_> {
+        const PAGE_SIZE: usize = PAGE_SIZE;
+        const PAGES_PER_BLOCK: usize = PAGES_PER_BLOCK;
+        const BLOCK_COUNT: usize = BLOCK_COUNT;
+        const ERASE_SIZE: usize = PAGE_SIZE * PAGES_PER_BLOCK;
+        const READ_SIZE: usize = 1;
+        const WRITE_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u64, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            self.flash.read(offset, bytes)
+        }
+
+        fn capacity(&self) -> u64 {
+            self.flash.capacity()
+        }
+
+        fn block_status(&mut self, block: BlockIndex) -> Result<BlockStatus, Self::Error> {
+            self.flash.block_status(block)
+        }
+
+        fn mark_block_bad(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+            self.flash.mark_block_bad(block)
+        }
+
+        fn erase(&mut self, from: u64, to: u64) -> Result<(), Self::Error> {
+            self.flash.erase(from, to)
+        }
+
+        fn erase_block(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+            self.flash.erase_block(block)
+        }
+
+        fn write(&mut self, offset: u64, bytes: &[u8]) -> Result<(), Self::Error> {
+            self.flash.write(offset, bytes)
+        }
+
+        fn copy(&mut self, src: u64, dest: u64, length: u64) -> Result<(), Self::Error> {
+            self.flash.copy(src, dest, length)
+        }
+    }
+
+    impl SplitProgram for Recorder<'_> {
+        fn program_start(&mut self, page: PageIndex, data: &[u8]) -> Result<(), Self::Error> {
+            self.events
+                .borrow_mut()
+                .push((self.device, true, page.as_u32()));
+            self.flash.program_start(page, data)
+        }
+
+        fn program_finish(&mut self, page: PageIndex) -> Result<(), Self::Error> {
+            self.events
+                .borrow_mut()
+                .push((self.device, false, page.as_u32()));
+            self.flash.program_finish(page)
+        }
+    }
+
+    /// Each device is loaded while the previous one is still programming
+    #[test]
+    fn test_interleaved_program_overlap() {
+        let events = Events::default();
+        let recorder = |device| Recorder {
+            flash: Flash::new(),
+            device,
+            events: &events,
+        };
+        let mut array: NandArray<Recorder, 2, Interleaved> =
+            NandArray::new([recorder(0), recorder(1)]);
+        array.write(0, &[0x5A; PAGE_SIZE * 4]).unwrap();
+        assert_eq!(
+            events.borrow().as_slice(),
+            &[
+                (0, true, 0),
+                (1, true, 0),
+                (0, false, 0),
+                (0, true, 1),
+                (1, false, 0),
+                (1, true, 1),
+                (0, false, 1),
+                (1, false, 1),
+            ]
+        );
+        let mut rbuffer = [0; PAGE_SIZE * 4];
+        array.read(0, &mut rbuffer).unwrap();
+        assert_eq!(rbuffer, [0x5A; PAGE_SIZE * 4]);
+    }
+
+    /// Copies between devices go through the page buffer
+    #[test]
+    fn test_copy_across_devices() {
+        let mut array: NandArray<Flash, 2> = NandArray::new([Flash::new(), Flash::new()]);
+        let buffer = [0x11; PAGE_SIZE];
+        array.write(0, &buffer).unwrap();
+        array.copy(0, DEVICE_SIZE as u64, PAGE_SIZE as u64).unwrap();
+        let mut rbuffer = [0; PAGE_SIZE];
+        array.read(DEVICE_SIZE as u64, &mut rbuffer).unwrap();
+        assert_eq!(buffer, rbuffer);
+        // A length that would truncate to one page as a 32 bit usize
+        assert_eq!(
+            array.copy(0, DEVICE_SIZE as u64, (1 << 32) + PAGE_SIZE as u64),
+            Err(ArrayError::OutOfBounds)
+        );
+        assert_eq!(
+            array.erase_block(BlockIndex::new(BLOCK_COUNT as u32 * 2)),
+            Err(ArrayError::OutOfBounds)
+        );
+    }
+    /// Block failures are reported at the array page
+    #[test]
+    fn test_failure_page() {
+        const DEVICE_PAGES: u32 = (PAGES_PER_BLOCK * BLOCK_COUNT) as u32;
+        let block = BlockIndex::new(2);
+        let mut buffer = [0; PAGE_SIZE];
+
+        let mut array: NandArray<Flash, 2> = NandArray::new([Flash::new(), Flash::new()]);
+        array.devices()[1].set_ecc_failing(block);
+        let offset = DEVICE_SIZE as u64 + 2 * (PAGE_SIZE * PAGES_PER_BLOCK) as u64;
+        let error = array.read(offset, &mut buffer).unwrap_err();
+        assert!(matches!(error, ArrayError::Flash { device: 1, .. }));
+        let failure = error.kind().block_failure().unwrap();
+        assert_eq!(failure.page, Some(PageIndex::new(DEVICE_PAGES + 16)));
+        assert_eq!(
+            failure.block::<NandArray<Flash, 2>>(),
+            Some(BlockIndex::new(BLOCK_COUNT as u32 + 2))
+        );
+
+        // Page 16 of the second device is page 33 of an interleaved array
+        let mut array: NandArray<Flash, 2, Interleaved> =
+            NandArray::new([Flash::new(), Flash::new()]);
+        array.devices()[1].set_ecc_failing(block);
+        let error = array.read(33 * PAGE_SIZE as u64, &mut buffer).unwrap_err();
+        let failure = error.kind().block_failure().unwrap();
+        assert_eq!(failure.page, Some(PageIndex::new(33)));
+        assert_eq!(
+            failure.block::<NandArray<Flash, 2, Interleaved>>(),
+            Some(block)
+        );
+    }
+}
@@ -0,0 +1,545 @@
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+use crate::{
+    check_erase, check_slice, BlockIndex, BlockStatus, BlockSwap, EraseCount, ErrorType, NandFlash,
+    NandFlashError, NandFlashErrorKind,
+};
+
+/// Magic bytes that identify a bad block table on flash
+const MAGIC: [u8; 4] = *b"NBBT";
+
+/// Number of blocks at the end of the device reserved for the bad block table
+pub const BBT_BLOCKS: u32 = 4;
+
+/// Size of the header: magic, sequence number, block count and CRC
+const HEADER_SIZE: usize = 16;
+
+/// Largest page size supported, for the buffer a table is written from
+const PAGE_BUFFER_SIZE: usize = 4096;
+
+/// CRC used to check the bad block table
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Error returned by a [BbtFlash]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BbtError<E> {
+    /// Error from the underlying flash
+    Flash(E),
+    /// The arguments are not properly aligned
+    NotAligned,
+    /// The arguments are out of bounds
+    OutOfBounds,
+    /// The bitmap is too small for the device, or the table doesn't fit in a block
+    InvalidConfig,
+    /// The block is reserved for the bad block table
+    Reserved,
+    /// None of the reserved blocks can hold the table
+    NoTableBlocks,
+}
+
+impl<E: NandFlashError> NandFlashError for BbtError<E> {
+    fn kind(&self) -> NandFlashErrorKind {
+        match self {
+            BbtError::Flash(e) => e.kind(),
+            BbtError::NotAligned => NandFlashErrorKind::NotAligned,
+            BbtError::OutOfBounds => NandFlashErrorKind::OutOfBounds,
+            _ => NandFlashErrorKind::Other,
+        }
+    }
+}
+
+// Conversion for the check_* helper functions
+impl<E> From<NandFlashErrorKind> for BbtError<E> {
+    fn from(kind: NandFlashErrorKind) -> Self {
+        match kind {
+            NandFlashErrorKind::NotAligned => BbtError::NotAligned,
+            _ => BbtError::OutOfBounds,
+        }
+    }
+}
+
+/// Keeps the status of every block in a table on flash, so it can be loaded at boot
+/// instead of reading the bad block marker of every block.
+///
+/// The last [BBT_BLOCKS] blocks of the device are reserved for the table, and are reported
+/// as [BlockStatus::Reserved]. The table is built from the factory markers the first time, then stored in two
+/// of the reserved blocks (main and mirror) with a sequence number and CRC. Each update is
+/// appended to both blocks, and the newest valid copy is loaded. If a table block fails, the
+/// table moves to another reserved block.
+///
+/// Each block takes 2 bits, so failed blocks keep their cause as [BlockStatus::FactoryBad],
+/// [BlockStatus::Worn] or [BlockStatus::Failed]. `N` is the size of the bitmap in bytes, which
+/// must be at least `F::BLOCK_COUNT / 4`.
+///
+/// ```ignore
+/// let flash: BbtFlash<_, { 2048 / 4 }> = BbtFlash::new(spi_nand)?;
+/// let map = FlashMap::<_, 1900>::init(flash)?;
+/// ```
+#[derive(Debug)]
+pub struct BbtFlash<F, const N: usize> {
+    flash: F,
+    /// 2 bits per block, see [BbtFlash::status]
+    bitmap: [u8; N],
+    /// Sequence number of the newest table
+    sequence: u32,
+    /// Blocks holding the main table and mirror
+    table_blocks: [Option<BlockIndex>; 2],
+    /// Page in each table block to write the next table to
+    next_page: [u32; 2],
+}
+
+impl<F: NandFlash, const N: usize> BbtFlash<F, N> {
+    /// Number of bytes of the bitmap used
+    const BITMAP_SIZE: usize = F::BLOCK_COUNT.div_ceil(4);
+    /// Number of pages a table takes up
+    const TABLE_PAGES: u32 = (HEADER_SIZE + Self::BITMAP_SIZE).div_ceil(F::PAGE_SIZE) as u32;
+    /// First block reserved for the table
+    const FIRST_RESERVED: u32 = F::BLOCK_COUNT as u32 - BBT_BLOCKS;
+
+    /// Load the table from flash, or build it from the factory markers if there isn't one
+    pub fn new(flash: F) -> Result<Self, BbtError<F::Error>> {
+        if N < Self::BITMAP_SIZE
+            || F::BLOCK_COUNT <= BBT_BLOCKS as usize
+            || Self::TABLE_PAGES as usize > F::PAGES_PER_BLOCK
+        {
+            return Err(BbtError::InvalidConfig);
+        }
+        let mut bbt = BbtFlash {
+            flash,
+            bitmap: [0; N],
+            sequence: 0,
+            table_blocks: [None; 2],
+            next_page: [0; 2],
+        };
+        if !bbt.load()? {
+            info!("No bad block table found, scanning device");
+            bbt.rebuild()?;
+        }
+        Ok(bbt)
+    }
+
+    /// Rebuild the table from the bad block markers of every block and write it
+    pub fn rebuild(&mut self) -> Result<(), BbtError<F::Error>> {
+        for block in 0..F::BLOCK_COUNT as u32 {
+            let status = self
+                .flash
+                .block_status(BlockIndex::new(block))
+                .map_err(BbtError::Flash)?;
+            let status = match status {
+                BlockStatus::FactoryBad | BlockStatus::Worn => status,
+                _ if status.is_ok() => BlockStatus::Ok,
+                _ => BlockStatus::Failed,
+            };
+            self.set_status(block, status);
+        }
+        // Table blocks must be found again, as they may have failed
+        self.table_blocks = [None; 2];
+        self.write_table()
+    }
+
+    /// Access the wrapped flash
+    pub fn inner(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Release the wrapped flash
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// Status of a block in the table: [BlockStatus::Ok] or the cause it failed
+    fn status(&self, block: u32) -> BlockStatus {
+        match (self.bitmap[block as usize / 4] >> (block % 4 * 2)) & 0b11 {
+            0b11 => BlockStatus::Ok,
+            0b10 => BlockStatus::Failed,
+            0b01 => BlockStatus::FactoryBad,
+            _ => BlockStatus::Worn,
+        }
+    }
+
+    /// Set the status of a block in the table. Anything not failed is stored as good
+    fn set_status(&mut self, block: u32, status: BlockStatus) {
+        let bits = match status {
+            BlockStatus::FactoryBad => 0b01,
+            BlockStatus::Worn => 0b00,
+            BlockStatus::Failed => 0b10,
+            _ => 0b11,
+        };
+        let shift = block % 4 * 2;
+        let byte = &mut self.bitmap[block as usize / 4];
+        *byte = (*byte & !(0b11 << shift)) | (bits << shift);
+    }
+
+    /// Return an error if the block is reserved for the table
+    fn check_not_reserved(&self, block: u32) -> Result<(), BbtError<F::Error>> {
+        if block >= Self::FIRST_RESERVED {
+            return Err(BbtError::Reserved);
+        }
+        Ok(())
+    }
+
+    /// Byte address of a table in a reserved block
+    fn table_address(block: BlockIndex, page: u32) -> u64 {
+        block.as_u32() as u64 * F::ERASE_SIZE as u64 + page as u64 * F::PAGE_SIZE as u64
+    }
+
+    /// Search the reserved blocks for tables, loading the newest.
+    ///
+    /// Returns false if none was found.
+    fn load(&mut self) -> Result<bool, BbtError<F::Error>> {
+        let mut newest: Option<(u32, [u8; N])> = None;
+        for block in (Self::FIRST_RESERVED..F::BLOCK_COUNT as u32).rev() {
+            let block = BlockIndex::new(block);
+            let mut found = false;
+            let mut page = 0;
+            while page + Self::TABLE_PAGES <= F::PAGES_PER_BLOCK as u32 {
+                let mut header = [0; HEADER_SIZE];
+                let mut bitmap = [0; N];
+                let address = Self::table_address(block, page);
+                let read = self.flash.read(address, &mut header).and_then(|_| {
+                    self.flash.read(
+                        address + HEADER_SIZE as u64,
+                        &mut bitmap[..Self::BITMAP_SIZE],
+                    )
+                });
+                match read {
+                    Ok(()) => {}
+                    Err(e) if matches!(e.kind(), NandFlashErrorKind::BlockFail(_)) => break,
+                    Err(e) => return Err(BbtError::Flash(e)),
+                }
+                // Tables are written in order, so the first without magic is the end
+                if header[..4] != MAGIC {
+                    break;
+                }
+                page += Self::TABLE_PAGES;
+                let word = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+                let mut digest = CRC.digest();
+                digest.update(&header[..12]);
+                digest.update(&bitmap[..Self::BITMAP_SIZE]);
+                if word(8) != F::BLOCK_COUNT as u32 || word(12) != digest.finalize() {
+                    warn!("Invalid bad block table in block {}", block.as_u32());
+                    continue;
+                }
+                found = true;
+                let sequence = word(4);
+                if newest.is_none_or(|(newest, _)| sequence > newest) {
+                    newest = Some((sequence, bitmap));
+                }
+            }
+            // Keep the first two blocks holding a table, even if out of date
+            if found {
+                if let Some(slot) = self.table_blocks.iter().position(Option::is_none) {
+                    self.table_blocks[slot] = Some(block);
+                    self.next_page[slot] = page;
+                }
+            }
+        }
+        let Some((sequence, bitmap)) = newest else {
+            return Ok(false);
+        };
+        debug!("Loaded bad block table {}", sequence);
+        self.sequence = sequence;
+        self.bitmap = bitmap;
+        Ok(true)
+    }
+
+    /// Write the table to the main and mirror blocks, moving it if a block fails
+    fn write_table(&mut self) -> Result<(), BbtError<F::Error>> {
+        self.sequence = self.sequence.wrapping_add(1);
+        for slot in 0..2 {
+            loop {
+                let block = match self.table_blocks[slot] {
+                    Some(block) => block,
+                    None => match self.spare_table_block() {
+                        Some(block) => {
+                            self.table_blocks[slot] = Some(block);
+                            // Start a full block, so it is erased before use
+                            self.next_page[slot] = F::PAGES_PER_BLOCK as u32;
+                            block
+                        }
+                        None => break,
+                    },
+                };
+                if self.write_to(slot, block)? {
+                    break;
+                }
+                warn!("Bad block table block {} failed", block.as_u32());
+                let _ = self.flash.mark_block_bad(block);
+                self.set_status(block.as_u32(), BlockStatus::Worn);
+                self.table_blocks[slot] = None;
+            }
+        }
+        if self.table_blocks == [None; 2] {
+            error!("No blocks available for the bad block table");
+            return Err(BbtError::NoTableBlocks);
+        }
+        Ok(())
+    }
+
+    /// Append the table to a table block, erasing it first if full.
+    ///
+    /// Returns false if the block failed.
+    fn write_to(&mut self, slot: usize, block: BlockIndex) -> Result<bool, BbtError<F::Error>> {
+        let mut result = Ok(());
+        if self.next_page[slot] + Self::TABLE_PAGES > F::PAGES_PER_BLOCK as u32 {
+            self.next_page[slot] = 0;
+            result = self.flash.erase_block(block);
+        }
+        let mut header = [0; HEADER_SIZE];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4..8].copy_from_slice(&self.sequence.to_le_bytes());
+        header[8..12].copy_from_slice(&(F::BLOCK_COUNT as u32).to_le_bytes());
+        let mut digest = CRC.digest();
+        digest.update(&header[..12]);
+        digest.update(&self.bitmap[..Self::BITMAP_SIZE]);
+        header[12..].copy_from_slice(&digest.finalize().to_le_bytes());
+
+        // Program each page of the table once, with the tail of the last page left erased
+        const { ::core::assert!(F::PAGE_SIZE <= PAGE_BUFFER_SIZE) };
+        let mut table = header
+            .iter()
+            .chain(&self.bitmap[..Self::BITMAP_SIZE])
+            .copied();
+        let mut buffer = [0xFF; PAGE_BUFFER_SIZE];
+        let page = &mut buffer[..F::PAGE_SIZE];
+        let mut address = Self::table_address(block, self.next_page[slot]);
+        for _ in 0..Self::TABLE_PAGES {
+            if result.is_err() {
+                break;
+            }
+            page.fill(0xFF);
+            for (byte, value) in page.iter_mut().zip(&mut table) {
+                *byte = value;
+            }
+            result = self.flash.write(address, page);
+            address += F::PAGE_SIZE as u64;
+        }
+        match result {
+            Ok(()) => {
+                self.next_page[slot] += Self::TABLE_PAGES;
+                Ok(true)
+            }
+            Err(e) if matches!(e.kind(), NandFlashErrorKind::BlockFail(_)) => Ok(false),
+            Err(e) => Err(BbtError::Flash(e)),
+        }
+    }
+
+    /// A good reserved block that isn't holding the table
+    fn spare_table_block(&self) -> Option<BlockIndex> {
+        (Self::FIRST_RESERVED..F::BLOCK_COUNT as u32)
+            .rev()
+            .map(BlockIndex::new)
+            .find(|&block| {
+                self.status(block.as_u32()).is_ok() && !self.table_blocks.contains(&Some(block))
+            })
+    }
+}
+
+impl<F: NandFlash, const N: usize> ErrorType for BbtFlash<F, N> {
+    type Error = BbtError<F::Error>;
+}
+
+impl<F: NandFlash, const N: usize> NandFlash for BbtFlash<F, N> {
+    const READ_SIZE: usize = F::READ_SIZE;
+    const PAGE_SIZE: usize = F::PAGE_SIZE;
+    const PAGES_PER_BLOCK: usize = F::PAGES_PER_BLOCK;
+    const BLOCK_COUNT: usize = F::BLOCK_COUNT;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+
+    fn read(&mut self, offset: u64, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.flash.read(offset, bytes).map_err(BbtError::Flash)
+    }
+
+    fn capacity(&self) -> u64 {
+        self.flash.capacity()
+    }
+
+    /// Status recorded in the table. Blocks reserved for the table are reported as reserved
+    fn block_status(&mut self, block: BlockIndex) -> Result<BlockStatus, Self::Error> {
+        if block.as_u32() as usize >= F::BLOCK_COUNT {
+            return Err(BbtError::OutOfBounds);
+        }
+        if block.as_u32() >= Self::FIRST_RESERVED {
+            Ok(BlockStatus::Reserved)
+        } else {
+            Ok(self.status(block.as_u32()))
+        }
+    }
+
+    /// Marks the block bad in the table and with its bad block marker
+    fn mark_block_bad(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+        if block.as_u32() as usize >= F::BLOCK_COUNT {
+            return Err(BbtError::OutOfBounds);
+        }
+        self.check_not_reserved(block.as_u32())?;
+        // The table is updated even if the marker can't be written
+        let marked = self.flash.mark_block_bad(block);
+        self.set_status(block.as_u32(), BlockStatus::Worn);
+        self.write_table()?;
+        marked.map_err(BbtError::Flash)
+    }
+
+    fn erase(&mut self, from: u64, to: u64) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        // from <= to, so checking the end keeps the whole range out of the reserved blocks
+        if to > Self::FIRST_RESERVED as u64 * F::ERASE_SIZE as u64 {
+            return Err(BbtError::Reserved);
+        }
+        self.flash.erase(from, to).map_err(BbtError::Flash)
+    }
+
+    fn erase_block(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+        self.check_not_reserved(block.as_u32())?;
+        self.flash.erase_block(block).map_err(BbtError::Flash)
+    }
+
+    fn write(&mut self, offset: u64, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_slice(self, Self::WRITE_SIZE, offset, bytes.len() as u64)?;
+        let last = (offset + bytes.len() as u64).saturating_sub(1);
+        self.check_not_reserved((last / F::ERASE_SIZE as u64) as u32)?;
+        self.flash.write(offset, bytes).map_err(BbtError::Flash)
+    }
+
+    fn copy(&mut self, src_offset: u64, dest_offset: u64, length: u64) -> Result<(), Self::Error> {
+        check_slice(self, Self::WRITE_SIZE, dest_offset, length)?;
+        let last = (dest_offset + length).saturating_sub(1);
+        self.check_not_reserved((last / F::ERASE_SIZE as u64) as u32)?;
+        self.flash
+            .copy(src_offset, dest_offset, length)
+            .map_err(BbtError::Flash)
+    }
+}
+
+impl<F: EraseCount, const N: usize> EraseCount for BbtFlash<F, N> {
+    fn erase_count(&self, block: BlockIndex) -> Option<u32> {
+        self.flash.erase_count(block)
+    }
+}
+
+/// Swaps in hardware, keeping the table blocks out of the lookup table
+impl<F: BlockSwap, const N: usize> BlockSwap for BbtFlash<F, N> {
+    fn swap_block(
+        &mut self,
+        bad: BlockIndex,
+        replacement: BlockIndex,
+    ) -> Result<bool, Self::Error> {
+        self.check_not_reserved(bad.as_u32())?;
+        self.check_not_reserved(replacement.as_u32())?;
+        self.flash
+            .swap_block(bad, replacement)
+            .map_err(BbtError::Flash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BbtError, BbtFlash};
+    use crate::test::VirtualNandFlash;
+    use crate::{BlockIndex, BlockStatus, NandFlash};
+
+    type Flash = VirtualNandFlash<512, 4, 64>;
+
+    #[test]
+    fn test_bbt_build_and_load() {
+        let mut flash = Flash::new();
+        flash.mark_block_bad(BlockIndex::new(10)).unwrap();
+        flash.set_factory_bad(BlockIndex::new(12));
+        let mut bbt: BbtFlash<_, 16> = BbtFlash::new(flash).unwrap();
+        assert_eq!(
+            bbt.block_status(BlockIndex::new(10)).unwrap(),
+            BlockStatus::Worn
+        );
+        assert!(bbt.block_status(BlockIndex::new(11)).unwrap().is_ok());
+        assert_eq!(
+            bbt.block_status(BlockIndex::new(12)).unwrap(),
+            BlockStatus::FactoryBad
+        );
+        // Reserved for the table
+        assert_eq!(
+            bbt.block_status(BlockIndex::new(63)).unwrap(),
+            BlockStatus::Reserved
+        );
+        assert_eq!(
+            bbt.erase_block(BlockIndex::new(62)),
+            Err(BbtError::Reserved)
+        );
+
+        // Loaded from the table, so a marker written behind its back is not seen
+        let mut flash = bbt.release();
+        flash.mark_block_bad(BlockIndex::new(20)).unwrap();
+        let mut bbt: BbtFlash<_, 16> = BbtFlash::new(flash).unwrap();
+        assert_eq!(
+            bbt.block_status(BlockIndex::new(10)).unwrap(),
+            BlockStatus::Worn
+        );
+        assert_eq!(
+            bbt.block_status(BlockIndex::new(12)).unwrap(),
+            BlockStatus::FactoryBad
+        );
+        assert!(bbt.block_status(BlockIndex::new(20)).unwrap().is_ok());
+        bbt.rebuild().unwrap();
+        assert!(bbt.block_status(BlockIndex::new(20)).unwrap().is_failed());
+
+        assert!(matches!(
+            BbtFlash::<_, 8>::new(Flash::new()),
+            Err(BbtError::InvalidConfig)
+        ));
+    }
+
+    #[test]
+    fn test_bbt_erase_range() {
+        const ES: u64 = 512 * 4;
+        let mut bbt: BbtFlash<_, 16> = BbtFlash::new(Flash::new()).unwrap();
+        bbt.erase(10 * ES, 12 * ES).unwrap();
+        assert_eq!(bbt.inner().erase_count(BlockIndex::new(9)), 0);
+        assert_eq!(bbt.inner().erase_count(BlockIndex::new(10)), 1);
+        assert_eq!(bbt.inner().erase_count(BlockIndex::new(11)), 1);
+        assert_eq!(bbt.inner().erase_count(BlockIndex::new(12)), 0);
+
+        // Ranges reaching into the table blocks are refused
+        assert_eq!(bbt.erase(10 * ES, 62 * ES), Err(BbtError::Reserved));
+        assert_eq!(bbt.erase(61 * ES, 63 * ES), Err(BbtError::Reserved));
+        assert_eq!(bbt.erase(62 * ES, 10 * ES), Err(BbtError::OutOfBounds));
+        assert_eq!(bbt.erase(10 * ES + 1, 12 * ES), Err(BbtError::NotAligned));
+        assert_eq!(bbt.inner().erase_count(BlockIndex::new(20)), 0);
+    }
+
+    /// Each page of a table is programmed once
+    #[test]
+    fn test_bbt_page_programs() {
+        let bbt: BbtFlash<_, 16> = BbtFlash::new(Flash::new()).unwrap();
+        for block in bbt.table_blocks.map(Option::unwrap) {
+            let page = crate::PageIndex::new(block.as_u32() * 4);
+            assert_eq!(bbt.flash.write_count(page), 1);
+        }
+    }
+
+    #[test]
+    fn test_bbt_update_and_mirror() {
+        let mut bbt: BbtFlash<_, 16> = BbtFlash::new(Flash::new()).unwrap();
+        // More updates than fit in a table block
+        for block in 0..6 {
+            bbt.mark_block_bad(BlockIndex::new(block)).unwrap();
+        }
+        let main = bbt.table_blocks[0].unwrap();
+        assert!(bbt.inner().erase_count(main) > 0);
+
+        // The main table block fails, so the mirror is used
+        let mut flash = bbt.release();
+        flash.mark_block_bad(main).unwrap();
+        let mut bbt: BbtFlash<_, 16> = BbtFlash::new(flash).unwrap();
+        for block in 0..6 {
+            assert!(bbt
+                .block_status(BlockIndex::new(block))
+                .unwrap()
+                .is_failed());
+        }
+        assert!(bbt.block_status(BlockIndex::new(6)).unwrap().is_ok());
+        // Moved to another reserved block on the next update
+        bbt.mark_block_bad(BlockIndex::new(6)).unwrap();
+        assert!(!bbt.table_blocks.contains(&Some(main)));
+        assert!(bbt.table_blocks.iter().all(Option::is_some));
+    }
+}
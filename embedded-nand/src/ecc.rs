@@ -0,0 +1,725 @@
+use core::marker::PhantomData;
+
+use crate::{
+    check_read, check_slice, check_write, BlockFailure, BlockIndex, BlockStatus, ErrorType,
+    NandFlash, NandFlashError, NandFlashErrorKind, NandFlashOob, NandOperation, PageIndex,
+};
+
+/// Largest [EccCode::STEP] supported by [SoftEcc].
+///
+/// This is the largest step of a [Bch] code, whose code word of `STEP * 8 + 13 * T` bits must
+/// fit in 8191 bits: 1022 bytes for `T = 1`, down to 1010 bytes for `T = 8`.
+pub const MAX_ECC_STEP: usize = 1022;
+
+/// Largest [NandFlashOob::OOB_SIZE] supported by [SoftEcc]
+const MAX_OOB: usize = 256;
+
+/// An error correcting code computed in software over fixed size steps of a page
+pub trait EccCode {
+    /// Bytes of data covered by each code word
+    const STEP: usize;
+    /// Bytes of parity for each step
+    const PARITY: usize;
+    /// Bit errors in a step that can be corrected
+    const STRENGTH: u32;
+
+    /// Compute the parity of a step.
+    ///
+    /// The parity of an erased (all 0xFF) step must be all 0xFF, so erased pages read as valid.
+    fn encode(data: &[u8], parity: &mut [u8]);
+
+    /// Correct a step in place, returning the number of bits corrected (including in the
+    /// parity), or None if there are too many errors.
+    fn correct(data: &mut [u8], parity: &[u8]) -> Option<u32>;
+}
+
+/// Hamming code correcting 1 bit and detecting 2 bit errors in every 256 bytes, with
+/// 3 bytes of parity. The same strength as the SmartMedia ECC.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hamming;
+
+impl Hamming {
+    /// Line parities of the odd and even byte indices, and column parities of the odd
+    /// and even bit indices. Inverted, so the parity of erased data is all 0xFF.
+    fn parity(data: &[u8]) -> [u8; 3] {
+        let (mut odd, mut even, mut column) = (0u8, 0u8, 0u8);
+        for (index, &byte) in data.iter().enumerate() {
+            column ^= byte;
+            if byte.count_ones() % 2 == 1 {
+                odd ^= index as u8;
+                even ^= !(index as u8);
+            }
+        }
+        let (mut column_odd, mut column_even) = (0u8, 0u8);
+        for bit in 0..8 {
+            if column & (1 << bit) != 0 {
+                column_odd ^= bit;
+                column_even ^= !bit & 0x7;
+            }
+        }
+        [!odd, !even, !(column_odd | column_even << 3)]
+    }
+}
+
+impl EccCode for Hamming {
+    const STEP: usize = 256;
+    const PARITY: usize = 3;
+    const STRENGTH: u32 = 1;
+
+    fn encode(data: &[u8], parity: &mut [u8]) {
+        parity.copy_from_slice(&Self::parity(data));
+    }
+
+    fn correct(data: &mut [u8], parity: &[u8]) -> Option<u32> {
+        let computed = Self::parity(data);
+        let diff = [
+            computed[0] ^ parity[0],
+            computed[1] ^ parity[1],
+            computed[2] ^ parity[2],
+        ];
+        if diff == [0; 3] {
+            return Some(0);
+        }
+        // A single data bit flips every odd/even pair, which locates it
+        let column = diff[2] & 0x3F;
+        if diff[0] ^ diff[1] == 0xFF && diff[2] & 0xC0 == 0 && (column & 0x7) ^ (column >> 3) == 0x7
+        {
+            data[diff[0] as usize] ^= 1 << (column & 0x7);
+            return Some(1);
+        }
+        // A single bit of the parity itself
+        if diff.iter().map(|b| b.count_ones()).sum::<u32>() == 1 {
+            return Some(1);
+        }
+        None
+    }
+}
+
+/// Primitive polynomial of GF(2^13), x^13 + x^4 + x^3 + x + 1
+const GF_POLY: u16 = 0x201B;
+/// Number of non-zero elements of GF(2^13), and the maximum code word length in bits
+const GF_ORDER: u32 = 8191;
+/// Largest number of bits [Bch] can correct in a step
+const BCH_MAX_T: usize = 8;
+
+const fn gf_mul(mut a: u16, mut b: u16) -> u16 {
+    let mut result = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            result ^= a;
+        }
+        b >>= 1;
+        a <<= 1;
+        if a & (1 << 13) != 0 {
+            a ^= GF_POLY;
+        }
+    }
+    result
+}
+
+const fn gf_pow(mut a: u16, mut exp: u32) -> u16 {
+    let mut result = 1;
+    exp %= GF_ORDER;
+    while exp != 0 {
+        if exp & 1 != 0 {
+            result = gf_mul(result, a);
+        }
+        a = gf_mul(a, a);
+        exp >>= 1;
+    }
+    result
+}
+
+/// a^-1, as a^8191 = 1
+const fn gf_inv(a: u16) -> u16 {
+    gf_pow(a, GF_ORDER - 1)
+}
+
+/// Generator polynomial correcting `t` bits, the product of the minimal polynomials of
+/// alpha^1, alpha^3 .. alpha^(2t-1). Bit n is the coefficient of x^n.
+const fn bch_generator(t: usize) -> u128 {
+    let mut generator: u128 = 1;
+    let mut i = 1;
+    while i < 2 * t as u32 {
+        // Skip if alpha^i is a conjugate of an earlier root, so has the same minimal polynomial
+        let mut duplicate = false;
+        let mut j = i;
+        let mut k = 0;
+        while k < 13 {
+            duplicate |= j < i && j % 2 == 1;
+            j = j * 2 % GF_ORDER;
+            k += 1;
+        }
+        if !duplicate {
+            // Product of (x + beta) over the conjugates beta of alpha^i
+            let mut poly = [0u16; 14];
+            poly[0] = 1;
+            let mut k = 0;
+            while k < 13 {
+                let beta = gf_pow(2, j);
+                let mut d = k + 1;
+                while d > 0 {
+                    poly[d] = poly[d - 1] ^ gf_mul(poly[d], beta);
+                    d -= 1;
+                }
+                poly[0] = gf_mul(poly[0], beta);
+                j = j * 2 % GF_ORDER;
+                k += 1;
+            }
+            // The coefficients are all 0 or 1, multiply into the generator over GF(2)
+            let mut product = 0;
+            let mut d = 0;
+            while d < 14 {
+                if poly[d] == 1 {
+                    product ^= generator << d;
+                }
+                d += 1;
+            }
+            generator = product;
+        }
+        i += 2;
+    }
+    generator
+}
+
+/// Shift one message bit into the parity register
+const fn bch_shift(remainder: u128, bit: bool, generator: u128, degree: usize) -> u128 {
+    let feedback = (remainder >> (degree - 1)) & 1 == 1;
+    let remainder = (remainder << 1) & ((1 << degree) - 1);
+    if feedback != bit {
+        remainder ^ (generator & ((1 << degree) - 1))
+    } else {
+        remainder
+    }
+}
+
+/// Binary BCH code over GF(2^13) correcting `T` bits in every `STEP` bytes, with
+/// `ceil(13 * T / 8)` bytes of parity.
+///
+/// `T` is 1 to 8, and `STEP` at most `(8191 - 13 * T) / 8` bytes so the code word fits in
+/// 8191 bits, which is 1010 bytes for `T = 8`.
+/// ```ignore
+/// // 4 bits per 512 bytes, 7 parity bytes per step
+/// let flash = SoftEcc::<_, Bch<512, 4>>::new(flash)?;
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bch<const STEP: usize, const T: usize>;
+
+impl<const STEP: usize, const T: usize> Bch<STEP, T> {
+    /// Fails to compile if the parameters are out of range
+    const VALID: () =
+        ::core::assert!(T >= 1 && T <= BCH_MAX_T && STEP * 8 + 13 * T <= GF_ORDER as usize);
+    const GENERATOR: u128 = bch_generator(T);
+    /// Number of parity bits
+    const DEGREE: usize = 127 - Self::GENERATOR.leading_zeros() as usize;
+    const PARITY_BYTES: usize = Self::DEGREE.div_ceil(8);
+    /// Stored parity is XORed with this, so the parity of erased data is all 0xFF
+    const ERASED: u128 = {
+        let mut remainder = 0;
+        let mut bit = 0;
+        while bit < STEP * 8 {
+            remainder = bch_shift(remainder, true, Self::GENERATOR, Self::DEGREE);
+            bit += 1;
+        }
+        remainder ^ ((1 << (Self::PARITY_BYTES * 8)) - 1)
+    };
+
+    /// Remainder of the data, multiplied by x^DEGREE, divided by the generator
+    fn remainder(data: &[u8]) -> u128 {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID;
+        data.iter().fold(0, |remainder, &byte| {
+            (0..8).rev().fold(remainder, |remainder, bit| {
+                bch_shift(
+                    remainder,
+                    byte & (1 << bit) != 0,
+                    Self::GENERATOR,
+                    Self::DEGREE,
+                )
+            })
+        })
+    }
+
+    /// Solve for the error positions from the syndromes, with Berlekamp-Massey and a
+    /// Chien search. Position n is the bit with coefficient x^n in the code word.
+    fn locate(difference: u128, positions: &mut [usize; BCH_MAX_T]) -> Option<usize> {
+        let mut syndromes = [0u16; 2 * BCH_MAX_T];
+        for (j, syndrome) in syndromes[..2 * T].iter_mut().enumerate() {
+            let x = gf_pow(2, j as u32 + 1);
+            *syndrome = (0..Self::DEGREE).rev().fold(0, |acc, bit| {
+                gf_mul(acc, x) ^ ((difference >> bit) & 1) as u16
+            });
+        }
+
+        // Error locator polynomial
+        let mut locator = [0u16; 2 * BCH_MAX_T + 1];
+        let mut previous = [0u16; 2 * BCH_MAX_T + 1];
+        locator[0] = 1;
+        previous[0] = 1;
+        let (mut errors, mut shift, mut previous_discrepancy) = (0, 1, 1);
+        for n in 0..2 * T {
+            let discrepancy = (1..=errors).fold(syndromes[n], |d, i| {
+                d ^ gf_mul(locator[i], syndromes[n - i])
+            });
+            if discrepancy == 0 {
+                shift += 1;
+                continue;
+            }
+            let scale = gf_mul(discrepancy, gf_inv(previous_discrepancy));
+            let last = locator;
+            for i in shift..locator.len() {
+                locator[i] ^= gf_mul(scale, previous[i - shift]);
+            }
+            if 2 * errors <= n {
+                errors = n + 1 - errors;
+                previous = last;
+                previous_discrepancy = discrepancy;
+                shift = 1;
+            } else {
+                shift += 1;
+            }
+        }
+        if errors > T {
+            return None;
+        }
+
+        // Roots of the locator are alpha^-position
+        let alpha_inv = gf_inv(2);
+        let mut x = 1;
+        let mut found = 0;
+        for position in 0..STEP * 8 + Self::DEGREE {
+            let value = locator[..=errors]
+                .iter()
+                .rev()
+                .fold(0, |acc, &c| gf_mul(acc, x) ^ c);
+            if value == 0 {
+                if found == errors {
+                    return None;
+                }
+                positions[found] = position;
+                found += 1;
+            }
+            x = gf_mul(x, alpha_inv);
+        }
+        (found == errors).then_some(errors)
+    }
+}
+
+impl<const STEP: usize, const T: usize> EccCode for Bch<STEP, T> {
+    const STEP: usize = STEP;
+    const PARITY: usize = Self::PARITY_BYTES;
+    const STRENGTH: u32 = T as u32;
+
+    fn encode(data: &[u8], parity: &mut [u8]) {
+        let stored = Self::remainder(data) ^ Self::ERASED;
+        parity.copy_from_slice(&stored.to_le_bytes()[..Self::PARITY_BYTES]);
+    }
+
+    fn correct(data: &mut [u8], parity: &[u8]) -> Option<u32> {
+        let mut bytes = [0; 16];
+        bytes[..Self::PARITY_BYTES].copy_from_slice(parity);
+        let stored = u128::from_le_bytes(bytes) ^ Self::ERASED;
+        let difference = (Self::remainder(data) ^ stored) & ((1 << Self::DEGREE) - 1);
+        if difference == 0 {
+            return Some(0);
+        }
+        let mut positions = [0; BCH_MAX_T];
+        let errors = Self::locate(difference, &mut positions)?;
+        // Errors in the parity don't need correcting
+        for &position in positions[..errors].iter().filter(|&&p| p >= Self::DEGREE) {
+            let bit = STEP * 8 - 1 - (position - Self::DEGREE);
+            data[bit / 8] ^= 0x80 >> (bit % 8);
+        }
+        Some(errors as u32)
+    }
+}
+
+/// Error returned by a [SoftEcc]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SoftEccError<E> {
+    /// Error from the underlying flash
+    Flash(E),
+    /// The arguments are not properly aligned
+    NotAligned,
+    /// The arguments are out of bounds
+    OutOfBounds,
+    /// The code doesn't fit the page size or spare area of the flash
+    InvalidConfig,
+    /// Bit errors were corrected and the data read is valid, but a step reached the failing
+    /// threshold. `bits` is the most corrected in one step, in `page`.
+    Corrected { page: PageIndex, bits: u8 },
+    /// A step in `page` had more errors than can be corrected, the data read is not valid
+    Uncorrectable { page: PageIndex },
+}
+
+impl<E: NandFlashError> NandFlashError for SoftEccError<E> {
+    fn kind(&self) -> NandFlashErrorKind {
+        match self {
+            SoftEccError::Flash(e) => e.kind(),
+            SoftEccError::NotAligned => NandFlashErrorKind::NotAligned,
+            SoftEccError::OutOfBounds => NandFlashErrorKind::OutOfBounds,
+            SoftEccError::InvalidConfig => NandFlashErrorKind::Other,
+            SoftEccError::Corrected { page, bits } => NandFlashErrorKind::BlockFailing(
+                BlockFailure::new(NandOperation::Read)
+                    .with_page(*page)
+                    .with_corrected_bits(*bits),
+            ),
+            SoftEccError::Uncorrectable { page } => NandFlashErrorKind::BlockFail(
+                BlockFailure::new(NandOperation::Read).with_page(*page),
+            ),
+        }
+    }
+}
+
+// Conversion for the check_* helper functions
+impl<E> From<NandFlashErrorKind> for SoftEccError<E> {
+    fn from(kind: NandFlashErrorKind) -> Self {
+        match kind {
+            NandFlashErrorKind::NotAligned => SoftEccError::NotAligned,
+            _ => SoftEccError::OutOfBounds,
+        }
+    }
+}
+
+/// Error correction in software, for flash without on-die ECC or with it disabled
+/// (e.g. `ECCBasicBlocking::disable_ecc` for continuous reads).
+///
+/// The parity of each [EccCode::STEP] bytes is stored at the start of the spare area, and
+/// errors are corrected on read. Reads that corrected [Self::set_failing_threshold] or more
+/// bits in a step return [SoftEccError::Corrected], which is
+/// [NandFlashErrorKind::BlockFailing] with the page and corrected bit count, with the
+/// corrected data still read into the buffer.
+///
+/// Whole pages are written at once, so [NandFlash::WRITE_SIZE] is the page size and layers
+/// above must write whole pages, as `FlashMap` and [crate::BbtFlash] do for their own data.
+/// Copies use the internal copy of the flash, which must keep the spare area.
+///
+/// ```ignore
+/// spi_nand.disable_ecc()?;
+/// let flash = SoftEcc::<_, Bch<512, 8>>::new(spi_nand)?;
+/// let map = FlashMap::<_, 1000>::init(flash)?;
+/// ```
+#[derive(Debug)]
+pub struct SoftEcc<F, E> {
+    flash: F,
+    /// Corrected bits in a step at which the block is reported as failing
+    failing_threshold: u32,
+    code: PhantomData<E>,
+}
+
+impl<F: NandFlashOob, E: EccCode> SoftEcc<F, E> {
+    /// Steps in each page
+    const STEPS: usize = F::PAGE_SIZE / E::STEP;
+    /// Bytes of parity in the spare area of each page
+    const PARITY_SIZE: usize = Self::STEPS * E::PARITY;
+
+    /// Wrap a flash, checking the code fits it.
+    ///
+    /// Blocks are reported as failing once a step needs half the strength of the code
+    /// corrected.
+    pub fn new(flash: F) -> Result<Self, SoftEccError<F::Error>> {
+        if E::STEP > MAX_ECC_STEP
+            || F::PAGE_SIZE % E::STEP != 0
+            || E::STEP % F::READ_SIZE != 0
+            || Self::PARITY_SIZE > F::OOB_SIZE
+            || F::OOB_SIZE > MAX_OOB
+        {
+            return Err(SoftEccError::InvalidConfig);
+        }
+        Ok(SoftEcc {
+            flash,
+            failing_threshold: E::STRENGTH.div_ceil(2),
+            code: PhantomData,
+        })
+    }
+
+    /// Set the number of corrected bits in a step at which reads report the block as failing
+    pub fn set_failing_threshold(&mut self, bits: u32) {
+        self.failing_threshold = bits.max(1);
+    }
+
+    /// Access the wrapped flash
+    pub fn inner(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Release the wrapped flash
+    pub fn release(self) -> F {
+        self.flash
+    }
+}
+
+impl<F: NandFlashOob, E: EccCode> ErrorType for SoftEcc<F, E> {
+    type Error = SoftEccError<F::Error>;
+}
+
+impl<F: NandFlashOob, E: EccCode> NandFlash for SoftEcc<F, E> {
+    const READ_SIZE: usize = F::READ_SIZE;
+    const PAGE_SIZE: usize = F::PAGE_SIZE;
+    const PAGES_PER_BLOCK: usize = F::PAGES_PER_BLOCK;
+    const BLOCK_COUNT: usize = F::BLOCK_COUNT;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+    const WRITE_SIZE: usize = F::PAGE_SIZE;
+
+    /// Read and correct every step the range touches
+    fn read(&mut self, offset: u64, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let mut step = [0; MAX_ECC_STEP];
+        let mut oob = [0; MAX_OOB];
+        let mut oob_page = None;
+        let (mut failing, mut uncorrectable) = (None, None);
+        let end = offset + bytes.len() as u64;
+        let mut position = offset;
+        while position < end {
+            let start = position - position % E::STEP as u64;
+            let page = (start / F::PAGE_SIZE as u64) as u32;
+            if oob_page != Some(page) {
+                self.flash
+                    .read_oob(PageIndex::new(page), &mut oob[..Self::PARITY_SIZE])
+                    .map_err(SoftEccError::Flash)?;
+                oob_page = Some(page);
+            }
+            let data = &mut step[..E::STEP];
+            self.flash.read(start, data).map_err(SoftEccError::Flash)?;
+            let index = (start % F::PAGE_SIZE as u64) as usize / E::STEP;
+            match E::correct(data, &oob[index * E::PARITY..][..E::PARITY]) {
+                Some(0) => {}
+                Some(bits) => {
+                    trace!("Corrected {} bits at {}", bits, start);
+                    if bits >= self.failing_threshold && failing.is_none_or(|(_, most)| bits > most)
+                    {
+                        failing = Some((page, bits));
+                    }
+                }
+                None => {
+                    warn!("Uncorrectable ECC error at {}", start);
+                    uncorrectable.get_or_insert(page);
+                }
+            }
+            let next = (start + E::STEP as u64).min(end);
+            bytes[(position - offset) as usize..(next - offset) as usize]
+                .copy_from_slice(&data[(position - start) as usize..(next - start) as usize]);
+            position = next;
+        }
+        if let Some(page) = uncorrectable {
+            return Err(SoftEccError::Uncorrectable {
+                page: PageIndex::new(page),
+            });
+        }
+        if let Some((page, bits)) = failing {
+            return Err(SoftEccError::Corrected {
+                page: PageIndex::new(page),
+                bits: bits as u8,
+            });
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> u64 {
+        self.flash.capacity()
+    }
+
+    fn mark_block_bad(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+        self.flash
+            .mark_block_bad(block)
+            .map_err(SoftEccError::Flash)
+    }
+
+    fn block_status(&mut self, block: BlockIndex) -> Result<BlockStatus, Self::Error> {
+        self.flash.block_status(block).map_err(SoftEccError::Flash)
+    }
+
+    fn erase(&mut self, from: u64, to: u64) -> Result<(), Self::Error> {
+        self.flash.erase(from, to).map_err(SoftEccError::Flash)
+    }
+
+    fn erase_block(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+        self.flash.erase_block(block).map_err(SoftEccError::Flash)
+    }
+
+    /// Write whole pages with their parity
+    fn write(&mut self, offset: u64, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        let mut oob = [0; MAX_OOB];
+        let first = (offset / F::PAGE_SIZE as u64) as u32;
+        for (page, data) in (first..).zip(bytes.chunks(F::PAGE_SIZE)) {
+            for (step, parity) in data.chunks(E::STEP).zip(oob.chunks_mut(E::PARITY)) {
+                E::encode(step, parity);
+            }
+            self.flash
+                .write_page_oob(PageIndex::new(page), data, &oob[..Self::PARITY_SIZE])
+                .map_err(SoftEccError::Flash)?;
+        }
+        Ok(())
+    }
+
+    fn copy(&mut self, src_offset: u64, dest_offset: u64, length: u64) -> Result<(), Self::Error> {
+        check_slice(self, Self::WRITE_SIZE, dest_offset, length)?;
+        check_slice(self, Self::WRITE_SIZE, src_offset, length)?;
+        self.flash
+            .copy(src_offset, dest_offset, length)
+            .map_err(SoftEccError::Flash)
+    }
+}
+
+/// The spare bytes after the parity, which are not covered by the code
+impl<F: NandFlashOob, E: EccCode> NandFlashOob for SoftEcc<F, E> {
+    const OOB_SIZE: usize = F::OOB_SIZE.saturating_sub(Self::PARITY_SIZE);
+
+    fn read_oob(&mut self, page: PageIndex, oob: &mut [u8]) -> Result<(), Self::Error> {
+        if oob.len() > Self::OOB_SIZE {
+            return Err(SoftEccError::OutOfBounds);
+        }
+        let mut spare = [0; MAX_OOB];
+        let spare = &mut spare[..Self::PARITY_SIZE + oob.len()];
+        self.flash
+            .read_oob(page, spare)
+            .map_err(SoftEccError::Flash)?;
+        oob.copy_from_slice(&spare[Self::PARITY_SIZE..]);
+        Ok(())
+    }
+
+    fn write_page_oob(
+        &mut self,
+        page: PageIndex,
+        data: &[u8],
+        oob: &[u8],
+    ) -> Result<(), Self::Error> {
+        if data.len() != F::PAGE_SIZE || oob.len() > Self::OOB_SIZE {
+            return Err(SoftEccError::NotAligned);
+        }
+        let mut spare = [0; MAX_OOB];
+        for (step, parity) in data.chunks(E::STEP).zip(spare.chunks_mut(E::PARITY)) {
+            E::encode(step, parity);
+        }
+        spare[Self::PARITY_SIZE..][..oob.len()].copy_from_slice(oob);
+        self.flash
+            .write_page_oob(page, data, &spare[..Self::PARITY_SIZE + oob.len()])
+            .map_err(SoftEccError::Flash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bch, EccCode, Hamming, SoftEcc, SoftEccError};
+    use crate::test::VirtualNandFlash;
+    use crate::{NandFlash, NandFlashError, NandFlashErrorKind, PageIndex};
+
+    /// Clear the lowest set bit of a byte
+    fn flip(byte: u8) -> u8 {
+        byte & (byte - 1)
+    }
+
+    fn pattern(data: &mut [u8]) {
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = (i as u8).wrapping_mul(37) ^ 0x5A;
+        }
+    }
+
+    #[test]
+    fn test_hamming() {
+        let mut data = [0; 256];
+        pattern(&mut data);
+        let mut parity = [0; 3];
+        Hamming::encode(&data, &mut parity);
+        for bit in 0..256 * 8 {
+            let mut flipped = data;
+            flipped[bit / 8] ^= 1 << (bit % 8);
+            assert_eq!(Hamming::correct(&mut flipped, &parity), Some(1));
+            assert_eq!(flipped, data);
+        }
+        let mut flipped = data;
+        assert_eq!(
+            Hamming::correct(&mut flipped, &[parity[0] ^ 4, parity[1], parity[2]]),
+            Some(1)
+        );
+        flipped[3] ^= 1;
+        flipped[200] ^= 0x10;
+        assert_eq!(Hamming::correct(&mut flipped, &parity), None);
+
+        Hamming::encode(&[0xFF; 256], &mut parity);
+        assert_eq!(parity, [0xFF; 3]);
+    }
+
+    #[test]
+    fn test_bch() {
+        type Code = Bch<512, 4>;
+        assert_eq!(Code::PARITY, 7);
+        let mut data = [0; 512];
+        pattern(&mut data);
+        let mut parity = [0; 7];
+        Code::encode(&data, &mut parity);
+
+        let mut flipped = data;
+        let mut bad_parity = parity;
+        flipped[0] ^= 0x80;
+        flipped[300] ^= 0x21;
+        bad_parity[6] ^= 0x08;
+        assert_eq!(Code::correct(&mut flipped, &bad_parity), Some(4));
+        assert_eq!(flipped, data);
+
+        // Erased steps are valid, and bit flips in them are corrected
+        Code::encode(&[0xFF; 512], &mut parity);
+        assert_eq!(parity, [0xFF; 7]);
+        let mut erased = [0xFF; 512];
+        erased[511] = 0x7E;
+        assert_eq!(Code::correct(&mut erased, &parity), Some(2));
+        assert_eq!(erased, [0xFF; 512]);
+    }
+
+    #[test]
+    fn test_soft_ecc() {
+        type Flash = VirtualNandFlash<512, 4, 8>;
+        let mut flash = SoftEcc::<_, Bch<256, 2>>::new(Flash::new()).unwrap();
+        flash.set_failing_threshold(2);
+        let mut data = [0; 1024];
+        pattern(&mut data);
+        flash.write(2048, &data).unwrap();
+        assert_eq!(
+            flash.write(2048, &data[..16]),
+            Err(SoftEccError::NotAligned)
+        );
+
+        // A single bit is below the threshold, so is corrected silently
+        let mut buffer = [0; 1024];
+        flash.inner().write(2048 + 700, &[flip(data[700])]).unwrap();
+        flash.read(2048, &mut buffer).unwrap();
+        assert_eq!(buffer, data);
+
+        let mut partial = [0; 600];
+        flash
+            .inner()
+            .write(2048 + 10, &[flip(flip(data[10]))])
+            .unwrap();
+        // The partial read also corrects the step with the earlier bit
+        let error = flash.read(2048 + 5, &mut partial).unwrap_err();
+        let failure = error.kind().block_failure().unwrap();
+        assert!(matches!(error.kind(), NandFlashErrorKind::BlockFailing(_)));
+        assert_eq!(failure.page, Some(PageIndex::new(4)));
+        assert_eq!(failure.corrected_bits, Some(2));
+        assert_eq!(partial, data[5..605]);
+
+        flash.inner().write(2048 + 11, &[flip(data[11])]).unwrap();
+        let error = flash.read(2048, &mut buffer).unwrap_err();
+        assert_eq!(
+            error,
+            SoftEccError::Uncorrectable {
+                page: PageIndex::new(4)
+            }
+        );
+
+        // Erased pages read back as erased
+        flash.read(0, &mut buffer).unwrap();
+        assert_eq!(buffer, [0xFF; 1024]);
+
+        assert!(matches!(
+            SoftEcc::<_, Bch<1000, 2>>::new(Flash::new()),
+            Err(SoftEccError::InvalidConfig)
+        ));
+        assert!(matches!(
+            SoftEcc::<_, Hamming>::new(VirtualNandFlash::<2048, 4, 8>::new()),
+            Err(SoftEccError::InvalidConfig)
+        ));
+    }
+}
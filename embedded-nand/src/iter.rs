@@ -1,4 +1,4 @@
-use crate::{BlockIndex, ByteAddress, NandFlash, PageIndex};
+use crate::{BlockIndex, ByteAddress, PageIndex};
 
 /// Iterate over block indices and byte addresses of blocks in nand flash
 pub struct BlockIter {
@@ -66,50 +66,80 @@ impl Iterator for PageIter {
     }
 }
 
-pub trait NandFlashIter {
-    /// Iterate over a range of block indices
-    fn block_iter_range(&self, start: BlockIndex, end: BlockIndex) -> BlockIter;
-    /// Iterate over a range of page indices
-    fn page_iter_range(&self, start: PageIndex, end: PageIndex) -> PageIter;
-    /// Iterate over all blocks
-    fn block_iter(&self) -> BlockIter;
-    /// Iterate over all pages
-    fn page_iter(&self) -> PageIter;
-    /// Iterate over blocks starting from a specific block
-    fn block_iter_from(&self, start: BlockIndex) -> BlockIter;
-    /// Iterate over pages starting from a specific page
-    fn page_iter_from(&self, start: PageIndex) -> PageIter;
-}
+/// Declare [NandFlashIter] and implement it for every flash implementing `$flash`.
+///
+/// Generated for both the blocking and async `NandFlash` traits from this one definition.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __nand_flash_iter {
+    ($flash:path) => {
+        pub trait NandFlashIter {
+            /// Iterate over a range of block indices
+            fn block_iter_range(
+                &self,
+                start: $crate::BlockIndex,
+                end: $crate::BlockIndex,
+            ) -> $crate::BlockIter;
+            /// Iterate over a range of page indices
+            fn page_iter_range(
+                &self,
+                start: $crate::PageIndex,
+                end: $crate::PageIndex,
+            ) -> $crate::PageIter;
+            /// Iterate over all blocks
+            fn block_iter(&self) -> $crate::BlockIter;
+            /// Iterate over all pages
+            fn page_iter(&self) -> $crate::PageIter;
+            /// Iterate over blocks starting from a specific block
+            fn block_iter_from(&self, start: $crate::BlockIndex) -> $crate::BlockIter;
+            /// Iterate over pages starting from a specific page
+            fn page_iter_from(&self, start: $crate::PageIndex) -> $crate::PageIter;
+        }
 
-impl<T: NandFlash> NandFlashIter for T {
-    fn block_iter_range(&self, start: BlockIndex, end: BlockIndex) -> BlockIter {
-        let block_size = Self::ERASE_SIZE as u32;
-        BlockIter::new(start, end, block_size)
-    }
+        impl<T: $flash> NandFlashIter for T {
+            fn block_iter_range(
+                &self,
+                start: $crate::BlockIndex,
+                end: $crate::BlockIndex,
+            ) -> $crate::BlockIter {
+                let block_size = Self::ERASE_SIZE as u32;
+                $crate::BlockIter::new(start, end, block_size)
+            }
 
-    fn page_iter_range(&self, start: PageIndex, end: PageIndex) -> PageIter {
-        let page_size = Self::PAGE_SIZE as u32;
-        PageIter::new(start, end, page_size)
-    }
+            fn page_iter_range(
+                &self,
+                start: $crate::PageIndex,
+                end: $crate::PageIndex,
+            ) -> $crate::PageIter {
+                let page_size = Self::PAGE_SIZE as u32;
+                $crate::PageIter::new(start, end, page_size)
+            }
 
-    fn block_iter(&self) -> BlockIter {
-        self.block_iter_range(BlockIndex(0), BlockIndex(Self::BLOCK_COUNT as u16))
-    }
+            fn block_iter(&self) -> $crate::BlockIter {
+                self.block_iter_range(
+                    $crate::BlockIndex::new(0),
+                    $crate::BlockIndex::new(Self::BLOCK_COUNT as u32),
+                )
+            }
 
-    fn page_iter(&self) -> PageIter {
-        self.page_iter_range(
-            PageIndex(0),
-            PageIndex((Self::PAGES_PER_BLOCK * Self::BLOCK_COUNT) as u32),
-        )
-    }
+            fn page_iter(&self) -> $crate::PageIter {
+                self.page_iter_range(
+                    $crate::PageIndex::new(0),
+                    $crate::PageIndex::new((Self::PAGES_PER_BLOCK * Self::BLOCK_COUNT) as u32),
+                )
+            }
 
-    fn block_iter_from(&self, start: BlockIndex) -> BlockIter {
-        self.block_iter_range(start, BlockIndex(Self::BLOCK_COUNT as u16))
-    }
-    fn page_iter_from(&self, start: PageIndex) -> PageIter {
-        self.page_iter_range(
-            start,
-            PageIndex((Self::PAGES_PER_BLOCK * Self::BLOCK_COUNT) as u32),
-        )
-    }
+            fn block_iter_from(&self, start: $crate::BlockIndex) -> $crate::BlockIter {
+                self.block_iter_range(start, $crate::BlockIndex::new(Self::BLOCK_COUNT as u32))
+            }
+            fn page_iter_from(&self, start: $crate::PageIndex) -> $crate::PageIter {
+                self.page_iter_range(
+                    start,
+                    $crate::PageIndex::new((Self::PAGES_PER_BLOCK * Self::BLOCK_COUNT) as u32),
+                )
+            }
+        }
+    };
 }
+
+crate::__nand_flash_iter!(crate::NandFlash);
@@ -1,11 +1,24 @@
 #![no_std]
 
-mod address;
+// Declared first so the logging macros are available to the other modules
 mod fmt;
+
+mod address;
+mod array;
+mod bbt;
+mod ecc;
 mod iter;
+mod nor;
+pub mod partition;
+mod sector;
 pub mod test;
 pub use address::{AddressConversions, BlockIndex, ByteAddress, ColumnAddress, PageIndex};
-pub use iter::NandFlashIter;
+pub use array::{ArrayError, ArrayLayout, Concatenated, Interleaved, NandArray};
+pub use bbt::{BbtError, BbtFlash, BBT_BLOCKS};
+pub use ecc::{Bch, EccCode, Hamming, SoftEcc, SoftEccError, MAX_ECC_STEP};
+pub use iter::{BlockIter, NandFlashIter, PageIter};
+pub use nor::{NorError, NorFlashAdapter};
+pub use sector::{BlockDevice, SectorDevice, SectorError, SECTOR_SIZE};
 
 pub trait NandFlashError: core::fmt::Debug {
     /// Convert a specific NAND flash error into a generic error kind
@@ -23,6 +36,7 @@ pub trait ErrorType {
 /// NAND flash implementations must map their error to those generic error kinds through the
 /// [`NandFlashError`] trait.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
 pub enum NandFlashErrorKind {
     /// The arguments are not properly aligned.
@@ -32,17 +46,110 @@ pub enum NandFlashErrorKind {
     OutOfBounds,
 
     /// Block has failed either during erase, write or read checksum.
-    /// Contains byte address of failed block, or [None] if specific block unknown
-    BlockFail(Option<u32>),
+    BlockFail(BlockFailure),
 
     /// Block is failing but operation was successful i.e ECC corrected read.
-    /// Contains byte address of failing block, or [None] if specific block unknown
-    BlockFailing(Option<u32>),
+    BlockFailing(BlockFailure),
+
+    /// The device did not finish the operation in time.
+    Timeout,
+
+    /// The device refused to program or erase as the block is write protected.
+    WriteProtected,
 
     /// Error specific to the implementation.
     Other,
 }
 
+impl NandFlashErrorKind {
+    /// Details of a failed or failing block, None for other kinds
+    pub fn block_failure(&self) -> Option<BlockFailure> {
+        match self {
+            NandFlashErrorKind::BlockFail(failure) | NandFlashErrorKind::BlockFailing(failure) => {
+                Some(*failure)
+            }
+            _ => None,
+        }
+    }
+
+    /// Map the page of a block failure, for errors passed up from a device whose pages are
+    /// numbered differently. The page becomes unknown if `map` returns None
+    pub(crate) fn map_page(self, map: impl FnOnce(u32) -> Option<u32>) -> Self {
+        let mapped = |failure: BlockFailure| BlockFailure {
+            page: failure
+                .page
+                .and_then(|page| map(page.as_u32()))
+                .map(PageIndex::new),
+            ..failure
+        };
+        match self {
+            NandFlashErrorKind::BlockFail(failure) => {
+                NandFlashErrorKind::BlockFail(mapped(failure))
+            }
+            NandFlashErrorKind::BlockFailing(failure) => {
+                NandFlashErrorKind::BlockFailing(mapped(failure))
+            }
+            kind => kind,
+        }
+    }
+}
+
+/// The operation that found a failed or failing block
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NandOperation {
+    /// Reading a page
+    Read,
+    /// Programming (writing) a page
+    Program,
+    /// Erasing a block
+    Erase,
+}
+
+/// Details of a failed or failing block. Each part is None if the implementation doesn't know it.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockFailure {
+    /// Page the operation failed at. The first page of the block for erases
+    pub page: Option<PageIndex>,
+    /// The operation that failed
+    pub operation: Option<NandOperation>,
+    /// Most bits corrected by ECC in one code word of the page, to tell how close a read
+    /// was to uncorrectable
+    pub corrected_bits: Option<u8>,
+}
+
+impl BlockFailure {
+    /// Failure of an operation, at an unknown page
+    pub const fn new(operation: NandOperation) -> Self {
+        BlockFailure {
+            page: None,
+            operation: Some(operation),
+            corrected_bits: None,
+        }
+    }
+
+    /// Set the page the operation failed at
+    pub const fn with_page(mut self, page: PageIndex) -> Self {
+        self.page = Some(page);
+        self
+    }
+
+    /// Set the most bits corrected in one code word
+    pub const fn with_corrected_bits(mut self, bits: u8) -> Self {
+        self.corrected_bits = Some(bits);
+        self
+    }
+
+    /// Block containing the page
+    pub fn block<F: NandFlash>(&self) -> Option<BlockIndex> {
+        self.page
+            .map(|page| BlockIndex::new(page.as_u32() / F::PAGES_PER_BLOCK as u32))
+    }
+}
+
 /// NAND flash trait.
 pub trait NandFlash: ErrorType {
     /// The minumum number of bytes the storage peripheral can read
@@ -71,10 +178,10 @@ pub trait NandFlash: ErrorType {
     /// Returns an error if the arguments are not aligned or out of bounds. The implementation
     /// can use the [`check_read`] helper function.
     ///
-    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error>;
+    fn read(&mut self, offset: u64, bytes: &mut [u8]) -> Result<(), Self::Error>;
 
     /// The capacity of the peripheral in bytes.
-    fn capacity(&self) -> u32;
+    fn capacity(&self) -> u64;
 
     /// Mark the block as bad
     fn mark_block_bad(&mut self, block: BlockIndex) -> Result<(), Self::Error>;
@@ -92,7 +199,7 @@ pub trait NandFlash: ErrorType {
     /// Returns an error if the arguments are not aligned or out of bounds (the case where `to >
     /// from` is considered out of bounds). The implementation can use the [`check_erase`]
     /// helper function.
-    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error>;
+    fn erase(&mut self, from: u64, to: u64) -> Result<(), Self::Error>;
 
     /// Erase a block by block index.
     fn erase_block(&mut self, block: BlockIndex) -> Result<(), Self::Error>;
@@ -105,76 +212,199 @@ pub trait NandFlash: ErrorType {
     ///
     /// Returns an error if the arguments are not aligned or out of bounds. The implementation
     /// can use the [`check_write`] helper function.
-    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error>;
+    fn write(&mut self, offset: u64, bytes: &[u8]) -> Result<(), Self::Error>;
 
     /// Copy data from one location to another.
     ///
     /// Some devices support internal copy commands, which are faster than
     /// reading and writing the data. This function should be used to
     /// implement the copy command.
-    fn copy(&mut self, src_offset: u32, dest_offset: u32, length: u32) -> Result<(), Self::Error>;
+    fn copy(&mut self, src_offset: u64, dest_offset: u64, length: u64) -> Result<(), Self::Error>;
+}
+
+/// NAND flash that can replace a bad block in hardware, such as with the bad block
+/// management lookup table (LUT) of Winbond W25N devices.
+///
+/// After a swap, accesses to the bad block go to the replacement block, so the
+/// replacement must no longer be used directly.
+pub trait BlockSwap: NandFlash {
+    /// Replace `bad` with `replacement`.
+    ///
+    /// Returns false if the block can't be swapped, e.g. the lookup table is full, in
+    /// which case the bad block must be handled in software.
+    fn swap_block(&mut self, bad: BlockIndex, replacement: BlockIndex)
+        -> Result<bool, Self::Error>;
+}
+
+/// NAND flash that records how many times each block has been erased.
+pub trait EraseCount: NandFlash {
+    /// Erase count of a block, None if not known
+    fn erase_count(&self, block: BlockIndex) -> Option<u32>;
+}
+
+/// NAND flash with access to the spare (out of band) area of each page.
+///
+/// Only the spare bytes free for the user are exposed, packed together from index 0, so
+/// the bad block marker and ECC parity are never touched. Where the device has them,
+/// these should be bytes covered by the ECC.
+pub trait NandFlashOob: NandFlash {
+    /// Number of free spare bytes in each page
+    const OOB_SIZE: usize;
+
+    /// Read free spare bytes of a page, starting from the first
+    fn read_oob(&mut self, page: PageIndex, oob: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Program a whole page with free spare bytes, starting from the first, in one operation.
+    ///
+    /// `data` must be [NandFlash::PAGE_SIZE] bytes and `oob` at most [NandFlashOob::OOB_SIZE].
+    fn write_page_oob(
+        &mut self,
+        page: PageIndex,
+        data: &[u8],
+        oob: &[u8],
+    ) -> Result<(), Self::Error>;
+}
+
+/// NAND flash that can start programming a page and finish it later.
+///
+/// While the device programs the page, the bus is free to load a page into another
+/// device, which [NandArray] uses to program [Interleaved] devices in parallel.
+pub trait SplitProgram: NandFlash {
+    /// Load a whole page and start programming it, without waiting for it to finish.
+    ///
+    /// `data` must be [NandFlash::PAGE_SIZE] bytes. No other operation may be used on the
+    /// device until [SplitProgram::program_finish] is called for the page.
+    fn program_start(&mut self, page: PageIndex, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Wait for the program started with [SplitProgram::program_start] and check its result
+    fn program_finish(&mut self, page: PageIndex) -> Result<(), Self::Error>;
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
 pub enum BlockStatus {
-    /// Marked OK and passes ECC / Checksum
+    /// Marked OK and passes ECC / Checksum. May contain data
     Ok,
-    /// Marked as failed or failed ECC / Checksum
+    /// Marked OK and the first page is erased
+    Erased,
+    /// Marked as failed or failed ECC / Checksum, cause unknown
     Failed,
+    /// Marked bad by the manufacturer
+    FactoryBad,
+    /// Retired at runtime with [NandFlash::mark_block_bad]
+    Worn,
+    /// Good, but reserved for metadata such as a map or bad block table
+    Reserved,
+    /// The status could not be determined
+    Unknown,
 }
 
 impl BlockStatus {
-    /// Return true if the block is marked as failed
+    /// Return true if the block is marked as failed, by the manufacturer or at runtime
     pub fn is_failed(&self) -> bool {
-        matches!(self, BlockStatus::Failed)
+        matches!(
+            self,
+            BlockStatus::Failed | BlockStatus::FactoryBad | BlockStatus::Worn
+        )
     }
 
-    /// Return true if the block is marked as OK
+    /// Return true if the block is marked as OK and free to use, erased or not
     pub fn is_ok(&self) -> bool {
-        matches!(self, BlockStatus::Ok)
+        matches!(self, BlockStatus::Ok | BlockStatus::Erased)
+    }
+
+    /// Return true if the block is good and known to be erased
+    pub fn is_erased(&self) -> bool {
+        matches!(self, BlockStatus::Erased)
     }
 }
 
-/// Return whether a read operation is within bounds.
-pub fn check_read<T: NandFlash>(
-    flash: &T,
-    offset: u32,
-    length: usize,
-) -> Result<(), NandFlashErrorKind> {
-    check_slice(flash, T::READ_SIZE, offset, length)
+/// Declare the `check_*` helpers for implementations of `$flash`.
+///
+/// Generated for both the blocking and async `NandFlash` traits from this one definition,
+/// on top of [check_range] and [check_erase_range].
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __check_helpers {
+    ($flash:path) => {
+        /// Return whether a read operation is within bounds.
+        pub fn check_read<T: $flash>(
+            flash: &T,
+            offset: u64,
+            length: usize,
+        ) -> Result<(), $crate::NandFlashErrorKind> {
+            check_slice(flash, T::READ_SIZE, offset, length as u64)
+        }
+
+        /// Return whether an erase operation is aligned and within bounds.
+        pub fn check_erase<T: $flash>(
+            flash: &T,
+            from: u64,
+            to: u64,
+        ) -> Result<(), $crate::NandFlashErrorKind> {
+            $crate::check_erase_range(flash.capacity(), T::ERASE_SIZE, from, to)
+        }
+
+        /// Return whether a write operation is aligned and within bounds.
+        pub fn check_write<T: $flash>(
+            flash: &T,
+            offset: u64,
+            length: usize,
+        ) -> Result<(), $crate::NandFlashErrorKind> {
+            check_slice(flash, T::WRITE_SIZE, offset, length as u64)
+        }
+
+        /// Return whether a slice of `length` bytes at `offset` is aligned to `align` and
+        /// within bounds.
+        ///
+        /// Overflow of `offset + length` is reported as [NandFlashErrorKind::OutOfBounds].
+        /// `length` is a u64 so lengths beyond `usize` on 32 bit targets aren't truncated.
+        pub fn check_slice<T: $flash>(
+            flash: &T,
+            align: usize,
+            offset: u64,
+            length: u64,
+        ) -> Result<(), $crate::NandFlashErrorKind> {
+            $crate::check_range(flash.capacity(), align, offset, length)
+        }
+    };
 }
 
-/// Return whether an erase operation is aligned and within bounds.
-pub fn check_erase<T: NandFlash>(flash: &T, from: u32, to: u32) -> Result<(), NandFlashErrorKind> {
-    if from > to || to > flash.capacity() {
-        return Err(NandFlashErrorKind::OutOfBounds);
+crate::__check_helpers!(crate::NandFlash);
+
+/// Return whether a slice of `length` bytes at `offset` is aligned to `align` and within
+/// `capacity` bytes.
+///
+/// Overflow of `offset + length` is reported as [NandFlashErrorKind::OutOfBounds].
+pub fn check_range(
+    capacity: u64,
+    align: usize,
+    offset: u64,
+    length: u64,
+) -> Result<(), NandFlashErrorKind> {
+    match offset.checked_add(length) {
+        Some(end) if end <= capacity => {}
+        _ => return Err(NandFlashErrorKind::OutOfBounds),
     }
-    if from % T::ERASE_SIZE as u32 != 0 || to % T::ERASE_SIZE as u32 != 0 {
+    if offset % (align as u64) != 0 || length % (align as u64) != 0 {
         return Err(NandFlashErrorKind::NotAligned);
     }
     Ok(())
 }
 
-/// Return whether a write operation is aligned and within bounds.
-pub fn check_write<T: NandFlash>(
-    flash: &T,
-    offset: u32,
-    length: usize,
-) -> Result<(), NandFlashErrorKind> {
-    check_slice(flash, T::WRITE_SIZE, offset, length)
-}
-
-pub fn check_slice<T: NandFlash>(
-    flash: &T,
-    align: usize,
-    offset: u32,
-    length: usize,
+/// Return whether an erase of `[from..to]` is aligned to `erase_size` and within
+/// `capacity` bytes.
+pub fn check_erase_range(
+    capacity: u64,
+    erase_size: usize,
+    from: u64,
+    to: u64,
 ) -> Result<(), NandFlashErrorKind> {
-    if length as u32 > flash.capacity() || offset > (flash.capacity() - (length as u32)) {
+    if from > to || to > capacity {
         return Err(NandFlashErrorKind::OutOfBounds);
     }
-    if offset % align as u32 != 0 || length % align != 0 {
+    if from % (erase_size as u64) != 0 || to % (erase_size as u64) != 0 {
         return Err(NandFlashErrorKind::NotAligned);
     }
     Ok(())
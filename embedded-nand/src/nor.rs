@@ -0,0 +1,189 @@
+use embedded_storage::nor_flash::{
+    self, check_erase, check_read, check_write, NorFlash, NorFlashErrorKind, ReadNorFlash,
+};
+
+use crate::{BlockIndex, NandFlash, NandFlashError, NandFlashErrorKind};
+
+/// Error returned by a [NorFlashAdapter]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NorError<E> {
+    /// Error from the NAND flash
+    Flash(E),
+    /// The arguments are not properly aligned
+    NotAligned,
+    /// The arguments are out of bounds
+    OutOfBounds,
+}
+
+impl<E: NandFlashError> nor_flash::NorFlashError for NorError<E> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            NorError::Flash(e) => match e.kind() {
+                NandFlashErrorKind::NotAligned => NorFlashErrorKind::NotAligned,
+                NandFlashErrorKind::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+                _ => NorFlashErrorKind::Other,
+            },
+            NorError::NotAligned => NorFlashErrorKind::NotAligned,
+            NorError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+        }
+    }
+}
+
+// Conversion for the embedded_storage check_* helper functions
+impl<E> From<NorFlashErrorKind> for NorError<E> {
+    fn from(kind: NorFlashErrorKind) -> Self {
+        match kind {
+            NorFlashErrorKind::NotAligned => NorError::NotAligned,
+            _ => NorError::OutOfBounds,
+        }
+    }
+}
+
+// Conversion for the crate::check_* helper functions, used by the async adapter
+impl<E> From<NandFlashErrorKind> for NorError<E> {
+    fn from(kind: NandFlashErrorKind) -> Self {
+        match kind {
+            NandFlashErrorKind::NotAligned => NorError::NotAligned,
+            _ => NorError::OutOfBounds,
+        }
+    }
+}
+
+/// Presents a [NandFlash] as an [embedded_storage] [NorFlash].
+///
+/// A NAND block is the erase unit, so `ERASE_SIZE` is the block size and erases
+/// are performed a block at a time. `WRITE_SIZE` is the page size, so every write
+/// programs whole pages. Reads and writes are passed straight through, so the NAND
+/// rules still apply: a page must only be written once between erases, and pages
+/// within a block should be written in order. NOR users that overwrite data in place,
+/// or write the same page more than once, break these rules.
+///
+/// The NOR traits use 32 bit offsets, so only the first 4 GiB (rounded down to a
+/// whole block) of larger devices is accessible.
+///
+/// Usually wraps a flash translation layer such as `FlashMap` so bad blocks are
+/// hidden from the NOR user.
+#[derive(Debug)]
+pub struct NorFlashAdapter<F> {
+    flash: F,
+}
+
+impl<F: NandFlash> NorFlashAdapter<F> {
+    /// Wrap a [NandFlash]
+    pub fn new(flash: F) -> Self {
+        NorFlashAdapter { flash }
+    }
+
+    /// Access the wrapped flash
+    pub fn inner(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Release the wrapped flash
+    pub fn release(self) -> F {
+        self.flash
+    }
+}
+
+impl<F: NandFlash> nor_flash::ErrorType for NorFlashAdapter<F> {
+    type Error = NorError<F::Error>;
+}
+
+impl<F: NandFlash> ReadNorFlash for NorFlashAdapter<F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        self.flash
+            .read(offset as u64, bytes)
+            .map_err(NorError::Flash)
+    }
+
+    fn capacity(&self) -> usize {
+        // Limit to what can be addressed with a u32, in whole blocks
+        let capacity = self.flash.capacity().min(u32::MAX as u64);
+        (capacity - capacity % F::ERASE_SIZE as u64) as usize
+    }
+}
+
+impl<F: NandFlash> NorFlash for NorFlashAdapter<F> {
+    const WRITE_SIZE: usize = F::PAGE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        let erase_size = F::ERASE_SIZE as u32;
+        for block in (from / erase_size)..(to / erase_size) {
+            self.flash
+                .erase_block(BlockIndex::new(block))
+                .map_err(NorError::Flash)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        self.flash
+            .write(offset as u64, bytes)
+            .map_err(NorError::Flash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_storage::nor_flash::{NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+    use super::NorFlashAdapter;
+    use crate::test::VirtualNandFlash;
+    use crate::BlockIndex;
+
+    #[test]
+    fn test_nor_rw() {
+        let mut nor = NorFlashAdapter::new(VirtualNandFlash::<64, 4, 16>::new());
+        assert_eq!(nor.capacity(), 64 * 4 * 16);
+        assert_eq!(
+            NorFlashAdapter::<VirtualNandFlash<64, 4, 16>>::ERASE_SIZE,
+            256
+        );
+
+        nor.erase(256, 768).unwrap();
+        assert_eq!(nor.inner().erase_count(BlockIndex::new(0)), 0);
+        assert_eq!(nor.inner().erase_count(BlockIndex::new(1)), 1);
+        assert_eq!(nor.inner().erase_count(BlockIndex::new(2)), 1);
+        assert_eq!(
+            NorFlashAdapter::<VirtualNandFlash<64, 4, 16>>::WRITE_SIZE,
+            64
+        );
+        nor.write(320, &[0x5A; 64]).unwrap();
+        let mut buf = [0; 66];
+        nor.read(319, &mut buf).unwrap();
+        assert_eq!(buf[0], 0xFF);
+        assert_eq!(buf[1..65], [0x5A; 64]);
+        assert_eq!(buf[65], 0xFF);
+    }
+
+    #[test]
+    fn test_nor_errors() {
+        let mut nor = NorFlashAdapter::new(VirtualNandFlash::<64, 4, 16>::new());
+        // NorFlashErrorKind has no defmt::Format, so compare with matches!
+        assert!(matches!(
+            nor.erase(0, 100).unwrap_err().kind(),
+            NorFlashErrorKind::NotAligned
+        ));
+        assert!(matches!(
+            nor.erase(0, 64 * 4 * 17).unwrap_err().kind(),
+            NorFlashErrorKind::OutOfBounds
+        ));
+        // Writes are whole pages
+        assert!(matches!(
+            nor.write(0, &[0; 4]).unwrap_err().kind(),
+            NorFlashErrorKind::NotAligned
+        ));
+        let mut buf = [0; 4];
+        assert!(matches!(
+            nor.read(64 * 4 * 16 - 2, &mut buf).unwrap_err().kind(),
+            NorFlashErrorKind::OutOfBounds
+        ));
+    }
+}
@@ -0,0 +1,628 @@
+use core::cell::RefCell;
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+use crate::{
+    check_erase, check_read, check_slice, check_write, BlockIndex, BlockStatus, ErrorType,
+    NandFlash, NandFlashError, NandFlashErrorKind,
+};
+
+/// Magic bytes that identify a [PartitionTable] on flash
+const MAGIC: [u8; 4] = *b"NPRT";
+
+/// Version of the [PartitionTable] format
+const VERSION: u8 = 1;
+
+/// Maximum number of entries in a [PartitionTable]
+pub const MAX_PARTITIONS: usize = 16;
+
+/// Length of a partition name in bytes
+pub const PARTITION_NAME_LENGTH: usize = 8;
+
+/// Size of a [PartitionTable] on flash: header, entries and CRC padded with 0xFF
+const TABLE_SIZE: usize = 512;
+/// Size of the header: magic, version, entry count and 2 reserved bytes
+const HEADER_SIZE: usize = 8;
+/// Size of an entry: name, first block and block count
+const ENTRY_SIZE: usize = PARTITION_NAME_LENGTH + 8;
+
+/// CRC used to check the partition table
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Error returned by a [Partition] or [PartitionTable]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PartitionError<E> {
+    /// Error from the underlying flash
+    Flash {
+        /// Error from the flash
+        error: E,
+        /// Flash page of the first page of the partition, 0 for the whole flash
+        base_page: u32,
+    },
+    /// The arguments are not properly aligned
+    NotAligned,
+    /// The arguments are out of bounds, or the partition is outside the flash
+    OutOfBounds,
+    /// The partition size doesn't match the size of the [Partition] type
+    SizeMismatch,
+    /// The partition overlaps an existing partition
+    Overlap,
+    /// The partition table is full
+    TableFull,
+    /// The name is too long
+    InvalidName,
+    /// No valid partition table found
+    NotFound,
+}
+
+impl<E: NandFlashError> NandFlashError for PartitionError<E> {
+    fn kind(&self) -> NandFlashErrorKind {
+        match self {
+            // Failures are reported at the partition page, not the flash page
+            PartitionError::Flash { error, base_page } => {
+                error.kind().map_page(|page| page.checked_sub(*base_page))
+            }
+            PartitionError::NotAligned => NandFlashErrorKind::NotAligned,
+            PartitionError::OutOfBounds => NandFlashErrorKind::OutOfBounds,
+            _ => NandFlashErrorKind::Other,
+        }
+    }
+}
+
+/// Error building a [PartitionTable]. Converts into the matching [PartitionError]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TableError {
+    /// The partition is empty or outside the flash
+    OutOfBounds,
+    /// The partition overlaps an existing partition
+    Overlap,
+    /// The partition table is full
+    TableFull,
+    /// The name is empty, too long or already used
+    InvalidName,
+}
+
+impl<E> From<TableError> for PartitionError<E> {
+    fn from(error: TableError) -> Self {
+        match error {
+            TableError::OutOfBounds => PartitionError::OutOfBounds,
+            TableError::Overlap => PartitionError::Overlap,
+            TableError::TableFull => PartitionError::TableFull,
+            TableError::InvalidName => PartitionError::InvalidName,
+        }
+    }
+}
+
+// Conversion for the check_* helper functions
+impl<E> From<NandFlashErrorKind> for PartitionError<E> {
+    fn from(kind: NandFlashErrorKind) -> Self {
+        match kind {
+            NandFlashErrorKind::NotAligned => PartitionError::NotAligned,
+            _ => PartitionError::OutOfBounds,
+        }
+    }
+}
+
+/// Wrap an error from the whole flash
+fn flash_error<E>(error: E) -> PartitionError<E> {
+    PartitionError::Flash {
+        error,
+        base_page: 0,
+    }
+}
+
+/// Shared access to a [NandFlash] so that several [Partition]s can use the same device.
+///
+/// Implemented for `&RefCell<F>`, and `&critical_section::Mutex<RefCell<F>>` with the
+/// `critical-section` feature. Implement it for other mutex types
+/// (e.g. `embassy_sync::blocking_mutex::Mutex`) by locking and passing the flash to `f`.
+pub trait FlashHandle {
+    /// The shared flash device
+    type Flash: NandFlash;
+
+    /// Run `f` with exclusive access to the flash
+    fn lock<R>(&self, f: impl FnOnce(&mut Self::Flash) -> R) -> R;
+}
+
+impl<F: NandFlash> FlashHandle for &RefCell<F> {
+    type Flash = F;
+
+    fn lock<R>(&self, f: impl FnOnce(&mut F) -> R) -> R {
+        f(&mut self.borrow_mut())
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl<F: NandFlash> FlashHandle for &critical_section::Mutex<RefCell<F>> {
+    type Flash = F;
+
+    fn lock<R>(&self, f: impl FnOnce(&mut F) -> R) -> R {
+        critical_section::with(|cs| f(&mut self.borrow_ref_mut(cs)))
+    }
+}
+
+/// A named range of blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PartitionEntry {
+    /// Name, padded with zeros
+    name: [u8; PARTITION_NAME_LENGTH],
+    /// First block of the partition
+    first_block: BlockIndex,
+    /// Number of blocks in the partition
+    block_count: u32,
+}
+
+impl PartitionEntry {
+    /// Create an entry. The name must be at most [PARTITION_NAME_LENGTH] bytes
+    pub fn new(name: &str, first_block: BlockIndex, block_count: u32) -> Result<Self, TableError> {
+        if name.is_empty() || name.len() > PARTITION_NAME_LENGTH {
+            return Err(TableError::InvalidName);
+        }
+        let mut padded = [0; PARTITION_NAME_LENGTH];
+        padded[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(PartitionEntry {
+            name: padded,
+            first_block,
+            block_count,
+        })
+    }
+
+    /// The name of the partition
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(PARTITION_NAME_LENGTH);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+
+    /// First block of the partition
+    pub fn first_block(&self) -> BlockIndex {
+        self.first_block
+    }
+
+    /// Number of blocks in the partition
+    pub fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    /// Block after the end of the partition
+    fn end_block(&self) -> u64 {
+        self.first_block.as_u32() as u64 + self.block_count as u64
+    }
+}
+
+/// A list of partitions that can be stored in a block of the flash.
+///
+/// On flash, the table is stored at the start of a block as little endian:
+/// magic `NPRT`, version, entry count, 2 reserved bytes, then each entry as an 8 byte
+/// name, first block (u32) and block count (u32), followed by a CRC-32 of the preceding
+/// bytes. [PartitionTable::find_in] scans the first blocks of the device for a valid table at boot.
+/// Writing the table to two blocks gives a backup if one fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PartitionTable {
+    entries: [PartitionEntry; MAX_PARTITIONS],
+    len: usize,
+}
+
+impl Default for PartitionTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartitionTable {
+    /// Create an empty table
+    pub fn new() -> Self {
+        PartitionTable {
+            entries: [PartitionEntry {
+                name: [0; PARTITION_NAME_LENGTH],
+                first_block: BlockIndex::new(0),
+                block_count: 0,
+            }; MAX_PARTITIONS],
+            len: 0,
+        }
+    }
+
+    /// The partitions in the table
+    pub fn entries(&self) -> &[PartitionEntry] {
+        &self.entries[..self.len]
+    }
+
+    /// Find a partition by name
+    pub fn find(&self, name: &str) -> Option<&PartitionEntry> {
+        self.entries().iter().find(|entry| entry.name() == name)
+    }
+
+    /// Add a partition to the table.
+    ///
+    /// Fails if the partition overlaps an existing one or doesn't fit in a
+    /// device of `block_count` blocks.
+    pub fn add(&mut self, entry: PartitionEntry, block_count: u32) -> Result<(), TableError> {
+        if entry.block_count == 0 || entry.end_block() > block_count as u64 {
+            return Err(TableError::OutOfBounds);
+        }
+        if self.entries().iter().any(|other| {
+            (entry.first_block.as_u32() as u64) < other.end_block()
+                && (other.first_block.as_u32() as u64) < entry.end_block()
+        }) {
+            return Err(TableError::Overlap);
+        }
+        if self.find(entry.name()).is_some() {
+            return Err(TableError::InvalidName);
+        }
+        if self.len == MAX_PARTITIONS {
+            return Err(TableError::TableFull);
+        }
+        self.entries[self.len] = entry;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Encode the table in its flash format
+    fn to_bytes(&self) -> [u8; TABLE_SIZE] {
+        let mut bytes = [0xFF; TABLE_SIZE];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4] = VERSION;
+        bytes[5] = self.len as u8;
+        bytes[6..8].fill(0);
+        for (entry, chunk) in self
+            .entries()
+            .iter()
+            .zip(bytes[HEADER_SIZE..].chunks_exact_mut(ENTRY_SIZE))
+        {
+            chunk[..8].copy_from_slice(&entry.name);
+            chunk[8..12].copy_from_slice(&entry.first_block.as_u32().to_le_bytes());
+            chunk[12..16].copy_from_slice(&entry.block_count.to_le_bytes());
+        }
+        let crc_offset = HEADER_SIZE + self.len * ENTRY_SIZE;
+        let crc = CRC.checksum(&bytes[..crc_offset]);
+        bytes[crc_offset..crc_offset + 4].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Decode a table from its flash format, None if not valid
+    fn from_bytes(bytes: &[u8; TABLE_SIZE]) -> Option<Self> {
+        if bytes[..4] != MAGIC {
+            return None;
+        }
+        if bytes[4] != VERSION {
+            warn!("Unsupported partition table version {}", bytes[4]);
+            return None;
+        }
+        let len = bytes[5] as usize;
+        if len > MAX_PARTITIONS {
+            return None;
+        }
+        let crc_offset = HEADER_SIZE + len * ENTRY_SIZE;
+        let crc = u32::from_le_bytes(bytes[crc_offset..crc_offset + 4].try_into().unwrap());
+        if crc != CRC.checksum(&bytes[..crc_offset]) {
+            warn!("Partition table CRC mismatch");
+            return None;
+        }
+        let mut table = PartitionTable::new();
+        for chunk in bytes[HEADER_SIZE..crc_offset].chunks_exact(ENTRY_SIZE) {
+            table.entries[table.len] = PartitionEntry {
+                name: chunk[..8].try_into().unwrap(),
+                first_block: BlockIndex::new(u32::from_le_bytes(chunk[8..12].try_into().unwrap())),
+                block_count: u32::from_le_bytes(chunk[12..16].try_into().unwrap()),
+            };
+            table.len += 1;
+        }
+        Some(table)
+    }
+
+    /// Erase `block` and write the table to it
+    pub fn write_to<F: NandFlash>(
+        &self,
+        flash: &mut F,
+        block: BlockIndex,
+    ) -> Result<(), PartitionError<F::Error>> {
+        const { ::core::assert!(TABLE_SIZE % F::WRITE_SIZE == 0 && TABLE_SIZE <= F::PAGE_SIZE) }
+        flash.erase_block(block).map_err(flash_error)?;
+        flash
+            .write(
+                block.as_u32() as u64 * F::ERASE_SIZE as u64,
+                &self.to_bytes(),
+            )
+            .map_err(flash_error)
+    }
+
+    /// Read a table from the start of `block`, None if there isn't a valid table
+    pub fn read_from<F: NandFlash>(
+        flash: &mut F,
+        block: BlockIndex,
+    ) -> Result<Option<Self>, PartitionError<F::Error>> {
+        const { ::core::assert!(TABLE_SIZE % F::READ_SIZE == 0) }
+        let mut bytes = [0; TABLE_SIZE];
+        flash
+            .read(block.as_u32() as u64 * F::ERASE_SIZE as u64, &mut bytes)
+            .map_err(flash_error)?;
+        Ok(Self::from_bytes(&bytes))
+    }
+
+    /// Search the first `search_blocks` blocks for a valid table.
+    ///
+    /// Bad or unreadable blocks are skipped. Returns the block the table was found in.
+    pub fn find_in<F: NandFlash>(
+        flash: &mut F,
+        search_blocks: u32,
+    ) -> Result<(BlockIndex, Self), PartitionError<F::Error>> {
+        for block in 0..search_blocks.min(F::BLOCK_COUNT as u32) {
+            let block = BlockIndex::new(block);
+            if flash.block_status(block).map_err(flash_error)?.is_failed() {
+                continue;
+            }
+            match Self::read_from(flash, block) {
+                Ok(Some(table)) => {
+                    debug!("Found partition table in block {}", block.as_u32());
+                    return Ok((block, table));
+                }
+                Ok(None) => {}
+                Err(PartitionError::Flash { error, .. })
+                    if matches!(error.kind(), NandFlashErrorKind::BlockFail(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(PartitionError::NotFound)
+    }
+}
+
+/// A [NandFlash] view of `BLOCKS` blocks of a shared device.
+///
+/// All offsets and block indices are relative to the start of the partition, and
+/// accesses outside it are rejected. The flash is shared through a [FlashHandle],
+/// so several partitions (or a partition and other users) can use one device.
+///
+/// ```ignore
+/// let flash = RefCell::new(flash);
+/// let (_, table) = PartitionTable::find_in(&mut *flash.borrow_mut(), 4)?;
+/// let mut slot_a: Partition<_, 64> = Partition::from_entry(&flash, table.find("slot_a").unwrap())?;
+/// let mut logs: Partition<_, 128> = Partition::from_entry(&flash, table.find("logs").unwrap())?;
+/// ```
+#[derive(Debug)]
+pub struct Partition<H, const BLOCKS: usize> {
+    handle: H,
+    first_block: BlockIndex,
+}
+
+impl<H: FlashHandle, const BLOCKS: usize> Partition<H, BLOCKS> {
+    /// Create a partition starting at `first_block`
+    pub fn new(
+        handle: H,
+        first_block: BlockIndex,
+    ) -> Result<Self, PartitionError<<H::Flash as ErrorType>::Error>> {
+        if first_block.as_u32() as u64 + BLOCKS as u64 > H::Flash::BLOCK_COUNT as u64 {
+            return Err(PartitionError::OutOfBounds);
+        }
+        Ok(Partition {
+            handle,
+            first_block,
+        })
+    }
+
+    /// Create a partition from a [PartitionTable] entry, which must be `BLOCKS` long
+    pub fn from_entry(
+        handle: H,
+        entry: &PartitionEntry,
+    ) -> Result<Self, PartitionError<<H::Flash as ErrorType>::Error>> {
+        if entry.block_count as usize != BLOCKS {
+            return Err(PartitionError::SizeMismatch);
+        }
+        Self::new(handle, entry.first_block)
+    }
+
+    /// First block of the partition on the device
+    pub fn first_block(&self) -> BlockIndex {
+        self.first_block
+    }
+
+    /// Release the flash handle
+    pub fn release(self) -> H {
+        self.handle
+    }
+
+    /// Byte offset of the partition on the device
+    fn base(&self) -> u64 {
+        self.first_block.as_u32() as u64 * Self::ERASE_SIZE as u64
+    }
+
+    /// Wrap an error from the device
+    fn flash_error(
+        &self,
+    ) -> impl FnOnce(<H::Flash as ErrorType>::Error) -> PartitionError<<H::Flash as ErrorType>::Error>
+    {
+        let base_page = self.first_block.as_u32() * Self::PAGES_PER_BLOCK as u32;
+        move |error| PartitionError::Flash { error, base_page }
+    }
+
+    /// Device block of a partition block
+    fn device_block(
+        &self,
+        block: BlockIndex,
+    ) -> Result<BlockIndex, PartitionError<<H::Flash as ErrorType>::Error>> {
+        if block.as_u32() as usize >= BLOCKS {
+            return Err(PartitionError::OutOfBounds);
+        }
+        Ok(BlockIndex::new(self.first_block.as_u32() + block.as_u32()))
+    }
+}
+
+impl<H: FlashHandle, const BLOCKS: usize> ErrorType for Partition<H, BLOCKS> {
+    type Error = PartitionError<<H::Flash as ErrorType>::Error>;
+}
+
+impl<H: FlashHandle, const BLOCKS: usize> NandFlash for Partition<H, BLOCKS> {
+    const READ_SIZE: usize = H::Flash::READ_SIZE;
+    const PAGE_SIZE: usize = H::Flash::PAGE_SIZE;
+    const PAGES_PER_BLOCK: usize = H::Flash::PAGES_PER_BLOCK;
+    const BLOCK_COUNT: usize = BLOCKS;
+    const ERASE_SIZE: usize = H::Flash::ERASE_SIZE;
+    const WRITE_SIZE: usize = H::Flash::WRITE_SIZE;
+
+    fn read(&mut self, offset: u64, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let offset = self.base() + offset;
+        self.handle
+            .lock(|flash| flash.read(offset, bytes))
+            .map_err(self.flash_error())
+    }
+
+    fn capacity(&self) -> u64 {
+        BLOCKS as u64 * Self::ERASE_SIZE as u64
+    }
+
+    fn block_status(&mut self, block: BlockIndex) -> Result<BlockStatus, Self::Error> {
+        let block = self.device_block(block)?;
+        self.handle
+            .lock(|flash| flash.block_status(block))
+            .map_err(self.flash_error())
+    }
+
+    fn mark_block_bad(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+        let block = self.device_block(block)?;
+        self.handle
+            .lock(|flash| flash.mark_block_bad(block))
+            .map_err(self.flash_error())
+    }
+
+    fn erase(&mut self, from: u64, to: u64) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        let first = (from / Self::ERASE_SIZE as u64) as u32;
+        let last = (to / Self::ERASE_SIZE as u64) as u32;
+        for block in first..last {
+            self.erase_block(BlockIndex::new(block))?;
+        }
+        Ok(())
+    }
+
+    fn erase_block(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+        let block = self.device_block(block)?;
+        self.handle
+            .lock(|flash| flash.erase_block(block))
+            .map_err(self.flash_error())
+    }
+
+    fn write(&mut self, offset: u64, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        let offset = self.base() + offset;
+        self.handle
+            .lock(|flash| flash.write(offset, bytes))
+            .map_err(self.flash_error())
+    }
+
+    fn copy(&mut self, src_offset: u64, dest_offset: u64, length: u64) -> Result<(), Self::Error> {
+        check_slice(self, Self::READ_SIZE, src_offset, length)?;
+        check_slice(self, Self::WRITE_SIZE, dest_offset, length)?;
+        let base = self.base();
+        self.handle
+            .lock(|flash| flash.copy(base + src_offset, base + dest_offset, length))
+            .map_err(self.flash_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use super::{Partition, PartitionEntry, PartitionError, PartitionTable, TableError};
+    use crate::test::{Error, VirtualNandFlash};
+    use crate::{BlockIndex, NandFlash, NandFlashError, PageIndex};
+
+    type Flash = VirtualNandFlash<512, 4, 32>;
+
+    #[test]
+    fn test_partition_table() -> Result<(), PartitionError<Error>> {
+        let mut flash = Flash::new();
+        let mut table = PartitionTable::new();
+        table
+            .add(PartitionEntry::new("boot", BlockIndex::new(2), 4)?, 32)
+            .unwrap();
+        table
+            .add(PartitionEntry::new("logs", BlockIndex::new(6), 10)?, 32)
+            .unwrap();
+        assert_eq!(
+            table.add(PartitionEntry::new("fs", BlockIndex::new(15), 4)?, 32),
+            Err(TableError::Overlap)
+        );
+        assert_eq!(
+            table.add(PartitionEntry::new("fs", BlockIndex::new(30), 4)?, 32),
+            Err(TableError::OutOfBounds)
+        );
+        assert!(PartitionEntry::new("too_long_name", BlockIndex::new(0), 1).is_err());
+
+        // Not present, then written to the second block as the first is bad
+        assert_eq!(
+            PartitionTable::find_in(&mut flash, 2),
+            Err(PartitionError::NotFound)
+        );
+        flash.mark_block_bad(BlockIndex::new(0)).unwrap();
+        table.write_to(&mut flash, BlockIndex::new(1)).unwrap();
+        let (block, found) = PartitionTable::find_in(&mut flash, 2).unwrap();
+        assert_eq!(block, BlockIndex::new(1));
+        assert_eq!(found, table);
+        assert_eq!(found.find("logs").unwrap().block_count(), 10);
+
+        // Corrupt the CRC
+        flash.write(512 * 4 + 20, &[0]).unwrap();
+        assert_eq!(
+            PartitionTable::read_from(&mut flash, BlockIndex::new(1)),
+            Ok(None)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_partitions() -> Result<(), PartitionError<Error>> {
+        let flash = RefCell::new(Flash::new());
+        let boot = PartitionEntry::new("boot", BlockIndex::new(2), 4)?;
+        let mut a: Partition<_, 4> = Partition::from_entry(&flash, &boot)?;
+        let mut b: Partition<_, 8> = Partition::new(&flash, BlockIndex::new(6))?;
+        assert_eq!(
+            Partition::<_, 8>::from_entry(&flash, &boot).unwrap_err(),
+            PartitionError::SizeMismatch
+        );
+        assert_eq!(
+            Partition::<_, 8>::new(&flash, BlockIndex::new(30)).unwrap_err(),
+            PartitionError::OutOfBounds
+        );
+
+        assert_eq!(a.capacity(), 4 * 512 * 4);
+        a.write(0, &[1; 16])?;
+        b.write(0, &[2; 16])?;
+        b.copy(0, 2048, 512)?;
+        assert_eq!(
+            b.erase_block(BlockIndex::new(8)),
+            Err(PartitionError::OutOfBounds)
+        );
+        assert_eq!(a.write(4 * 2048, &[0]), Err(PartitionError::OutOfBounds));
+
+        let mut buf = [0; 16];
+        flash.borrow_mut().read(2 * 2048, &mut buf).unwrap();
+        assert_eq!(buf, [1; 16]);
+        flash.borrow_mut().read(7 * 2048, &mut buf).unwrap();
+        assert_eq!(buf, [2; 16]);
+
+        a.erase(0, 2048)?;
+        a.read(0, &mut buf)?;
+        assert_eq!(buf, [0xFF; 16]);
+        b.read(0, &mut buf)?;
+        assert_eq!(buf, [2; 16]);
+
+        // Block failures are reported at the partition page
+        flash.borrow_mut().set_ecc_failing(BlockIndex::new(7));
+        let error = b.read(2048, &mut buf).unwrap_err();
+        let failure = error.kind().block_failure().unwrap();
+        assert_eq!(failure.page, Some(PageIndex::new(4)));
+        assert_eq!(
+            failure.block::<Partition<&RefCell<Flash>, 8>>(),
+            Some(BlockIndex::new(1))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,499 @@
+use crate::{BlockIndex, ErrorType, NandFlash, NandFlashError, NandFlashErrorKind};
+
+/// Size of a sector exposed by a [BlockDevice]
+pub const SECTOR_SIZE: usize = 512;
+
+/// Marks the commit page of a [SectorDevice] block ("SECT")
+const COMMIT_MAGIC: u32 = 0x5345_4354;
+
+/// Length of the commit record at the start of the last page of a block
+const COMMIT_LENGTH: usize = 16;
+
+/// A device of fixed size sectors that can be rewritten in any order.
+///
+/// Equivalent to the `BlockDevice` traits used by FAT filesystem crates
+/// (e.g. embedded-sdmmc or fatfs), which can be implemented on top of this.
+pub trait BlockDevice: ErrorType {
+    /// Read consecutive sectors starting at `start`
+    fn read_sectors(
+        &mut self,
+        start: u32,
+        sectors: &mut [[u8; SECTOR_SIZE]],
+    ) -> Result<(), Self::Error>;
+
+    /// Write consecutive sectors starting at `start`
+    fn write_sectors(
+        &mut self,
+        start: u32,
+        sectors: &[[u8; SECTOR_SIZE]],
+    ) -> Result<(), Self::Error>;
+
+    /// The number of sectors in the device
+    fn sector_count(&self) -> u32;
+
+    /// Make all previous writes persistent
+    fn flush(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Error returned by a [SectorDevice]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SectorError<E> {
+    /// Error from the NAND flash
+    Flash(E),
+    /// The sector is out of bounds
+    OutOfBounds,
+    /// No spare block to write to, there must be more flash blocks than logical blocks
+    NoSpareBlock,
+}
+
+impl<E: NandFlashError> NandFlashError for SectorError<E> {
+    fn kind(&self) -> NandFlashErrorKind {
+        match self {
+            SectorError::Flash(e) => e.kind(),
+            SectorError::OutOfBounds => NandFlashErrorKind::OutOfBounds,
+            SectorError::NoSpareBlock => NandFlashErrorKind::Other,
+        }
+    }
+}
+
+/// The block currently being written by a [SectorDevice]
+#[derive(Debug, Clone, Copy)]
+struct OpenBlock {
+    /// Logical block being replaced
+    logical: usize,
+    /// Flash block the new copy is written to
+    dest: BlockIndex,
+    /// Page held in the buffer. Pages before this have been written to `dest`
+    page: u32,
+}
+
+/// 512 byte sectors with arbitrary rewrite on top of a [NandFlash].
+///
+/// The sectors are split into `LBC` logical blocks, each stored in one flash block.
+/// All but the last page of a block hold sectors, the last page holds a commit record
+/// containing the logical block index and a sequence number.
+///
+/// Writing a sector copies its logical block to a spare flash block. Pages before the
+/// written one are copied, and the page containing the sector is held in a RAM buffer
+/// so further writes to it, or later pages in the same block, are merged. Writing to an
+/// earlier page or another logical block, or calling [BlockDevice::flush], completes the
+/// copy and programs the commit record. This means pages are only ever programmed once and in
+/// order, and sequential writes cost little more than the data written.
+///
+/// Until the commit record is programmed the previous copy of the block is untouched, so
+/// after power loss each logical block holds either its old or new contents. Writes since
+/// the last commit are lost, so call [BlockDevice::flush] when the filesystem syncs.
+///
+/// `N` must equal the page size and be a multiple of [SECTOR_SIZE], and the flash must have
+/// more blocks than `LBC`. This does not handle bad blocks, so usually wraps a flash
+/// translation layer such as `FlashMap`.
+#[derive(Debug)]
+pub struct SectorDevice<F, const N: usize, const LBC: usize> {
+    flash: F,
+    /// Flash block holding each logical block, None if never written
+    map: [Option<BlockIndex>; LBC],
+    /// Sequence number of the next commit
+    sequence: u32,
+    /// Next flash block to try when looking for a spare block
+    next_free: u32,
+    /// Block being written, if any
+    open: Option<OpenBlock>,
+    /// Page buffer for the open block
+    buffer: [u8; N],
+}
+
+impl<F: NandFlash, const N: usize, const LBC: usize> SectorDevice<F, N, LBC> {
+    /// Pages in a block that hold sectors
+    const DATA_PAGES: u32 = F::PAGES_PER_BLOCK as u32 - 1;
+    /// Sectors in a page
+    const SECTORS_PER_PAGE: u32 = (F::PAGE_SIZE / SECTOR_SIZE) as u32;
+    /// Sectors in a logical block
+    const SECTORS_PER_BLOCK: u32 = Self::DATA_PAGES * Self::SECTORS_PER_PAGE;
+
+    /// Load the sector device from flash.
+    ///
+    /// Scans the commit record of every block to rebuild the logical block map.
+    /// Blocks without a valid commit record are treated as spare.
+    pub fn new(mut flash: F) -> Result<Self, SectorError<F::Error>> {
+        const {
+            ::core::assert!(N == F::PAGE_SIZE);
+            ::core::assert!(N % SECTOR_SIZE == 0);
+            ::core::assert!(F::PAGES_PER_BLOCK > 1);
+        }
+        if LBC >= F::BLOCK_COUNT {
+            return Err(SectorError::NoSpareBlock);
+        }
+
+        let mut map = [None; LBC];
+        let mut sequences = [0; LBC];
+        let mut sequence = 0;
+        let mut record = [0; COMMIT_LENGTH];
+        for block in 0..F::BLOCK_COUNT as u32 {
+            let block = BlockIndex::new(block);
+            match flash.read(Self::page_offset(block, Self::DATA_PAGES), &mut record) {
+                Ok(()) => {}
+                // An interrupted commit can leave an unreadable page
+                Err(e) if matches!(e.kind(), NandFlashErrorKind::BlockFail(_)) => {
+                    warn!("Unreadable commit record in block {}", block.as_u32());
+                    continue;
+                }
+                Err(e) => return Err(SectorError::Flash(e)),
+            }
+            let Some((logical, seq)) = Self::parse_commit(&record) else {
+                continue;
+            };
+            trace!(
+                "Block {} holds logical block {} ({})",
+                block.as_u32(),
+                logical,
+                seq
+            );
+            // Latest commit of a logical block is the valid one
+            if map[logical].is_none() || seq > sequences[logical] {
+                map[logical] = Some(block);
+                sequences[logical] = seq;
+            }
+            sequence = sequence.max(seq.wrapping_add(1));
+        }
+
+        Ok(SectorDevice {
+            flash,
+            map,
+            sequence,
+            next_free: 0,
+            open: None,
+            buffer: [0xFF; N],
+        })
+    }
+
+    /// Release the flash, discarding writes since the last flush
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// Byte offset of a page in a flash block
+    fn page_offset(block: BlockIndex, page: u32) -> u64 {
+        block.as_u32() as u64 * F::ERASE_SIZE as u64 + page as u64 * F::PAGE_SIZE as u64
+    }
+
+    /// Logical block, page and column of a sector
+    fn locate(sector: u32) -> (usize, u32, usize) {
+        let logical = (sector / Self::SECTORS_PER_BLOCK) as usize;
+        let sector = sector % Self::SECTORS_PER_BLOCK;
+        let page = sector / Self::SECTORS_PER_PAGE;
+        let column = (sector % Self::SECTORS_PER_PAGE) as usize * SECTOR_SIZE;
+        (logical, page, column)
+    }
+
+    /// Check a range of sectors is within the device
+    fn check_sectors(&self, start: u32, count: usize) -> Result<(), SectorError<F::Error>> {
+        match (start as u64).checked_add(count as u64) {
+            Some(end) if end <= self.sector_count() as u64 => Ok(()),
+            _ => Err(SectorError::OutOfBounds),
+        }
+    }
+
+    /// Decode a commit record into logical block and sequence number
+    fn parse_commit(record: &[u8; COMMIT_LENGTH]) -> Option<(usize, u32)> {
+        let word = |i: usize| u32::from_le_bytes(record[i * 4..i * 4 + 4].try_into().unwrap());
+        let (magic, logical, seq, check) = (word(0), word(1), word(2), word(3));
+        if magic != COMMIT_MAGIC || check != !(magic ^ logical ^ seq) || logical as usize >= LBC {
+            return None;
+        }
+        Some((logical as usize, seq))
+    }
+
+    /// Find and erase a flash block that isn't holding a logical block
+    fn allocate(&mut self) -> Result<BlockIndex, SectorError<F::Error>> {
+        let block_count = F::BLOCK_COUNT as u32;
+        for i in 0..block_count {
+            let block = BlockIndex::new((self.next_free + i) % block_count);
+            if self.map.contains(&Some(block)) {
+                continue;
+            }
+            self.next_free = (block.as_u32() + 1) % block_count;
+            self.flash.erase_block(block).map_err(SectorError::Flash)?;
+            return Ok(block);
+        }
+        Err(SectorError::NoSpareBlock)
+    }
+
+    /// Copy pages `from..to` of a logical block to `dest`
+    fn copy_pages(
+        &mut self,
+        logical: usize,
+        dest: BlockIndex,
+        from: u32,
+        to: u32,
+    ) -> Result<(), SectorError<F::Error>> {
+        match self.map[logical] {
+            Some(src) if to > from => self
+                .flash
+                .copy(
+                    Self::page_offset(src, from),
+                    Self::page_offset(dest, from),
+                    (to - from) as u64 * F::PAGE_SIZE as u64,
+                )
+                .map_err(SectorError::Flash),
+            // Never written, leave erased
+            _ => Ok(()),
+        }
+    }
+
+    /// Load a page of a logical block into the buffer
+    fn load_page(&mut self, logical: usize, page: u32) -> Result<(), SectorError<F::Error>> {
+        match self.map[logical] {
+            Some(src) => self
+                .flash
+                .read(Self::page_offset(src, page), &mut self.buffer)
+                .map_err(SectorError::Flash),
+            None => {
+                self.buffer.fill(0xFF);
+                Ok(())
+            }
+        }
+    }
+
+    /// Program the buffer to a page
+    fn program_buffer(
+        &mut self,
+        block: BlockIndex,
+        page: u32,
+    ) -> Result<(), SectorError<F::Error>> {
+        self.flash
+            .write(Self::page_offset(block, page), &self.buffer)
+            .map_err(SectorError::Flash)
+    }
+
+    /// Finish writing the open block and commit it
+    fn close(&mut self) -> Result<(), SectorError<F::Error>> {
+        let Some(open) = self.open.take() else {
+            return Ok(());
+        };
+        self.program_buffer(open.dest, open.page)?;
+        self.copy_pages(open.logical, open.dest, open.page + 1, Self::DATA_PAGES)?;
+
+        // Commit record
+        let logical = open.logical as u32;
+        self.buffer.fill(0xFF);
+        for (i, word) in [
+            COMMIT_MAGIC,
+            logical,
+            self.sequence,
+            !(COMMIT_MAGIC ^ logical ^ self.sequence),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            self.buffer[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        self.program_buffer(open.dest, Self::DATA_PAGES)?;
+        debug!(
+            "Committed logical block {} to block {}",
+            logical,
+            open.dest.as_u32()
+        );
+
+        self.map[open.logical] = Some(open.dest);
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Merge a sector into the open block, opening one if required
+    fn write_sector(
+        &mut self,
+        sector: u32,
+        data: &[u8; SECTOR_SIZE],
+    ) -> Result<(), SectorError<F::Error>> {
+        let (logical, page, column) = Self::locate(sector);
+        // Pages can only be programmed in order
+        if let Some(open) = self.open {
+            if open.logical != logical || page < open.page {
+                self.close()?;
+            }
+        }
+        match self.open {
+            Some(open) if open.page == page => {}
+            Some(open) => {
+                self.program_buffer(open.dest, open.page)?;
+                self.copy_pages(logical, open.dest, open.page + 1, page)?;
+                self.load_page(logical, page)?;
+                self.open = Some(OpenBlock { page, ..open });
+            }
+            None => {
+                let dest = self.allocate()?;
+                self.copy_pages(logical, dest, 0, page)?;
+                self.load_page(logical, page)?;
+                self.open = Some(OpenBlock {
+                    logical,
+                    dest,
+                    page,
+                });
+            }
+        }
+        self.buffer[column..column + SECTOR_SIZE].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Read a sector, from the open block if it holds it
+    fn read_sector(
+        &mut self,
+        sector: u32,
+        data: &mut [u8; SECTOR_SIZE],
+    ) -> Result<(), SectorError<F::Error>> {
+        let (logical, page, column) = Self::locate(sector);
+        let block = match self.open {
+            Some(open) if open.logical == logical && open.page == page => {
+                data.copy_from_slice(&self.buffer[column..column + SECTOR_SIZE]);
+                return Ok(());
+            }
+            Some(open) if open.logical == logical && page < open.page => Some(open.dest),
+            _ => self.map[logical],
+        };
+        match block {
+            Some(block) => self
+                .flash
+                .read(Self::page_offset(block, page) + column as u64, data)
+                .map_err(SectorError::Flash),
+            None => {
+                data.fill(0xFF);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<F: NandFlash, const N: usize, const LBC: usize> ErrorType for SectorDevice<F, N, LBC> {
+    type Error = SectorError<F::Error>;
+}
+
+impl<F: NandFlash, const N: usize, const LBC: usize> BlockDevice for SectorDevice<F, N, LBC> {
+    fn read_sectors(
+        &mut self,
+        start: u32,
+        sectors: &mut [[u8; SECTOR_SIZE]],
+    ) -> Result<(), Self::Error> {
+        self.check_sectors(start, sectors.len())?;
+        for (sector, data) in (start..).zip(sectors.iter_mut()) {
+            self.read_sector(sector, data)?;
+        }
+        Ok(())
+    }
+
+    fn write_sectors(
+        &mut self,
+        start: u32,
+        sectors: &[[u8; SECTOR_SIZE]],
+    ) -> Result<(), Self::Error> {
+        self.check_sectors(start, sectors.len())?;
+        for (sector, data) in (start..).zip(sectors.iter()) {
+            if let Err(e) = self.write_sector(sector, data) {
+                // The open block is in an unknown state, drop it
+                self.open = None;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn sector_count(&self) -> u32 {
+        LBC as u32 * Self::SECTORS_PER_BLOCK
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.close().inspect_err(|_| self.open = None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockDevice, SectorDevice, SECTOR_SIZE};
+    use crate::test::VirtualNandFlash;
+    use crate::{BlockIndex, PageIndex};
+
+    type Flash = VirtualNandFlash<2048, 4, 8>;
+
+    #[test]
+    fn test_sector_rewrite() {
+        let mut device: SectorDevice<Flash, 2048, 6> = SectorDevice::new(Flash::new()).unwrap();
+        // 3 data pages of 4 sectors per block
+        assert_eq!(device.sector_count(), 6 * 12);
+
+        let mut buf = [[0; SECTOR_SIZE]; 2];
+        device.read_sectors(0, &mut buf).unwrap();
+        assert_eq!(buf, [[0xFF; SECTOR_SIZE]; 2]);
+
+        // Rewrite the same sectors, out of order and across blocks
+        for value in 0..4 {
+            device.write_sectors(5, &[[value; SECTOR_SIZE]; 2]).unwrap();
+            device
+                .write_sectors(1, &[[value + 10; SECTOR_SIZE]])
+                .unwrap();
+            device
+                .write_sectors(11, &[[value + 20; SECTOR_SIZE]; 2])
+                .unwrap();
+            device.read_sectors(5, &mut buf).unwrap();
+            assert_eq!(buf, [[value; SECTOR_SIZE]; 2]);
+        }
+        device.flush().unwrap();
+
+        let mut sector = [[0; SECTOR_SIZE]];
+        for (index, value) in [
+            (1, 13),
+            (5, 3),
+            (6, 3),
+            (11, 23),
+            (12, 23),
+            (0, 0xFF),
+            (13, 0xFF),
+        ] {
+            device.read_sectors(index, &mut sector).unwrap();
+            assert_eq!(sector[0], [value; SECTOR_SIZE], "sector {}", index);
+        }
+
+        // Reload from flash
+        let mut device: SectorDevice<Flash, 2048, 6> = SectorDevice::new(device.release()).unwrap();
+        device.read_sectors(11, &mut buf).unwrap();
+        assert_eq!(buf, [[23; SECTOR_SIZE]; 2]);
+        device.read_sectors(1, &mut sector).unwrap();
+        assert_eq!(sector[0], [13; SECTOR_SIZE]);
+
+        assert!(device.read_sectors(71, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_sector_power_loss() {
+        let mut device: SectorDevice<Flash, 2048, 6> = SectorDevice::new(Flash::new()).unwrap();
+        device.write_sectors(0, &[[1; SECTOR_SIZE]; 8]).unwrap();
+        device.flush().unwrap();
+
+        // Partially rewrite then lose power before the commit
+        device.write_sectors(0, &[[2; SECTOR_SIZE]; 6]).unwrap();
+        let flash = device.release();
+
+        let mut device: SectorDevice<Flash, 2048, 6> = SectorDevice::new(flash).unwrap();
+        let mut buf = [[0; SECTOR_SIZE]; 8];
+        device.read_sectors(0, &mut buf).unwrap();
+        assert_eq!(buf, [[1; SECTOR_SIZE]; 8]);
+    }
+
+    #[test]
+    fn test_sector_program_once() {
+        let mut device: SectorDevice<Flash, 2048, 6> = SectorDevice::new(Flash::new()).unwrap();
+        // Sequential writes to a block program each page once
+        for sector in 0..12 {
+            device
+                .write_sectors(sector, &[[sector as u8; SECTOR_SIZE]])
+                .unwrap();
+        }
+        device.flush().unwrap();
+        let flash = device.release();
+        let block = (0..8)
+            .find(|&b| flash.erase_count(BlockIndex::new(b)) == 1)
+            .unwrap();
+        for page in 0..4 {
+            assert_eq!(flash.write_count(PageIndex::new(block * 4 + page)), 1);
+        }
+    }
+}
@@ -1,6 +1,11 @@
 use crate::AddressConversions;
-use crate::BlockIndex;
+use crate::BlockFailure;
 use crate::ByteAddress;
+use crate::NandOperation;
+use crate::PageIndex;
+
+/// Free spare bytes in each page of a [VirtualNandFlash]
+pub const OOB_SIZE: usize = 16;
 
 /// A virtual NAND flash implementation that can be used for testing purposes.
 #[derive(Debug, Clone)]
@@ -10,11 +15,17 @@ pub struct VirtualNandFlash<
     const BLOCK_COUNT: usize,
 > {
     storage: [[[u8; PAGE_SIZE]; PAGES_PER_BLOCK]; BLOCK_COUNT],
+    oob: [[[u8; OOB_SIZE]; PAGES_PER_BLOCK]; BLOCK_COUNT],
     block_status: [crate::BlockStatus; BLOCK_COUNT],
     ecc_failing: [bool; BLOCK_COUNT],
+    corrected_bits: [Option<u8>; BLOCK_COUNT],
     erase_count: [u32; BLOCK_COUNT],
     read_count: [[u32; PAGES_PER_BLOCK]; BLOCK_COUNT],
     write_count: [[u32; PAGES_PER_BLOCK]; BLOCK_COUNT],
+    /// Page started with [crate::SplitProgram::program_start] and not yet finished
+    programming: Option<u32>,
+    /// Number of following erases that fail without affecting the block
+    erase_errors: u32,
 }
 
 impl<const PAGE_SIZE: usize, const PAGES_PER_BLOCK: usize, const BLOCK_COUNT: usize>
@@ -24,23 +35,106 @@ impl<const PAGE_SIZE: usize, const PAGES_PER_BLOCK: usize, const BLOCK_COUNT: us
     pub fn new() -> Self {
         Self {
             storage: [[[0xFF; PAGE_SIZE]; PAGES_PER_BLOCK]; BLOCK_COUNT],
+            oob: [[[0xFF; OOB_SIZE]; PAGES_PER_BLOCK]; BLOCK_COUNT],
             block_status: [crate::BlockStatus::Ok; BLOCK_COUNT],
             ecc_failing: [false; BLOCK_COUNT],
+            corrected_bits: [None; BLOCK_COUNT],
             erase_count: [0; BLOCK_COUNT],
             read_count: [[0; PAGES_PER_BLOCK]; BLOCK_COUNT],
             write_count: [[0; PAGES_PER_BLOCK]; BLOCK_COUNT],
+            programming: None,
+            erase_errors: 0,
+        }
+    }
+
+    /// Number of times a block has been erased
+    pub fn erase_count(&self, block: crate::BlockIndex) -> u32 {
+        self.erase_count[block.0 as usize]
+    }
+
+    /// Number of times a page has been read
+    pub fn read_count(&self, page: crate::PageIndex) -> u32 {
+        self.read_count[page.0 as usize / PAGES_PER_BLOCK][page.0 as usize % PAGES_PER_BLOCK]
+    }
+
+    /// Number of times a page has been written
+    pub fn write_count(&self, page: crate::PageIndex) -> u32 {
+        self.write_count[page.0 as usize / PAGES_PER_BLOCK][page.0 as usize % PAGES_PER_BLOCK]
+    }
+
+    /// Make reads of a block report that ECC corrected errors, until it is erased.
+    ///
+    /// The data is still read, as it would be after correction.
+    pub fn set_ecc_failing(&mut self, block: crate::BlockIndex) {
+        self.ecc_failing[block.0 as usize] = true;
+    }
+
+    /// Make reads of a block report that ECC corrected `bits` errors, until it is erased
+    pub fn set_ecc_corrected(&mut self, block: crate::BlockIndex, bits: u8) {
+        self.ecc_failing[block.0 as usize] = true;
+        self.corrected_bits[block.0 as usize] = Some(bits);
+    }
+
+    /// Make the next erase fail with [Error::Misc], as for a bus error, leaving the block
+    /// as it was
+    pub fn fail_next_erase(&mut self) {
+        self.erase_errors += 1;
+    }
+
+    /// Mark a block bad as if by the manufacturer
+    pub fn set_factory_bad(&mut self, block: crate::BlockIndex) {
+        self.block_status[block.0 as usize] = crate::BlockStatus::FactoryBad;
+    }
+
+    /// First page of a block
+    fn first_page(block: u32) -> PageIndex {
+        PageIndex::new(block * PAGES_PER_BLOCK as u32)
+    }
+
+    /// Error for an operation on a block marked failed
+    fn block_error(block: u32, operation: NandOperation) -> Error {
+        Error::BlockFail(BlockFailure::new(operation).with_page(Self::first_page(block)))
+    }
+
+    /// Error for a corrected read of a block set as failing
+    fn failing_error(&self, block: u32) -> Error {
+        let failure = BlockFailure::new(NandOperation::Read).with_page(Self::first_page(block));
+        match self.corrected_bits[block as usize] {
+            Some(bits) => Error::BlockFailing(failure.with_corrected_bits(bits)),
+            None => Error::BlockFailing(failure),
+        }
+    }
+
+    /// Increment the counters of all pages touched by `length` bytes at `offset`
+    fn count_pages(counts: &mut [[u32; PAGES_PER_BLOCK]; BLOCK_COUNT], offset: u64, length: usize) {
+        if length == 0 {
+            return;
+        }
+        let first = offset as usize / PAGE_SIZE;
+        let last = (offset as usize + length - 1) / PAGE_SIZE;
+        for page in first..=last {
+            counts[page / PAGES_PER_BLOCK][page % PAGES_PER_BLOCK] += 1;
         }
     }
 }
 
+impl<const PAGE_SIZE: usize, const PAGES_PER_BLOCK: usize, const BLOCK_COUNT: usize> Default
+    for VirtualNandFlash<PAGE_SIZE, PAGES_PER_BLOCK, BLOCK_COUNT>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     /// Misc
     Misc,
     /// block is failing
-    BlockFailing,
+    BlockFailing(BlockFailure),
     /// block is failed
-    BlockFail,
+    BlockFail(BlockFailure),
     /// Out of bounds
     OutOfBounds,
     /// Not aligned
@@ -51,14 +145,25 @@ impl crate::NandFlashError for Error {
     fn kind(&self) -> crate::NandFlashErrorKind {
         match self {
             Error::Misc => crate::NandFlashErrorKind::Other,
-            Error::BlockFailing => crate::NandFlashErrorKind::BlockFailing(None),
-            Error::BlockFail => crate::NandFlashErrorKind::BlockFail(None),
+            Error::BlockFailing(failure) => crate::NandFlashErrorKind::BlockFailing(*failure),
+            Error::BlockFail(failure) => crate::NandFlashErrorKind::BlockFail(*failure),
             Error::OutOfBounds => crate::NandFlashErrorKind::OutOfBounds,
             Error::NotAligned => crate::NandFlashErrorKind::NotAligned,
         }
     }
 }
 
+// Conversion for the check_* helper functions
+impl From<crate::NandFlashErrorKind> for Error {
+    fn from(kind: crate::NandFlashErrorKind) -> Self {
+        match kind {
+            crate::NandFlashErrorKind::NotAligned => Error::NotAligned,
+            crate::NandFlashErrorKind::OutOfBounds => Error::OutOfBounds,
+            _ => Error::Misc,
+        }
+    }
+}
+
 impl<const PAGE_SIZE: usize, const PAGES_PER_BLOCK: usize, const BLOCK_COUNT: usize>
     crate::ErrorType for VirtualNandFlash<PAGE_SIZE, PAGES_PER_BLOCK, BLOCK_COUNT>
 {
@@ -70,106 +175,139 @@ impl<const PAGE_SIZE: usize, const PAGES_PER_BLOCK: usize, const BLOCK_COUNT: us
 {
     const READ_SIZE: usize = 1;
 
-    const PAGE_SIZE: usize = PAGE_SIZE as usize;
+    const PAGE_SIZE: usize = PAGE_SIZE;
 
-    const PAGES_PER_BLOCK: usize = PAGES_PER_BLOCK as usize;
+    const PAGES_PER_BLOCK: usize = PAGES_PER_BLOCK;
 
-    const BLOCK_COUNT: usize = BLOCK_COUNT as usize;
+    const BLOCK_COUNT: usize = BLOCK_COUNT;
 
-    const ERASE_SIZE: usize = Self::PAGE_SIZE as usize * Self::PAGES_PER_BLOCK as usize;
+    const ERASE_SIZE: usize = Self::PAGE_SIZE * Self::PAGES_PER_BLOCK;
 
     const WRITE_SIZE: usize = 1;
 
-    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+    fn read(&mut self, offset: u64, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        crate::check_read(self, offset, bytes.len()).map_err(Error::from)?;
         let first_block = Self::byte_to_block_index(ByteAddress::new(offset));
         let last_block =
-            Self::byte_to_block_index(ByteAddress::new(offset + bytes.len() as u32 - 1));
-        for block in first_block.as_u16()..=last_block.as_u16() {
-            if self.block_status[block as usize] == crate::BlockStatus::Failed {
-                return Err(Error::BlockFail);
-            } else if self.ecc_failing[block as usize] {
-                return Err(Error::BlockFailing);
+            Self::byte_to_block_index(ByteAddress::new(offset + bytes.len() as u64 - 1));
+        let mut failing = None;
+        for block in first_block.as_u32()..=last_block.as_u32() {
+            if self.block_status[block as usize].is_failed() {
+                return Err(Self::block_error(block, NandOperation::Read));
+            }
+            if self.ecc_failing[block as usize] {
+                failing.get_or_insert(block);
             }
         }
         trace!("Reading from blocks {} to {}", first_block.0, last_block.0);
-        let mut start = unsafe { (self.storage.as_ptr() as *const u8).add(offset as usize) };
+        Self::count_pages(&mut self.read_count, offset, bytes.len());
+        let start = unsafe { (self.storage.as_ptr() as *const u8).add(offset as usize) };
         bytes.copy_from_slice(unsafe { core::slice::from_raw_parts(start, bytes.len()) });
+        // The data was corrected, so is still returned
+        if let Some(block) = failing {
+            return Err(self.failing_error(block));
+        }
         Ok(())
     }
 
-    fn capacity(&self) -> u32 {
-        Self::PAGE_SIZE as u32 * Self::PAGES_PER_BLOCK as u32 * Self::BLOCK_COUNT as u32
+    fn capacity(&self) -> u64 {
+        Self::PAGE_SIZE as u64 * Self::PAGES_PER_BLOCK as u64 * Self::BLOCK_COUNT as u64
     }
 
     fn block_status(
         &mut self,
         block: crate::BlockIndex,
     ) -> Result<crate::BlockStatus, Self::Error> {
-        if block.0 >= Self::BLOCK_COUNT as u16 {
+        if block.0 >= Self::BLOCK_COUNT as u32 {
             return Err(Error::OutOfBounds);
         }
-        Ok(self.block_status[block.0 as usize])
+        let block = block.0 as usize;
+        let status = self.block_status[block];
+        // Good blocks report whether the first page is erased
+        if status.is_ok()
+            && self.storage[block][0].iter().all(|&b| b == 0xFF)
+            && self.oob[block][0].iter().all(|&b| b == 0xFF)
+        {
+            Ok(crate::BlockStatus::Erased)
+        } else {
+            Ok(status)
+        }
     }
 
-    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
-        if ByteAddress::new(from).block_offset(Self::ERASE_SIZE as u32) != 0 {
-            return Err(Error::NotAligned);
-        }
-        if ByteAddress::new(to).block_offset(Self::ERASE_SIZE as u32) != 0 {
-            return Err(Error::NotAligned);
-        }
+    fn erase(&mut self, from: u64, to: u64) -> Result<(), Self::Error> {
+        crate::check_erase(self, from, to).map_err(Error::from)?;
         let first_block = Self::byte_to_block_index(ByteAddress::new(from));
         let last_block = Self::byte_to_block_index(ByteAddress::new(to));
         trace!(
             "Erasing blocks {} to {}",
-            first_block.as_u16(),
-            last_block.as_u16() - 1
+            first_block.as_u32(),
+            last_block.as_u32().saturating_sub(1)
         );
-        for block in first_block.as_u16()..last_block.as_u16() {
-            if self.block_status[block as usize] == crate::BlockStatus::Failed {
-                return Err(Error::BlockFail);
+        for block in first_block.as_u32()..last_block.as_u32() {
+            if self.block_status[block as usize].is_failed() {
+                return Err(Self::block_error(block, NandOperation::Erase));
             }
+            self.erase_count[block as usize] += 1;
+            self.ecc_failing[block as usize] = false;
+            self.corrected_bits[block as usize] = None;
             self.storage[block as usize]
                 .iter_mut()
                 .for_each(|page| page.fill(0xFF));
+            self.oob[block as usize]
+                .iter_mut()
+                .for_each(|oob| oob.fill(0xFF));
         }
         Ok(())
     }
 
     fn erase_block(&mut self, block: crate::BlockIndex) -> Result<(), Self::Error> {
-        if block.0 >= Self::BLOCK_COUNT as u16 {
+        if block.0 >= Self::BLOCK_COUNT as u32 {
             return Err(Error::OutOfBounds);
         }
-        if self.block_status[block.0 as usize] == crate::BlockStatus::Failed {
-            Err(Error::BlockFail)
+        if self.erase_errors > 0 {
+            self.erase_errors -= 1;
+            return Err(Error::Misc);
+        }
+        if self.block_status[block.0 as usize].is_failed() {
+            Err(Self::block_error(block.0, NandOperation::Erase))
         } else {
+            self.erase_count[block.0 as usize] += 1;
+            self.ecc_failing[block.0 as usize] = false;
+            self.corrected_bits[block.0 as usize] = None;
             self.storage[block.0 as usize]
                 .iter_mut()
                 .for_each(|page| page.fill(0xFF));
+            self.oob[block.0 as usize]
+                .iter_mut()
+                .for_each(|oob| oob.fill(0xFF));
             Ok(())
         }
     }
 
-    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+    fn write(&mut self, offset: u64, bytes: &[u8]) -> Result<(), Self::Error> {
+        crate::check_write(self, offset, bytes.len()).map_err(Error::from)?;
         // check for block status
         let first_block = Self::byte_to_block_index(ByteAddress::new(offset));
         let last_block =
-            Self::byte_to_block_index(ByteAddress::new(offset + bytes.len() as u32 - 1));
-        for block in first_block.as_u16()..=last_block.as_u16() {
-            if self.block_status[block as usize] == crate::BlockStatus::Failed {
-                return Err(Error::BlockFail);
+            Self::byte_to_block_index(ByteAddress::new(offset + bytes.len() as u64 - 1));
+        for block in first_block.as_u32()..=last_block.as_u32() {
+            if self.block_status[block as usize].is_failed() {
+                return Err(Self::block_error(block, NandOperation::Program));
             }
         }
         trace!("Writing to blocks {} to {}", first_block.0, last_block.0);
-        let mut start = unsafe { (self.storage.as_ptr() as *mut u8).add(offset as usize) };
-        let mut slice = unsafe { core::slice::from_raw_parts_mut(start, bytes.len()) };
+        Self::count_pages(&mut self.write_count, offset, bytes.len());
+        let start = unsafe { (self.storage.as_ptr() as *mut u8).add(offset as usize) };
+        let slice = unsafe { core::slice::from_raw_parts_mut(start, bytes.len()) };
         for (a, b) in slice.iter_mut().zip(bytes.iter()) {
             *a &= *b;
         }
         Ok(())
     }
 
-    fn copy(&mut self, src_offset: u32, dest_offset: u32, length: u32) -> Result<(), Self::Error> {
+    fn copy(&mut self, src_offset: u64, dest_offset: u64, length: u64) -> Result<(), Self::Error> {
+        crate::check_slice(self, Self::READ_SIZE, src_offset, length).map_err(Error::from)?;
+        crate::check_slice(self, Self::WRITE_SIZE, dest_offset, length).map_err(Error::from)?;
         let src_slice = unsafe {
             core::slice::from_raw_parts(
                 (self.storage.as_ptr() as *const u8).add(src_offset as usize),
@@ -183,14 +321,104 @@ impl<const PAGE_SIZE: usize, const PAGES_PER_BLOCK: usize, const BLOCK_COUNT: us
             )
         };
         dest_slice.copy_from_slice(src_slice);
+        // Whole pages keep their spare area, like an internal data move
+        if src_offset % (PAGE_SIZE as u64) == 0 && dest_offset % (PAGE_SIZE as u64) == 0 {
+            let (src, dest) = (
+                src_offset as usize / PAGE_SIZE,
+                dest_offset as usize / PAGE_SIZE,
+            );
+            for page in 0..length as usize / PAGE_SIZE {
+                let (s, d) = (src + page, dest + page);
+                self.oob[d / PAGES_PER_BLOCK][d % PAGES_PER_BLOCK] =
+                    self.oob[s / PAGES_PER_BLOCK][s % PAGES_PER_BLOCK];
+            }
+        }
         Ok(())
     }
 
     fn mark_block_bad(&mut self, block: crate::BlockIndex) -> Result<(), Self::Error> {
-        if block.0 >= Self::BLOCK_COUNT as u16 {
+        if block.0 >= Self::BLOCK_COUNT as u32 {
+            return Err(Error::OutOfBounds);
+        }
+        self.block_status[block.0 as usize] = crate::BlockStatus::Worn;
+        Ok(())
+    }
+}
+
+impl<const PAGE_SIZE: usize, const PAGES_PER_BLOCK: usize, const BLOCK_COUNT: usize>
+    crate::EraseCount for VirtualNandFlash<PAGE_SIZE, PAGES_PER_BLOCK, BLOCK_COUNT>
+{
+    fn erase_count(&self, block: crate::BlockIndex) -> Option<u32> {
+        self.erase_count.get(block.0 as usize).copied()
+    }
+}
+
+impl<const PAGE_SIZE: usize, const PAGES_PER_BLOCK: usize, const BLOCK_COUNT: usize>
+    crate::NandFlashOob for VirtualNandFlash<PAGE_SIZE, PAGES_PER_BLOCK, BLOCK_COUNT>
+{
+    const OOB_SIZE: usize = OOB_SIZE;
+
+    fn read_oob(&mut self, page: crate::PageIndex, oob: &mut [u8]) -> Result<(), Self::Error> {
+        let (block, page) = (
+            page.0 as usize / PAGES_PER_BLOCK,
+            page.0 as usize % PAGES_PER_BLOCK,
+        );
+        if block >= BLOCK_COUNT || oob.len() > OOB_SIZE {
             return Err(Error::OutOfBounds);
         }
-        self.block_status[block.0 as usize] = crate::BlockStatus::Failed;
+        if self.block_status[block].is_failed() {
+            return Err(Self::block_error(block as u32, NandOperation::Read));
+        }
+        self.read_count[block][page] += 1;
+        oob.copy_from_slice(&self.oob[block][page][..oob.len()]);
+        if self.ecc_failing[block] {
+            return Err(self.failing_error(block as u32));
+        }
+        Ok(())
+    }
+
+    fn write_page_oob(
+        &mut self,
+        page: crate::PageIndex,
+        data: &[u8],
+        oob: &[u8],
+    ) -> Result<(), Self::Error> {
+        if data.len() != PAGE_SIZE || oob.len() > OOB_SIZE {
+            return Err(Error::NotAligned);
+        }
+        crate::NandFlash::write(self, page.0 as u64 * PAGE_SIZE as u64, data)?;
+        let (block, page) = (
+            page.0 as usize / PAGES_PER_BLOCK,
+            page.0 as usize % PAGES_PER_BLOCK,
+        );
+        for (a, b) in self.oob[block][page].iter_mut().zip(oob.iter()) {
+            *a &= *b;
+        }
+        Ok(())
+    }
+}
+
+/// The page is written by [crate::SplitProgram::program_start]. Starting a second program
+/// before the first is finished, or finishing a different page, is an error.
+impl<const PAGE_SIZE: usize, const PAGES_PER_BLOCK: usize, const BLOCK_COUNT: usize>
+    crate::SplitProgram for VirtualNandFlash<PAGE_SIZE, PAGES_PER_BLOCK, BLOCK_COUNT>
+{
+    fn program_start(&mut self, page: crate::PageIndex, data: &[u8]) -> Result<(), Self::Error> {
+        if self.programming.is_some() {
+            return Err(Error::Misc);
+        }
+        if data.len() != PAGE_SIZE {
+            return Err(Error::NotAligned);
+        }
+        crate::NandFlash::write(self, page.0 as u64 * PAGE_SIZE as u64, data)?;
+        self.programming = Some(page.0);
+        Ok(())
+    }
+
+    fn program_finish(&mut self, page: crate::PageIndex) -> Result<(), Self::Error> {
+        if self.programming.take() != Some(page.0) {
+            return Err(Error::Misc);
+        }
         Ok(())
     }
 }
@@ -219,7 +447,7 @@ mod tests {
         let mut rbuffer = [1; CAPACITY];
         flash.read(0, &mut rbuffer).unwrap();
         assert_eq!(buffer, rbuffer);
-        flash.erase(0, CAPACITY as u32).unwrap();
+        flash.erase(0, CAPACITY as u64).unwrap();
         flash.read(0, &mut rbuffer).unwrap();
         assert_eq!(rbuffer, [0xFF; CAPACITY]);
     }
@@ -231,18 +459,18 @@ mod tests {
         for page in 0..BLOCK_COUNT * PAGES_PER_BLOCK {
             let offset = page * PAGE_SIZE;
             let buffer = [page as u8; PAGE_SIZE];
-            flash.write(offset as u32, &buffer).unwrap();
+            flash.write(offset as u64, &buffer).unwrap();
             assert_eq!(
                 flash.storage[page / PAGES_PER_BLOCK][page % PAGES_PER_BLOCK],
                 buffer
             );
             let mut rbuffer = [0; PAGE_SIZE];
-            flash.read(offset as u32, &mut rbuffer).unwrap();
+            flash.read(offset as u64, &mut rbuffer).unwrap();
             assert_eq!(buffer, rbuffer);
             let block = PAGES_PER_BLOCK * PAGE_SIZE * (page / PAGES_PER_BLOCK);
             debug!("Erasing block ast {}", block);
             flash
-                .erase(block as u32, (block + PAGES_PER_BLOCK * PAGE_SIZE) as u32)
+                .erase(block as u64, (block + PAGES_PER_BLOCK * PAGE_SIZE) as u64)
                 .unwrap();
 
             assert!(flash
@@ -252,12 +480,36 @@ mod tests {
         }
     }
 
+    /// Test block status of erased, programmed, factory bad and retired blocks
+    #[test]
+    fn test_block_status() {
+        use crate::{BlockIndex, BlockStatus};
+        let mut flash = VirtualNandFlash::<PAGE_SIZE, PAGES_PER_BLOCK, BLOCK_COUNT>::new();
+        let block = |i| BlockIndex::new(i);
+        assert_eq!(flash.block_status(block(0)).unwrap(), BlockStatus::Erased);
+        flash.write(0, &[0; 4]).unwrap();
+        assert_eq!(flash.block_status(block(0)).unwrap(), BlockStatus::Ok);
+        flash.erase_block(block(0)).unwrap();
+        assert!(flash.block_status(block(0)).unwrap().is_erased());
+
+        flash.set_factory_bad(block(1));
+        flash.mark_block_bad(block(2)).unwrap();
+        assert_eq!(
+            flash.block_status(block(1)).unwrap(),
+            BlockStatus::FactoryBad
+        );
+        assert_eq!(flash.block_status(block(2)).unwrap(), BlockStatus::Worn);
+        assert!(flash.block_status(block(1)).unwrap().is_failed());
+        assert!(!flash.block_status(block(2)).unwrap().is_ok());
+        assert!(flash.erase_block(block(2)).is_err());
+    }
+
     /// Test reading and writing over block boundaries
     #[test]
     fn test_block_boundary_rwe() {
         let mut flash = VirtualNandFlash::<PAGE_SIZE, PAGES_PER_BLOCK, BLOCK_COUNT>::new();
         let offset = PAGE_SIZE * 15 + PAGE_SIZE / 2;
-        const length: usize = PAGE_SIZE * 2;
+        const LENGTH: usize = PAGE_SIZE * 2;
         let block = offset / (PAGE_SIZE * PAGES_PER_BLOCK);
         let page_in_block = (offset / PAGE_SIZE) - (block * PAGES_PER_BLOCK);
         let byte_in_page = offset % PAGE_SIZE;
@@ -265,28 +517,24 @@ mod tests {
             "Writing at offset {}, block {}, page in block {}, byte in page {}",
             offset, block, page_in_block, byte_in_page
         );
-        let buffer = [0; length];
-        flash.write(offset as u32, &buffer).unwrap();
-        assert!(
-            flash.storage[block as usize][page_in_block as usize][byte_in_page..]
-                .iter()
-                .all(|&x| x == 0),
-        );
-        assert!(
-            flash.storage[block as usize][page_in_block as usize][..byte_in_page]
-                .iter()
-                .all(|&x| x == 0xFF)
-        );
-        assert!(flash.storage[block as usize + 1][0].iter().all(|&x| x == 0));
-        assert!(flash.storage[block as usize + 1][1][0..byte_in_page]
+        let buffer = [0; LENGTH];
+        flash.write(offset as u64, &buffer).unwrap();
+        assert!(flash.storage[block][page_in_block][byte_in_page..]
+            .iter()
+            .all(|&x| x == 0),);
+        assert!(flash.storage[block][page_in_block][..byte_in_page]
+            .iter()
+            .all(|&x| x == 0xFF));
+        assert!(flash.storage[block + 1][0].iter().all(|&x| x == 0));
+        assert!(flash.storage[block + 1][1][0..byte_in_page]
             .iter()
             .all(|&x| x == 0));
-        assert!(flash.storage[block as usize + 1][1][byte_in_page..]
+        assert!(flash.storage[block + 1][1][byte_in_page..]
             .iter()
             .all(|&x| x == 0xFF));
 
-        let mut rbuffer = [1; length];
-        flash.read(offset as u32, &mut rbuffer).unwrap();
+        let mut rbuffer = [1; LENGTH];
+        flash.read(offset as u64, &mut rbuffer).unwrap();
         assert_eq!(buffer, rbuffer);
     }
 }
@@ -4,7 +4,7 @@
 use cortex_m_semihosting::debug;
 use defmt::dbg;
 use embassy_executor::Spawner;
-use embedded_nand::{NandFlash, NandFlashIter};
+use embedded_nand::{BbtFlash, NandFlash, NandFlashIter};
 
 use embassy_stm32::gpio::Output;
 
@@ -56,13 +56,16 @@ async fn main(spawner: Spawner) {
     dbg!(flash.jedec_blocking());
     // dbg!(flash.disable_block_protection().await);
 
-    // initialise the flashmap with 2000 logical blocks (46 spare, 2 for map)
+    // Block status comes from the bad block table rather than reading every block
+    let flash: BbtFlash<_, { 2048 / 4 }> = BbtFlash::new(flash).unwrap();
+
+    // initialise the flashmap with 2000 logical blocks (42 spare, 2 for map, 4 for the bad block table)
     let mut flashmap = flashmap::FlashMap::<_, 2000>::init(flash).unwrap();
 
     // Read the first page
     let mut buf = [0; 2048];
     for (page, byte) in flashmap.page_iter() {
         defmt::info!("Page: {}, byte {}", page, byte);
-        flashmap.read(byte.as_u32(), &mut buf).unwrap();
+        flashmap.read(byte.as_u64(), &mut buf).unwrap();
     }
 }
@@ -0,0 +1,68 @@
+// Share the SPI bus between the NAND flash and a sensor with embedded-hal-bus RefCellDevice.
+//
+// Each flash command is its own transaction, and erases and programs wait with a delay
+// between busy polls rather than polling back to back.
+#![no_main]
+#![no_std]
+
+use core::cell::RefCell;
+
+use embassy_executor::Spawner;
+use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_time::Delay;
+use embedded_hal::spi::SpiDevice;
+use embedded_hal_bus::spi::{NoDelay, RefCellDevice};
+
+use embedded_nand::{BlockIndex, PageIndex};
+use spi_nand::{PollDelay, SpiNandDevice};
+use spi_nand_devices::winbond::w25n::W25N02KV;
+
+use {defmt_rtt as _, panic_probe as _}; // global logger
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_stm32::init(embassy_stm32::Config::default());
+
+    defmt::info!("Initialised peripherals");
+
+    // Create an SPI instance that implements [embedded_hal::spi::SpiBus]
+    let spi = embassy_stm32::spi::Spi::new_blocking(
+        p.SPI2,
+        p.PB13,
+        p.PB15,
+        p.PB14,
+        embassy_stm32::spi::Config::default(),
+    );
+    let bus = RefCell::new(spi);
+
+    // One chip select per device on the bus
+    let flash_cs = Output::new(p.PB12, Level::High, Speed::High);
+    let sensor_cs = Output::new(p.PB11, Level::High, Speed::High);
+
+    // Each device gets its own [embedded_hal::spi::SpiDevice] on the shared bus
+    let flash_dev = RefCellDevice::new(&bus, flash_cs, NoDelay).unwrap();
+    let mut sensor = RefCellDevice::new(&bus, sensor_cs, NoDelay).unwrap();
+
+    // Poll the busy flag every 100us while erasing or programming
+    let mut flash =
+        SpiNandDevice::new(flash_dev, W25N02KV::new()).with_busy_wait(PollDelay::new(Delay, 100));
+    flash.reset_blocking().unwrap();
+    defmt::assert!(flash.verify_jedec_blocking().unwrap());
+
+    let block = BlockIndex::new(10);
+    let page = PageIndex::from_block_address(block, 64);
+    flash.erase_block_blocking(block).unwrap();
+
+    // Read the sensor between flash operations
+    let mut who_am_i = [0x80 | 0x0F, 0];
+    sensor.transfer_in_place(&mut who_am_i).unwrap();
+    defmt::info!("Sensor ID {:02X}", who_am_i[1]);
+
+    flash.write_page_blocking(page, &[0x5A; 2048]).unwrap();
+    let mut buf = [0; 2048];
+    flash.read_page_blocking(page, &mut buf).unwrap();
+    defmt::assert!(buf.iter().all(|&b| b == 0x5A));
+
+    sensor.transfer_in_place(&mut who_am_i).unwrap();
+    defmt::info!("Done");
+}
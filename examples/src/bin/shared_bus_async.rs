@@ -0,0 +1,79 @@
+// Share the SPI bus between the NAND flash and a sensor task with an embassy_sync Mutex.
+//
+// While the flash is erasing or programming it waits between busy polls, so the sensor
+// task gets the bus. The flash holds a BusLock over the write enable, program load and
+// execute commands that start an operation, and the sensor waits for it before each
+// transaction, so no sensor transaction runs between them.
+#![no_main]
+#![no_std]
+
+use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice as SharedSpiDevice;
+use embassy_executor::Spawner;
+use embassy_futures::join::join;
+use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Delay, Timer};
+use embedded_hal_async::spi::SpiDevice;
+
+use embedded_nand::{BlockIndex, PageIndex};
+use spi_nand::{BusLock, LockedDevice, LockedWait, PollDelay, SpiNandDevice};
+use spi_nand_devices::winbond::w25n::W25N02KV;
+
+use {defmt_rtt as _, panic_probe as _}; // global logger
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_stm32::init(embassy_stm32::Config::default());
+
+    defmt::info!("Initialised peripherals");
+
+    // Create an SPI instance that implements [embedded_hal_async::spi::SpiBus]
+    let spi = embassy_stm32::spi::Spi::new(
+        p.SPI2,
+        p.PB13,
+        p.PB15,
+        p.PB14,
+        p.GPDMA1_CH5,
+        p.GPDMA1_CH4,
+        embassy_stm32::spi::Config::default(),
+    );
+    let bus: Mutex<NoopRawMutex, _> = Mutex::new(spi);
+
+    // One chip select per device on the bus
+    let flash_cs = Output::new(p.PB12, Level::High, Speed::High);
+    let sensor_cs = Output::new(p.PB11, Level::High, Speed::High);
+
+    // Each transaction locks the bus, so other tasks can use it between commands
+    let flash_dev = SharedSpiDevice::new(&bus, flash_cs);
+    // The sensor waits while the flash starts an erase or program
+    let lock = BusLock::new();
+    let mut sensor = LockedDevice::new(SharedSpiDevice::new(&bus, sensor_cs), &lock);
+
+    // Yield to the sensor for 100us between busy polls while erasing or programming
+    let mut flash = SpiNandDevice::new(flash_dev, W25N02KV::new())
+        .with_busy_wait(LockedWait::new(&lock, PollDelay::new(Delay, 100)));
+
+    let flash_task = async {
+        flash.reset_async().await.unwrap();
+        defmt::assert!(flash.verify_jedec_async().await.unwrap());
+        for block in 10..20 {
+            let block = BlockIndex::new(block);
+            let page = PageIndex::from_block_address(block, 64);
+            flash.erase_block_async(block).await.unwrap();
+            flash.write_page_async(page, &[0x5A; 2048]).await.unwrap();
+        }
+        defmt::info!("Flash done");
+    };
+
+    let sensor_task = async {
+        for _ in 0..100 {
+            let mut who_am_i = [0x80 | 0x0F, 0];
+            sensor.transfer_in_place(&mut who_am_i).await.unwrap();
+            defmt::info!("Sensor ID {:02X}", who_am_i[1]);
+            Timer::after_millis(1).await;
+        }
+    };
+
+    join(flash_task, sensor_task).await;
+}
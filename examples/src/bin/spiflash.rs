@@ -7,7 +7,7 @@ use embassy_executor::Spawner;
 
 use embassy_stm32::gpio::Output;
 
-use embedded_nand::{BlockIndex, PageIndex};
+use embedded_nand::{BbtFlash, BlockIndex, NandFlash, PageIndex};
 use spi_nand::cmd_blocking::SpiNandBlocking;
 use spi_nand::{SpiNand, SpiNandDevice};
 use spi_nand_devices::winbond::w25n::W25N02KV;
@@ -65,11 +65,13 @@ async fn main(spawner: Spawner) {
     defmt::info!("Checking bad blocks");
     // flash.mark_block_bad_blocking(1.into());
 
+    // Load the bad block table, only scanning every block on first boot
+    let mut flash: BbtFlash<_, { 2048 / 4 }> = BbtFlash::new(flash).unwrap();
     for i in 0..2048 {
         if flash
-            .device
-            .block_marked_bad(&mut flash.spi, BlockIndex::new(i))
+            .block_status(BlockIndex::new(i))
             .unwrap_or_else(|_| panic!("Failed to read block status"))
+            .is_failed()
         {
             defmt::error!("Block {} is marked bad", i);
         }
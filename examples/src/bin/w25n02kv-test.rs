@@ -16,7 +16,9 @@ use embedded_nand::{BlockIndex, PageIndex};
 use spi_nand::cmd_blocking::SpiNandBlocking;
 use spi_nand::{SpiNand, SpiNandDevice};
 use spi_nand_devices::winbond::w25n::blocking::{ECCBlocking, ODSBlocking};
-use spi_nand_devices::winbond::w25n::{ECCThreshold, ODSStrength, W25N02KV};
+use spi_nand_devices::winbond::w25n::{
+    ConfigReg, ECCThreshold, ODSRegister, ODSStrength, ProtectionReg, StatusReg, W25N02KV,
+};
 
 use {defmt_rtt as _, panic_probe as _}; // global logger
 
@@ -67,40 +69,25 @@ async fn main(spawner: Spawner) {
 
     // Check the registers are in default state
     info!("Checking registers");
-    assert_eq!(
-        flash
-            .device
-            .read_register_cmd(&mut flash.spi, 0xA0)
-            .unwrap(),
-        0b01111100
-    );
-    assert_eq!(
-        flash
-            .device
-            .read_register_cmd(&mut flash.spi, 0xB0)
-            .unwrap(),
-        0b00011001
-    );
-    assert_eq!(
-        flash
-            .device
-            .read_register_cmd(&mut flash.spi, 0xC0)
-            .unwrap(),
-        0b00000000
-    );
+    let protection: ProtectionReg = flash.device.read_reg(&mut flash.spi).unwrap();
+    assert_eq!(protection.block_protect(), 0b1111);
+    assert!(protection.top_bottom());
+    let config: ConfigReg = flash.device.read_reg(&mut flash.spi).unwrap();
+    assert!(config.ecc_enable());
+    assert!(config.buffer_mode());
+    assert!(config.hold_disable());
+    assert_eq!(config.output_driver_strength(), ODSStrength::Full);
+    let status: StatusReg = flash.device.read_reg(&mut flash.spi).unwrap();
+    assert_eq!(status, StatusReg::default());
 
     // Check writing to registers
     flash
         .device
         .disable_block_protection(&mut flash.spi)
         .unwrap();
-    assert_eq!(
-        flash
-            .device
-            .read_register_cmd(&mut flash.spi, 0xA0)
-            .unwrap(),
-        0b100
-    );
+    let protection: ProtectionReg = flash.device.read_reg(&mut flash.spi).unwrap();
+    assert_eq!(protection.block_protect(), 0);
+    assert!(protection.top_bottom());
 
     // Set driver strength
     info!("Checking driver strength");
@@ -0,0 +1,320 @@
+//! On-flash format of the map.
+//!
+//! Version 2 (current), all values little endian:
+//!
+//! | Offset | Size    | Field                           |
+//! |--------|---------|---------------------------------|
+//! | 0      | 4       | Magic `FMAP`                    |
+//! | 4      | 2       | Version (2)                     |
+//! | 6      | 2       | Reserved (0)                    |
+//! | 8      | 4       | Block count                     |
+//! | 12     | 4       | Logical block count (LBC)       |
+//! | 16     | 4       | Last spare block allocated      |
+//! | 20     | 4       | Write count                     |
+//! | 24     | 8       | Map blocks (2 x u32)            |
+//! | 32     | 4       | Scrub cursor (logical block)    |
+//! | 36     | 4       | Bad blocks from failed erases   |
+//! | 40     | 4       | Bad blocks from failed programs |
+//! | 44     | 4       | Bad blocks from failing ECC     |
+//! | 48     | 4       | Bad blocks from other causes    |
+//! | 52     | 4       | CRC-32 of the rest of the map   |
+//! | 56     | 4 x LBC | Map array of physical blocks    |
+//! | ..     | 2 x LBC | Spare pool                      |
+//!
+//! Map array entries of `0xFFFFFFFF` are logical blocks that have been trimmed, and
+//! have no physical block.
+//!
+//! The spare pool holds the [BlockState] of each physical block, 2 bits per block
+//! with block 0 in the least significant bits of the first byte. It can describe
+//! up to 8 x LBC blocks.
+//!
+//! Version 1 was written by casting `#[repr(C)]` structs to bytes, so is only
+//! readable on the little endian targets it was written on. It has no CRC, and
+//! is migrated to version 2 when found.
+
+use crc::{CRC_32_ISO_HDLC, Crc, Digest};
+use embedded_nand::BlockIndex;
+
+use crate::health::BadBlockCauses;
+
+/// Magic bytes at the start of the flashmap
+pub(crate) const MAGIC: [u8; 4] = *b"FMAP";
+/// Version of the flashmap format that is written
+pub(crate) const VERSION: u16 = 2;
+/// Size of the encoded header
+pub(crate) const HEADER_SIZE: usize = 56;
+/// Offset of the CRC in the header, which covers the bytes before it
+const CRC_OFFSET: usize = 52;
+/// Size of a map array entry
+const ENTRY_SIZE: usize = 4;
+/// Map array entry of a logical block with no physical block
+const UNMAPPED: [u8; ENTRY_SIZE] = [0xFF; ENTRY_SIZE];
+/// Bytes of spare pool per logical block
+const POOL_SIZE: usize = 2;
+/// Number of blocks described by a byte of the spare pool
+const BLOCKS_PER_POOL_BYTE: usize = 4;
+
+/// CRC over the header and map array
+static CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Data structure  that contains the
+/// configuration of the mapping
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct FlashMapHeader {
+    /// Number of blocks in the device
+    pub(crate) block_count: u32,
+    /// Number of logical blocks
+    pub(crate) logical_block_count: u32,
+    /// Last spare block allocated, the search for the next starts after it
+    pub(crate) final_block: u32,
+    /// Number of times the map has been written
+    pub(crate) write_count: u32,
+    /// The blocks used for the map
+    pub(crate) map_blocks: [u32; 2],
+    /// Next logical block to scrub
+    pub(crate) scrub_cursor: u32,
+    /// Blocks marked bad since the map was created, by cause
+    pub(crate) bad_causes: BadBlockCauses,
+}
+
+impl Ord for FlashMapHeader {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.write_count.cmp(&other.write_count)
+    }
+}
+
+impl PartialOrd for FlashMapHeader {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FlashMapHeader {
+    /// Create a new instance of the data structure
+    pub(crate) fn new(block_count: u32, logical_block_count: u32) -> Self {
+        FlashMapHeader {
+            block_count,
+            logical_block_count,
+            final_block: 0,
+            write_count: 0,
+            map_blocks: [0; 2],
+            scrub_cursor: 0,
+            bad_causes: BadBlockCauses::default(),
+        }
+    }
+
+    /// Block count and logical block count must be the same for a map to be used
+    pub(crate) fn matches(&self, other: &Self) -> bool {
+        self.block_count == other.block_count
+            && self.logical_block_count == other.logical_block_count
+    }
+
+    /// Encode the header with the given CRC
+    pub(crate) fn encode(&self, crc: u32) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.block_count.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.logical_block_count.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.final_block.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.write_count.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.map_blocks[0].to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.map_blocks[1].to_le_bytes());
+        bytes[32..36].copy_from_slice(&self.scrub_cursor.to_le_bytes());
+        bytes[36..40].copy_from_slice(&self.bad_causes.erase.to_le_bytes());
+        bytes[40..44].copy_from_slice(&self.bad_causes.program.to_le_bytes());
+        bytes[44..48].copy_from_slice(&self.bad_causes.ecc.to_le_bytes());
+        bytes[48..52].copy_from_slice(&self.bad_causes.other.to_le_bytes());
+        bytes[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Decode a header, returning it with the stored CRC.
+    ///
+    /// Returns None if the magic bytes or version don't match.
+    pub(crate) fn decode(bytes: &[u8; HEADER_SIZE]) -> Option<(Self, u32)> {
+        if bytes[0..4] != MAGIC || u16::from_le_bytes([bytes[4], bytes[5]]) != VERSION {
+            return None;
+        }
+        let word = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        Some((
+            FlashMapHeader {
+                block_count: word(8),
+                logical_block_count: word(12),
+                final_block: word(16),
+                write_count: word(20),
+                map_blocks: [word(24), word(28)],
+                scrub_cursor: word(32),
+                bad_causes: BadBlockCauses {
+                    erase: word(36),
+                    program: word(40),
+                    ecc: word(44),
+                    other: word(48),
+                },
+            },
+            word(CRC_OFFSET),
+        ))
+    }
+
+    /// Start a CRC with the encoded header, to be continued with the map array
+    pub(crate) fn digest(&self) -> Digest<'static, u32> {
+        let mut digest = CRC.digest();
+        digest.update(&self.encode(0)[..CRC_OFFSET]);
+        digest
+    }
+}
+
+/// State of a physical block in the spare pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum BlockState {
+    /// Bad, never used again
+    Bad = 0b00,
+    /// Used to store the map
+    Reserved = 0b01,
+    /// Mapped to a logical block
+    Mapped = 0b10,
+    /// Spare block available for use. Erased flash reads as free
+    Free = 0b11,
+}
+
+impl From<u8> for BlockState {
+    fn from(bits: u8) -> Self {
+        match bits & 0b11 {
+            0b00 => BlockState::Bad,
+            0b01 => BlockState::Reserved,
+            0b10 => BlockState::Mapped,
+            _ => BlockState::Free,
+        }
+    }
+}
+
+/// Header, map array and spare pool, stored little endian as they are on flash
+#[derive(Debug)]
+pub(crate) struct FlashMapData<const LBC: usize> {
+    /// Header
+    pub(crate) header: FlashMapHeader,
+    /// Map array of physical block indices
+    pub(crate) map: [[u8; ENTRY_SIZE]; LBC],
+    /// State of each physical block
+    pub(crate) pool: [[u8; POOL_SIZE]; LBC],
+}
+
+impl<const LBC: usize> FlashMapData<LBC> {
+    /// Size of the header, map array and spare pool on flash
+    pub(crate) const SIZE: usize = HEADER_SIZE + (ENTRY_SIZE + POOL_SIZE) * LBC;
+    /// Number of blocks the spare pool can describe
+    pub(crate) const MAX_BLOCKS: usize = POOL_SIZE * BLOCKS_PER_POOL_BYTE * LBC;
+
+    /// Create a new instance of the data structure, with all blocks free
+    pub(crate) fn new(block_count: u32, logical_block_count: u32) -> Self {
+        FlashMapData {
+            header: FlashMapHeader::new(block_count, logical_block_count),
+            map: [[0; ENTRY_SIZE]; LBC],
+            pool: [[0xFF; POOL_SIZE]; LBC],
+        }
+    }
+
+    /// State of a physical block
+    pub(crate) fn block_state(&self, block: BlockIndex) -> BlockState {
+        let (byte, shift) = Self::pool_position(block);
+        BlockState::from(self.pool.as_flattened()[byte] >> shift)
+    }
+
+    /// Set the state of a physical block
+    pub(crate) fn set_block_state(&mut self, block: BlockIndex, state: BlockState) {
+        let (byte, shift) = Self::pool_position(block);
+        let byte = &mut self.pool.as_flattened_mut()[byte];
+        *byte = (*byte & !(0b11 << shift)) | ((state as u8) << shift);
+    }
+
+    /// Byte and bit shift of a block in the spare pool
+    fn pool_position(block: BlockIndex) -> (usize, u32) {
+        let block = block.as_u32() as usize;
+        (
+            block / BLOCKS_PER_POOL_BYTE,
+            (block % BLOCKS_PER_POOL_BYTE) as u32 * 2,
+        )
+    }
+
+    /// Number of free blocks in the spare pool
+    pub(crate) fn free_blocks(&self) -> u32 {
+        (0..self.header.block_count)
+            .filter(|&b| self.block_state(BlockIndex::new(b)) == BlockState::Free)
+            .count() as u32
+    }
+
+    /// The next free block after `after`, wrapping around the device
+    pub(crate) fn next_free_block(&self, after: u32) -> Option<BlockIndex> {
+        let block_count = self.header.block_count;
+        (1..=block_count)
+            .map(|i| BlockIndex::new((after + i) % block_count))
+            .find(|&b| self.block_state(b) == BlockState::Free)
+    }
+
+    /// Physical block of a logical block, None if it is unmapped
+    pub(crate) fn physical(&self, logical: usize) -> Option<BlockIndex> {
+        (self.map[logical] != UNMAPPED)
+            .then(|| BlockIndex::new(u32::from_le_bytes(self.map[logical])))
+    }
+
+    /// Remove the physical block of a logical block
+    pub(crate) fn unmap(&mut self, logical: usize) {
+        self.map[logical] = UNMAPPED;
+    }
+
+    /// Set the physical block of a logical block
+    pub(crate) fn set_physical(&mut self, logical: usize, physical: BlockIndex) {
+        self.map[logical] = physical.as_u32().to_le_bytes();
+    }
+
+    /// CRC of the header and map array
+    pub(crate) fn crc(&self) -> u32 {
+        let mut digest = self.header.digest();
+        digest.update(self.map.as_flattened());
+        digest.update(self.pool.as_flattened());
+        digest.finalize()
+    }
+
+    /// Widen a version 1 map array, read into the start of the map array, in place.
+    pub(crate) fn expand_v1_map(&mut self) {
+        let bytes = self.map.as_flattened_mut();
+        // Work backwards so entries are not overwritten before being read
+        for i in (0..LBC).rev() {
+            let entry = u16::from_le_bytes([bytes[2 * i], bytes[2 * i + 1]]) as u32;
+            bytes[ENTRY_SIZE * i..ENTRY_SIZE * (i + 1)].copy_from_slice(&entry.to_le_bytes());
+        }
+    }
+}
+
+/// Version 1 format, read for migration
+pub(crate) mod v1 {
+    use super::{BadBlockCauses, FlashMapHeader, MAGIC};
+
+    /// Version of the format
+    const VERSION: u16 = 1;
+    /// Size of the header
+    pub(crate) const HEADER_SIZE: usize = 20;
+
+    /// Size of the header, map array and terminator
+    pub(crate) const fn size(logical_block_count: usize) -> usize {
+        HEADER_SIZE + 2 * logical_block_count + MAGIC.len()
+    }
+
+    /// Decode a version 1 header. Returns None if the magic bytes or version don't match
+    pub(crate) fn decode(bytes: &[u8; HEADER_SIZE]) -> Option<FlashMapHeader> {
+        let half = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]) as u32;
+        if bytes[0..4] != MAGIC || half(4) != VERSION as u32 {
+            return None;
+        }
+        Some(FlashMapHeader {
+            block_count: half(6),
+            logical_block_count: half(8),
+            final_block: half(10),
+            write_count: u32::from_le_bytes(bytes[12..16].try_into().unwrap()),
+            map_blocks: [half(16), half(18)],
+            scrub_cursor: 0,
+            bad_causes: BadBlockCauses::default(),
+        })
+    }
+}
@@ -0,0 +1,151 @@
+//! Statistics for telemetry and end of life estimates.
+
+use core::fmt::Debug;
+
+use embedded_nand::{BlockIndex, EraseCount, NandFlash};
+
+use crate::{FlashMap, format::BlockState};
+
+/// Reads the erase count of a block, see [EraseCount::erase_count]
+pub(crate) type EraseCountFn<F> = fn(&F, BlockIndex) -> Option<u32>;
+
+/// Why a block was marked bad
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum BadCause {
+    /// An erase failed
+    Erase,
+    /// A write failed or reported the block is failing
+    Program,
+    /// A read needed ECC correction at the failing threshold
+    Ecc,
+    /// Found bad when allocated, or marked bad by the user
+    Other,
+}
+
+/// Blocks marked bad since the map was created, by cause.
+///
+/// Saved with the map, so a block retired shortly before power is lost may not be counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BadBlockCauses {
+    /// Erase failed
+    pub erase: u32,
+    /// Write failed
+    pub program: u32,
+    /// Read reported the block as failing by ECC
+    pub ecc: u32,
+    /// Found bad when allocated, or marked bad through [NandFlash::mark_block_bad]
+    pub other: u32,
+}
+
+impl BadBlockCauses {
+    /// Count a block marked bad
+    pub(crate) fn record(&mut self, cause: BadCause) {
+        let count = match cause {
+            BadCause::Erase => &mut self.erase,
+            BadCause::Program => &mut self.program,
+            BadCause::Ecc => &mut self.ecc,
+            BadCause::Other => &mut self.other,
+        };
+        *count = count.saturating_add(1);
+    }
+}
+
+/// Erase counts over all physical blocks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EraseCounts {
+    /// Lowest erase count of a block
+    pub min: u32,
+    /// Highest erase count of a block, compare with the rated endurance for end of life
+    pub max: u32,
+    /// Sum of the erase counts of all blocks
+    pub total: u64,
+}
+
+/// Health of a [FlashMap], from [FlashMap::health].
+///
+/// The map write count, spare blocks and bad blocks (including their causes) are stored
+/// on flash. The remap and refresh counts are since the map was loaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Health {
+    /// Number of times the map has been written
+    pub map_writes: u32,
+    /// Spare blocks left to replace failing blocks
+    pub spare_blocks: u32,
+    /// Blocks marked bad in the spare pool, including factory bad blocks
+    pub bad_blocks: u32,
+    /// Logical blocks moved because their physical block failed
+    pub remaps: u32,
+    /// Logical blocks moved by scrubbing before they failed
+    pub refreshes: u32,
+    /// Blocks marked bad, by cause
+    pub bad_causes: BadBlockCauses,
+    /// Erase counts, if the flash records them (see [FlashMap::enable_erase_counts])
+    pub erase_counts: Option<EraseCounts>,
+}
+
+impl<F, const LBC: usize> FlashMap<F, LBC>
+where
+    F: NandFlash + Debug,
+{
+    /// Report the health of the map and flash
+    pub fn health(&self) -> Health {
+        let block_count = self.data.header.block_count;
+        Health {
+            map_writes: self.data.header.write_count,
+            spare_blocks: self.data.free_blocks(),
+            bad_blocks: (0..block_count)
+                .filter(|&b| self.data.block_state(BlockIndex::new(b)) == BlockState::Bad)
+                .count() as u32,
+            remaps: self.remaps,
+            refreshes: self.refreshes,
+            bad_causes: self.data.header.bad_causes,
+            erase_counts: self.erase_count.and_then(|erase_count| {
+                (0..block_count)
+                    .filter_map(|b| erase_count(&self.flash, BlockIndex::new(b)))
+                    .fold(None, |counts: Option<EraseCounts>, count| {
+                        let counts = counts.unwrap_or(EraseCounts {
+                            min: count,
+                            max: count,
+                            total: 0,
+                        });
+                        Some(EraseCounts {
+                            min: counts.min.min(count),
+                            max: counts.max.max(count),
+                            total: counts.total + count as u64,
+                        })
+                    })
+            }),
+        }
+    }
+
+    /// Include erase counts from the flash in [Self::health]
+    pub fn enable_erase_counts(&mut self)
+    where
+        F: EraseCount,
+    {
+        self.erase_count = Some(F::erase_count);
+    }
+
+    /// Erase count of a physical block, if enabled with [Self::enable_erase_counts] and
+    /// recorded by the flash
+    pub fn erase_count(&self, block: BlockIndex) -> Option<u32> {
+        self.erase_count
+            .and_then(|erase_count| erase_count(&self.flash, block))
+    }
+
+    /// Mark a physical block bad, in the flash and the spare pool
+    pub(crate) fn retire(&mut self, block: BlockIndex, cause: BadCause) {
+        warn!("Block {} is bad ({:?})", block, cause);
+        // Ignore errors, the pool stops it being used again
+        let _ = self.flash.mark_block_bad(block);
+        self.data.set_block_state(block, BlockState::Bad);
+        self.data.header.bad_causes.record(cause);
+    }
+}
@@ -2,15 +2,21 @@
 
 use core::{fmt::Debug, ops::Range};
 
-use embedded_nand::{BlockIndex, ByteAddress, NandFlashError, NandFlashErrorKind, PageIndex};
+use embedded_nand::{
+    BlockIndex, BlockSwap, ByteAddress, NandFlashError, NandFlashErrorKind, PageIndex,
+};
 use thiserror::Error;
 mod fmt;
+mod format;
+mod health;
+mod pagemap;
+mod txn;
 use embedded_nand::{AddressConversions, NandFlashIter};
-
-/// Magic bytes at the start of the flashmap
-const MAGIC: [u8; 4] = *b"FMAP";
-/// Version of the flashmap
-const VERSION: u16 = 1;
+use format::{BlockState, FlashMapData, FlashMapHeader, HEADER_SIZE, MAGIC, VERSION, v1};
+pub use health::{BadBlockCauses, EraseCounts, Health};
+use health::{BadCause, EraseCountFn};
+pub use pagemap::PageMap;
+pub use txn::{DEFAULT_TXN_BLOCKS, Txn};
 
 #[derive(Debug, Error)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -27,6 +33,8 @@ pub enum Error<F: embedded_nand::NandFlash> {
     OutOfBounds,
     #[error("No superblocks available")]
     NoSuperBlocks,
+    #[error("Transaction full")]
+    TransactionFull,
     #[error("Other Error")]
     Other,
 }
@@ -41,6 +49,7 @@ where
             Error::InvalidConfg => embedded_nand::NandFlashErrorKind::Other,
             Error::NotEnoughValidBlocks => embedded_nand::NandFlashErrorKind::Other,
             Error::NoSuperBlocks => embedded_nand::NandFlashErrorKind::Other,
+            Error::TransactionFull => embedded_nand::NandFlashErrorKind::Other,
             Error::NotAligned => embedded_nand::NandFlashErrorKind::NotAligned,
             Error::OutOfBounds => embedded_nand::NandFlashErrorKind::OutOfBounds,
             Error::Other => embedded_nand::NandFlashErrorKind::Other,
@@ -59,28 +68,93 @@ where
             NandFlashErrorKind::NotAligned => Error::NotAligned,
             NandFlashErrorKind::OutOfBounds => Error::OutOfBounds,
             NandFlashErrorKind::Other => Error::Other,
-            _ => todo!(),
+            _ => Error::Other,
         }
     }
 }
 
+/// Default number of reads of a block before it is refreshed by [FlashMap::scrub_step]
+pub const DEFAULT_READ_DISTURB_LIMIT: u32 = 100_000;
+
+/// Number of blocks scrubbed between saves of the scrub cursor
+const SCRUB_SAVE_INTERVAL: u32 = 16;
+
+/// Number of blocks at the start of the device reserved for the map.
+///
+/// The map is written to the first 2 good blocks in the region and the others replace
+/// them when they fail, so [FlashMap::init] only has to search this region for the map.
+pub const MAP_REGION_BLOCKS: u32 = 8;
+
+/// Largest page size supported, for buffers holding a whole page
+const PAGE_BUFFER_SIZE: usize = 4096;
+
+/// Outcome of a [FlashMap::scrub_step]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ScrubStep {
+    /// Logical block that was scrubbed
+    pub block: BlockIndex,
+    /// The block was moved to a fresh physical block
+    pub refreshed: bool,
+    /// The scrub cursor wrapped back to the first block
+    pub pass_complete: bool,
+}
+
+/// Swaps a bad block for a replacement, see [BlockSwap::swap_block]
+type SwapFn<F> =
+    fn(&mut F, BlockIndex, BlockIndex) -> Result<bool, <F as embedded_nand::ErrorType>::Error>;
+
 /// Mapping of logical blocks to physical blocks
 ///
 /// BC is the number of blocks in the device
 /// LBC is the number of logical blocks
 ///
-/// The map consumes the first 2 valid blocks in the device.
-/// Spare blocks = BC - LBC - 2
+/// The map consumes the first [MAP_REGION_BLOCKS] blocks in the device. It is written to
+/// the first 2 good blocks in the region. If a map block fails, it is replaced by another
+/// block in the region and the map records where it has moved to.
+/// Spare blocks = BC - LBC - [MAP_REGION_BLOCKS] - bad blocks
+///
+/// Maps migrated from version 1 keep their data in the region, so have no replacements
+/// for failed map blocks.
+///
+/// The map also holds a spare pool recording which physical blocks are free, mapped,
+/// bad or used by the map. Spare blocks are allocated from the pool round-robin and
+/// retired blocks are marked bad in it. The pool can describe up to 8 x LBC blocks,
+/// so BC must not be larger than this.
+///
+/// The map is stored little endian and protected by a CRC (see `format.rs`).
+/// Maps in the older version 1 format are migrated when loaded.
+///
+/// Devices with a hardware lookup table for bad blocks can replace failed blocks
+/// themselves, see [Self::enable_block_swap].
 #[derive(Debug)]
-pub struct FlashMap<F, const LBC: usize> {
+pub struct FlashMap<F: embedded_nand::NandFlash, const LBC: usize> {
     /// The flash device that is used to store the mapping
     flash: F,
     /// Data that defines the map
     data: FlashMapData<LBC>,
-    /// Number of pages for the [FlashMapData] struct, map array and terminator
+    /// Number of pages for the [FlashMapData] header, map array and spare pool
     map_page_count: u32,
     /// Address of map data
     data_address: ByteAddress,
+    /// Threshold and hook called when the spare blocks remaining falls to the threshold
+    low_spares: Option<(u32, fn(u32))>,
+    /// Reads of each logical block since it was last refreshed or the map was loaded
+    read_counts: [u32; LBC],
+    /// Reads of a block before it is refreshed
+    read_disturb_limit: u32,
+    /// Corrected bits in a read before [Self::scrub_step] refreshes the block
+    scrub_threshold: u8,
+    /// Blocks scrubbed since the scrub cursor was saved
+    scrub_unsaved: u32,
+    /// Hardware block swap, used before remapping in the map until it reports it is full
+    block_swap: Option<SwapFn<F>>,
+    /// Erase counts from the flash, for [Self::health]
+    erase_count: Option<EraseCountFn<F>>,
+    /// Logical blocks remapped since the map was loaded
+    remaps: u32,
+    /// Logical blocks refreshed since the map was loaded
+    refreshes: u32,
 }
 
 impl<F, const LBC: usize> FlashMap<F, LBC>
@@ -92,12 +166,20 @@ where
     /// SPI flash must be initialised (verify prescence, disable block protection)
     pub fn init(flash: F) -> Result<Self, Error<F>> {
         // Do some verification on block count, logical block count etc.
-        if (LBC + 2) > F::BLOCK_COUNT as usize {
+        if LBC + MAP_REGION_BLOCKS as usize > F::BLOCK_COUNT {
+            return Err(Error::InvalidConfg);
+        }
+        // Block indices are stored as u32 in the map
+        if F::BLOCK_COUNT > u32::MAX as usize {
             return Err(Error::InvalidConfg);
         }
         if LBC == 0 {
             return Err(Error::InvalidConfg);
         }
+        // Every block must fit in the spare pool
+        if F::BLOCK_COUNT > FlashMapData::<LBC>::MAX_BLOCKS {
+            return Err(Error::InvalidConfg);
+        }
 
         info!(
             "Initialising flashmap with {} logical blocks and {} physical blocks",
@@ -106,19 +188,31 @@ where
         );
 
         // Calculate how many pages the map takes up in flash
-        let map_page_count = (Self::map_size_in_flash()).div_ceil(F::PAGE_SIZE) as u32;
+        let map_page_count = FlashMapData::<LBC>::SIZE.div_ceil(F::PAGE_SIZE) as u32;
         info!(
             "Pages per flashmap: {} ({} bytes)",
             map_page_count,
-            Self::map_size_in_flash()
+            FlashMapData::<LBC>::SIZE
         );
-
+        // Must fit in a block
+        if map_page_count as usize > F::PAGES_PER_BLOCK {
+            return Err(Error::InvalidConfg);
+        }
         // Create a new flashmap
         let mut flashmap = FlashMap {
             flash,
-            data: FlashMapData::new(F::BLOCK_COUNT as u16, LBC as u16),
+            data: FlashMapData::new(F::BLOCK_COUNT as u32, LBC as u32),
             data_address: Default::default(),
             map_page_count,
+            low_spares: None,
+            read_counts: [0; LBC],
+            read_disturb_limit: DEFAULT_READ_DISTURB_LIMIT,
+            scrub_threshold: 0,
+            scrub_unsaved: 0,
+            block_swap: None,
+            erase_count: None,
+            remaps: 0,
+            refreshes: 0,
         };
 
         // Track which are the first 2 valid blocks.
@@ -127,48 +221,32 @@ where
         // is bad, no other blocks are overwritten
         let mut count = 0;
         let mut map_blocks = [BlockIndex::default(); 2];
-        let mut valid_header = None;
+        let mut valid_header: Option<(FlashMapHeader, ByteAddress)> = None;
+        let mut valid_v1_header: Option<(FlashMapHeader, ByteAddress)> = None;
         // Go through first 2 valid blocks to try find a map
-        for (block_ind, block_address) in flashmap.flash.block_iter_from(BlockIndex::new(0)) {
+        for (block_ind, block_address) in flashmap
+            .flash
+            .block_iter_from(BlockIndex::new(0))
+            .take(MAP_REGION_BLOCKS as usize)
+        {
             debug!(
                 "Checking block {} at {} for map",
-                block_ind.as_u16(),
-                block_address.as_u32()
+                block_ind.as_u32(),
+                block_address.as_u64()
             );
             // check if block is good
             if flashmap
                 .flash
                 .block_status(block_ind)
-                .map_err(|e| Error::Flash(e))?
+                .map_err(Error::Flash)?
                 .is_ok()
             {
                 map_blocks[count] = block_ind;
                 count += 1;
-
-                // Go through each possible map location
-                for page in (0..F::PAGES_PER_BLOCK).step_by(map_page_count as usize) {
-                    let address = block_address + (page * F::PAGE_SIZE) as u32;
-                    // Check if the map is valid
-                    if let Some(new_header) = flashmap
-                        .get_map_data(address)
-                        .map_err(|e| Error::Flash(e))?
-                    {
-                        debug!(
-                            "Found valid map at {} with {} writes",
-                            address.as_u32(),
-                            new_header.write_count
-                        );
-                        // Check if it is more recent than the current one
-                        if let Some((current_header, _)) = valid_header {
-                            if new_header > current_header {
-                                // Found a more recent map
-                                valid_header = Some((new_header, address));
-                            }
-                        } else {
-                            // First valid map found
-                            valid_header = Some((new_header, address));
-                        }
-                    }
+                flashmap.scan_map_block(block_ind, &mut valid_header)?;
+                // Look for maps in the old format if none found
+                if valid_header.is_none() {
+                    flashmap.scan_v1_map_block(block_ind, &mut valid_v1_header)?;
                 }
                 if count >= 2 {
                     break;
@@ -176,6 +254,46 @@ where
             }
         }
 
+        // If both of the original map blocks have failed, the map has been relocated
+        // within the map region, so check the rest of the region
+        if valid_header.is_none() && valid_v1_header.is_none() {
+            debug!("No map in first blocks, searching map region");
+            for block in 0..MAP_REGION_BLOCKS.min(F::BLOCK_COUNT as u32) {
+                let block_ind = BlockIndex::new(block);
+                if !map_blocks[..count].contains(&block_ind)
+                    && flashmap
+                        .flash
+                        .block_status(block_ind)
+                        .map_err(Error::Flash)?
+                        .is_ok()
+                {
+                    flashmap.scan_map_block(block_ind, &mut valid_header)?;
+                }
+            }
+        }
+
+        // Each map records its map blocks, follow them to any newer maps in relocated blocks
+        let mut scanned = map_blocks.map(|b| b.as_u32());
+        while let Some((header, _)) = valid_header {
+            let write_count = header.write_count;
+            for block in header.map_blocks.map(BlockIndex::new) {
+                if !scanned.contains(&block.as_u32())
+                    && flashmap
+                        .flash
+                        .block_status(block)
+                        .map_err(Error::Flash)?
+                        .is_ok()
+                {
+                    debug!("Following map to block {}", block);
+                    flashmap.scan_map_block(block, &mut valid_header)?;
+                }
+            }
+            scanned = header.map_blocks;
+            if valid_header.is_none_or(|(header, _)| header.write_count == write_count) {
+                break;
+            }
+        }
+
         // If a valid map was found, load it
         if let Some((new_map, address)) = valid_header {
             flashmap.data.header = new_map;
@@ -183,15 +301,38 @@ where
             flashmap.load_map_array()?;
             info!(
                 "Loaded map from {} with {} writes",
-                address.as_u32(),
+                address.as_u64(),
                 flashmap.data.header.write_count
             );
             return Ok(flashmap);
         }
+        // Migrate a version 1 map to the current format
+        if let Some((new_map, address)) = valid_v1_header {
+            info!(
+                "Migrating version 1 map from {} with {} writes",
+                address.as_u64(),
+                new_map.write_count
+            );
+            flashmap.data.header = new_map;
+            flashmap.data_address = address;
+            flashmap.load_v1_map_array()?;
+            // Version 1 maps allocated spare blocks in order, so those up to the final
+            // block that are not in use have been retired
+            flashmap.build_pool(flashmap.data.header.final_block + 1)?;
+            // Write to the other map block, leaving the old map intact until written
+            flashmap.data.header.write_count += 1;
+            flashmap.switch_map_block()?;
+            flashmap.commit_map()?;
+            return Ok(flashmap);
+        }
         // No valid map found, create a new one
         info!("No valid map found, creating new one");
-        let first_block = map_blocks[1] + 1;
-        debug!("First block to use: {}", first_block.as_u16());
+        if count < 2 {
+            error!("Not enough valid blocks for the map");
+            return Err(Error::NoSuperBlocks);
+        }
+        let first_block = BlockIndex::new(MAP_REGION_BLOCKS);
+        debug!("First block to use: {}", first_block.as_u32());
 
         // Iterate over the blocks to find LBC good blocks
         let mut logical_ind = 0;
@@ -201,11 +342,11 @@ where
             if flashmap
                 .flash
                 .block_status(block_ind)
-                .map_err(|e| Error::Flash(e))?
+                .map_err(Error::Flash)?
                 .is_ok()
             {
                 // Record logical to physical mapping
-                flashmap.data.map[logical_ind] = block_ind;
+                flashmap.data.set_physical(logical_ind, block_ind);
                 logical_ind += 1;
                 // Check if we have enough blocks
                 if logical_ind >= LBC {
@@ -215,7 +356,7 @@ where
                     break;
                 }
             } else {
-                warn!("Block {} is bad", block_ind.as_u16());
+                warn!("Block {} is bad", block_ind.as_u32());
             }
         }
 
@@ -225,46 +366,63 @@ where
             return Err(Error::NotEnoughValidBlocks);
         } else if let Some(next) = final_block {
             // Set the next block to use
-            flashmap.data.header.final_block = next;
-            info!("Next block to use: {}", next.as_u16());
+            flashmap.data.header.final_block = next.as_u32();
+            info!("Next block to use: {}", next.as_u32());
         } else {
             warn!("No valid blocks remaining");
-            flashmap.data.header.final_block = BlockIndex::new(0);
+            flashmap.data.header.final_block = 0;
         }
         // Save the map config
-        flashmap.data.header.map_blocks = map_blocks;
+        flashmap.data.header.map_blocks = map_blocks.map(|b| b.as_u32());
+        flashmap.build_pool(flashmap.data.header.final_block + 1)?;
+        // Keep the rest of the map region to replace failed map blocks
+        for block in 0..MAP_REGION_BLOCKS {
+            let block = BlockIndex::new(block);
+            if flashmap
+                .flash
+                .block_status(block)
+                .map_err(Error::Flash)?
+                .is_ok()
+            {
+                flashmap.data.set_block_state(block, BlockState::Reserved);
+            }
+        }
         flashmap.data.header.write_count = 1;
         flashmap.data_address = Self::block_to_byte_address(map_blocks[0]);
         // erase the block that will be used for the map
-        debug!("Erasing block {} for map", map_blocks[0].as_u16());
+        debug!("Erasing block {} for map", map_blocks[0].as_u32());
         flashmap
             .flash
             .erase_block(map_blocks[0])
-            .map_err(|e| Error::Flash(e))?;
+            .map_err(Error::Flash)?;
         // Write the map to flash
-        flashmap.write_map()?;
+        flashmap.commit_map()?;
 
         Ok(flashmap)
     }
 
-    /// Size of flashmap, map array and terminator in bytes
-    const fn map_size_in_flash() -> usize {
-        // Size of the map array
-        let map_size = core::mem::size_of::<u16>() * LBC;
-        // Size of the flashmap data structure
-        let data_size = core::mem::size_of::<FlashMapHeader>();
-        // Size of the terminator
-        let terminator_size = MAGIC.len();
-        // Total size
-        map_size + data_size + terminator_size
+    /// Convert a logical block index to a physical block, None if it is unmapped
+    fn logical_to_physical(
+        &self,
+        logical_block: BlockIndex,
+    ) -> Result<Option<BlockIndex>, Error<F>> {
+        if logical_block.as_u32() >= LBC as u32 {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(self.data.physical(logical_block.as_u32() as usize))
     }
 
-    /// Convert a logical block index to a physical block
-    fn logical_to_physical(&self, logical_block: BlockIndex) -> Result<BlockIndex, Error<F>> {
-        if logical_block.as_u16() >= LBC as u16 {
-            return Err(Error::OutOfBounds);
+    /// Physical block of a logical block, giving it a spare block if it is unmapped
+    fn map_block(&mut self, logical_block: BlockIndex) -> Result<BlockIndex, Error<F>> {
+        if let Some(physical) = self.logical_to_physical(logical_block)? {
+            return Ok(physical);
         }
-        Ok(self.data.map[logical_block.as_u16() as usize])
+        let physical = self.next_spare_block()?;
+        debug!("Mapping block {} to {}", logical_block, physical);
+        self.data
+            .set_physical(logical_block.as_u32() as usize, physical);
+        self.update_map()?;
+        Ok(physical)
     }
 
     /// Convert a logical page index to a physical page
@@ -273,82 +431,424 @@ where
             return Err(Error::OutOfBounds);
         }
         let logical_block = logical_page.as_block_index(F::PAGES_PER_BLOCK as u32);
-        let physical_block = self.logical_to_physical(logical_block)?;
+        let physical_block = self
+            .logical_to_physical(logical_block)?
+            .ok_or(Error::OutOfBounds)?;
         let page_offset = Self::page_in_block(logical_page);
-        Ok(Self::block_to_page_index(physical_block) + page_offset)
+        Ok(Self::block_to_page_index(physical_block).checked_add(page_offset)?)
     }
 
-    /// Convert a logical byte address to a physical byte address
-    fn logical_to_physical_byte(&self, logical_byte: ByteAddress) -> Result<ByteAddress, Error<F>> {
+    /// Convert a logical byte address to a physical byte address, None if the block is unmapped
+    fn logical_to_physical_byte(
+        &self,
+        logical_byte: ByteAddress,
+    ) -> Result<Option<ByteAddress>, Error<F>> {
         let logical_block = Self::byte_to_block_index(logical_byte);
-        let physical_block = self.logical_to_physical(logical_block)?;
+        let Some(physical_block) = self.logical_to_physical(logical_block)? else {
+            return Ok(None);
+        };
         let block_offset = Self::byte_in_block(logical_byte);
-        Ok(Self::block_to_byte_address(physical_block) + block_offset)
+        Ok(Some(
+            Self::block_to_byte_address(physical_block).checked_add(block_offset as u64)?,
+        ))
     }
 
     /// Address of the map array
     fn map_address(&self) -> ByteAddress {
-        self.data_address + core::mem::size_of::<FlashMapHeader>() as u32
+        self.data_address + HEADER_SIZE as u32
     }
 
-    /// Try to load a map from the given address
-    fn get_map_data(&mut self, address: ByteAddress) -> Result<Option<FlashMapHeader>, F::Error> {
-        // Create data and
-        let mut data = FlashMapHeader::default();
+    /// Address of the spare pool
+    fn pool_address(&self) -> ByteAddress {
+        self.map_address() + core::mem::size_of_val(&self.data.map) as u32
+    }
 
-        let slice = unsafe {
-            core::slice::from_raw_parts_mut(
-                &mut data as *mut FlashMapHeader as *mut u8,
-                core::mem::size_of::<FlashMapHeader>(),
-            )
-        };
-        self.flash.read(address.into(), slice)?;
-        // Check it matches what is expected
-        if self.data.header.is_valid(&data) {
-            // check that terminating magic bytes are present
-            let mut term = [0; 4];
-            let term_location = Self::map_size_in_flash() - MAGIC.len();
-            self.flash
-                .read((address + term_location as u32).as_u32(), &mut term)?;
+    /// Number of spare blocks left to replace failing blocks
+    pub fn spare_blocks_remaining(&self) -> u32 {
+        self.data.free_blocks()
+    }
 
-            if term != MAGIC {
-                trace!("Missing terminator at {:#X}", address);
-                Ok(None)
-            } else {
-                trace!("Found valid map at {:#X}", address);
-                Ok(Some(data))
+    /// Unmap a range of logical blocks, returning their physical blocks to the spare pool.
+    ///
+    /// Trimmed blocks read as erased (0xFF) without accessing the flash, and erasing them
+    /// does nothing. They are given a spare block again when next written.
+    pub fn trim(&mut self, logical_blocks: Range<BlockIndex>) -> Result<(), Error<F>> {
+        if logical_blocks.end.as_u32() > LBC as u32 {
+            return Err(Error::OutOfBounds);
+        }
+        let mut trimmed = false;
+        for logical in logical_blocks.start.as_u32()..logical_blocks.end.as_u32() {
+            let logical = logical as usize;
+            if let Some(physical) = self.data.physical(logical) {
+                self.data.set_block_state(physical, BlockState::Free);
+                self.data.unmap(logical);
+                self.read_counts[logical] = 0;
+                trimmed = true;
+            }
+        }
+        if trimmed {
+            debug!(
+                "Trimmed blocks {} to {}",
+                logical_blocks.start, logical_blocks.end
+            );
+            self.update_map()?;
+        }
+        Ok(())
+    }
+
+    /// Set a hook that is called with the number of spare blocks remaining whenever a
+    /// spare block is allocated and leaves `threshold` or fewer.
+    ///
+    /// This allows the device to report that it is nearing end of life before
+    /// writes start to fail.
+    pub fn set_low_spares_hook(&mut self, threshold: u32, hook: fn(u32)) {
+        self.low_spares = Some((threshold, hook));
+    }
+
+    /// Replace failed blocks using the hardware lookup table of the device.
+    ///
+    /// Blocks are still taken from the spare pool, but the device redirects the failed
+    /// block to the spare so the map entry is unchanged. Once the device reports its
+    /// lookup table is full, failed blocks are remapped in the map instead.
+    pub fn enable_block_swap(&mut self)
+    where
+        F: BlockSwap,
+    {
+        self.block_swap = Some(F::swap_block);
+    }
+
+    /// Access the underlying flash, such as to read the device lookup table for diagnostics.
+    ///
+    /// WARNING: Writing to the flash directly can corrupt the map.
+    pub fn inner(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Set the number of reads of a block before [Self::scrub_step] refreshes it
+    pub fn set_read_disturb_limit(&mut self, limit: u32) {
+        self.read_disturb_limit = limit;
+    }
+
+    /// Set the number of bits corrected by ECC in a read before [Self::scrub_step] refreshes
+    /// the block.
+    ///
+    /// Only reads the flash reports as failing are checked, so thresholds below the one
+    /// configured in the flash device have no effect. The default of 0 refreshes on every
+    /// failing read. Reads that don't report the corrected bits always refresh.
+    pub fn set_scrub_threshold(&mut self, bits: u8) {
+        self.scrub_threshold = bits;
+    }
+
+    /// Scrub the next logical block, refreshing it before its data is lost.
+    ///
+    /// Intended to be called periodically, such as when idle. Blocks read more than the
+    /// read disturb limit are refreshed first. Otherwise every page of the block at the scrub
+    /// cursor is read, and the block is refreshed if the ECC corrected at least the scrub
+    /// threshold of bits, see [Self::set_scrub_threshold].
+    ///
+    /// Refreshing copies the block to a spare block and returns the old one to the spare pool.
+    /// The scrub cursor is saved in the map whenever it is written, and at least every 16 blocks.
+    /// Read counts are only held in RAM, so are lost on reboot.
+    pub fn scrub_step(&mut self) -> Result<ScrubStep, Error<F>> {
+        // Read disturbed blocks take priority
+        if let Some(logical) = self
+            .read_counts
+            .iter()
+            .position(|&count| count >= self.read_disturb_limit)
+        {
+            let block = BlockIndex::new(logical as u32);
+            info!(
+                "Block {} read {} times, refreshing",
+                logical, self.read_counts[logical]
+            );
+            self.refresh_block(block)?;
+            return Ok(ScrubStep {
+                block,
+                refreshed: true,
+                pass_complete: false,
+            });
+        }
+
+        let block = BlockIndex::new(self.data.header.scrub_cursor);
+        let physical = self.logical_to_physical(block)?;
+        // Move the cursor on first, so a block that can't be read is not retried forever
+        self.data.header.scrub_cursor = (block.as_u32() + 1) % LBC as u32;
+        let pass_complete = self.data.header.scrub_cursor == 0;
+
+        let mut failing = false;
+        // Unmapped blocks hold no data to scrub
+        if let Some(physical) = physical {
+            trace!("Scrubbing block {} at {}", block, physical);
+            const { ::core::assert!(F::PAGE_SIZE <= PAGE_BUFFER_SIZE) };
+            let mut buffer = [0; PAGE_BUFFER_SIZE];
+            let page = &mut buffer[..F::PAGE_SIZE];
+            let mut offset = Self::block_to_byte_address(physical).as_u64();
+            for _ in 0..F::PAGES_PER_BLOCK {
+                match self.flash.read(offset, page) {
+                    Ok(()) => {}
+                    Err(e) => match e.kind() {
+                        NandFlashErrorKind::BlockFailing(failure) => {
+                            failing |= failure
+                                .corrected_bits
+                                .is_none_or(|bits| bits >= self.scrub_threshold);
+                        }
+                        _ => return Err(Error::Flash(e)),
+                    },
+                }
+                offset += F::PAGE_SIZE as u64;
             }
+        }
+
+        if failing {
+            info!("Block {} is failing, refreshing", block);
+            self.refresh_block(block)?;
         } else {
-            // Logs as to why this isnt a map (if magic bytes are present)
-            // Would be better to pass this on and have a force init function
-            if data.magic == MAGIC {
-                warn!("Invalid map at {:#X}", address);
-                warn!("Version: {} != {}", data.version, VERSION);
-                warn!("Block count: {} != {}", data.block_count, F::BLOCK_COUNT);
-                warn!(
-                    "Logical block count: {} != {}",
-                    data.logical_block_count, LBC
+            self.scrub_unsaved += 1;
+            if pass_complete || self.scrub_unsaved >= SCRUB_SAVE_INTERVAL {
+                self.update_map()?;
+            }
+        }
+        Ok(ScrubStep {
+            block,
+            refreshed: failing,
+            pass_complete,
+        })
+    }
+
+    /// Build the spare pool for a map with no pool.
+    ///
+    /// Blocks before `scanned_end` are bad unless they are in the map, blocks
+    /// from `scanned_end` are free unless their status shows they are bad.
+    fn build_pool(&mut self, scanned_end: u32) -> Result<(), Error<F>> {
+        let map_blocks = self.data.header.map_blocks;
+        for block in 0..F::BLOCK_COUNT as u32 {
+            let state = if map_blocks.contains(&block) {
+                BlockState::Reserved
+            } else if block < scanned_end
+                || !self
+                    .flash
+                    .block_status(BlockIndex::new(block))
+                    .map_err(Error::Flash)?
+                    .is_ok()
+            {
+                BlockState::Bad
+            } else {
+                BlockState::Free
+            };
+            self.data.set_block_state(BlockIndex::new(block), state);
+        }
+        for logical in 0..LBC {
+            if let Some(physical) = self.data.physical(logical) {
+                self.data.set_block_state(physical, BlockState::Mapped);
+            }
+        }
+        debug!("{} spare blocks available", self.data.free_blocks());
+        Ok(())
+    }
+
+    /// Check each map location in a block, keeping the newest valid map
+    fn scan_map_block(
+        &mut self,
+        block: BlockIndex,
+        newest: &mut Option<(FlashMapHeader, ByteAddress)>,
+    ) -> Result<(), Error<F>> {
+        let block_address = Self::block_to_byte_address(block);
+        for page in (0..=F::PAGES_PER_BLOCK - self.map_page_count as usize)
+            .step_by(self.map_page_count as usize)
+        {
+            let address = block_address
+                .checked_add((page * F::PAGE_SIZE) as u64)
+                .map_err(Error::from)?;
+            // Check if the map is valid
+            let Some((new_header, crc)) = Self::no_map_on_fail(self.get_map_data(address))? else {
+                continue;
+            };
+            // Only check the CRC if it is more recent than the current one
+            if newest.is_some_and(|(current, _)| new_header <= current) {
+                continue;
+            }
+            if Self::no_map_on_fail(self.check_map_crc(address, &new_header, crc).map(Some))?
+                .unwrap_or(false)
+            {
+                debug!(
+                    "Found valid map at {} with {} writes",
+                    address.as_u64(),
+                    new_header.write_count
                 );
+                *newest = Some((new_header, address));
             }
-            Ok(None)
         }
+        Ok(())
     }
 
-    /// Load the map array from flash. Must only be called when it is guaranteed to be valid.
+    /// Check each version 1 map location in a block, keeping the newest valid map
+    fn scan_v1_map_block(
+        &mut self,
+        block: BlockIndex,
+        newest: &mut Option<(FlashMapHeader, ByteAddress)>,
+    ) -> Result<(), Error<F>> {
+        let v1_page_count = v1::size(LBC).div_ceil(F::PAGE_SIZE);
+        if v1_page_count > F::PAGES_PER_BLOCK {
+            return Ok(());
+        }
+        let block_address = Self::block_to_byte_address(block);
+        for page in (0..=F::PAGES_PER_BLOCK - v1_page_count).step_by(v1_page_count) {
+            let address = block_address
+                .checked_add((page * F::PAGE_SIZE) as u64)
+                .map_err(Error::from)?;
+            if let Some(new_header) = Self::no_map_on_fail(self.get_v1_map_data(address))? {
+                if newest.is_none_or(|(current, _)| new_header > current) {
+                    *newest = Some((new_header, address));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Result of reading a possible map while scanning, a location that has failed to
+    /// read holds no map
+    fn no_map_on_fail<T>(result: Result<Option<T>, F::Error>) -> Result<Option<T>, Error<F>> {
+        match result {
+            Err(e) if matches!(e.kind(), NandFlashErrorKind::BlockFail(_)) => {
+                warn!("Failed to read possible map, skipping");
+                Ok(None)
+            }
+            result => result.map_err(Error::Flash),
+        }
+    }
+
+    /// Read part of a map from flash. The data of an ECC corrected read is still valid.
+    ///
+    /// Takes the flash rather than self so the map can be read into place.
+    fn read_map_bytes(flash: &mut F, offset: u64, bytes: &mut [u8]) -> Result<(), F::Error> {
+        match flash.read(offset, bytes) {
+            Err(e) if matches!(e.kind(), NandFlashErrorKind::BlockFailing(_)) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Try to load a map header from the given address.
+    ///
+    /// Returns the header and its CRC, which must be checked with [Self::check_map_crc].
+    fn get_map_data(
+        &mut self,
+        address: ByteAddress,
+    ) -> Result<Option<(FlashMapHeader, u32)>, F::Error> {
+        let mut bytes = [0; HEADER_SIZE];
+        Self::read_map_bytes(&mut self.flash, address.into(), &mut bytes)?;
+        let Some((data, crc)) = FlashMapHeader::decode(&bytes) else {
+            if bytes[0..4] == MAGIC {
+                debug!("Map at {:#X} is not version {}", address.as_u64(), VERSION);
+            }
+            return Ok(None);
+        };
+        // Check it matches what is expected
+        if !self.data.header.matches(&data) {
+            // Logs as to why this isnt a map
+            // Would be better to pass this on and have a force init function
+            warn!("Invalid map at {:#X}", address.as_u64());
+            warn!("Block count: {} != {}", data.block_count, F::BLOCK_COUNT);
+            warn!(
+                "Logical block count: {} != {}",
+                data.logical_block_count, LBC
+            );
+            return Ok(None);
+        }
+        Ok(Some((data, crc)))
+    }
+
+    /// Check the CRC of the header and map array at the given address
+    fn check_map_crc(
+        &mut self,
+        address: ByteAddress,
+        header: &FlashMapHeader,
+        crc: u32,
+    ) -> Result<bool, F::Error> {
+        let mut digest = header.digest();
+        let mut buffer = [0; 64];
+        let mut offset = address.as_u64() + HEADER_SIZE as u64;
+        let end = address.as_u64() + FlashMapData::<LBC>::SIZE as u64;
+        while offset < end {
+            let length = buffer.len().min((end - offset) as usize);
+            Self::read_map_bytes(&mut self.flash, offset, &mut buffer[..length])?;
+            digest.update(&buffer[..length]);
+            offset += length as u64;
+        }
+        if digest.finalize() != crc {
+            warn!("CRC mismatch for map at {:#X}", address.as_u64());
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Try to load a version 1 map header from the given address
+    fn get_v1_map_data(
+        &mut self,
+        address: ByteAddress,
+    ) -> Result<Option<FlashMapHeader>, F::Error> {
+        let mut bytes = [0; v1::HEADER_SIZE];
+        Self::read_map_bytes(&mut self.flash, address.into(), &mut bytes)?;
+        let Some(data) = v1::decode(&bytes) else {
+            return Ok(None);
+        };
+        if !self.data.header.matches(&data) {
+            warn!("Invalid version 1 map at {:#X}", address.as_u64());
+            return Ok(None);
+        }
+        // check that terminating magic bytes are present
+        let mut term = [0; 4];
+        let term_location = v1::size(LBC) - MAGIC.len();
+        Self::read_map_bytes(
+            &mut self.flash,
+            (address + term_location as u32).as_u64(),
+            &mut term,
+        )?;
+        if term != MAGIC {
+            trace!("Missing terminator at {:#X}", address.as_u64());
+            return Ok(None);
+        }
+        trace!("Found valid version 1 map at {:#X}", address.as_u64());
+        Ok(Some(data))
+    }
+
+    /// Load the map array and spare pool from flash. Must only be called when it is guaranteed to be valid.
     ///
     /// Map must have been loaded with [Self::get_map_data].
     fn load_map_array(&mut self) -> Result<(), Error<F>> {
         let map_address = self.map_address();
-        debug!("Loading map array from {:#X}", map_address);
-        let slice = unsafe {
-            core::slice::from_raw_parts_mut(
-                &mut self.data.map as *mut [BlockIndex; LBC] as *mut u8,
-                core::mem::size_of::<[u16; LBC]>(),
-            )
-        };
+        debug!("Loading map array from {:#X}", map_address.as_u64());
+        Self::read_map_bytes(
+            &mut self.flash,
+            map_address.as_u64(),
+            self.data.map.as_flattened_mut(),
+        )
+        .map_err(Error::Flash)?;
+        let pool_address = self.pool_address();
+        Self::read_map_bytes(
+            &mut self.flash,
+            pool_address.as_u64(),
+            self.data.pool.as_flattened_mut(),
+        )
+        .map_err(Error::Flash)?;
+        Ok(())
+    }
+
+    /// Load a version 1 map array from flash.
+    ///
+    /// Map must have been loaded with [Self::get_v1_map_data].
+    fn load_v1_map_array(&mut self) -> Result<(), Error<F>> {
+        let map_address = self.data_address + v1::HEADER_SIZE as u32;
+        debug!(
+            "Loading version 1 map array from {:#X}",
+            map_address.as_u64()
+        );
         self.flash
-            .read(map_address.as_u32(), slice)
-            .map_err(|e| Error::Flash(e))?;
+            .read(
+                map_address.as_u64(),
+                &mut self.data.map.as_flattened_mut()[..2 * LBC],
+            )
+            .map_err(Error::Flash)?;
+        self.data.expand_v1_map();
         Ok(())
     }
 
@@ -358,86 +858,196 @@ where
     fn update_map(&mut self) -> Result<(), Error<F>> {
         // Increment the write count
         self.data.header.write_count += 1;
+        // The scrub cursor is saved with the map
+        self.scrub_unsaved = 0;
         // Check if we need to write to a new block
         let current_block = Self::byte_to_block_index(self.data_address);
-        self.data_address += self.map_page_count * F::PAGE_SIZE as u32;
-        let mut new_block = Self::byte_to_block_index(self.data_address);
-        if new_block != current_block {
-            // Get the other block for map
-            new_block = if self.data.header.map_blocks[0] == current_block {
-                self.data.header.map_blocks[1]
-            } else {
-                self.data.header.map_blocks[0]
+        let map_size = self.map_page_count * F::PAGE_SIZE as u32;
+        self.data_address += map_size;
+        let new_block = Self::byte_to_block_index(self.data_address);
+        if new_block != current_block
+            || self.data_address.block_offset(F::ERASE_SIZE as u32) + map_size
+                > F::ERASE_SIZE as u32
+        {
+            self.data_address = Self::block_to_byte_address(current_block);
+            self.switch_map_block()?;
+        }
+
+        // Write the map to flash
+        self.commit_map()
+    }
+
+    /// Write the map to flash, moving it to a replacement block if the write fails
+    fn commit_map(&mut self) -> Result<(), Error<F>> {
+        while !self.write_map()? {
+            let failed = Self::byte_to_block_index(self.data_address);
+            warn!("Failed to write map to block {}", failed);
+            let new_block = match self.relocate_map_block(failed, BadCause::Program) {
+                Err(Error::NotEnoughValidBlocks) => return Err(Error::NoSuperBlocks),
+                result => result?,
             };
-            // Erase the block
-            // if it fails, keep using the current block
+            self.data_address = Self::block_to_byte_address(new_block);
+        }
+        Ok(())
+    }
+
+    /// Replace a failed map block with an erased block from the map region.
+    ///
+    /// The failed block is marked bad and the replacement recorded in the header,
+    /// so the map can be found from the remaining map block.
+    fn relocate_map_block(
+        &mut self,
+        failed: BlockIndex,
+        cause: BadCause,
+    ) -> Result<BlockIndex, Error<F>> {
+        self.retire(failed, cause);
+        let new_block = self.next_map_block()?;
+        for block in self.data.header.map_blocks.iter_mut() {
+            if *block == failed.as_u32() {
+                *block = new_block.as_u32();
+            }
+        }
+        info!("Relocated map block {} to {}", failed, new_block);
+        Ok(new_block)
+    }
+
+    /// Find and erase an unused block in the map region
+    fn next_map_block(&mut self) -> Result<BlockIndex, Error<F>> {
+        for block in 0..MAP_REGION_BLOCKS.min(F::BLOCK_COUNT as u32) {
+            let block = BlockIndex::new(block);
+            if self.data.block_state(block) != BlockState::Reserved
+                || self.data.header.map_blocks.contains(&block.as_u32())
+            {
+                continue;
+            }
+            if !self
+                .flash
+                .block_status(block)
+                .map_err(Error::Flash)?
+                .is_ok()
+            {
+                self.retire(block, BadCause::Other);
+            } else if !self.checked_erase_block(block)? {
+                self.retire(block, BadCause::Erase);
+            } else {
+                return Ok(block);
+            }
+        }
+        error!("No blocks remaining in the map region");
+        Err(Error::NotEnoughValidBlocks)
+    }
+
+    /// Erase the other map block and move the map address to its start.
+    ///
+    /// If the other block fails to erase, it is replaced from the spare pool. If there are no
+    /// spare blocks, the current block is erased and reused.
+    fn switch_map_block(&mut self) -> Result<(), Error<F>> {
+        let current_block = Self::byte_to_block_index(self.data_address);
+        // Get the other block for map
+        let map_blocks = self.data.header.map_blocks.map(BlockIndex::new);
+        let mut new_block = if map_blocks[0] == current_block {
+            map_blocks[1]
+        } else {
+            map_blocks[0]
+        };
+        // Erase the block
+        // if it fails, relocate it or keep using the current block
+        if !self.checked_erase_block(new_block)? {
+            warn!("Failed to erase map block {}", new_block);
+            match self.relocate_map_block(new_block, BadCause::Erase) {
+                Ok(block) => {
+                    self.data_address = Self::block_to_byte_address(block);
+                    return Ok(());
+                }
+                Err(Error::NotEnoughValidBlocks) => {}
+                Err(e) => return Err(e),
+            }
+            warn!("No spare blocks, only 1 superblock available");
+            new_block = current_block;
+            // if cannot erase other block, critical error
             if !self.checked_erase_block(new_block)? {
-                warn!(
-                    "Failed to erase block {}, only 1 superblock available",
+                error!(
+                    "Failed to erase block {}, no superblocks available",
                     new_block
                 );
-                let _ = self.flash.mark_block_bad(new_block);
-                new_block = current_block;
-                // if cannot erase other block, critical error
-                if !self.checked_erase_block(new_block)? {
-                    error!(
-                        "Failed to erase block {}, no superblocks available",
-                        new_block
-                    );
-                    let _ = self.flash.mark_block_bad(new_block);
-                    return Err(Error::NoSuperBlocks);
-                }
+                self.retire(new_block, BadCause::Erase);
+                return Err(Error::NoSuperBlocks);
             }
-            // Update the address
-            self.data_address = Self::block_to_byte_address(new_block);
         }
-
-        // Write the map to flash
-        self.write_map()?;
+        // Update the address
+        self.data_address = Self::block_to_byte_address(new_block);
         Ok(())
     }
 
     /// Write the map to flash.
     ///
-    /// Includes the map data, map array and terminator.
-    fn write_map(&mut self) -> Result<(), Error<F>> {
+    /// Includes the header, with the CRC, the map array and the spare pool.
+    ///
+    /// Returns false if the map block is failing.
+    fn write_map(&mut self) -> Result<bool, Error<F>> {
         trace!("Writing map to {}", self.data_address);
-        // Cast the data structure to a byte array
-        let slice = unsafe {
-            core::slice::from_raw_parts_mut(
-                &mut self.data as *mut FlashMapData<LBC> as *mut u8,
-                core::mem::size_of::<FlashMapData<LBC>>(),
-            )
-        };
-        // Write the data to flash
-        self.flash
-            .write(self.data_address.as_u32(), slice)
-            .map_err(|e| Error::Flash(e))?;
-        Ok(())
+        const { ::core::assert!(F::PAGE_SIZE <= PAGE_BUFFER_SIZE) };
+        let header = self.data.header.encode(self.data.crc());
+        let mut image = header
+            .iter()
+            .chain(self.data.map.as_flattened())
+            .chain(self.data.pool.as_flattened())
+            .copied();
+        // Program each page once, in order, with the tail of the last page left erased.
+        // Partial programs of a page would rewrite an on-die ECC sector, and fail on
+        // flash with a WRITE_SIZE of a whole page.
+        let mut buffer = [0xFF; PAGE_BUFFER_SIZE];
+        let page = &mut buffer[..F::PAGE_SIZE];
+        let mut address = self.data_address;
+        for _ in 0..FlashMapData::<LBC>::SIZE.div_ceil(F::PAGE_SIZE) {
+            page.fill(0xFF);
+            for (byte, value) in page.iter_mut().zip(&mut image) {
+                *byte = value;
+            }
+            if !Self::checked_write_slice(&mut self.flash, address, page)? {
+                return Ok(false);
+            }
+            address += F::PAGE_SIZE as u32;
+        }
+        Ok(true)
     }
 
-    /// Finds the next usable block that is spare.
+    /// Allocates the next free block from the spare pool.
     ///
-    /// This will erase the block and return the block number, updating the final block in the header.
-    /// If no blocks are available, it will return an error.
+    /// The search starts after the last block allocated, so wear is spread over the spare blocks.
+    /// This will erase the block and return the block number, marking it as mapped in the pool.
+    /// Blocks that fail are marked bad. If no blocks are available, it will return an error.
     fn next_spare_block(&mut self) -> Result<BlockIndex, Error<F>> {
         loop {
-            self.data.header.final_block += 1;
-            if self.data.header.final_block.as_u16() >= self.data.header.block_count {
-                // No more blocks available
+            let Some(block) = self.data.next_free_block(self.data.header.final_block) else {
+                error!("No spare blocks remaining");
                 return Err(Error::NotEnoughValidBlocks);
-            }
+            };
+            self.data.header.final_block = block.as_u32();
             // Check if the block is good
-            if self
+            if !self
                 .flash
-                .block_status(self.data.header.final_block)
-                .map_err(|e| Error::Flash(e))?
+                .block_status(block)
+                .map_err(Error::Flash)?
                 .is_ok()
-                && self.flash.erase_block(self.data.header.final_block).is_ok()
             {
-                return Ok(self.data.header.final_block);
+                self.retire(block, BadCause::Other);
+            } else if !self.checked_erase_block(block)? {
+                // Only a failed erase retires the block, other errors leave it free
+                self.retire(block, BadCause::Erase);
+            } else {
+                self.data.set_block_state(block, BlockState::Mapped);
+                break;
+            }
+        }
+        let remaining = self.data.free_blocks();
+        if let Some((threshold, hook)) = self.low_spares {
+            if remaining <= threshold {
+                warn!("Only {} spare blocks remaining", remaining);
+                hook(remaining);
             }
         }
+        Ok(BlockIndex::new(self.data.header.final_block))
     }
 
     /// Erase a physical block, checking if the erase fails and it needs replacing.
@@ -466,7 +1076,7 @@ where
         offset: ByteAddress,
         bytes: &mut [u8],
     ) -> Result<bool, Error<F>> {
-        match self.flash.read(offset.as_u32(), bytes) {
+        match self.flash.read(offset.as_u64(), bytes) {
             Ok(_) => Ok(true),
             Err(e) => match e.kind() {
                 embedded_nand::NandFlashErrorKind::BlockFailing(_) => Ok(false),
@@ -480,8 +1090,14 @@ where
     /// WARNING: Does not move data if failing, up to caller to handle this.
     ///
     /// Both BlockFailing and BlockFail are considered recoverable errors.
-    fn checked_write_slice(&mut self, offset: ByteAddress, bytes: &[u8]) -> Result<bool, Error<F>> {
-        match self.flash.write(offset.as_u32(), bytes) {
+    ///
+    /// Takes the flash rather than self so slices of the map can be written.
+    fn checked_write_slice(
+        flash: &mut F,
+        offset: ByteAddress,
+        bytes: &[u8],
+    ) -> Result<bool, Error<F>> {
+        match flash.write(offset.as_u64(), bytes) {
             Ok(_) => Ok(true),
             Err(e) => match e.kind() {
                 embedded_nand::NandFlashErrorKind::BlockFailing(_) => Ok(false),
@@ -491,13 +1107,59 @@ where
         }
     }
 
+    /// Replace a physical block with a spare block using the hardware block swap.
+    ///
+    /// Copies over the block up to length before swapping. The spare is marked bad in the
+    /// pool as it is now only reachable through the failed block.
+    ///
+    /// Returns false if block swap is not enabled or the device can't swap, in which case
+    /// the block must be remapped in the map.
+    fn hardware_swap(&mut self, physical_block: BlockIndex, length: u64) -> Result<bool, Error<F>> {
+        let Some(swap) = self.block_swap else {
+            return Ok(false);
+        };
+        let spare = self.next_spare_block()?;
+        if length > 0 {
+            self.flash
+                .copy(
+                    Self::block_to_byte_address(physical_block).as_u64(),
+                    Self::block_to_byte_address(spare).as_u64(),
+                    length,
+                )
+                .map_err(Error::Flash)?;
+        }
+        if swap(&mut self.flash, physical_block, spare).map_err(Error::Flash)? {
+            info!("Swapped block {} for {} in hardware", physical_block, spare);
+            self.data.set_block_state(spare, BlockState::Bad);
+            Ok(true)
+        } else {
+            warn!("Hardware block swap unavailable, remapping in the map");
+            self.block_swap = None;
+            self.data.set_block_state(spare, BlockState::Free);
+            Ok(false)
+        }
+    }
+
     /// Move the physical block of a logical block to a new location.
     ///
     /// Copies over the block up to length, updates the map, marks the old block as bad
     /// and erases it.
-    fn remap_block(&mut self, logical_block: BlockIndex, length: u32) -> Result<(), Error<F>> {
-        // Get the physical block
-        let physical_block = self.logical_to_physical(logical_block)?;
+    fn remap_block(
+        &mut self,
+        logical_block: BlockIndex,
+        length: u64,
+        cause: BadCause,
+    ) -> Result<(), Error<F>> {
+        // Get the physical block, an unmapped block has nothing to move
+        let Some(physical_block) = self.logical_to_physical(logical_block)? else {
+            return Ok(());
+        };
+        self.remaps = self.remaps.saturating_add(1);
+        if self.hardware_swap(physical_block, length)? {
+            self.data.header.bad_causes.record(cause);
+            self.read_counts[logical_block.as_u32() as usize] = 0;
+            return self.update_map();
+        }
 
         // Get the next spare block
         let next_block = self.next_spare_block()?;
@@ -510,35 +1172,94 @@ where
             .copy(
                 physical_block
                     .as_byte_address(F::ERASE_SIZE as u32)
-                    .as_u32(),
-                next_block.as_byte_address(F::ERASE_SIZE as u32).as_u32(),
+                    .as_u64(),
+                next_block.as_byte_address(F::ERASE_SIZE as u32).as_u64(),
                 length,
             )
-            .map_err(|e| Error::Flash(e))?;
-        // Mark the old block as bad (ignore errors)
-        let _ = self.flash.mark_block_bad(physical_block);
+            .map_err(Error::Flash)?;
+        // Mark the old block as bad
+        self.retire(physical_block, cause);
+        self.read_counts[logical_block.as_u32() as usize] = 0;
         // Update the map
-        self.data.map[logical_block.as_u16() as usize] = next_block;
+        self.data
+            .set_physical(logical_block.as_u32() as usize, next_block);
         // Write the map to flash
         self.update_map()
     }
+    /// Replace the physical block of a logical block without copying its contents,
+    /// marking the old block bad
+    fn replace_block(
+        &mut self,
+        logical_block: BlockIndex,
+        cause: BadCause,
+    ) -> Result<(), Error<F>> {
+        let Some(physical_block) = self.logical_to_physical(logical_block)? else {
+            return Ok(());
+        };
+        self.remaps = self.remaps.saturating_add(1);
+        // The device may be able to replace the block itself
+        if self.hardware_swap(physical_block, 0)? {
+            self.data.header.bad_causes.record(cause);
+            return self.update_map();
+        }
+        self.retire(physical_block, cause);
+        // Find the next valid block
+        let next_block = self.next_spare_block()?;
+        // Update the mapping
+        self.data
+            .set_physical(logical_block.as_u32() as usize, next_block);
+        // write to flash
+        self.update_map()
+    }
+
+    /// Move a logical block that is still good to a fresh physical block.
+    ///
+    /// Copies the whole block and returns the old block to the spare pool, to be
+    /// erased when it is next allocated.
+    fn refresh_block(&mut self, logical_block: BlockIndex) -> Result<(), Error<F>> {
+        let Some(physical_block) = self.logical_to_physical(logical_block)? else {
+            return Ok(());
+        };
+        let next_block = self.next_spare_block()?;
+        debug!(
+            "Refreshing block {} from {} to {}",
+            logical_block, physical_block, next_block
+        );
+        self.flash
+            .copy(
+                Self::block_to_byte_address(physical_block).as_u64(),
+                Self::block_to_byte_address(next_block).as_u64(),
+                F::ERASE_SIZE as u64,
+            )
+            .map_err(Error::Flash)?;
+        self.data.set_block_state(physical_block, BlockState::Free);
+        self.data
+            .set_physical(logical_block.as_u32() as usize, next_block);
+        self.read_counts[logical_block.as_u32() as usize] = 0;
+        self.refreshes = self.refreshes.saturating_add(1);
+        self.update_map()
+    }
 
     /// Convert a logical offset and slice into a physical offset and range within block boundaries
+    ///
+    /// The physical offset is None if the logical block is unmapped.
     fn logical_to_physical_range(
         &self,
-        logical_offset: u32,
+        logical_offset: u64,
         bytes: &[u8],
         slice_offset: usize,
-    ) -> Result<(ByteAddress, Range<usize>), Error<F>> {
-        let logical_offset = ByteAddress::new(logical_offset + slice_offset as u32);
+    ) -> Result<(Option<ByteAddress>, Range<usize>), Error<F>> {
+        let logical_offset = ByteAddress::new(logical_offset).checked_add(slice_offset as u64)?;
         // Get the logical block
-        let logical_block = logical_offset.as_block_index(F::ERASE_SIZE as u32);
+        let logical_block =
+            BlockIndex::try_from_byte_address(logical_offset, F::ERASE_SIZE as u32)?;
         // Get the physical block
         let physical_block = self.logical_to_physical(logical_block)?;
         // Get the offset into the block
         let block_offset = logical_offset.block_offset(F::ERASE_SIZE as u32);
         // Get the physical byte address
-        let physical_offset = physical_block.as_byte_address(F::ERASE_SIZE as u32) + block_offset;
+        let physical_offset =
+            physical_block.map(|block| block.as_byte_address(F::ERASE_SIZE as u32) + block_offset);
         // Get the number of bytes to read
         let block_remaining = F::ERASE_SIZE as u32 - block_offset;
         // The number of bytes left to in slice
@@ -572,11 +1293,11 @@ where
     const READ_SIZE: usize = F::READ_SIZE;
     const PAGE_SIZE: usize = F::PAGE_SIZE;
     const PAGES_PER_BLOCK: usize = F::PAGES_PER_BLOCK;
-    const BLOCK_COUNT: usize = LBC as usize;
+    const BLOCK_COUNT: usize = LBC;
     const ERASE_SIZE: usize = F::ERASE_SIZE;
     const WRITE_SIZE: usize = F::WRITE_SIZE;
 
-    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+    fn read(&mut self, offset: u64, bytes: &mut [u8]) -> Result<(), Self::Error> {
         // Only required to not read over block boundaries (would invalidate the map)
         // Alignment is checked by the flash device
 
@@ -584,13 +1305,23 @@ where
         let mut read = 0;
         loop {
             let (physical_offset, range) = self.logical_to_physical_range(offset, bytes, read)?;
+            let logical_block =
+                BlockIndex::from_raw_byte_offset(offset + read as u64, F::ERASE_SIZE as u32);
             read += range.len();
+            // Unmapped blocks read as erased
+            let Some(physical_offset) = physical_offset else {
+                bytes[range].fill(0xFF);
+                if read >= bytes.len() {
+                    return Ok(());
+                }
+                continue;
+            };
+            // Track reads for read disturb
+            let count = &mut self.read_counts[logical_block.as_u32() as usize];
+            *count = count.saturating_add(1);
             if !self.checked_read_slice(physical_offset, &mut bytes[range])? {
                 // Block is failing but read was fine, remap the whole block
-                self.remap_block(
-                    BlockIndex::from_raw_byte_offset(offset, F::ERASE_SIZE as u32),
-                    Self::ERASE_SIZE as u32,
-                )?;
+                self.remap_block(logical_block, Self::ERASE_SIZE as u64, BadCause::Ecc)?;
             }
             if read >= bytes.len() {
                 return Ok(());
@@ -598,22 +1329,24 @@ where
         }
     }
 
-    fn capacity(&self) -> u32 {
-        // The capacity of the device is the number of blocks * block size
-        F::BLOCK_COUNT as u32 * Self::PAGES_PER_BLOCK as u32 * F::PAGE_SIZE as u32
+    fn capacity(&self) -> u64 {
+        // Only the logical blocks are addressable
+        LBC as u64 * Self::ERASE_SIZE as u64
     }
 
-    /// This should always return OK
+    /// Status of the physical block, which should always be good.
+    /// Unmapped blocks read as erased, so are reported as [embedded_nand::BlockStatus::Erased]
     fn block_status(
         &mut self,
         block: BlockIndex,
     ) -> Result<embedded_nand::BlockStatus, Self::Error> {
-        self.flash
-            .block_status(self.logical_to_physical(block)?)
-            .map_err(|e| Error::Flash(e))
+        match self.logical_to_physical(block)? {
+            Some(physical) => self.flash.block_status(physical).map_err(Error::Flash),
+            None => Ok(embedded_nand::BlockStatus::Erased),
+        }
     }
 
-    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+    fn erase(&mut self, from: u64, to: u64) -> Result<(), Self::Error> {
         // check alignment
         for (block, _) in self.block_iter_range(
             Self::byte_to_block_index(ByteAddress::new(from)),
@@ -624,7 +1357,7 @@ where
         Ok(())
     }
 
-    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+    fn write(&mut self, offset: u64, bytes: &[u8]) -> Result<(), Self::Error> {
         // Only required to not write over block boundaries (would invalidate the map)
         // Alignment is checked by the flash device
 
@@ -634,17 +1367,26 @@ where
             // Get the physical offset and range to write
             let (physical_offset, range) =
                 self.logical_to_physical_range(offset, bytes, written)?;
+            let Some(physical_offset) = physical_offset else {
+                // Give the unmapped block a physical block and try again
+                self.map_block(BlockIndex::from_raw_byte_offset(
+                    offset + written as u64,
+                    F::ERASE_SIZE as u32,
+                ))?;
+                continue;
+            };
 
             let write_length = range.len();
 
             // Try to write the slice to flash
-            if !self.checked_write_slice(physical_offset, &bytes[range])? {
+            if !Self::checked_write_slice(&mut self.flash, physical_offset, &bytes[range])? {
                 // Block is failing, write was not successful
                 // Remap the block and try again on new physical block
                 // Only remap up to just before this write
                 self.remap_block(
-                    BlockIndex::from_raw_byte_offset(offset, F::ERASE_SIZE as u32),
-                    physical_offset.block_offset(Self::ERASE_SIZE as u32),
+                    BlockIndex::from_raw_byte_offset(offset + written as u64, F::ERASE_SIZE as u32),
+                    physical_offset.block_offset(Self::ERASE_SIZE as u32) as u64,
+                    BadCause::Program,
                 )?;
                 // Continue allows a retry on the new block
                 continue;
@@ -661,134 +1403,464 @@ where
     ///
     /// This will find the next spare block and remap the logical block to it.
     /// WARNING: Does not move data, which is effectively lost.
+    /// Unmapped blocks have no physical block, so are left unmapped.
     fn mark_block_bad(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
-        let physical = self.logical_to_physical(block)?;
-        // Mark the block as bad (ignore error if it fails)
-        let _ = self.flash.mark_block_bad(physical);
-        // Find the next valid block
-        let next_block = self.next_spare_block()?;
-        // Update the mapping
-        self.data.map[block.as_u16() as usize] = next_block;
-        // write to flash
-        self.update_map()
+        self.replace_block(block, BadCause::Other)
     }
 
     /// Erases the physical block of the supplied logical block.
     ///
     /// If the erase fails with [embedded_nand::NandFlashErrorKind::BlockFail], it will mark the block as bad and remap it.
+    /// Unmapped blocks are already erased, so are not touched.
     fn erase_block(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
-        let block = self.logical_to_physical(block)?;
+        let Some(physical) = self.logical_to_physical(block)? else {
+            return Ok(());
+        };
         // Erase the block, checing for fail
-        if self.checked_erase_block(block)? {
+        if self.checked_erase_block(physical)? {
             Ok(())
         } else {
-            self.mark_block_bad(block)
+            self.replace_block(block, BadCause::Erase)
         }
     }
 
-    fn copy(&mut self, src_offset: u32, dest_offset: u32, length: u32) -> Result<(), Self::Error> {
+    fn copy(&mut self, src_offset: u64, dest_offset: u64, length: u64) -> Result<(), Self::Error> {
         // Check that everything is within a single block
         let src_block = Self::byte_to_block_index(ByteAddress::new(src_offset));
-        let final_block = Self::byte_to_block_index(ByteAddress::new(src_offset + length));
+        let final_block = BlockIndex::try_from_byte_address(
+            ByteAddress::new(src_offset).checked_add(length)?,
+            F::ERASE_SIZE as u32,
+        )?;
         if src_block != final_block {
             error!("Cannot copy slice over a block boundary");
             return Err(Error::NotAligned);
         }
+        // Copying from an unmapped block would only program erased bytes
+        let Some(src) = self.logical_to_physical_byte(ByteAddress::new(src_offset))? else {
+            return self
+                .logical_to_physical_byte(ByteAddress::new(dest_offset))
+                .map(|_| ());
+        };
+        let dest_offset = ByteAddress::new(dest_offset);
+        let dest_block = self.map_block(Self::byte_to_block_index(dest_offset))?;
+        let dest = Self::block_to_byte_address(dest_block)
+            .checked_add(Self::byte_in_block(dest_offset) as u64)?;
         // Pass copy operation to the flash device
         self.flash
-            .copy(
-                self.logical_to_physical_byte(ByteAddress::new(src_offset))?
-                    .as_u32(),
-                self.logical_to_physical_byte(ByteAddress::new(dest_offset))?
-                    .as_u32(),
-                length,
-            )
-            .map_err(|e| Error::Flash(e))
+            .copy(src.as_u64(), dest.as_u64(), length)
+            .map_err(Error::Flash)
     }
 }
 
-/// Data structure that is written to flash consiting of header, map array and terminator
-#[derive(Debug)]
-#[repr(C)]
-struct FlashMapData<const LBC: usize> {
-    /// Header
-    header: FlashMapHeader,
-    /// Map array
-    map: [BlockIndex; LBC],
-    /// Terminator
-    terminator: [u8; 4],
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU32, Ordering};
+    use embedded_nand::{NandFlash, test::VirtualNandFlash};
 
-impl<const LBC: usize> FlashMapData<LBC> {
-    /// Create a new instance of the data structure
-    fn new(block_count: u16, logical_block_count: u16) -> Self {
-        FlashMapData {
-            header: FlashMapHeader::new(block_count, logical_block_count),
-            map: [BlockIndex::new(0); LBC],
-            terminator: MAGIC,
+    const PAGE_SIZE: usize = 128;
+    const PAGES_PER_BLOCK: usize = 8;
+    const BLOCK_COUNT: usize = 64;
+    const LBC: usize = 32;
+    const BLOCK_SIZE: usize = PAGE_SIZE * PAGES_PER_BLOCK;
+
+    type Flash = VirtualNandFlash<PAGE_SIZE, PAGES_PER_BLOCK, BLOCK_COUNT>;
+
+    /// Data written through the map survives re-initialising from the same flash
+    #[test]
+    fn test_init_reload() {
+        let mut map = FlashMap::<_, LBC>::init(Flash::new()).unwrap();
+        let buffer = [0xA5; BLOCK_SIZE];
+        map.write((LBC as u64 - 1) * BLOCK_SIZE as u64, &buffer)
+            .unwrap();
+
+        let mut map = FlashMap::<_, LBC>::init(map.flash).unwrap();
+        let mut rbuffer = [0; BLOCK_SIZE];
+        map.read((LBC as u64 - 1) * BLOCK_SIZE as u64, &mut rbuffer)
+            .unwrap();
+        assert_eq!(buffer, rbuffer);
+    }
+
+    /// A spare block that fails to erase for a reason other than a block failure is
+    /// returned as an error and not retired
+    #[test]
+    fn test_spare_erase_error() {
+        let mut map = FlashMap::<_, LBC>::init(Flash::new()).unwrap();
+        let spares = map.spare_blocks_remaining();
+        let old = map.data.physical(0).unwrap();
+        map.flash.fail_next_erase();
+        assert!(matches!(
+            map.mark_block_bad(BlockIndex::new(0)),
+            Err(Error::Flash(_))
+        ));
+        assert_eq!(map.spare_blocks_remaining(), spares);
+        // Only the replaced block is marked bad
+        for block in (0..BLOCK_COUNT as u32).map(BlockIndex::new) {
+            let status = map.flash.block_status(block).unwrap();
+            assert_eq!(status.is_ok(), block != old);
         }
+        // The next attempt uses a spare
+        map.mark_block_bad(BlockIndex::new(0)).unwrap();
+        assert_eq!(map.spare_blocks_remaining(), spares - 1);
     }
-}
 
-/// Data structure  that contains the
-/// configuration of the mapping
-///
-/// When written to flash, it takes up an integer number of pages.
-///
-#[repr(C)]
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
-struct FlashMapHeader {
-    /// Magic bytes that idenitfy the structure
-    magic: [u8; 4],
-    /// Version of the structure
-    version: u16,
-    /// Number of blocks in the device
-    block_count: u16,
-    /// Number of logical blocks
-    logical_block_count: u16,
-    /// Next block to use
-    final_block: BlockIndex,
-    /// Number of times the map has been written
-    write_count: u32,
-    /// The blocks used for the map
-    map_blocks: [BlockIndex; 2],
-}
+    /// The NOR adapter covers exactly the logical blocks of the map
+    #[test]
+    fn test_nor_adapter() {
+        use embedded_nand::NorFlashAdapter;
+        use embedded_storage::nor_flash::{
+            NorFlash as _, NorFlashError as _, NorFlashErrorKind, ReadNorFlash as _,
+        };
+
+        let map = FlashMap::<_, LBC>::init(Flash::new()).unwrap();
+        let mut nor = NorFlashAdapter::new(map);
+        assert_eq!(nor.capacity(), LBC * BLOCK_SIZE);
 
-impl Ord for FlashMapHeader {
-    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
-        self.write_count.cmp(&other.write_count)
+        // The last logical block is usable
+        let last = ((LBC - 1) * BLOCK_SIZE) as u32;
+        nor.erase(last, last + BLOCK_SIZE as u32).unwrap();
+        nor.write(last, &[0x5A; PAGE_SIZE]).unwrap();
+        let mut buf = [0; PAGE_SIZE];
+        nor.read(last, &mut buf).unwrap();
+        assert_eq!(buf, [0x5A; PAGE_SIZE]);
+
+        // Nothing past it is advertised
+        let end = (LBC * BLOCK_SIZE) as u32;
+        assert!(matches!(
+            nor.erase(end, end + BLOCK_SIZE as u32).unwrap_err().kind(),
+            NorFlashErrorKind::OutOfBounds
+        ));
     }
-}
 
-impl PartialOrd for FlashMapHeader {
-    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
-        Some(self.cmp(other))
+    /// A map with a bad CRC is ignored in favour of the previous one
+    #[test]
+    fn test_map_crc() {
+        let mut map = FlashMap::<_, LBC>::init(Flash::new()).unwrap();
+        let original = map.data.physical(0).unwrap();
+        // Remap logical block 0, writing the map to the second slot
+        map.mark_block_bad(BlockIndex::new(0)).unwrap();
+        assert_ne!(map.data.physical(0).unwrap(), original);
+        assert_eq!(map.data_address.as_u64(), 2 * PAGE_SIZE as u64);
+
+        // Corrupt an entry of the second map
+        let mut flash = map.flash;
+        flash
+            .write(2 * PAGE_SIZE as u64 + HEADER_SIZE as u64 + 4, &[0; 4])
+            .unwrap();
+        let map = FlashMap::<_, LBC>::init(flash).unwrap();
+        assert_eq!(map.data.header.write_count, 1);
+        assert_eq!(map.data.physical(0).unwrap(), original);
     }
-}
 
-impl FlashMapHeader {
-    /// Create a new instance of the data structure
-    fn new(block_count: u16, logical_block_count: u16) -> Self {
-        FlashMapHeader {
-            magic: MAGIC,
-            version: VERSION,
-            block_count,
-            logical_block_count,
-            final_block: BlockIndex::new(0),
-            write_count: 0,
-            map_blocks: [BlockIndex::new(0); 2],
+    /// Each page of the map is programmed once, with the header, map and pool in one write
+    #[test]
+    fn test_map_page_programs() {
+        let map = FlashMap::<_, LBC>::init(Flash::new()).unwrap();
+        let pages = FlashMapData::<LBC>::SIZE.div_ceil(PAGE_SIZE) as u32;
+        assert!(pages > 1);
+        for page in 0..pages {
+            assert_eq!(map.flash.write_count(PageIndex::new(page)), 1);
         }
+        assert_eq!(map.flash.write_count(PageIndex::new(pages)), 0);
     }
 
-    /// Compare 2 instances and return if they are valid
-    /// Magic, version, block count and logical block count must be the same
-    fn is_valid(&self, other: &Self) -> bool {
-        self.magic == other.magic
-            && self.version == other.version
-            && self.block_count == other.block_count
-            && self.logical_block_count == other.logical_block_count
+    /// Software ECC only accepts whole page writes, which is how the map is written
+    #[test]
+    fn test_soft_ecc() {
+        type EccFlash =
+            embedded_nand::SoftEcc<VirtualNandFlash<256, 4, 48>, embedded_nand::Hamming>;
+        let flash = EccFlash::new(VirtualNandFlash::new()).unwrap();
+        let mut map = FlashMap::<_, LBC>::init(flash).unwrap();
+        let buffer = [0x5A; 256];
+        map.write(3 * 256 * 4, &buffer).unwrap();
+
+        let mut map = FlashMap::<_, LBC>::init(map.flash).unwrap();
+        let mut rbuffer = [0; 256];
+        map.read(3 * 256 * 4, &mut rbuffer).unwrap();
+        assert_eq!(buffer, rbuffer);
     }
-}
 
-#[cfg(test)]
-mod tests {}
+    /// A map in the version 1 format is loaded and rewritten in the current format
+    #[test]
+    fn test_v1_migration() {
+        let mut flash = Flash::new();
+        // Header, map of logical block i to physical block i + 2 and terminator
+        let mut v1_map = [0; v1::size(LBC)];
+        v1_map[0..4].copy_from_slice(b"FMAP");
+        v1_map[4..6].copy_from_slice(&1u16.to_le_bytes());
+        v1_map[6..8].copy_from_slice(&(BLOCK_COUNT as u16).to_le_bytes());
+        v1_map[8..10].copy_from_slice(&(LBC as u16).to_le_bytes());
+        v1_map[10..12].copy_from_slice(&(LBC as u16 + 1).to_le_bytes());
+        v1_map[12..16].copy_from_slice(&5u32.to_le_bytes());
+        v1_map[16..20].copy_from_slice(&[0, 0, 1, 0]);
+        for i in 0..LBC {
+            v1_map[20 + 2 * i..22 + 2 * i].copy_from_slice(&(i as u16 + 2).to_le_bytes());
+        }
+        v1_map[20 + 2 * LBC..].copy_from_slice(b"FMAP");
+        flash.write(0, &v1_map).unwrap();
+        flash
+            .write((LBC as u64 + 1) * BLOCK_SIZE as u64, &[7; 4])
+            .unwrap();
+
+        let mut map = FlashMap::<_, LBC>::init(flash).unwrap();
+        for i in 0..LBC {
+            assert_eq!(map.data.physical(i).unwrap(), BlockIndex::new(i as u32 + 2));
+        }
+        assert_eq!(map.data.header.final_block, LBC as u32 + 1);
+        // Written to the start of the other map block
+        assert_eq!(map.data_address.as_u64(), BLOCK_SIZE as u64);
+        let mut buffer = [0; 4];
+        map.read((LBC as u64 - 1) * BLOCK_SIZE as u64, &mut buffer)
+            .unwrap();
+        assert_eq!(buffer, [7; 4]);
+
+        // Reloads in the current format
+        let map = FlashMap::<_, LBC>::init(map.flash).unwrap();
+        assert_eq!(map.data.header.write_count, 6);
+        assert_eq!(map.data_address.as_u64(), BLOCK_SIZE as u64);
+        assert_eq!(
+            map.data.physical(LBC - 1).unwrap(),
+            BlockIndex::new(LBC as u32 + 1)
+        );
+    }
+
+    /// Spare blocks are allocated from the pool, which is persisted, until exhausted
+    #[test]
+    fn test_spare_pool() {
+        static LOW_SPARES: AtomicU32 = AtomicU32::new(u32::MAX);
+        let mut map = FlashMap::<_, LBC>::init(Flash::new()).unwrap();
+        let spares = (BLOCK_COUNT - LBC) as u32 - MAP_REGION_BLOCKS;
+        assert_eq!(map.spare_blocks_remaining(), spares);
+        map.set_low_spares_hook(2, |remaining| {
+            LOW_SPARES.store(remaining, Ordering::Relaxed)
+        });
+
+        let original = map.data.physical(0).unwrap();
+        map.mark_block_bad(BlockIndex::new(0)).unwrap();
+        assert_eq!(map.data.block_state(original), BlockState::Bad);
+        assert_eq!(map.spare_blocks_remaining(), spares - 1);
+        assert_eq!(LOW_SPARES.load(Ordering::Relaxed), u32::MAX);
+
+        let mut map = FlashMap::<_, LBC>::init(map.flash).unwrap();
+        assert_eq!(map.spare_blocks_remaining(), spares - 1);
+        map.set_low_spares_hook(2, |remaining| {
+            LOW_SPARES.store(remaining, Ordering::Relaxed)
+        });
+        for _ in 1..spares {
+            map.mark_block_bad(BlockIndex::new(1)).unwrap();
+        }
+        assert_eq!(map.spare_blocks_remaining(), 0);
+        assert_eq!(LOW_SPARES.load(Ordering::Relaxed), 0);
+        assert!(matches!(
+            map.mark_block_bad(BlockIndex::new(1)),
+            Err(Error::NotEnoughValidBlocks)
+        ));
+    }
+
+    /// Failed map blocks are replaced from the map region and found again on init
+    #[test]
+    fn test_map_relocation() {
+        let mut map = FlashMap::<_, LBC>::init(Flash::new()).unwrap();
+        let spares = map.spare_blocks_remaining();
+        // Each map block holds 4 maps, so the 4th update moves to the other map block
+        map.flash.mark_block_bad(BlockIndex::new(1)).unwrap();
+        for _ in 0..4 {
+            map.mark_block_bad(BlockIndex::new(0)).unwrap();
+        }
+        let relocated = map.data.header.map_blocks[1];
+        assert_eq!(relocated, 2);
+        assert_eq!(
+            map.data_address.as_u64(),
+            relocated as u64 * BLOCK_SIZE as u64
+        );
+        assert_eq!(map.spare_blocks_remaining(), spares - 4);
+
+        // Losing both original map blocks
+        map.flash.mark_block_bad(BlockIndex::new(0)).unwrap();
+        for _ in 0..4 {
+            map.mark_block_bad(BlockIndex::new(0)).unwrap();
+        }
+        assert!(!map.data.header.map_blocks.contains(&0));
+        let physical = map.data.physical(0).unwrap();
+
+        let map = FlashMap::<_, LBC>::init(map.flash).unwrap();
+        assert_eq!(map.data.header.write_count, 9);
+        assert_eq!(map.data.physical(0).unwrap(), physical);
+        assert_eq!(map.spare_blocks_remaining(), spares - 8);
+    }
+
+    /// Only the map region is searched for a map
+    #[test]
+    fn test_map_region_search() {
+        let mut flash = Flash::new();
+        flash.mark_block_bad(BlockIndex::new(0)).unwrap();
+        flash.mark_block_bad(BlockIndex::new(1)).unwrap();
+        let map = FlashMap::<_, LBC>::init(flash).unwrap();
+        assert_eq!(map.data.header.map_blocks, [2, 3]);
+        assert_eq!(map.data.physical(0).unwrap().as_u32(), MAP_REGION_BLOCKS);
+        for page in
+            MAP_REGION_BLOCKS * PAGES_PER_BLOCK as u32..(BLOCK_COUNT * PAGES_PER_BLOCK) as u32
+        {
+            assert_eq!(map.flash.read_count(PageIndex::new(page)), 0);
+        }
+    }
+
+    /// Scrubbing refreshes failing and read disturbed blocks and saves its progress
+    #[test]
+    fn test_scrub() {
+        let mut map = FlashMap::<_, LBC>::init(Flash::new()).unwrap();
+        let spares = map.spare_blocks_remaining();
+        let failing = map.data.physical(3).unwrap();
+        map.flash.set_ecc_failing(failing);
+        for i in 0..3 {
+            let step = map.scrub_step().unwrap();
+            assert_eq!(step.block, BlockIndex::new(i));
+            assert!(!step.refreshed);
+        }
+        assert!(map.scrub_step().unwrap().refreshed);
+        assert_ne!(map.data.physical(3).unwrap(), failing);
+        assert_eq!(map.data.block_state(failing), BlockState::Free);
+        assert_eq!(map.spare_blocks_remaining(), spares);
+
+        // Cursor was saved when the refreshed block was written to the map
+        let mut map = FlashMap::<_, LBC>::init(map.flash).unwrap();
+        assert_eq!(map.data.header.scrub_cursor, 4);
+
+        map.set_read_disturb_limit(2);
+        let mut buffer = [0; 4];
+        map.read(5 * BLOCK_SIZE as u64, &mut buffer).unwrap();
+        map.read(5 * BLOCK_SIZE as u64, &mut buffer).unwrap();
+        let step = map.scrub_step().unwrap();
+        assert_eq!(step.block, BlockIndex::new(5));
+        assert!(step.refreshed);
+
+        let steps = core::iter::repeat_with(|| map.scrub_step().unwrap())
+            .position(|step| step.pass_complete)
+            .unwrap();
+        assert_eq!(steps, LBC - 5);
+    }
+
+    /// Scrubbing only refreshes blocks with at least the threshold of corrected bits, and
+    /// reads each page once
+    #[test]
+    fn test_scrub_threshold() {
+        let mut map = FlashMap::<_, LBC>::init(Flash::new()).unwrap();
+        map.set_scrub_threshold(4);
+        let corrected = map.data.physical(0).unwrap();
+        map.flash.set_ecc_corrected(corrected, 3);
+        let step = map.scrub_step().unwrap();
+        assert!(!step.refreshed);
+        let first_page = corrected.as_u32() * PAGES_PER_BLOCK as u32;
+        for page in first_page..first_page + PAGES_PER_BLOCK as u32 {
+            assert_eq!(map.flash.read_count(PageIndex::new(page)), 1);
+        }
+
+        let corrected = map.data.physical(1).unwrap();
+        map.flash.set_ecc_corrected(corrected, 4);
+        assert!(map.scrub_step().unwrap().refreshed);
+        assert_ne!(map.data.physical(1).unwrap(), corrected);
+    }
+
+    /// Failed blocks are swapped in hardware until the lookup table is full
+    #[test]
+    fn test_block_swap() {
+        static SWAPS: AtomicU32 = AtomicU32::new(0);
+        let mut map = FlashMap::<_, LBC>::init(Flash::new()).unwrap();
+        let spares = map.spare_blocks_remaining();
+        // A lookup table with room for 2 entries
+        map.block_swap = Some(|_, _, _| Ok(SWAPS.fetch_add(1, Ordering::Relaxed) < 2));
+        let physical = map.data.physical(0).unwrap();
+        map.mark_block_bad(BlockIndex::new(0)).unwrap();
+        map.mark_block_bad(BlockIndex::new(0)).unwrap();
+        assert_eq!(map.data.physical(0).unwrap(), physical);
+        assert_eq!(map.data.block_state(physical), BlockState::Mapped);
+        assert_eq!(map.spare_blocks_remaining(), spares - 2);
+
+        // Full, so remapped in the map and the swap is not tried again
+        map.mark_block_bad(BlockIndex::new(0)).unwrap();
+        assert_ne!(map.data.physical(0).unwrap(), physical);
+        assert_eq!(map.data.block_state(physical), BlockState::Bad);
+        assert_eq!(map.spare_blocks_remaining(), spares - 3);
+        assert!(map.block_swap.is_none());
+        assert_eq!(SWAPS.load(Ordering::Relaxed), 3);
+    }
+
+    /// Bad blocks are counted by cause and erase counts are read from the flash
+    #[test]
+    fn test_health() {
+        let mut map = FlashMap::<_, LBC>::init(Flash::new()).unwrap();
+        let before = map.health();
+        assert_eq!(before.erase_counts, None);
+        assert_eq!(before.spare_blocks, map.spare_blocks_remaining());
+
+        map.enable_erase_counts();
+        map.mark_block_bad(BlockIndex::new(1)).unwrap();
+        let health = map.health();
+        assert_eq!(health.bad_causes.other, 1);
+        // Causes are saved with the map
+        let map = FlashMap::<_, LBC>::init(map.flash).unwrap();
+        assert_eq!(map.health().bad_causes, health.bad_causes);
+        assert_eq!(health.remaps, 1);
+        assert_eq!(health.bad_blocks, before.bad_blocks + 1);
+        assert_eq!(health.spare_blocks, before.spare_blocks - 1);
+        assert!(health.map_writes > before.map_writes);
+        let counts = health.erase_counts.unwrap();
+        assert!(counts.max >= 1 && counts.min <= counts.max);
+    }
+
+    /// Trimmed blocks read as erased without touching flash and are remapped when written
+    #[test]
+    fn test_trim() {
+        let mut map = FlashMap::<_, LBC>::init(Flash::new()).unwrap();
+        let spares = map.spare_blocks_remaining();
+        map.write(3 * BLOCK_SIZE as u64, &[0x5A; 16]).unwrap();
+        let physical = map.data.physical(3).unwrap();
+        map.trim(BlockIndex::new(2)..BlockIndex::new(4)).unwrap();
+        assert_eq!(map.spare_blocks_remaining(), spares + 2);
+        assert_eq!(map.data.block_state(physical), BlockState::Free);
+
+        let mut map = FlashMap::<_, LBC>::init(map.flash).unwrap();
+        assert!(map.data.physical(3).is_none());
+        let page = PageIndex::new(physical.as_u32() * PAGES_PER_BLOCK as u32);
+        let reads = map.flash.read_count(page);
+        let mut buffer = [0; 16];
+        map.read(3 * BLOCK_SIZE as u64, &mut buffer).unwrap();
+        assert_eq!(buffer, [0xFF; 16]);
+        assert_eq!(map.flash.read_count(page), reads);
+        assert_eq!(
+            map.block_status(BlockIndex::new(3)).unwrap(),
+            embedded_nand::BlockStatus::Erased
+        );
+        let erases = map.flash.erase_count(physical);
+        map.erase_block(BlockIndex::new(3)).unwrap();
+        assert_eq!(map.flash.erase_count(physical), erases);
+
+        map.write(3 * BLOCK_SIZE as u64, &[0x5A; 16]).unwrap();
+        map.read(3 * BLOCK_SIZE as u64, &mut buffer).unwrap();
+        assert_eq!(buffer, [0x5A; 16]);
+        assert_eq!(
+            map.block_status(BlockIndex::new(3)).unwrap(),
+            embedded_nand::BlockStatus::Ok
+        );
+        assert_eq!(map.spare_blocks_remaining(), spares + 1);
+        assert!(matches!(
+            map.trim(BlockIndex::new(0)..BlockIndex::new(LBC as u32 + 1)),
+            Err(Error::OutOfBounds)
+        ));
+    }
+
+    /// Out of range logical addresses are rejected rather than wrapping
+    #[test]
+    fn test_out_of_bounds() {
+        let mut map = FlashMap::<_, LBC>::init(Flash::new()).unwrap();
+        let mut rbuffer = [0; PAGE_SIZE];
+        assert!(matches!(
+            map.read(LBC as u64 * BLOCK_SIZE as u64, &mut rbuffer),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            map.read(u64::MAX - 1, &mut rbuffer),
+            Err(Error::OutOfBounds)
+        ));
+    }
+}
@@ -0,0 +1,612 @@
+//! Page mapped flash translation layer.
+//!
+//! Every logical page write goes to the next free physical page, so rewriting a page
+//! never needs an erase. The logical page and a sequence number are stored in the spare
+//! area of each page, and the map is rebuilt from them when loaded:
+//!
+//! | Offset | Size | Field                               |
+//! |--------|------|-------------------------------------|
+//! | 0      | 4    | Logical page                        |
+//! | 4      | 4    | Sequence number                     |
+//! | 8      | 4    | Check, `!(PMAP ^ page ^ sequence)`  |
+//!
+//! All values are little endian. The newest copy of a logical page has the highest
+//! sequence number, compared allowing for the counter wrapping. Older copies are stale
+//! and reclaimed by garbage collection.
+
+use core::fmt::Debug;
+
+use embedded_nand::{
+    BlockDevice, BlockIndex, ErrorType, NandFlashError, NandFlashErrorKind, NandFlashOob,
+    PageIndex, SECTOR_SIZE,
+};
+
+use crate::Error;
+
+/// Identifies a page written by a [PageMap] ("PMAP")
+const TAG_MAGIC: u32 = 0x504D_4150;
+/// Size of the tag in the spare area
+const TAG_SIZE: usize = 12;
+/// Map entry of a logical page that has never been written
+const UNWRITTEN: u32 = u32::MAX;
+/// Garbage collection runs when a block is needed and no more than this many are free,
+/// so there is always a block to move valid pages to.
+const GC_RESERVE_BLOCKS: usize = 1;
+
+/// True if sequence number `a` was written after `b`, allowing for the counter wrapping
+fn is_newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// State of a physical block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum PageBlock {
+    /// Erased when next allocated
+    Free,
+    /// Holds pages, valid or stale
+    Used,
+    /// Holds pages but is failing, so is collected first and then marked bad
+    Retired,
+    /// Never used
+    Bad,
+}
+
+/// A page mapped flash translation layer, exposed as 512 byte sectors.
+///
+/// N is the page size, LPC the number of logical pages and BC the number of blocks
+/// in the flash. The map takes 4 bytes of RAM per logical page.
+///
+/// Sector writes are merged into a page buffer, which is written out-of-place when a
+/// different page is written or on [BlockDevice::flush]. When a block is needed and
+/// only one is free, garbage collection greedily picks the block with the fewest valid
+/// pages, moves them, and erases it. Failing and bad blocks are retired.
+///
+/// There is no stored map, it is rebuilt from the spare area of every page when loaded,
+/// so an unclean shutdown loses at most the unflushed page.
+///
+/// The flash must provide at least 12 free spare bytes per page, and LPC must leave at
+/// least 2 blocks of spare pages for garbage collection.
+#[derive(Debug)]
+pub struct PageMap<F, const N: usize, const LPC: usize, const BC: usize> {
+    /// The flash device holding the pages
+    flash: F,
+    /// Physical page of each logical page
+    map: [u32; LPC],
+    /// Number of valid pages in each block
+    valid: [u16; BC],
+    /// State of each block
+    blocks: [PageBlock; BC],
+    /// Block being written and the next page in it
+    head: Option<(BlockIndex, u32)>,
+    /// Next block to try when allocating
+    next_free: u32,
+    /// Sequence number of the next page written
+    sequence: u32,
+    /// Running garbage collection, which must not start again
+    collecting: bool,
+    /// Logical page held in the buffer with unwritten changes
+    dirty: Option<u32>,
+    /// Page buffer for sector writes
+    buffer: [u8; N],
+}
+
+impl<F, const N: usize, const LPC: usize, const BC: usize> PageMap<F, N, LPC, BC>
+where
+    F: NandFlashOob + Debug,
+{
+    /// Sectors in a page
+    const SECTORS_PER_PAGE: u32 = (N / SECTOR_SIZE) as u32;
+    /// Pages in a block
+    const PPB: u32 = F::PAGES_PER_BLOCK as u32;
+
+    /// Load the page map, rebuilding it from the spare area of every page
+    pub fn init(flash: F) -> Result<Self, Error<F>> {
+        const {
+            ::core::assert!(N == F::PAGE_SIZE);
+            ::core::assert!(N % SECTOR_SIZE == 0);
+        }
+        if BC != F::BLOCK_COUNT
+            || F::OOB_SIZE < TAG_SIZE
+            || F::PAGES_PER_BLOCK > u16::MAX as usize
+            || LPC == 0
+            || LPC > (BC - 2 * GC_RESERVE_BLOCKS).saturating_mul(F::PAGES_PER_BLOCK)
+        {
+            return Err(Error::InvalidConfg);
+        }
+        info!(
+            "Initialising page map with {} logical pages and {} blocks",
+            LPC, BC
+        );
+
+        let mut pagemap = PageMap {
+            flash,
+            map: [UNWRITTEN; LPC],
+            valid: [0; BC],
+            blocks: [PageBlock::Free; BC],
+            head: None,
+            next_free: 0,
+            sequence: 0,
+            collecting: false,
+            dirty: None,
+            buffer: [0xFF; N],
+        };
+        pagemap.rebuild()?;
+        Ok(pagemap)
+    }
+
+    /// Release the flash, discarding the unflushed page
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// Number of blocks that are erased or can be erased without moving pages
+    pub fn free_blocks(&self) -> usize {
+        self.blocks
+            .iter()
+            .filter(|&&state| state == PageBlock::Free)
+            .count()
+    }
+
+    /// Scan the spare area of every page to find the newest copy of each logical page
+    fn rebuild(&mut self) -> Result<(), Error<F>> {
+        let mut tagged = false;
+        for block in 0..BC as u32 {
+            // Bad, reserved for other metadata, or unknown blocks are never used
+            let status = self
+                .flash
+                .block_status(BlockIndex::new(block))
+                .map_err(Error::Flash)?;
+            if !status.is_ok() {
+                warn!("Block {} is not usable ({:?})", block, status);
+                self.blocks[block as usize] = PageBlock::Bad;
+                continue;
+            }
+            // Used until shown to be empty, so failing reads retire it
+            self.blocks[block as usize] = PageBlock::Used;
+            let mut written = false;
+            for page in block * Self::PPB..(block + 1) * Self::PPB {
+                let (logical, sequence) = match self.read_tag(page)? {
+                    Tag::Erased => continue,
+                    // Torn or unreadable, the block is still in use
+                    Tag::Invalid => {
+                        written = true;
+                        continue;
+                    }
+                    Tag::Page(logical, sequence) => (logical, sequence),
+                };
+                written = true;
+                if !tagged || is_newer(sequence.wrapping_add(1), self.sequence) {
+                    self.sequence = sequence.wrapping_add(1);
+                    tagged = true;
+                }
+                let current = self.map[logical as usize];
+                let newer = current == UNWRITTEN
+                    || match self.read_tag(current)? {
+                        Tag::Page(_, current_sequence) => is_newer(sequence, current_sequence),
+                        _ => true,
+                    };
+                if newer {
+                    self.map[logical as usize] = page;
+                }
+            }
+            if !written && self.blocks[block as usize] == PageBlock::Used {
+                self.blocks[block as usize] = PageBlock::Free;
+            }
+        }
+        for &page in self.map.iter().filter(|&&page| page != UNWRITTEN) {
+            self.valid[(page / Self::PPB) as usize] += 1;
+        }
+        debug!(
+            "Page map rebuilt, {} free blocks, next sequence {}",
+            self.free_blocks(),
+            self.sequence
+        );
+        Ok(())
+    }
+
+    /// Read and decode the tag of a physical page
+    fn read_tag(&mut self, page: u32) -> Result<Tag, Error<F>> {
+        let mut tag = [0; TAG_SIZE];
+        match self.flash.read_oob(PageIndex::new(page), &mut tag) {
+            Ok(()) => {}
+            Err(e) => match e.kind() {
+                NandFlashErrorKind::BlockFailing(_) => self.retire(page / Self::PPB),
+                NandFlashErrorKind::BlockFail(_) => {
+                    self.retire(page / Self::PPB);
+                    return Ok(Tag::Invalid);
+                }
+                _ => return Err(Error::Flash(e)),
+            },
+        }
+        if tag == [0xFF; TAG_SIZE] {
+            return Ok(Tag::Erased);
+        }
+        let word = |i: usize| u32::from_le_bytes(tag[i * 4..i * 4 + 4].try_into().unwrap());
+        let (logical, sequence, check) = (word(0), word(1), word(2));
+        if check != !(TAG_MAGIC ^ logical ^ sequence) || logical as usize >= LPC {
+            return Ok(Tag::Invalid);
+        }
+        Ok(Tag::Page(logical, sequence))
+    }
+
+    /// Retire a block so its pages are moved and it is not used again
+    fn retire(&mut self, block: u32) {
+        if self.blocks[block as usize] == PageBlock::Used {
+            info!("Retiring block {}", block);
+            self.blocks[block as usize] = PageBlock::Retired;
+        }
+    }
+
+    /// Write a logical page to the next free physical page
+    fn write_page(&mut self, logical: u32, data: &[u8; N]) -> Result<(), Error<F>> {
+        let page = loop {
+            let page = self.next_page()?;
+            let mut tag = [0; TAG_SIZE];
+            for (i, word) in [
+                logical,
+                self.sequence,
+                !(TAG_MAGIC ^ logical ^ self.sequence),
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                tag[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+            }
+            match self.flash.write_page_oob(PageIndex::new(page), data, &tag) {
+                Ok(()) => break page,
+                Err(e) => match e.kind() {
+                    NandFlashErrorKind::BlockFail(_) | NandFlashErrorKind::BlockFailing(_) => {
+                        warn!("Write to page {} failed, retiring block", page);
+                        self.retire(page / Self::PPB);
+                        self.head = None;
+                    }
+                    _ => return Err(Error::Flash(e)),
+                },
+            }
+        };
+        trace!("Logical page {} written to {}", logical, page);
+        self.sequence = self.sequence.wrapping_add(1);
+        let old = self.map[logical as usize];
+        if old != UNWRITTEN {
+            self.valid[(old / Self::PPB) as usize] -= 1;
+        }
+        self.map[logical as usize] = page;
+        self.valid[(page / Self::PPB) as usize] += 1;
+        Ok(())
+    }
+
+    /// The next physical page to write, opening a new block if required
+    fn next_page(&mut self) -> Result<u32, Error<F>> {
+        if let Some((block, page)) = self.head {
+            if page < Self::PPB {
+                self.head = Some((block, page + 1));
+                return Ok(block.as_u32() * Self::PPB + page);
+            }
+        }
+        self.head = None;
+        self.collect_garbage()?;
+        let block = self.allocate()?;
+        self.head = Some((block, 1));
+        Ok(block.as_u32() * Self::PPB)
+    }
+
+    /// Find and erase a free block
+    fn allocate(&mut self) -> Result<BlockIndex, Error<F>> {
+        for i in 0..BC as u32 {
+            let block = (self.next_free + i) % BC as u32;
+            if self.blocks[block as usize] != PageBlock::Free {
+                continue;
+            }
+            self.next_free = (block + 1) % BC as u32;
+            if self.erase(block)? {
+                self.blocks[block as usize] = PageBlock::Used;
+                return Ok(BlockIndex::new(block));
+            }
+        }
+        error!("No free blocks remaining");
+        Err(Error::NotEnoughValidBlocks)
+    }
+
+    /// Erase a block, marking it bad if the erase fails. Returns true if erased
+    fn erase(&mut self, block: u32) -> Result<bool, Error<F>> {
+        match self.flash.erase_block(BlockIndex::new(block)) {
+            Ok(()) => Ok(true),
+            Err(e) if matches!(e.kind(), NandFlashErrorKind::BlockFail(_)) => {
+                warn!("Erase of block {} failed", block);
+                let _ = self.flash.mark_block_bad(BlockIndex::new(block));
+                self.blocks[block as usize] = PageBlock::Bad;
+                Ok(false)
+            }
+            Err(e) => Err(Error::Flash(e)),
+        }
+    }
+
+    /// Reclaim blocks until more than the reserve are free
+    fn collect_garbage(&mut self) -> Result<(), Error<F>> {
+        if self.collecting {
+            return Ok(());
+        }
+        self.collecting = true;
+        let result = self.collect();
+        self.collecting = false;
+        result
+    }
+
+    /// Move the valid pages out of the cheapest blocks to collect, and erase them
+    fn collect(&mut self) -> Result<(), Error<F>> {
+        while self.free_blocks() <= GC_RESERVE_BLOCKS {
+            let Some(victim) = self.victim() else {
+                error!("No blocks to collect");
+                return Err(Error::NotEnoughValidBlocks);
+            };
+            debug!(
+                "Collecting block {} with {} valid pages",
+                victim, self.valid[victim as usize]
+            );
+            let mut data = [0; N];
+            for page in victim * Self::PPB..(victim + 1) * Self::PPB {
+                if self.valid[victim as usize] == 0 {
+                    break;
+                }
+                let logical = match self.read_tag(page)? {
+                    Tag::Page(logical, _) if self.map[logical as usize] == page => logical,
+                    Tag::Page(..) | Tag::Erased => continue,
+                    // The tag can't be read, so check the map for a page still in use
+                    Tag::Invalid => match self.map.iter().position(|&mapped| mapped == page) {
+                        Some(logical) => logical as u32,
+                        None => continue,
+                    },
+                };
+                match self.flash.read(page as u64 * N as u64, &mut data) {
+                    Ok(()) => {}
+                    // Corrected, so the data is good
+                    Err(e) if matches!(e.kind(), NandFlashErrorKind::BlockFailing(_)) => {}
+                    Err(e) => return Err(Error::Flash(e)),
+                }
+                self.write_page(logical, &data)?;
+            }
+            // Only reclaim the block once nothing is mapped to it
+            if self.valid[victim as usize] != 0 {
+                error!(
+                    "Block {} still holds {} valid pages",
+                    victim, self.valid[victim as usize]
+                );
+                return Err(Error::Other);
+            }
+            if self.blocks[victim as usize] == PageBlock::Retired {
+                let _ = self.flash.mark_block_bad(BlockIndex::new(victim));
+                self.blocks[victim as usize] = PageBlock::Bad;
+            } else if self.erase(victim)? {
+                self.blocks[victim as usize] = PageBlock::Free;
+            }
+        }
+        Ok(())
+    }
+
+    /// Block to collect: retired blocks first, then the one with the fewest valid pages.
+    ///
+    /// Full blocks are never chosen, as collecting them frees nothing.
+    fn victim(&self) -> Option<u32> {
+        let head = self.head.map(|(block, _)| block.as_u32());
+        let candidates = (0..BC as u32).filter(|&block| Some(block) != head);
+        candidates
+            .clone()
+            .find(|&block| self.blocks[block as usize] == PageBlock::Retired)
+            .or_else(|| {
+                candidates
+                    .filter(|&block| {
+                        self.blocks[block as usize] == PageBlock::Used
+                            && (self.valid[block as usize] as u32) < Self::PPB
+                    })
+                    .min_by_key(|&block| self.valid[block as usize])
+            })
+    }
+
+    /// Write the buffered page, if it has changes
+    fn write_buffer(&mut self) -> Result<(), Error<F>> {
+        if let Some(logical) = self.dirty.take() {
+            let buffer = self.buffer;
+            self.write_page(logical, &buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Read a logical page into the buffer
+    fn load_page(&mut self, logical: u32) -> Result<(), Error<F>> {
+        let page = self.map[logical as usize];
+        if page == UNWRITTEN {
+            self.buffer.fill(0xFF);
+            return Ok(());
+        }
+        match self.flash.read(page as u64 * N as u64, &mut self.buffer) {
+            Ok(()) => Ok(()),
+            Err(e) if matches!(e.kind(), NandFlashErrorKind::BlockFailing(_)) => {
+                self.retire(page / Self::PPB);
+                Ok(())
+            }
+            Err(e) => Err(Error::Flash(e)),
+        }
+    }
+
+    /// Check a range of sectors is within the device
+    fn check_sectors(&self, start: u32, count: usize) -> Result<(), Error<F>> {
+        match (start as u64).checked_add(count as u64) {
+            Some(end) if end <= self.sector_count() as u64 => Ok(()),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+
+    /// Merge a sector into the buffer, writing out the previous page if it is different
+    fn write_sector(&mut self, sector: u32, data: &[u8; SECTOR_SIZE]) -> Result<(), Error<F>> {
+        let logical = sector / Self::SECTORS_PER_PAGE;
+        let column = (sector % Self::SECTORS_PER_PAGE) as usize * SECTOR_SIZE;
+        if self.dirty != Some(logical) {
+            self.write_buffer()?;
+            self.load_page(logical)?;
+            self.dirty = Some(logical);
+        }
+        self.buffer[column..column + SECTOR_SIZE].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Read a sector, from the buffer if it holds it
+    fn read_sector(&mut self, sector: u32, data: &mut [u8; SECTOR_SIZE]) -> Result<(), Error<F>> {
+        let logical = sector / Self::SECTORS_PER_PAGE;
+        let column = (sector % Self::SECTORS_PER_PAGE) as usize * SECTOR_SIZE;
+        if self.dirty == Some(logical) {
+            data.copy_from_slice(&self.buffer[column..column + SECTOR_SIZE]);
+            return Ok(());
+        }
+        let page = self.map[logical as usize];
+        if page == UNWRITTEN {
+            data.fill(0xFF);
+            return Ok(());
+        }
+        match self
+            .flash
+            .read(page as u64 * N as u64 + column as u64, data)
+        {
+            Ok(()) => Ok(()),
+            Err(e) if matches!(e.kind(), NandFlashErrorKind::BlockFailing(_)) => {
+                self.retire(page / Self::PPB);
+                Ok(())
+            }
+            Err(e) => Err(Error::Flash(e)),
+        }
+    }
+}
+
+/// Decoded tag of a physical page
+enum Tag {
+    /// Never written
+    Erased,
+    /// Written but the tag doesn't check, e.g. an interrupted write
+    Invalid,
+    /// Logical page and sequence number
+    Page(u32, u32),
+}
+
+impl<F, const N: usize, const LPC: usize, const BC: usize> ErrorType for PageMap<F, N, LPC, BC>
+where
+    F: NandFlashOob + Debug,
+{
+    type Error = Error<F>;
+}
+
+impl<F, const N: usize, const LPC: usize, const BC: usize> BlockDevice for PageMap<F, N, LPC, BC>
+where
+    F: NandFlashOob + Debug,
+{
+    fn read_sectors(
+        &mut self,
+        start: u32,
+        sectors: &mut [[u8; SECTOR_SIZE]],
+    ) -> Result<(), Self::Error> {
+        self.check_sectors(start, sectors.len())?;
+        for (sector, data) in (start..).zip(sectors.iter_mut()) {
+            self.read_sector(sector, data)?;
+        }
+        Ok(())
+    }
+
+    fn write_sectors(
+        &mut self,
+        start: u32,
+        sectors: &[[u8; SECTOR_SIZE]],
+    ) -> Result<(), Self::Error> {
+        self.check_sectors(start, sectors.len())?;
+        for (sector, data) in (start..).zip(sectors.iter()) {
+            self.write_sector(sector, data)?;
+        }
+        Ok(())
+    }
+
+    fn sector_count(&self) -> u32 {
+        LPC as u32 * Self::SECTORS_PER_PAGE
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.write_buffer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PageMap;
+    use embedded_nand::{BlockDevice, BlockIndex, SECTOR_SIZE, test::VirtualNandFlash};
+
+    type Flash = VirtualNandFlash<1024, 8, 16>;
+    type Map = PageMap<Flash, 1024, 64, 16>;
+
+    /// Rewriting sectors many times garbage collects and survives reloading
+    #[test]
+    fn test_pagemap_rewrite_and_gc() {
+        let mut map = Map::init(Flash::new()).unwrap();
+        assert_eq!(map.sector_count(), 128);
+        let mut expected = [0xFF; 128];
+        // Far more page writes than the flash holds, so blocks must be reclaimed
+        for i in 0..2000u32 {
+            let sector = (i * 37) % 96;
+            let value = i as u8;
+            map.write_sectors(sector, &[[value; SECTOR_SIZE]]).unwrap();
+            expected[sector as usize] = value;
+        }
+        map.flush().unwrap();
+        let flash = map.release();
+        assert!((0..16).all(|b| flash.erase_count(BlockIndex::new(b)) > 0));
+
+        let mut map = Map::init(flash).unwrap();
+        let mut sector = [[0; SECTOR_SIZE]];
+        for (index, &value) in expected.iter().enumerate() {
+            map.read_sectors(index as u32, &mut sector).unwrap();
+            assert_eq!(sector[0], [value; SECTOR_SIZE], "sector {}", index);
+        }
+        assert!(map.read_sectors(128, &mut sector).is_err());
+    }
+
+    /// Losing power keeps every flushed page and drops the buffered one
+    #[test]
+    fn test_pagemap_unclean_shutdown() {
+        let mut map = Map::init(Flash::new()).unwrap();
+        map.write_sectors(0, &[[1; SECTOR_SIZE]; 4]).unwrap();
+        map.flush().unwrap();
+        map.write_sectors(2, &[[2; SECTOR_SIZE]; 2]).unwrap();
+        map.write_sectors(8, &[[3; SECTOR_SIZE]]).unwrap();
+        let flash = map.release();
+
+        let mut map = Map::init(flash).unwrap();
+        let mut sectors = [[0; SECTOR_SIZE]; 4];
+        map.read_sectors(0, &mut sectors).unwrap();
+        assert_eq!(
+            sectors,
+            [
+                [1; SECTOR_SIZE],
+                [1; SECTOR_SIZE],
+                [2; SECTOR_SIZE],
+                [2; SECTOR_SIZE]
+            ]
+        );
+        map.read_sectors(8, &mut sectors[..1]).unwrap();
+        assert_eq!(sectors[0], [0xFF; SECTOR_SIZE]);
+    }
+
+    /// The newest copy of a page is found when the sequence number wraps
+    #[test]
+    fn test_pagemap_sequence_wrap() {
+        let mut map = Map::init(Flash::new()).unwrap();
+        map.sequence = u32::MAX - 1;
+        for value in 1..=4 {
+            map.write_sectors(0, &[[value; SECTOR_SIZE]]).unwrap();
+            map.flush().unwrap();
+        }
+        assert_eq!(map.sequence, 2);
+        let flash = map.release();
+
+        let mut map = Map::init(flash).unwrap();
+        assert_eq!(map.sequence, 2);
+        let mut sector = [[0; SECTOR_SIZE]];
+        map.read_sectors(0, &mut sector).unwrap();
+        assert_eq!(sector[0], [4; SECTOR_SIZE]);
+    }
+}
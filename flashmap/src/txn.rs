@@ -0,0 +1,191 @@
+//! Transactions that replace several logical blocks at once.
+//!
+//! New data is written to spare physical blocks, and the map is only written when the
+//! transaction is committed. A power loss before then leaves the previous map, which
+//! still points at the old blocks, so either all or none of the blocks change.
+
+use core::fmt::Debug;
+
+use embedded_nand::{AddressConversions, BlockIndex, NandFlash};
+
+use crate::{Error, FlashMap, format::BlockState, health::BadCause};
+
+/// Default number of blocks a transaction from [FlashMap::begin] can hold
+pub const DEFAULT_TXN_BLOCKS: usize = 8;
+
+/// A set of logical block writes that are published together by [Txn::commit].
+///
+/// N is the maximum number of logical blocks in the transaction.
+///
+/// Dropping the transaction without committing discards the writes and returns the
+/// spare blocks to the pool.
+#[derive(Debug)]
+pub struct Txn<'a, F: NandFlash, const LBC: usize, const N: usize = DEFAULT_TXN_BLOCKS> {
+    /// The map being updated
+    map: &'a mut FlashMap<F, LBC>,
+    /// Logical block and the spare physical block holding its new data
+    pending: [(BlockIndex, BlockIndex); N],
+    /// Number of entries used in pending
+    len: usize,
+}
+
+impl<F, const LBC: usize> FlashMap<F, LBC>
+where
+    F: NandFlash + Debug,
+{
+    /// Start a transaction of up to [DEFAULT_TXN_BLOCKS] logical blocks
+    pub fn begin(&mut self) -> Txn<'_, F, LBC> {
+        self.begin_sized()
+    }
+
+    /// Start a transaction of up to N logical blocks
+    pub fn begin_sized<const N: usize>(&mut self) -> Txn<'_, F, LBC, N> {
+        Txn {
+            map: self,
+            pending: [(BlockIndex::new(0), BlockIndex::new(0)); N],
+            len: 0,
+        }
+    }
+}
+
+impl<F, const LBC: usize, const N: usize> Txn<'_, F, LBC, N>
+where
+    F: NandFlash + Debug,
+{
+    /// Write the new contents of a logical block, starting at the beginning of the block.
+    ///
+    /// The data is written to a spare block and is not visible through the map until
+    /// the transaction is committed. Writing the same block again replaces the data.
+    /// If the write fails, the block is left out of the transaction.
+    pub fn write_block(&mut self, logical_block: BlockIndex, data: &[u8]) -> Result<(), Error<F>> {
+        if logical_block.as_u32() as usize >= LBC || data.len() > F::ERASE_SIZE {
+            return Err(Error::OutOfBounds);
+        }
+        let index = match self.pending[..self.len]
+            .iter()
+            .position(|&(logical, _)| logical == logical_block)
+        {
+            Some(index) => {
+                // Discard the previous data for this block
+                let (_, spare) = self.pending[index];
+                self.map.data.set_block_state(spare, BlockState::Free);
+                index
+            }
+            None if self.len < N => {
+                self.len += 1;
+                self.len - 1
+            }
+            None => return Err(Error::TransactionFull),
+        };
+        match self.write_spare(data) {
+            Ok(spare) => {
+                self.pending[index] = (logical_block, spare);
+                Ok(())
+            }
+            Err(e) => {
+                self.remove(index);
+                Err(e)
+            }
+        }
+    }
+
+    /// Publish every block written in the transaction with a single map update.
+    ///
+    /// The previous physical blocks are returned to the spare pool.
+    pub fn commit(mut self) -> Result<(), Error<F>> {
+        debug!("Committing transaction of {} blocks", self.len);
+        for &(logical_block, spare) in &self.pending[..self.len] {
+            let logical = logical_block.as_u32() as usize;
+            if let Some(old) = self.map.data.physical(logical) {
+                self.map.data.set_block_state(old, BlockState::Free);
+            }
+            self.map.data.set_physical(logical, spare);
+            self.map.read_counts[logical] = 0;
+        }
+        // Nothing left to discard when dropped
+        self.len = 0;
+        self.map.update_map()
+    }
+
+    /// Write data to the start of a spare block, retiring spare blocks that fail
+    fn write_spare(&mut self, data: &[u8]) -> Result<BlockIndex, Error<F>> {
+        loop {
+            let spare = self.map.next_spare_block()?;
+            let address = FlashMap::<F, LBC>::block_to_byte_address(spare);
+            match FlashMap::<F, LBC>::checked_write_slice(&mut self.map.flash, address, data) {
+                Ok(true) => return Ok(spare),
+                Ok(false) => self.map.retire(spare, BadCause::Program),
+                Err(e) => {
+                    self.map.data.set_block_state(spare, BlockState::Free);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Remove an entry that has no spare block allocated to it
+    fn remove(&mut self, index: usize) {
+        self.pending.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+    }
+}
+
+impl<F: NandFlash, const LBC: usize, const N: usize> Drop for Txn<'_, F, LBC, N> {
+    fn drop(&mut self) {
+        for &(_, spare) in &self.pending[..self.len] {
+            self.map.data.set_block_state(spare, BlockState::Free);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, FlashMap, format::BlockState};
+    use embedded_nand::{BlockIndex, NandFlash, test::VirtualNandFlash};
+
+    const BLOCK_SIZE: usize = 128 * 8;
+
+    type Flash = VirtualNandFlash<128, 8, 64>;
+
+    /// Committed blocks are all visible after reloading, uncommitted blocks never are
+    #[test]
+    fn test_txn_commit_and_discard() {
+        let mut map = FlashMap::<_, 32>::init(Flash::new()).unwrap();
+        let spares = map.spare_blocks_remaining();
+        let old = map.data.physical(2).unwrap();
+
+        let mut txn = map.begin();
+        txn.write_block(BlockIndex::new(2), &[0x11; BLOCK_SIZE])
+            .unwrap();
+        txn.write_block(BlockIndex::new(5), &[0x22; 16]).unwrap();
+        txn.write_block(BlockIndex::new(2), &[0x33; BLOCK_SIZE])
+            .unwrap();
+        txn.commit().unwrap();
+        assert_eq!(map.data.block_state(old), BlockState::Free);
+        assert_eq!(map.spare_blocks_remaining(), spares);
+
+        // Power loss during a transaction, the map on flash is unchanged
+        let mut txn = map.begin();
+        txn.write_block(BlockIndex::new(5), &[0x44; 16]).unwrap();
+        core::mem::forget(txn);
+
+        let mut map = FlashMap::<_, 32>::init(map.flash).unwrap();
+        let mut buffer = [0; 16];
+        map.read(2 * BLOCK_SIZE as u64, &mut buffer).unwrap();
+        assert_eq!(buffer, [0x33; 16]);
+        map.read(5 * BLOCK_SIZE as u64, &mut buffer).unwrap();
+        assert_eq!(buffer, [0x22; 16]);
+
+        // Dropped transactions return their spares
+        let mut txn = map.begin_sized::<1>();
+        txn.write_block(BlockIndex::new(5), &[0x55; 16]).unwrap();
+        assert!(matches!(
+            txn.write_block(BlockIndex::new(6), &[0x55; 16]),
+            Err(Error::TransactionFull)
+        ));
+        drop(txn);
+        assert_eq!(map.spare_blocks_remaining(), spares);
+        map.read(5 * BLOCK_SIZE as u64, &mut buffer).unwrap();
+        assert_eq!(buffer, [0x22; 16]);
+    }
+}
@@ -0,0 +1,424 @@
+//! Declaring devices.
+//!
+//! Each supported part is declared once with [crate::spi_nand_device], listing its ID,
+//! geometry and features. The macro generates the [spi_nand::SpiNand] implementation, the
+//! blocking and async command wiring and the feature trait implementations, after which
+//! [spi_nand::SpiNandDevice] implements [embedded_nand::NandFlash] for the part.
+//!
+//! The features of every declared device are also available at runtime through
+//! [DeviceCapabilities], e.g. to pick an ECC setup or check for a bad block lookup table
+//! without knowing the part at compile time.
+
+use spi_nand::SpiNand;
+
+/// On die ECC supported by a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EccCapability {
+    /// No on die ECC
+    None,
+    /// Single bit correction ([crate::winbond::w25n::ECCBasic])
+    Basic,
+    /// Multi bit correction with a configurable bit flip threshold
+    /// ([crate::winbond::w25n::ECC])
+    Threshold,
+}
+
+/// Geometry and features of a device, see [DeviceCapabilities]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Capabilities {
+    /// JEDEC manufacturer ID
+    pub jedec_manufacturer_id: u8,
+    /// JEDEC device ID
+    pub jedec_device_id: u16,
+    /// Bytes in a page, not including the spare area
+    pub page_size: u32,
+    /// Pages in a block
+    pub pages_per_block: u32,
+    /// Blocks across all dies
+    pub block_count: u32,
+    /// Number of stacked dies
+    pub die_count: u32,
+    /// On die ECC
+    pub ecc: EccCapability,
+    /// Configurable output driver strength
+    pub output_driver_strength: bool,
+    /// The /HOLD pin can be disabled
+    pub hold_disable: bool,
+    /// Buffer and continuous read modes
+    pub buffer_mode: bool,
+    /// One time programmable pages
+    pub otp: bool,
+    /// Entries in the bad block lookup table, 0 without one
+    pub bad_block_lut: usize,
+    /// Free spare area bytes are accessible ([spi_nand::cmd_blocking::SpiNandOobBlocking],
+    /// [spi_nand::cmd_async::SpiNandOobAsync])
+    pub oob: bool,
+}
+
+impl Capabilities {
+    /// Geometry of `D` without any features
+    pub const fn new<D: SpiNand<N>, const N: usize>() -> Self {
+        Self {
+            jedec_manufacturer_id: D::JEDEC_MANUFACTURER_ID,
+            jedec_device_id: D::JEDEC_DEVICE_ID,
+            page_size: D::PAGE_SIZE,
+            pages_per_block: D::PAGES_PER_BLOCK,
+            block_count: D::BLOCK_COUNT,
+            die_count: D::DIE_COUNT,
+            ecc: EccCapability::None,
+            output_driver_strength: false,
+            hold_disable: false,
+            buffer_mode: false,
+            otp: false,
+            bad_block_lut: 0,
+            oob: false,
+        }
+    }
+}
+
+/// Devices declared with [crate::spi_nand_device]
+pub trait DeviceCapabilities {
+    /// Geometry and features of the device
+    const CAPABILITIES: Capabilities;
+
+    /// Geometry and features of the device
+    fn capabilities(&self) -> Capabilities {
+        Self::CAPABILITIES
+    }
+}
+
+/// Declare a device: its ID, geometry and features.
+///
+/// A new part is declared as a `struct`, which generates the type, its
+/// [spi_nand::SpiNand] implementation and default [spi_nand::cmd_blocking::SpiNandBlocking]
+/// and [spi_nand::cmd_async::SpiNandAsync] implementations:
+///
+/// ```
+/// use spi_nand_devices::device::{DeviceCapabilities, EccCapability};
+/// use spi_nand_devices::spi_nand_device;
+///
+/// spi_nand_device! {
+///     /// A 1Gbit part
+///     pub struct Example;
+///     page_size: 2048,
+///     pages_per_block: 64,
+///     blocks: 1024,
+///     jedec: (0xEF, 0xAA21),
+///     features: [ECCBasic, ODS, BufferMode, BBM<20>],
+/// }
+///
+/// let capabilities = Example::new().capabilities();
+/// assert_eq!(capabilities.ecc, EccCapability::Basic);
+/// assert_eq!(capabilities.bad_block_lut, 20);
+/// assert_eq!(capabilities.block_count, 1024);
+/// ```
+///
+/// `dies: 2,` after `blocks` declares a stacked die part, with `blocks` counting all dies.
+///
+/// Members of a device family that share their [spi_nand::SpiNand] and command
+/// implementations are declared as a `type` alias of the family type, and a hand written
+/// type with `impl`. Both only generate the features:
+///
+/// ```ignore
+/// spi_nand_device! {
+///     /// W25N01GV
+///     pub type W25N01GV = W25N<1024, 0xAA21>;
+///     page_size: 2048,
+///     features: [ECCBasic, BufferMode, OTP, BBM<20>, OOB],
+/// }
+///
+/// spi_nand_device! {
+///     impl W25N04LW;
+///     page_size: 4096,
+///     features: [ECC, ODS = ConfigReg4, HoldDisable, BufferMode, OTP, BBM<40>],
+/// }
+/// ```
+///
+/// Features are the [crate::winbond::w25n] feature traits:
+/// - `ECCBasic`, `ECC`: on die ECC
+/// - `ODS`: output driver strength in [crate::winbond::w25n::ConfigReg], or
+///   `ODS = Register` for another [crate::winbond::w25n::ODSRegister]
+/// - `HoldDisable`, `BufferMode`, `OTP`: configuration register bits
+/// - `BBM<LUT>`: bad block lookup table with `LUT` entries, also used for
+///   [spi_nand::cmd_blocking::SpiNandLutBlocking] and [spi_nand::cmd_async::SpiNandLutAsync]
+/// - `OOB`: [spi_nand::cmd_blocking::SpiNandOobBlocking] and
+///   [spi_nand::cmd_async::SpiNandOobAsync] with the free bytes of the 64 byte spare area of
+///   2048 byte pages
+#[macro_export]
+macro_rules! spi_nand_device {
+    (@impl $ty:ty, $n:literal, ECCBasic) => {
+        impl $crate::winbond::w25n::ECCBasic for $ty {}
+    };
+    (@impl $ty:ty, $n:literal, ECC) => {
+        impl $crate::winbond::w25n::ECC for $ty {}
+    };
+    (@impl $ty:ty, $n:literal, ODS) => {
+        impl $crate::winbond::w25n::ODS for $ty {
+            type Register = $crate::winbond::w25n::ConfigReg;
+        }
+    };
+    (@impl $ty:ty, $n:literal, ODS = $register:ty) => {
+        impl $crate::winbond::w25n::ODS for $ty {
+            type Register = $register;
+        }
+    };
+    (@impl $ty:ty, $n:literal, HoldDisable) => {
+        impl $crate::winbond::w25n::HoldDisable for $ty {}
+    };
+    (@impl $ty:ty, $n:literal, BufferMode) => {
+        impl $crate::winbond::w25n::BufferMode for $ty {}
+    };
+    (@impl $ty:ty, $n:literal, OTP) => {
+        impl $crate::winbond::w25n::OTP for $ty {}
+    };
+    (@impl $ty:ty, $n:literal, BBM<$lut:literal>) => {
+        impl $crate::winbond::w25n::BBM<$lut> for $ty {}
+
+        impl<SPI: $crate::__private::embedded_hal::spi::SpiDevice>
+            $crate::__private::spi_nand::cmd_blocking::SpiNandLutBlocking<SPI, $n> for $ty
+        {
+            fn lut_swap_block(
+                &self,
+                spi: &mut SPI,
+                bad: $crate::__private::embedded_nand::BlockIndex,
+                replacement: $crate::__private::embedded_nand::BlockIndex,
+            ) -> Result<bool, $crate::__private::spi_nand::error::SpiFlashError<SPI::Error>> {
+                $crate::winbond::w25n::blocking::BBMBlocking::<SPI, $n, $lut>::replace_block(
+                    self,
+                    spi,
+                    bad,
+                    replacement,
+                )
+            }
+        }
+
+        impl<SPI: $crate::__private::embedded_hal_async::spi::SpiDevice>
+            $crate::__private::spi_nand::cmd_async::SpiNandLutAsync<SPI, $n> for $ty
+        {
+            async fn lut_swap_block(
+                &self,
+                spi: &mut SPI,
+                bad: $crate::__private::embedded_nand::BlockIndex,
+                replacement: $crate::__private::embedded_nand::BlockIndex,
+            ) -> Result<bool, $crate::__private::spi_nand::error::SpiFlashError<SPI::Error>> {
+                $crate::winbond::w25n::asyn::BBMAsync::<SPI, $n, $lut>::replace_block(
+                    self,
+                    spi,
+                    bad,
+                    replacement,
+                )
+                .await
+            }
+        }
+    };
+    (@impl $ty:ty, $n:literal, OOB) => {
+        // Spare bytes 4 to 7 of each 16 byte sector of the 64 byte spare area are free and
+        // covered by the ECC. Bytes 0 and 1 of the first are the bad block marker.
+        impl<SPI: $crate::__private::embedded_hal::spi::SpiDevice>
+            $crate::__private::spi_nand::cmd_blocking::SpiNandOobBlocking<SPI, $n> for $ty
+        {
+            const OOB_FREE: &'static [(u16, u16)] = &[(4, 4), (20, 4), (36, 4), (52, 4)];
+        }
+
+        impl<SPI: $crate::__private::embedded_hal_async::spi::SpiDevice>
+            $crate::__private::spi_nand::cmd_async::SpiNandOobAsync<SPI, $n> for $ty
+        {
+            const OOB_FREE: &'static [(u16, u16)] = &[(4, 4), (20, 4), (36, 4), (52, 4)];
+        }
+    };
+    (@capability $c:ident, ECCBasic) => {
+        $crate::device::Capabilities {
+            ecc: $crate::device::EccCapability::Basic,
+            ..$c
+        }
+    };
+    (@capability $c:ident, ECC) => {
+        $crate::device::Capabilities {
+            ecc: $crate::device::EccCapability::Threshold,
+            ..$c
+        }
+    };
+    (@capability $c:ident, ODS) => {
+        $crate::device::Capabilities {
+            output_driver_strength: true,
+            ..$c
+        }
+    };
+    (@capability $c:ident, HoldDisable) => {
+        $crate::device::Capabilities {
+            hold_disable: true,
+            ..$c
+        }
+    };
+    (@capability $c:ident, BufferMode) => {
+        $crate::device::Capabilities {
+            buffer_mode: true,
+            ..$c
+        }
+    };
+    (@capability $c:ident, OTP) => {
+        $crate::device::Capabilities { otp: true, ..$c }
+    };
+    (@capability $c:ident, BBM<$lut:literal>) => {
+        $crate::device::Capabilities {
+            bad_block_lut: $lut,
+            ..$c
+        }
+    };
+    (@capability $c:ident, OOB) => {
+        $crate::device::Capabilities { oob: true, ..$c }
+    };
+    (@features $ty:ty, $n:literal, [$($feature:ident $(<$lut:literal>)? $(= $register:ty)?),*]) => {
+        $(
+            $crate::spi_nand_device!(@impl $ty, $n, $feature $(<$lut>)? $(= $register)?);
+        )*
+
+        impl $crate::device::DeviceCapabilities for $ty {
+            const CAPABILITIES: $crate::device::Capabilities = {
+                let capabilities = $crate::device::Capabilities::new::<$ty, $n>();
+                $(
+                    let capabilities =
+                        $crate::spi_nand_device!(@capability capabilities, $feature $(<$lut>)?);
+                )*
+                capabilities
+            };
+        }
+    };
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident;
+        page_size: $n:literal,
+        pages_per_block: $pages_per_block:literal,
+        blocks: $blocks:literal,
+        $(dies: $dies:literal,)?
+        jedec: ($manufacturer:literal, $device:literal),
+        features: [$($feature:ident $(<$lut:literal>)? $(= $register:ty)?),* $(,)?] $(,)?
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        $vis struct $name;
+
+        $crate::__spi_nand_device_format!($name);
+        $crate::__spi_nand_device_serde!($name);
+
+        impl $name {
+            /// Creates a new instance of the flash device.
+            pub fn new() -> Self {
+                Self
+            }
+        }
+
+        impl $crate::__private::spi_nand::SpiNand<$n> for $name {
+            const PAGES_PER_BLOCK: u32 = $pages_per_block;
+            const BLOCK_COUNT: u32 = $blocks;
+            $(const DIE_COUNT: u32 = $dies;)?
+            const JEDEC_MANUFACTURER_ID: u8 = $manufacturer;
+            const JEDEC_DEVICE_ID: u16 = $device;
+        }
+
+        impl<SPI: $crate::__private::embedded_hal::spi::SpiDevice>
+            $crate::__private::spi_nand::cmd_blocking::SpiNandBlocking<SPI, $n> for $name
+        {
+        }
+
+        impl<SPI: $crate::__private::embedded_hal_async::spi::SpiDevice>
+            $crate::__private::spi_nand::cmd_async::SpiNandAsync<SPI, $n> for $name
+        {
+        }
+
+        $crate::spi_nand_device!(@features $name, $n, [$($feature $(<$lut>)? $(= $register)?),*]);
+    };
+    (
+        $(#[$meta:meta])*
+        $vis:vis type $name:ident = $ty:ty;
+        page_size: $n:literal,
+        features: [$($feature:ident $(<$lut:literal>)? $(= $register:ty)?),* $(,)?] $(,)?
+    ) => {
+        $(#[$meta])*
+        $vis type $name = $ty;
+
+        $crate::spi_nand_device!(@features $name, $n, [$($feature $(<$lut>)? $(= $register)?),*]);
+    };
+    (
+        impl $ty:ty;
+        page_size: $n:literal,
+        features: [$($feature:ident $(<$lut:literal>)? $(= $register:ty)?),* $(,)?] $(,)?
+    ) => {
+        $crate::spi_nand_device!(@features $ty, $n, [$($feature $(<$lut>)? $(= $register)?),*]);
+    };
+}
+
+/// [defmt::Format] for a device declared with [crate::spi_nand_device], implemented when
+/// spi-nand-devices has the `defmt` feature so the declaring crate doesn't need defmt
+#[cfg(feature = "defmt")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __spi_nand_device_format {
+    ($name:ident) => {
+        impl $crate::__private::defmt::Format for $name {
+            fn format(&self, f: $crate::__private::defmt::Formatter<'_>) {
+                $crate::__private::defmt::Format::format(stringify!($name), f)
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "defmt"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __spi_nand_device_format {
+    ($name:ident) => {};
+}
+
+/// Serde implementations for a device declared with [crate::spi_nand_device], as a unit
+/// struct like the derive, when spi-nand-devices has the `serde` feature
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __spi_nand_device_serde {
+    ($name:ident) => {
+        impl $crate::__private::serde::Serialize for $name {
+            fn serialize<S: $crate::__private::serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                serializer.serialize_unit_struct(stringify!($name))
+            }
+        }
+
+        impl<'de> $crate::__private::serde::Deserialize<'de> for $name {
+            fn deserialize<D: $crate::__private::serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<Self, D::Error> {
+                struct Visitor;
+
+                impl $crate::__private::serde::de::Visitor<'_> for Visitor {
+                    type Value = $name;
+
+                    fn expecting(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                        f.write_str(concat!("unit struct ", stringify!($name)))
+                    }
+
+                    fn visit_unit<E: $crate::__private::serde::de::Error>(
+                        self,
+                    ) -> Result<$name, E> {
+                        Ok($name)
+                    }
+                }
+
+                deserializer.deserialize_unit_struct(stringify!($name), Visitor)
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __spi_nand_device_serde {
+    ($name:ident) => {};
+}
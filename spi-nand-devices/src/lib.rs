@@ -1,2 +1,19 @@
 #![no_std]
+pub mod device;
 pub mod winbond;
+
+pub use device::{Capabilities, DeviceCapabilities, EccCapability};
+
+// Used by the code generated by [spi_nand_device]
+#[doc(hidden)]
+pub mod __private {
+    pub use embedded_hal;
+    pub use embedded_hal_async;
+    pub use embedded_nand;
+    pub use spi_nand;
+
+    #[cfg(feature = "defmt")]
+    pub use defmt;
+    #[cfg(feature = "serde")]
+    pub use serde;
+}
@@ -1,4 +1,7 @@
-use spi_nand::SpiNand;
+use embedded_nand::BlockIndex;
+use spi_nand::{nand_register, register::WritableRegister, SpiNand};
+
+pub use spi_nand::register::ProtectionReg;
 
 /// Concrete type that implements all the flash device features
 /// for the W25N series of NAND flash devices.
@@ -9,80 +12,84 @@ pub struct W25N<const B: u32, const ID: u16>();
 
 // Specific flash devices with block count, ID and features
 
-/// W25N512G(V/W)
-pub type W25N512G = W25N<512, 0xAA20>;
-impl ECCBasic for W25N512G {}
-impl ODS for W25N512G {}
-impl HoldDisable for W25N512G {}
-impl BBM<10> for W25N512G {}
-
-/// W25N01GV
-pub type W25N01GV = W25N<1024, 0xAA21>;
-impl ECCBasic for W25N01GV {}
-impl BBM<20> for W25N01GV {}
-
-/// W25N01GW
-pub type W25N01GW = W25N<1024, 0xBA21>;
-impl ECCBasic for W25N01GW {}
-impl BBM<20> for W25N01GW {}
-impl ODS for W25N01GW {}
-
-/// W25N01JW
-pub type W25N01JW = W25N<1024, 0xBC21>;
-impl ECCBasic for W25N01JW {}
-impl BBM<20> for W25N01JW {}
-impl ODS for W25N01JW {
-    const ODS_REGISTER: u8 = 0xD0;
-    const ODS_BIT: u8 = 5;
-}
-
-/// W25N01KV
-pub type W25N01KV = W25N<1024, 0xAE21>;
-impl ODS for W25N01KV {}
-impl HoldDisable for W25N01KV {}
+crate::spi_nand_device! {
+    /// W25N512G(V/W)
+    pub type W25N512G = W25N<512, 0xAA20>;
+    page_size: 2048,
+    features: [ECCBasic, ODS, HoldDisable, BufferMode, OTP, BBM<10>, OOB],
+}
+
+crate::spi_nand_device! {
+    /// W25N01GV
+    pub type W25N01GV = W25N<1024, 0xAA21>;
+    page_size: 2048,
+    features: [ECCBasic, BufferMode, OTP, BBM<20>, OOB],
+}
+
+crate::spi_nand_device! {
+    /// W25N01GW
+    pub type W25N01GW = W25N<1024, 0xBA21>;
+    page_size: 2048,
+    features: [ECCBasic, ODS, HoldDisable, BufferMode, OTP, BBM<20>, OOB],
+}
+
+crate::spi_nand_device! {
+    /// W25N01JW
+    pub type W25N01JW = W25N<1024, 0xBC21>;
+    page_size: 2048,
+    features: [ECCBasic, ODS = ConfigReg4, BufferMode, OTP, BBM<20>],
+}
+
 // TODO: This is 4 bit ECC not 8 bit
-impl ECC for W25N01KV {}
+crate::spi_nand_device! {
+    /// W25N01KV
+    pub type W25N01KV = W25N<1024, 0xAE21>;
+    page_size: 2048,
+    features: [ECC, ODS, HoldDisable, BufferMode, OTP],
+}
 
-/// W25N01KW
-pub type W25N01KW = W25N<1024, 0xBE21>;
-impl ODS for W25N01KW {}
-impl HoldDisable for W25N01KW {}
 // TODO: This is 4 bit ECC not 8 bit
-impl ECC for W25N01KW {}
-impl BBM<20> for W25N01KW {}
-
-/// W25N02JW
-pub type W25N02JW = W25N<2048, 0xBF22>;
-impl BBM<40> for W25N02JW {}
-impl ECCBasic for W25N02JW {}
-impl ODS for W25N02JW {
-    const ODS_REGISTER: u8 = 0xD0;
-    const ODS_BIT: u8 = 5;
-}
-
-/// W25N02KV
-pub type W25N02KV = W25N<2048, 0xAA22>;
-impl ECC for W25N02KV {}
-impl ODS for W25N02KV {}
-impl HoldDisable for W25N02KV {}
-
-/// W25N02KW
-pub type W25N02KW = W25N<2048, 0xBA22>;
-impl ODS for W25N02KW {}
-impl HoldDisable for W25N02KW {}
-impl ECC for W25N02KW {}
-
-/// W25N04KV
-pub type W25N04KV = W25N<4096, 0xAA23>;
-impl ECC for W25N04KV {}
-impl ODS for W25N04KV {}
-impl HoldDisable for W25N04KV {}
-
-/// W25N04KW
-pub type W25N04KW = W25N<4096, 0xBA23>;
-impl ODS for W25N04KW {}
-impl HoldDisable for W25N04KW {}
-impl ECC for W25N04KW {}
+crate::spi_nand_device! {
+    /// W25N01KW
+    pub type W25N01KW = W25N<1024, 0xBE21>;
+    page_size: 2048,
+    features: [ECC, ODS, HoldDisable, BufferMode, OTP, BBM<20>],
+}
+
+crate::spi_nand_device! {
+    /// W25N02JW
+    pub type W25N02JW = W25N<2048, 0xBF22>;
+    page_size: 2048,
+    features: [ECCBasic, ODS = ConfigReg4, BufferMode, OTP, BBM<40>],
+}
+
+crate::spi_nand_device! {
+    /// W25N02KV
+    pub type W25N02KV = W25N<2048, 0xAA22>;
+    page_size: 2048,
+    features: [ECC, ODS, HoldDisable, BufferMode, OTP],
+}
+
+crate::spi_nand_device! {
+    /// W25N02KW
+    pub type W25N02KW = W25N<2048, 0xBA22>;
+    page_size: 2048,
+    features: [ECC, ODS, HoldDisable, BufferMode, OTP],
+}
+
+crate::spi_nand_device! {
+    /// W25N04KV
+    pub type W25N04KV = W25N<4096, 0xAA23>;
+    page_size: 2048,
+    features: [ECC, ODS, HoldDisable, BufferMode, OTP],
+}
+
+crate::spi_nand_device! {
+    /// W25N04KW
+    pub type W25N04KW = W25N<4096, 0xBA23>;
+    page_size: 2048,
+    features: [ECC, ODS, HoldDisable, BufferMode, OTP],
+}
 
 /// W25N04LW.
 ///
@@ -91,13 +98,12 @@ impl ECC for W25N04KW {}
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct W25N04LW;
-impl HoldDisable for W25N04LW {}
-impl BBM<40> for W25N04LW {}
+
 // TODO: Slightly different to standard ECC
-impl ECC for W25N04LW {}
-impl ODS for W25N04LW {
-    const ODS_REGISTER: u8 = 0xD0;
-    const ODS_BIT: u8 = 5;
+crate::spi_nand_device! {
+    impl W25N04LW;
+    page_size: 4096,
+    features: [ECC, ODS = ConfigReg4, HoldDisable, BufferMode, OTP, BBM<40>],
 }
 
 impl SpiNand<4096> for W25N04LW {
@@ -117,6 +123,35 @@ impl W25N04LW {
     }
 }
 
+impl Default for W25N04LW {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Concrete type for the W25M series of stacked die devices.
+///
+/// Each die is a W25N device with B blocks, selected with the Software
+/// Die Select command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct W25M<const B: u32, const ID: u16>();
+
+crate::spi_nand_device! {
+    /// W25M02GV. Two W25N01GV dies
+    pub type W25M02GV = W25M<1024, 0xAB21>;
+    page_size: 2048,
+    features: [ECCBasic, BufferMode, OTP, BBM<20>],
+}
+
+crate::spi_nand_device! {
+    /// W25M02GW. Two W25N01GW dies
+    pub type W25M02GW = W25M<1024, 0xBB21>;
+    page_size: 2048,
+    features: [ECCBasic, ODS, HoldDisable, BufferMode, OTP, BBM<20>],
+}
+
 impl<const B: u32, const ID: u16> W25N<B, ID> {
     /// Creates a new instance of the W25N flash device.
     pub fn new() -> Self {
@@ -137,51 +172,155 @@ impl<const B: u32, const ID: u16> SpiNand<2048> for W25N<B, ID> {
     const JEDEC_DEVICE_ID: u16 = ID;
 }
 
+impl<const B: u32, const ID: u16> W25M<B, ID> {
+    /// Creates a new instance of the W25M flash device.
+    pub fn new() -> Self {
+        Self()
+    }
+}
+
+impl<const B: u32, const ID: u16> Default for W25M<B, ID> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+// W25M devices stack two W25N dies of B blocks each
+impl<const B: u32, const ID: u16> SpiNand<2048> for W25M<B, ID> {
+    const PAGES_PER_BLOCK: u32 = 64;
+    const BLOCK_COUNT: u32 = B * 2;
+    const DIE_COUNT: u32 = 2;
+    const JEDEC_MANUFACTURER_ID: u8 = 0xEF;
+    const JEDEC_DEVICE_ID: u16 = ID;
+}
+
+/// IDs of the W25N devices with the bit flip count report registers ([ECC] devices)
+const BIT_FLIP_REPORT_IDS: [u16; 6] = [0xAE21, 0xBE21, 0xAA22, 0xBA22, 0xAA23, 0xBA23];
+/// Bit flip count report registers, BFR7->BFR0 then BFR15->BFR8
+const BIT_FLIP_REPORT_REGISTERS: [u8; 2] = [0x40, 0x50];
+
+/// Most bits corrected in one sector, from the 4 bit per sector bit flip count report
+fn max_bit_flips(report: [u8; 2]) -> u8 {
+    report
+        .iter()
+        .flat_map(|byte| [byte & 0x0F, byte >> 4])
+        .max()
+        .unwrap_or(0)
+}
+
+// ================== Registers ==================
+
+nand_register! {
+    /// Configuration register (2)
+    pub struct ConfigReg(0xB0) {
+        /// Lock the OTP area
+        otp_lock, set_otp_lock: 7;
+        /// Access the OTP area instead of the array
+        otp_enable, set_otp_enable: 6;
+        /// Lock the protection register
+        protection_lock, set_protection_lock: 5;
+        /// On die ECC enabled ([ECCBasic] and [ECC] devices)
+        ecc_enable, set_ecc_enable: 4;
+        /// Buffer read mode (1) or continuous read mode (0)
+        buffer_mode, set_buffer_mode: 3;
+        /// Output driver strength, see [ODSStrength]
+        ods, set_ods: 2, 1;
+        /// /HOLD pin disabled ([HoldDisable] devices)
+        hold_disable, set_hold_disable: 0;
+    }
+}
+
+nand_register! {
+    /// Configuration register (4) of the W25NxxJW and W25N04LW
+    pub struct ConfigReg4(0xD0) {
+        /// Output driver strength, see [ODSStrength]
+        ods, set_ods: 6, 5;
+    }
+}
+
+nand_register! {
+    /// Status register (3). Read only
+    pub struct StatusReg(0xC0) {
+        /// The bad block lookup table is full ([BBM] devices)
+        lut_full: 6;
+        /// ECC status bits
+        ecc: 5, 4;
+        /// The last program failed
+        program_failed: 3;
+        /// The last erase failed
+        erase_failed: 2;
+        /// Write enable latch
+        write_enabled: 1;
+        /// An operation is in progress
+        busy: 0;
+    }
+}
+
+nand_register! {
+    /// Bit flip detect threshold register of [ECC] devices
+    pub struct BitFlipThresholdReg(0x10) {
+        /// Corrected bits per sector that set the ECC status to failing, see [ECCThreshold]
+        threshold, set_threshold: 7, 4;
+    }
+}
+
+/// A register with the output driver strength bits
+pub trait ODSRegister: WritableRegister {
+    /// Get the output driver strength
+    fn output_driver_strength(&self) -> ODSStrength;
+    /// Set the output driver strength
+    fn set_output_driver_strength(&mut self, strength: ODSStrength);
+}
+
+impl ODSRegister for ConfigReg {
+    fn output_driver_strength(&self) -> ODSStrength {
+        self.ods().into()
+    }
+
+    fn set_output_driver_strength(&mut self, strength: ODSStrength) {
+        self.set_ods(strength as u8)
+    }
+}
+
+impl ODSRegister for ConfigReg4 {
+    fn output_driver_strength(&self) -> ODSStrength {
+        self.ods().into()
+    }
+
+    fn set_output_driver_strength(&mut self, strength: ODSStrength) {
+        self.set_ods(strength as u8)
+    }
+}
+
 // ================== Feature traits ==================
 
 /// For devices that implement Basic ECC. (single bit correction)
+/// Enabled with [ConfigReg::ecc_enable]
 pub trait ECCBasic {
-    // Register of 1 bit
-    const ECC_ENABLE_REGISTER: u8 = 0xB0;
-    // Position of lsb
-    const ECC_ENABLE_BIT: u8 = 4;
-    // Location of ECC status bits
-    const ECC_STATUS_REGISTER: u8 = 0xC0;
-    // Location of ECC status bits
-    const ECC_STATUS_BIT: u8 = 4;
     // Command to lookup ECC page failure
     const ECC_PAGE_FAILURE_COMMAND: u8 = 0xA9;
 }
 
 /// For devices that implement ECC with configurable threshold (W25N512G)
+/// Enabled with [ConfigReg::ecc_enable]
 pub trait ECC {
-    /// Located in register 2
-    const ECC_ENABLE_REGISTER: u8 = 0xB0;
-    /// bit 4
-    const ECC_ENABLE_MASK: u8 = 0b10000;
-    // Location of ECC status bits
-    const ECC_STATUS_REGISTER: u8 = 0xC0;
-    // Location of ECC status bits
-    const ECC_STATUS_BIT: u8 = 4;
-    /// Extended registers. Only first register can be written
+    /// Extended registers. Only first register ([BitFlipThresholdReg]) can be written
     const ECC_EXTENDED_REGISTERS: [u8; 5] = [0x10, 0x20, 0x30, 0x40, 0x50];
 }
 
 /// Configurable output driver strength
 pub trait ODS {
-    // Register of 2 bits
-    const ODS_REGISTER: u8 = 0xB0;
-    // Position of lsb
-    const ODS_BIT: u8 = 1;
+    /// Register with the output driver strength bits
+    type Register: ODSRegister;
 }
 
-/// Hold disable
-pub trait HoldDisable {
-    // Register of 1 bit
-    const HOLD_DISABLE_REGISTER: u8 = 0xB0;
-    // Position of lsb
-    const HOLD_DISABLE_BIT: u8 = 0;
-}
+/// Hold disable, with [ConfigReg::hold_disable]
+pub trait HoldDisable {}
+
+/// Buffer read mode or continuous read mode, with [ConfigReg::buffer_mode]
+pub trait BufferMode {}
+
+/// One time programmable (OTP) pages, with [ConfigReg::otp_enable] and [ConfigReg::otp_lock]
+pub trait OTP {}
 
 /// Bad block managment with loookup table
 /// LUT is the size of the lookup table
@@ -190,10 +329,27 @@ pub trait BBM<const LUT: usize> {
     const SWAP_BLOCK_COMMAND: u8 = 0xA1;
     // Command to read LUT
     const READ_LUT_COMMAND: u8 = 0xA5;
-    // Register with LUT full flag
-    const LUT_FULL_REGISTER: u8 = 0xB0;
-    // Position of LUT full flag
-    const LUT_FULL_BIT: u8 = 6;
+
+    /// Swap block frame. Block addresses are 16 bit on the wire, None if either doesn't fit
+    fn swap_block_frame(logical: BlockIndex, physical: BlockIndex) -> Option<[u8; 5]> {
+        let logical = u16::try_from(logical.as_u32()).ok()?.to_be_bytes();
+        let physical = u16::try_from(physical.as_u32()).ok()?.to_be_bytes();
+        Some([
+            Self::SWAP_BLOCK_COMMAND,
+            logical[0],
+            logical[1],
+            physical[0],
+            physical[1],
+        ])
+    }
+
+    /// Decode a lookup table entry read after the read LUT command and dummy byte,
+    /// as the bad block and its replacement
+    fn lut_entry(entry: [u8; 4]) -> (BlockIndex, BlockIndex) {
+        let block = u16::from_be_bytes([entry[0], entry[1]]);
+        let swap = u16::from_be_bytes([entry[2], entry[3]]);
+        (BlockIndex::new(block as u32), BlockIndex::new(swap as u32))
+    }
 }
 
 /// ECC error threshold for considering a block failed when reading
@@ -261,12 +417,16 @@ impl From<u8> for ODSStrength {
 
 // Implement blocking trait
 pub mod blocking {
-    use super::{ECCBasic, ECCThreshold, ODSStrength, BBM, ECC, ODS, W25N, W25N04LW};
-    use embedded_hal::spi::SpiDevice;
+    use super::{
+        max_bit_flips, BitFlipThresholdReg, BufferMode, ConfigReg, ECCBasic, ECCThreshold,
+        HoldDisable, ODSRegister, ODSStrength, StatusReg, BBM, BIT_FLIP_REPORT_IDS,
+        BIT_FLIP_REPORT_REGISTERS, ECC, ODS, OTP, W25M, W25N, W25N04LW,
+    };
+    use embedded_hal::spi::{Operation, SpiDevice};
     use embedded_nand::{BlockIndex, PageIndex};
     use spi_nand::{
         cmd_blocking::{
-            utils::{spi_transfer_in_place, spi_write},
+            utils::{spi_transaction, spi_transfer_in_place, spi_write},
             SpiNandBlocking,
         },
         error::SpiFlashError,
@@ -279,18 +439,15 @@ pub mod blocking {
     {
         /// Enable ECC
         fn enable_ecc(&self, spi: &mut SPI) -> Result<(), SpiFlashError<SPI::Error>> {
-            self.set_register_cmd(spi, Self::ECC_ENABLE_REGISTER, Self::ECC_ENABLE_BIT)
+            self.modify_reg(spi, |config: &mut ConfigReg| config.set_ecc_enable(true))
         }
         /// Disable ECC
         fn disable_ecc(&self, spi: &mut SPI) -> Result<(), SpiFlashError<SPI::Error>> {
-            self.clear_register_cmd(spi, Self::ECC_ENABLE_REGISTER, Self::ECC_ENABLE_BIT)
+            self.modify_reg(spi, |config: &mut ConfigReg| config.set_ecc_enable(false))
         }
         /// Read the ECC status bits        
         fn ecc_status(&self, spi: &mut SPI) -> Result<ECCStatus, SpiFlashError<SPI::Error>> {
-            let status = (self.read_register_cmd(spi, Self::ECC_STATUS_REGISTER)?
-                >> Self::ECC_STATUS_BIT)
-                & 0b11;
-            match status {
+            match self.read_reg::<StatusReg>(spi)?.ecc() {
                 0b00 => Ok(ECCStatus::Ok),
                 0b01 => Ok(ECCStatus::Corrected),
                 0b10 => Ok(ECCStatus::Failed),
@@ -315,18 +472,15 @@ pub mod blocking {
     pub trait ECCBlocking<SPI: SpiDevice, const N: usize>: SpiNandBlocking<SPI, N> + ECC {
         /// Enable ECC
         fn enable_ecc(&self, spi: &mut SPI) -> Result<(), SpiFlashError<SPI::Error>> {
-            self.set_register_cmd(spi, Self::ECC_ENABLE_REGISTER, Self::ECC_ENABLE_MASK)
+            self.modify_reg(spi, |config: &mut ConfigReg| config.set_ecc_enable(true))
         }
         /// Disable ECC
         fn disable_ecc(&self, spi: &mut SPI) -> Result<(), SpiFlashError<SPI::Error>> {
-            self.clear_register_cmd(spi, Self::ECC_ENABLE_REGISTER, Self::ECC_ENABLE_MASK)
+            self.modify_reg(spi, |config: &mut ConfigReg| config.set_ecc_enable(false))
         }
         /// Read the ECC status bits        
         fn ecc_status(&self, spi: &mut SPI) -> Result<ECCStatus, SpiFlashError<SPI::Error>> {
-            let status = (self.read_register_cmd(spi, Self::ECC_STATUS_REGISTER)?
-                >> Self::ECC_STATUS_BIT)
-                & 0b11;
-            match status {
+            match self.read_reg::<StatusReg>(spi)?.ecc() {
                 0b00 => Ok(ECCStatus::Ok),
                 0b01 => Ok(ECCStatus::Corrected),
                 0b10 => Ok(ECCStatus::Failed),
@@ -338,7 +492,10 @@ pub mod blocking {
             &self,
             spi: &mut SPI,
         ) -> Result<ECCThreshold, SpiFlashError<SPI::Error>> {
-            Ok((self.read_register_cmd(spi, Self::ECC_EXTENDED_REGISTERS[0])? >> 4).into())
+            Ok(self
+                .read_reg::<BitFlipThresholdReg>(spi)?
+                .threshold()
+                .into())
         }
 
         /// Set the bit flip detect threshold (1 to 7 bits)
@@ -347,7 +504,9 @@ pub mod blocking {
             spi: &mut SPI,
             threshold: ECCThreshold,
         ) -> Result<(), SpiFlashError<SPI::Error>> {
-            self.write_register_cmd(spi, Self::ECC_EXTENDED_REGISTERS[0], (threshold as u8) << 4)
+            let mut register = BitFlipThresholdReg::default();
+            register.set_threshold(threshold as u8);
+            self.write_reg(spi, register)
         }
 
         /// Get the bit flip count detection status (BFS3->BFS0)
@@ -378,7 +537,9 @@ pub mod blocking {
             spi: &mut SPI,
             strength: ODSStrength,
         ) -> Result<(), SpiFlashError<SPI::Error>> {
-            self.write_register_cmd(spi, Self::ODS_REGISTER, (strength as u8) << Self::ODS_BIT)
+            self.modify_reg(spi, |register: &mut Self::Register| {
+                register.set_output_driver_strength(strength)
+            })
         }
 
         /// Get the output driver strength
@@ -386,8 +547,82 @@ pub mod blocking {
             &self,
             spi: &mut SPI,
         ) -> Result<ODSStrength, SpiFlashError<SPI::Error>> {
-            let status = self.read_register_cmd(spi, Self::ODS_REGISTER)?;
-            Ok(ODSStrength::from((status >> Self::ODS_BIT) & 0b11))
+            Ok(self
+                .read_reg::<Self::Register>(spi)?
+                .output_driver_strength())
+        }
+    }
+
+    /// For W25N that can disable the /HOLD pin
+    pub trait HoldDisableBlocking<SPI: SpiDevice, const N: usize>:
+        HoldDisable + SpiNandBlocking<SPI, N>
+    {
+        /// Disable (true) or enable (false) the /HOLD pin
+        fn set_hold_disabled(
+            &self,
+            spi: &mut SPI,
+            disabled: bool,
+        ) -> Result<(), SpiFlashError<SPI::Error>> {
+            self.modify_reg(spi, |config: &mut ConfigReg| {
+                config.set_hold_disable(disabled)
+            })
+        }
+
+        /// Check if the /HOLD pin is disabled
+        fn is_hold_disabled(&self, spi: &mut SPI) -> Result<bool, SpiFlashError<SPI::Error>> {
+            Ok(self.read_reg::<ConfigReg>(spi)?.hold_disable())
+        }
+    }
+
+    /// For W25N with buffer and continuous read modes.
+    ///
+    /// [spi_nand::SpiNandDevice] reads from the buffer at a column, so it requires buffer
+    /// mode, the default for most parts.
+    pub trait BufferModeBlocking<SPI: SpiDevice, const N: usize>:
+        BufferMode + SpiNandBlocking<SPI, N>
+    {
+        /// Use buffer read mode (true) or continuous read mode (false)
+        fn set_buffer_mode(
+            &self,
+            spi: &mut SPI,
+            enabled: bool,
+        ) -> Result<(), SpiFlashError<SPI::Error>> {
+            self.modify_reg(spi, |config: &mut ConfigReg| {
+                config.set_buffer_mode(enabled)
+            })
+        }
+
+        /// Check if buffer read mode is enabled
+        fn is_buffer_mode(&self, spi: &mut SPI) -> Result<bool, SpiFlashError<SPI::Error>> {
+            Ok(self.read_reg::<ConfigReg>(spi)?.buffer_mode())
+        }
+    }
+
+    /// For W25N with one time programmable (OTP) pages
+    pub trait OTPBlocking<SPI: SpiDevice, const N: usize>: OTP + SpiNandBlocking<SPI, N> {
+        /// Access the OTP pages (true) instead of the main array (false) with the page
+        /// read and program commands
+        fn set_otp_access(
+            &self,
+            spi: &mut SPI,
+            enabled: bool,
+        ) -> Result<(), SpiFlashError<SPI::Error>> {
+            self.modify_reg(spi, |config: &mut ConfigReg| config.set_otp_enable(enabled))
+        }
+
+        /// Check if the OTP pages are accessed instead of the main array
+        fn is_otp_access(&self, spi: &mut SPI) -> Result<bool, SpiFlashError<SPI::Error>> {
+            Ok(self.read_reg::<ConfigReg>(spi)?.otp_enable())
+        }
+
+        /// Check if the OTP pages are permanently locked
+        fn is_otp_locked(&self, spi: &mut SPI) -> Result<bool, SpiFlashError<SPI::Error>> {
+            Ok(self.read_reg::<ConfigReg>(spi)?.otp_lock())
+        }
+
+        /// Check if the protection register is locked
+        fn is_protection_locked(&self, spi: &mut SPI) -> Result<bool, SpiFlashError<SPI::Error>> {
+            Ok(self.read_reg::<ConfigReg>(spi)?.protection_lock())
         }
     }
 
@@ -397,8 +632,7 @@ pub mod blocking {
     {
         /// Check if the LUT is full
         fn is_lut_full(&self, spi: &mut SPI) -> Result<bool, SpiFlashError<SPI::Error>> {
-            let status = self.read_register_cmd(spi, Self::LUT_FULL_REGISTER)?;
-            Ok((status >> Self::LUT_FULL_BIT) & 0b1 == 1)
+            Ok(self.read_reg::<StatusReg>(spi)?.lut_full())
         }
 
         /// Read the lookup table
@@ -406,18 +640,16 @@ pub mod blocking {
             &self,
             spi: &mut SPI,
         ) -> Result<[(BlockIndex, BlockIndex); LUT], SpiFlashError<SPI::Error>> {
-            // This reads 40 blocks. Ideally would be configured by LUT
-            let mut buf = [0; 162];
-            buf[0] = Self::READ_LUT_COMMAND;
-            spi_transfer_in_place(spi, &mut buf)?;
-            let mut lut: [(BlockIndex, BlockIndex); LUT] =
-                [(BlockIndex::new(0), BlockIndex::new(0)); LUT];
-            for (i, chunk) in buf[2..].chunks_exact(4).enumerate().take(LUT) {
-                let block = u16::from_be_bytes([chunk[0], chunk[1]]);
-                let swap = u16::from_be_bytes([chunk[2], chunk[3]]);
-                lut[i] = (BlockIndex::new(block), BlockIndex::new(swap));
-            }
-            Ok(lut)
+            // Command and dummy byte, then 4 bytes for each entry
+            let mut entries = [[0; 4]; LUT];
+            spi_transaction(
+                spi,
+                &mut [
+                    Operation::Write(&[Self::READ_LUT_COMMAND, 0]),
+                    Operation::Read(entries.as_flattened_mut()),
+                ],
+            )?;
+            Ok(entries.map(Self::lut_entry))
         }
 
         /// Swap a block with the lookup table
@@ -428,20 +660,52 @@ pub mod blocking {
             logical: BlockIndex,
             physical: BlockIndex,
         ) -> Result<(), SpiFlashError<SPI::Error>> {
-            let mut buf = [Self::SWAP_BLOCK_COMMAND, 0, 0, 0, 0];
-            buf[1..3].copy_from_slice(&logical.as_u16().to_be_bytes());
-            buf[3..5].copy_from_slice(&physical.as_u16().to_be_bytes());
-            spi_write(spi, &mut buf)?;
+            let buf =
+                Self::swap_block_frame(logical, physical).ok_or(SpiFlashError::OutOfBounds)?;
+            spi_write(spi, &buf)?;
             Ok(())
         }
+
+        /// Add an entry to the lookup table if there is space, waiting for it to complete.
+        ///
+        /// Returns false if the lookup table is full.
+        fn replace_block(
+            &self,
+            spi: &mut SPI,
+            bad: BlockIndex,
+            replacement: BlockIndex,
+        ) -> Result<bool, SpiFlashError<SPI::Error>> {
+            if self.is_lut_full(spi)? {
+                return Ok(false);
+            }
+            self.write_enable_cmd(spi)?;
+            self.swap_block_cmd(spi, bad, replacement)?;
+            self.wait_ready(spi)?;
+            Ok(true)
+        }
     }
 
     // Implement ECCBasicBlocking for ECCBasic devices
-    impl<SPI: SpiDevice, const N: usize, T: ECCBasicBlocking<SPI, N>> ECCBasicBlocking<SPI, N> for T {}
+    impl<SPI: SpiDevice, const N: usize, T: ECCBasic + SpiNandBlocking<SPI, N>>
+        ECCBasicBlocking<SPI, N> for T
+    {
+    }
     // Implement ECCBlocking for ECC devices
     impl<SPI: SpiDevice, const N: usize, T: ECC + SpiNandBlocking<SPI, N>> ECCBlocking<SPI, N> for T {}
     // Implement ODSBlocking for ODS devices
     impl<SPI: SpiDevice, const N: usize, T: ODS + SpiNandBlocking<SPI, N>> ODSBlocking<SPI, N> for T {}
+    // Implement HoldDisableBlocking for HoldDisable devices
+    impl<SPI: SpiDevice, const N: usize, T: HoldDisable + SpiNandBlocking<SPI, N>>
+        HoldDisableBlocking<SPI, N> for T
+    {
+    }
+    // Implement BufferModeBlocking for BufferMode devices
+    impl<SPI: SpiDevice, const N: usize, T: BufferMode + SpiNandBlocking<SPI, N>>
+        BufferModeBlocking<SPI, N> for T
+    {
+    }
+    // Implement OTPBlocking for OTP devices
+    impl<SPI: SpiDevice, const N: usize, T: OTP + SpiNandBlocking<SPI, N>> OTPBlocking<SPI, N> for T {}
     // Implement BBMBlocking for BBM devices
     impl<
             SPI: SpiDevice,
@@ -452,19 +716,68 @@ pub mod blocking {
     {
     }
 
-    impl<SPI: SpiDevice, const B: u32, const ID: u16> SpiNandBlocking<SPI, 2048> for W25N<B, ID> {}
+    /// Check the ECC status of a page read, reading the bit flip count report when the
+    /// corrected bits reached the bit flip detect threshold
+    fn check_read_ecc_report<SPI: SpiDevice, const N: usize, D: SpiNandBlocking<SPI, N>>(
+        device: &D,
+        spi: &mut SPI,
+        page_address: PageIndex,
+        status: u8,
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        let ecc = ECCStatus::from_status_register(status);
+        let corrected_bits = if ecc == ECCStatus::Failing {
+            let mut report = [0; 2];
+            for (byte, register) in report.iter_mut().zip(BIT_FLIP_REPORT_REGISTERS) {
+                *byte = device.read_register_cmd(spi, register)?;
+            }
+            Some(max_bit_flips(report))
+        } else {
+            None
+        };
+        ecc.check(page_address, corrected_bits)
+    }
+
+    impl<SPI: SpiDevice, const B: u32, const ID: u16> SpiNandBlocking<SPI, 2048> for W25N<B, ID> {
+        fn check_read_ecc(
+            &self,
+            spi: &mut SPI,
+            page_address: PageIndex,
+            status: u8,
+        ) -> Result<(), SpiFlashError<SPI::Error>> {
+            if BIT_FLIP_REPORT_IDS.contains(&ID) {
+                check_read_ecc_report(self, spi, page_address, status)
+            } else {
+                ECCStatus::from_status_register(status).check(page_address, None)
+            }
+        }
+    }
+
+    impl<SPI: SpiDevice> SpiNandBlocking<SPI, 4096> for W25N04LW {
+        fn check_read_ecc(
+            &self,
+            spi: &mut SPI,
+            page_address: PageIndex,
+            status: u8,
+        ) -> Result<(), SpiFlashError<SPI::Error>> {
+            check_read_ecc_report(self, spi, page_address, status)
+        }
+    }
 
-    impl<SPI: SpiDevice> SpiNandBlocking<SPI, 4096> for W25N04LW {}
+    impl<SPI: SpiDevice, const B: u32, const ID: u16> SpiNandBlocking<SPI, 2048> for W25M<B, ID> {}
 }
 
 // Implement async trait
-mod asyn {
-    use super::{ECCBasic, ECCThreshold, ODSStrength, BBM, ECC, ODS, W25N, W25N04LW};
-    use embedded_hal_async::spi::SpiDevice;
+pub mod asyn {
+    use super::{
+        max_bit_flips, BitFlipThresholdReg, BufferMode, ConfigReg, ECCBasic, ECCThreshold,
+        HoldDisable, ODSRegister, ODSStrength, StatusReg, BBM, BIT_FLIP_REPORT_IDS,
+        BIT_FLIP_REPORT_REGISTERS, ECC, ODS, OTP, W25M, W25N, W25N04LW,
+    };
+    use embedded_hal_async::spi::{Operation, SpiDevice};
     use embedded_nand::{BlockIndex, PageIndex};
     use spi_nand::{
         cmd_async::{
-            utils::{spi_transfer_in_place, spi_write},
+            utils::{spi_transaction, spi_transfer_in_place, spi_write},
             SpiNandAsync,
         },
         error::SpiFlashError,
@@ -472,27 +785,23 @@ mod asyn {
     };
 
     /// For W25N that implement the basic ECC
+    #[allow(async_fn_in_trait)]
     pub trait ECCBasicAsync<SPI: SpiDevice, const N: usize>:
         SpiNandAsync<SPI, N> + ECCBasic
     {
         /// Enable ECC
         async fn enable_ecc(&self, spi: &mut SPI) -> Result<(), SpiFlashError<SPI::Error>> {
-            self.set_register_cmd(spi, Self::ECC_ENABLE_REGISTER, Self::ECC_ENABLE_BIT)
+            self.modify_reg(spi, |config: &mut ConfigReg| config.set_ecc_enable(true))
                 .await
         }
         /// Disable ECC
         async fn disable_ecc(&self, spi: &mut SPI) -> Result<(), SpiFlashError<SPI::Error>> {
-            self.clear_register_cmd(spi, Self::ECC_ENABLE_REGISTER, Self::ECC_ENABLE_BIT)
+            self.modify_reg(spi, |config: &mut ConfigReg| config.set_ecc_enable(false))
                 .await
         }
         /// Read the ECC status bits        
         async fn ecc_status(&self, spi: &mut SPI) -> Result<ECCStatus, SpiFlashError<SPI::Error>> {
-            let status = (self
-                .read_register_cmd(spi, Self::ECC_STATUS_REGISTER)
-                .await?
-                >> Self::ECC_STATUS_BIT)
-                & 0b11;
-            match status {
+            match self.read_reg::<StatusReg>(spi).await?.ecc() {
                 0b00 => Ok(ECCStatus::Ok),
                 0b01 => Ok(ECCStatus::Corrected),
                 0b10 => Ok(ECCStatus::Failed),
@@ -514,25 +823,21 @@ mod asyn {
     }
 
     /// For W25N that implement the more advanced ECC
+    #[allow(async_fn_in_trait)]
     pub trait ECCAsync<SPI: SpiDevice, const N: usize>: SpiNandAsync<SPI, N> + ECC {
         /// Enable ECC
         async fn enable_ecc(&self, spi: &mut SPI) -> Result<(), SpiFlashError<SPI::Error>> {
-            self.set_register_cmd(spi, Self::ECC_ENABLE_REGISTER, Self::ECC_ENABLE_MASK)
+            self.modify_reg(spi, |config: &mut ConfigReg| config.set_ecc_enable(true))
                 .await
         }
         /// Disable ECC
         async fn disable_ecc(&self, spi: &mut SPI) -> Result<(), SpiFlashError<SPI::Error>> {
-            self.clear_register_cmd(spi, Self::ECC_ENABLE_REGISTER, Self::ECC_ENABLE_MASK)
+            self.modify_reg(spi, |config: &mut ConfigReg| config.set_ecc_enable(false))
                 .await
         }
         /// Read the ECC status bits        
         async fn ecc_status(&self, spi: &mut SPI) -> Result<ECCStatus, SpiFlashError<SPI::Error>> {
-            let status = (self
-                .read_register_cmd(spi, Self::ECC_STATUS_REGISTER)
-                .await?
-                >> Self::ECC_STATUS_BIT)
-                & 0b11;
-            match status {
+            match self.read_reg::<StatusReg>(spi).await?.ecc() {
                 0b00 => Ok(ECCStatus::Ok),
                 0b01 => Ok(ECCStatus::Corrected),
                 0b10 => Ok(ECCStatus::Failed),
@@ -544,10 +849,10 @@ mod asyn {
             &self,
             spi: &mut SPI,
         ) -> Result<ECCThreshold, SpiFlashError<SPI::Error>> {
-            Ok((self
-                .read_register_cmd(spi, Self::ECC_EXTENDED_REGISTERS[0])
+            Ok(self
+                .read_reg::<BitFlipThresholdReg>(spi)
                 .await?
-                >> 4)
+                .threshold()
                 .into())
         }
 
@@ -557,8 +862,9 @@ mod asyn {
             spi: &mut SPI,
             threshold: ECCThreshold,
         ) -> Result<(), SpiFlashError<SPI::Error>> {
-            self.write_register_cmd(spi, Self::ECC_EXTENDED_REGISTERS[0], (threshold as u8) << 4)
-                .await
+            let mut register = BitFlipThresholdReg::default();
+            register.set_threshold(threshold as u8);
+            self.write_reg(spi, register).await
         }
 
         /// Get the bit flip count detection status (BFS3->BFS0)
@@ -586,6 +892,7 @@ mod asyn {
     }
 
     /// For W25N that implement the output driver strength configuration
+    #[allow(async_fn_in_trait)]
     pub trait ODSAsync<SPI: SpiDevice, const N: usize>: ODS + SpiNandAsync<SPI, N> {
         /// Set the output driver strength
         async fn set_output_driver_strength(
@@ -593,28 +900,114 @@ mod asyn {
             spi: &mut SPI,
             strength: ODSStrength,
         ) -> Result<(), SpiFlashError<SPI::Error>> {
-            self.write_register_cmd(spi, Self::ODS_REGISTER, (strength as u8) << Self::ODS_BIT)
-                .await
+            self.modify_reg(spi, |register: &mut Self::Register| {
+                register.set_output_driver_strength(strength)
+            })
+            .await
         }
 
         /// Get the output driver strength
-        async fn get_output_driver_strength<SpiDevice>(
+        async fn get_output_driver_strength(
             &self,
             spi: &mut SPI,
         ) -> Result<ODSStrength, SpiFlashError<SPI::Error>> {
-            let status = self.read_register_cmd(spi, Self::ODS_REGISTER).await?;
-            Ok(ODSStrength::from((status >> Self::ODS_BIT) & 0b11))
+            Ok(self
+                .read_reg::<Self::Register>(spi)
+                .await?
+                .output_driver_strength())
+        }
+    }
+
+    /// For W25N that can disable the /HOLD pin
+    #[allow(async_fn_in_trait)]
+    pub trait HoldDisableAsync<SPI: SpiDevice, const N: usize>:
+        HoldDisable + SpiNandAsync<SPI, N>
+    {
+        /// Disable (true) or enable (false) the /HOLD pin
+        async fn set_hold_disabled(
+            &self,
+            spi: &mut SPI,
+            disabled: bool,
+        ) -> Result<(), SpiFlashError<SPI::Error>> {
+            self.modify_reg(spi, |config: &mut ConfigReg| {
+                config.set_hold_disable(disabled)
+            })
+            .await
+        }
+
+        /// Check if the /HOLD pin is disabled
+        async fn is_hold_disabled(&self, spi: &mut SPI) -> Result<bool, SpiFlashError<SPI::Error>> {
+            Ok(self.read_reg::<ConfigReg>(spi).await?.hold_disable())
+        }
+    }
+
+    /// For W25N with buffer and continuous read modes.
+    ///
+    /// [spi_nand::SpiNandDevice] reads from the buffer at a column, so it requires buffer
+    /// mode, the default for most parts.
+    #[allow(async_fn_in_trait)]
+    pub trait BufferModeAsync<SPI: SpiDevice, const N: usize>:
+        BufferMode + SpiNandAsync<SPI, N>
+    {
+        /// Use buffer read mode (true) or continuous read mode (false)
+        async fn set_buffer_mode(
+            &self,
+            spi: &mut SPI,
+            enabled: bool,
+        ) -> Result<(), SpiFlashError<SPI::Error>> {
+            self.modify_reg(spi, |config: &mut ConfigReg| {
+                config.set_buffer_mode(enabled)
+            })
+            .await
+        }
+
+        /// Check if buffer read mode is enabled
+        async fn is_buffer_mode(&self, spi: &mut SPI) -> Result<bool, SpiFlashError<SPI::Error>> {
+            Ok(self.read_reg::<ConfigReg>(spi).await?.buffer_mode())
+        }
+    }
+
+    /// For W25N with one time programmable (OTP) pages
+    #[allow(async_fn_in_trait)]
+    pub trait OTPAsync<SPI: SpiDevice, const N: usize>: OTP + SpiNandAsync<SPI, N> {
+        /// Access the OTP pages (true) instead of the main array (false) with the page
+        /// read and program commands
+        async fn set_otp_access(
+            &self,
+            spi: &mut SPI,
+            enabled: bool,
+        ) -> Result<(), SpiFlashError<SPI::Error>> {
+            self.modify_reg(spi, |config: &mut ConfigReg| config.set_otp_enable(enabled))
+                .await
+        }
+
+        /// Check if the OTP pages are accessed instead of the main array
+        async fn is_otp_access(&self, spi: &mut SPI) -> Result<bool, SpiFlashError<SPI::Error>> {
+            Ok(self.read_reg::<ConfigReg>(spi).await?.otp_enable())
+        }
+
+        /// Check if the OTP pages are permanently locked
+        async fn is_otp_locked(&self, spi: &mut SPI) -> Result<bool, SpiFlashError<SPI::Error>> {
+            Ok(self.read_reg::<ConfigReg>(spi).await?.otp_lock())
+        }
+
+        /// Check if the protection register is locked
+        async fn is_protection_locked(
+            &self,
+            spi: &mut SPI,
+        ) -> Result<bool, SpiFlashError<SPI::Error>> {
+            Ok(self.read_reg::<ConfigReg>(spi).await?.protection_lock())
         }
     }
 
     /// For W25N that implement the bad block management lookup table (LUT)
+    #[allow(async_fn_in_trait)]
     pub trait BBMAsync<SPI: SpiDevice, const N: usize, const LUT: usize>:
         BBM<LUT> + SpiNandAsync<SPI, N>
     {
         /// Check if the LUT is full
         async fn is_lut_full(&self, spi: &mut SPI) -> Result<bool, SpiFlashError<SPI::Error>> {
-            let status = self.read_register_cmd(spi, Self::LUT_FULL_REGISTER).await?;
-            Ok((status >> Self::LUT_FULL_BIT) & 0b1 == 1)
+            Ok(self.read_reg::<StatusReg>(spi).await?.lut_full())
         }
 
         /// Read the lookup table
@@ -622,18 +1015,17 @@ mod asyn {
             &self,
             spi: &mut SPI,
         ) -> Result<[(BlockIndex, BlockIndex); LUT], SpiFlashError<SPI::Error>> {
-            // This reads 40 blocks. Ideally would be configured by LUT
-            let mut buf = [0; 162];
-            buf[0] = Self::READ_LUT_COMMAND;
-            spi_transfer_in_place(spi, &mut buf).await?;
-            let mut lut: [(BlockIndex, BlockIndex); LUT] =
-                [(BlockIndex::new(0), BlockIndex::new(0)); LUT];
-            for (i, chunk) in buf[2..].chunks_exact(4).enumerate().take(LUT) {
-                let block = u16::from_be_bytes([chunk[0], chunk[1]]);
-                let swap = u16::from_be_bytes([chunk[2], chunk[3]]);
-                lut[i] = (BlockIndex::new(block), BlockIndex::new(swap));
-            }
-            Ok(lut)
+            // Command and dummy byte, then 4 bytes for each entry
+            let mut entries = [[0; 4]; LUT];
+            spi_transaction(
+                spi,
+                &mut [
+                    Operation::Write(&[Self::READ_LUT_COMMAND, 0]),
+                    Operation::Read(entries.as_flattened_mut()),
+                ],
+            )
+            .await?;
+            Ok(entries.map(Self::lut_entry))
         }
 
         /// Swap a block with the lookup table
@@ -644,51 +1036,442 @@ mod asyn {
             logical: BlockIndex,
             physical: BlockIndex,
         ) -> Result<(), SpiFlashError<SPI::Error>> {
-            let mut buf = [Self::SWAP_BLOCK_COMMAND, 0, 0, 0, 0];
-            buf[1..3].copy_from_slice(&logical.as_u16().to_be_bytes());
-            buf[3..5].copy_from_slice(&physical.as_u16().to_be_bytes());
-            spi_write(spi, &mut buf).await?;
+            let buf =
+                Self::swap_block_frame(logical, physical).ok_or(SpiFlashError::OutOfBounds)?;
+            spi_write(spi, &buf).await?;
             Ok(())
         }
+
+        /// Add an entry to the lookup table if there is space, waiting for it to complete.
+        ///
+        /// Returns false if the lookup table is full.
+        async fn replace_block(
+            &self,
+            spi: &mut SPI,
+            bad: BlockIndex,
+            replacement: BlockIndex,
+        ) -> Result<bool, SpiFlashError<SPI::Error>> {
+            if self.is_lut_full(spi).await? {
+                return Ok(false);
+            }
+            self.write_enable_cmd(spi).await?;
+            self.swap_block_cmd(spi, bad, replacement).await?;
+            self.wait_ready(spi).await?;
+            Ok(true)
+        }
     }
 
-    // Implement ECCBasicBlocking for ECCBasic devices
-    impl<SPI: SpiDevice, const N: usize, T: ECCBasicAsync<SPI, N>> ECCBasicAsync<SPI, N> for T {}
-    // Implement ECCBlocking for ECC devices
+    // Implement ECCBasicAsync for ECCBasic devices
+    impl<SPI: SpiDevice, const N: usize, T: ECCBasic + SpiNandAsync<SPI, N>> ECCBasicAsync<SPI, N>
+        for T
+    {
+    }
+    // Implement ECCAsync for ECC devices
     impl<SPI: SpiDevice, const N: usize, T: ECC + SpiNandAsync<SPI, N>> ECCAsync<SPI, N> for T {}
-    // Implement ODSBlocking for ODS devices
+    // Implement ODSAsync for ODS devices
     impl<SPI: SpiDevice, const N: usize, T: ODS + SpiNandAsync<SPI, N>> ODSAsync<SPI, N> for T {}
-    // Implement BBMBlocking for BBM devices
+    // Implement HoldDisableAsync for HoldDisable devices
+    impl<SPI: SpiDevice, const N: usize, T: HoldDisable + SpiNandAsync<SPI, N>>
+        HoldDisableAsync<SPI, N> for T
+    {
+    }
+    // Implement BufferModeAsync for BufferMode devices
+    impl<SPI: SpiDevice, const N: usize, T: BufferMode + SpiNandAsync<SPI, N>>
+        BufferModeAsync<SPI, N> for T
+    {
+    }
+    // Implement OTPAsync for OTP devices
+    impl<SPI: SpiDevice, const N: usize, T: OTP + SpiNandAsync<SPI, N>> OTPAsync<SPI, N> for T {}
+    // Implement BBMAsync for BBM devices
     impl<SPI: SpiDevice, const N: usize, const LUT: usize, T: BBM<LUT> + SpiNandAsync<SPI, N>>
         BBMAsync<SPI, N, LUT> for T
     {
     }
 
+    /// Check the ECC status of a page read, reading the bit flip count report when the
+    /// corrected bits reached the bit flip detect threshold
+    async fn check_read_ecc_report<SPI: SpiDevice, const N: usize, D: SpiNandAsync<SPI, N>>(
+        device: &D,
+        spi: &mut SPI,
+        page_address: PageIndex,
+        status: u8,
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        let ecc = ECCStatus::from_status_register(status);
+        let corrected_bits = if ecc == ECCStatus::Failing {
+            let mut report = [0; 2];
+            for (byte, register) in report.iter_mut().zip(BIT_FLIP_REPORT_REGISTERS) {
+                *byte = device.read_register_cmd(spi, register).await?;
+            }
+            Some(max_bit_flips(report))
+        } else {
+            None
+        };
+        ecc.check(page_address, corrected_bits)
+    }
+
     impl<SPI: embedded_hal_async::spi::SpiDevice, const B: u32, const ID: u16>
         SpiNandAsync<SPI, 2048> for W25N<B, ID>
     {
+        async fn check_read_ecc(
+            &self,
+            spi: &mut SPI,
+            page_address: PageIndex,
+            status: u8,
+        ) -> Result<(), SpiFlashError<SPI::Error>> {
+            if BIT_FLIP_REPORT_IDS.contains(&ID) {
+                check_read_ecc_report(self, spi, page_address, status).await
+            } else {
+                ECCStatus::from_status_register(status).check(page_address, None)
+            }
+        }
     }
 
-    impl<SPI: embedded_hal_async::spi::SpiDevice> SpiNandAsync<SPI, 4096> for W25N04LW {}
+    impl<SPI: embedded_hal_async::spi::SpiDevice> SpiNandAsync<SPI, 4096> for W25N04LW {
+        async fn check_read_ecc(
+            &self,
+            spi: &mut SPI,
+            page_address: PageIndex,
+            status: u8,
+        ) -> Result<(), SpiFlashError<SPI::Error>> {
+            check_read_ecc_report(self, spi, page_address, status).await
+        }
+    }
+
+    impl<SPI: embedded_hal_async::spi::SpiDevice, const B: u32, const ID: u16>
+        SpiNandAsync<SPI, 2048> for W25M<B, ID>
+    {
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::blocking::BBMBlocking;
-    use super::blocking::ECCBasicBlocking;
-    use super::blocking::ECCBlocking;
-    use super::blocking::ODSBlocking;
-    use super::W25N04LW;
-    use super::W25N512G;
+    extern crate std;
+
+    use embassy_futures::block_on;
+    use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::spi::{Mock, Transaction};
+    use embedded_nand::{
+        BlockIndex, BlockStatus, NandFlash, NandFlashError, NandFlashErrorKind, PageIndex,
+        SplitProgram,
+    };
+    use spi_nand::{
+        cmd_async::SpiNandAsync, error::SpiFlashError, BusLock, LockedDevice, LockedError,
+        LockedWait, NoWait, SpiNandDevice,
+    };
+    use std::{vec, vec::Vec};
+
+    use super::asyn::{ECCAsync, ODSAsync};
+    use super::{
+        ConfigReg, ECCThreshold, ODSRegister, ODSStrength, ProtectionReg, StatusReg, W25N02KV,
+        W25N512G,
+    };
+
+    /// Expected transactions to read `value` from `register`
+    fn read_register(register: u8, value: u8) -> [Transaction<u8>; 3] {
+        [
+            Transaction::transaction_start(),
+            Transaction::transfer_in_place(vec![0x0F, register, 0], vec![0, 0, value]),
+            Transaction::transaction_end(),
+        ]
+    }
+
+    /// Expected transactions to write `value` to `register`
+    fn write_register(register: u8, value: u8) -> [Transaction<u8>; 3] {
+        [
+            Transaction::transaction_start(),
+            Transaction::write_vec(vec![0x1F, register, value]),
+            Transaction::transaction_end(),
+        ]
+    }
+
+    /// Expected transactions to erase the block starting at `page`
+    fn erase_block(page: u32) -> Vec<Transaction<u8>> {
+        let mut expected = vec![
+            Transaction::transaction_start(),
+            Transaction::write_vec(vec![0x06]),
+            Transaction::transaction_end(),
+            Transaction::transaction_start(),
+            Transaction::write_vec(vec![
+                0xD8,
+                (page >> 16) as u8,
+                (page >> 8) as u8,
+                page as u8,
+            ]),
+            Transaction::transaction_end(),
+        ];
+        // Ready, then erase not failed
+        expected.extend(read_register(0xC0, 0));
+        expected.extend(read_register(0xC0, 0));
+        expected
+    }
 
     #[test]
-    fn features() {
-        let device = W25N512G::new();
-        let other = W25N04LW::new();
-
-        // device.
-        // device
-        //     .set_output_driver_strength(spi, super::ODSStrength::Full)
-        //     .unwrap();
+    fn erase_range() {
+        const BLOCK_SIZE: u64 = 64 * 2048;
+        let mut expected = Vec::new();
+        expected.extend(erase_block(64));
+        expected.extend(erase_block(128));
+
+        let mut spi = Mock::new(&expected);
+        let mut flash = SpiNandDevice::new(spi.clone(), W25N02KV::new());
+        // Erases [from, to), so blocks 1 and 2
+        flash.erase(BLOCK_SIZE, 3 * BLOCK_SIZE).unwrap();
+        assert_eq!(
+            flash
+                .erase(3 * BLOCK_SIZE, 2 * BLOCK_SIZE)
+                .unwrap_err()
+                .kind(),
+            NandFlashErrorKind::OutOfBounds
+        );
+        assert_eq!(
+            flash
+                .erase(BLOCK_SIZE, 2049 * BLOCK_SIZE)
+                .unwrap_err()
+                .kind(),
+            NandFlashErrorKind::OutOfBounds
+        );
+        spi.done();
+    }
+
+    /// Tries a transaction on another device sharing the bus lock before each NAND
+    /// transaction, recording whether it was refused
+    struct Interleaved<'a> {
+        spi: Mock<u8>,
+        other: LockedDevice<'a, Mock<u8>>,
+        refused: Vec<bool>,
+    }
+
+    impl ErrorType for Interleaved<'_> {
+        type Error = <Mock<u8> as ErrorType>::Error;
+    }
+
+    impl SpiDevice for Interleaved<'_> {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+            let result = self.other.write(&[0x55]);
+            self.refused.push(result == Err(LockedError::Locked));
+            self.spi.transaction(operations)
+        }
+    }
+
+    #[test]
+    fn erase_locks_bus() {
+        let lock = BusLock::new();
+        let mut spi = Mock::new(&erase_block(64));
+        // Only the transactions between the status polls reach the other device
+        let other_write = [
+            Transaction::transaction_start(),
+            Transaction::write_vec(vec![0x55]),
+            Transaction::transaction_end(),
+        ];
+        let mut other = Mock::new(&[other_write.clone(), other_write].concat());
+        let interleaved = Interleaved {
+            spi: spi.clone(),
+            other: LockedDevice::new(other.clone(), &lock),
+            refused: Vec::new(),
+        };
+        let mut flash = SpiNandDevice::new(interleaved, W25N02KV::new())
+            .with_busy_wait(LockedWait::new(&lock, NoWait));
+        flash.erase_block(BlockIndex::new(1)).unwrap();
+        // Write enable and block erase, then the status polls
+        assert_eq!(flash.spi.refused, [true, true, false, false]);
+        assert!(!lock.is_held());
+        spi.done();
+        other.done();
+    }
+
+    #[test]
+    fn split_program() {
+        let data = vec![0x5A; 2048];
+        let mut expected = vec![
+            Transaction::transaction_start(),
+            Transaction::write_vec(vec![0x06]),
+            Transaction::transaction_end(),
+            Transaction::transaction_start(),
+            Transaction::write_vec(vec![0x02, 0, 0]),
+            Transaction::write_vec(data.clone()),
+            Transaction::transaction_end(),
+            Transaction::transaction_start(),
+            Transaction::write_vec(vec![0x10, 0, 0, 64]),
+            Transaction::transaction_end(),
+        ];
+        // Nothing is polled until the program is finished
+        let mut spi = Mock::new(&expected);
+        let mut flash = SpiNandDevice::new(spi.clone(), W25N02KV::new());
+        flash.program_start(PageIndex::new(64), &data).unwrap();
+        spi.done();
+
+        // Ready, then program not failed
+        expected = read_register(0xC0, 0).to_vec();
+        expected.extend(read_register(0xC0, 0));
+        spi.update_expectations(&expected);
+        flash.program_finish(PageIndex::new(64)).unwrap();
+        spi.done();
+    }
+
+    /// Expected transactions to read `data` from the start of `column` of `page`
+    fn read_page(page: u32, column: u16, data: Vec<u8>) -> Vec<Transaction<u8>> {
+        let mut expected = vec![
+            Transaction::transaction_start(),
+            Transaction::write_vec(vec![
+                0x13,
+                (page >> 16) as u8,
+                (page >> 8) as u8,
+                page as u8,
+            ]),
+            Transaction::transaction_end(),
+        ];
+        // Ready, with no ECC errors
+        expected.extend(read_register(0xC0, 0));
+        expected.extend([
+            Transaction::transaction_start(),
+            Transaction::write_vec(vec![0x03, (column >> 8) as u8, column as u8, 0]),
+            Transaction::read_vec(data),
+            Transaction::transaction_end(),
+        ]);
+        expected
+    }
+
+    /// Block status only reads the marker, the erased check reads the page at once
+    #[test]
+    fn block_status_and_erased() {
+        let mut expected = read_page(64, 2048, vec![0xFF; 4]);
+        expected.extend(read_page(64, 0, vec![0xFF; 2048]));
+        let mut data = vec![0xFF; 2048];
+        data[100] = 0;
+        expected.extend(read_page(128, 0, data));
+
+        let mut spi = Mock::new(&expected);
+        let mut flash = SpiNandDevice::new(spi.clone(), W25N02KV::new());
+        assert_eq!(
+            flash.block_status(BlockIndex::new(1)).unwrap(),
+            BlockStatus::Ok
+        );
+        let mut buf = [0; 2048];
+        assert!(flash
+            .block_erased_blocking(BlockIndex::new(1), &mut buf)
+            .unwrap());
+        assert!(!flash
+            .block_erased_blocking(BlockIndex::new(2), &mut buf)
+            .unwrap());
+        spi.done();
+    }
+
+    /// Async spare area reads only touch the free regions
+    #[test]
+    fn read_oob_async() {
+        let mut expected = read_page(64, 2052, vec![1, 2, 3, 4]);
+        expected.extend([
+            Transaction::transaction_start(),
+            Transaction::write_vec(vec![0x03, (2068u16 >> 8) as u8, 2068u16 as u8, 0]),
+            Transaction::read_vec(vec![5, 6]),
+            Transaction::transaction_end(),
+        ]);
+
+        let mut spi = Mock::new(&expected);
+        let mut flash = SpiNandDevice::new(spi.clone(), W25N512G::new());
+        let mut oob = [0; 6];
+        block_on(flash.read_oob_async(PageIndex::new(64), &mut oob)).unwrap();
+        assert_eq!(oob, [1, 2, 3, 4, 5, 6]);
+        spi.done();
+    }
+
+    #[test]
+    fn sleeping_refuses_operations() {
+        let expected = [
+            Transaction::transaction_start(),
+            Transaction::write_vec(vec![0xB9]),
+            Transaction::transaction_end(),
+            Transaction::transaction_start(),
+            Transaction::write_vec(vec![0xAB]),
+            Transaction::transaction_end(),
+        ];
+        let mut spi = Mock::new(&expected);
+        let mut flash = SpiNandDevice::new(spi.clone(), W25N02KV::new());
+        flash.sleep_blocking(&mut NoopDelay).unwrap();
+        // Nothing is sent while asleep
+        let mut buf = [0; 4];
+        assert!(matches!(
+            flash.read(0, &mut buf),
+            Err(SpiFlashError::Sleeping)
+        ));
+        assert!(matches!(
+            flash.erase_block(BlockIndex::new(1)),
+            Err(SpiFlashError::Sleeping)
+        ));
+        assert!(matches!(
+            flash.jedec_blocking(),
+            Err(SpiFlashError::Sleeping)
+        ));
+        flash.wake_blocking(&mut NoopDelay).unwrap();
+        assert!(!flash.is_sleeping());
+        spi.done();
+    }
+
+    #[test]
+    fn registers_async() {
+        let mut expected: Vec<Transaction<u8>> = Vec::new();
+        // Default state
+        expected.extend(read_register(0xA0, 0b0111_1100));
+        expected.extend(read_register(0xB0, 0b0001_1001));
+        expected.extend(read_register(0xC0, 0));
+        // Disable block protection
+        expected.extend(read_register(0xA0, 0b0111_1100));
+        expected.extend(write_register(0xA0, 0b0000_0100));
+        // Driver strength, read modify write keeps the other bits
+        let mut config = 0b0001_1001;
+        for strength in 0..=3 {
+            expected.extend(read_register(0xB0, config));
+            config = (config & !0b110) | (strength << 1);
+            expected.extend(write_register(0xB0, config));
+            expected.extend(read_register(0xB0, config));
+        }
+        // ECC threshold
+        for threshold in 1..=7 {
+            expected.extend(write_register(0x10, threshold << 4));
+            expected.extend(read_register(0x10, threshold << 4));
+        }
+
+        let mut spi = Mock::new(&expected);
+        let device = W25N02KV::new();
+        block_on(async {
+            let protection: ProtectionReg = device.read_reg(&mut spi).await.unwrap();
+            assert_eq!(protection.block_protect(), 0b1111);
+            assert!(protection.top_bottom());
+            let config: ConfigReg = device.read_reg(&mut spi).await.unwrap();
+            assert!(config.ecc_enable());
+            assert!(config.buffer_mode());
+            assert!(config.hold_disable());
+            assert_eq!(config.output_driver_strength(), ODSStrength::Full);
+            let status: StatusReg = device.read_reg(&mut spi).await.unwrap();
+            assert_eq!(status, StatusReg::default());
+
+            device.disable_block_protection(&mut spi).await.unwrap();
+
+            for strength in 0..=3 {
+                let strength = ODSStrength::from(strength);
+                device
+                    .set_output_driver_strength(&mut spi, strength)
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    device.get_output_driver_strength(&mut spi).await.unwrap(),
+                    strength
+                );
+            }
+
+            for threshold in 1..=7 {
+                let threshold = ECCThreshold::from(threshold);
+                device
+                    .ecc_set_bit_flip_threshold(&mut spi, threshold)
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    device.ecc_bit_flip_threshold(&mut spi).await.unwrap(),
+                    threshold
+                );
+            }
+        });
+        spi.done();
     }
 }
@@ -0,0 +1,280 @@
+//! Waiting for long operations on a shared SPI bus.
+//!
+//! Erasing a block or programming a page keeps the device busy for up to a few
+//! milliseconds. By default [crate::SpiNandDevice] polls the status register back to
+//! back until it is done, which keeps other devices on the bus (or other tasks) waiting.
+//!
+//! Each command is a separate [embedded_hal::spi::SpiDevice] transaction with its own
+//! chip select, so when the bus is shared (e.g. `embedded_hal_bus::spi::RefCellDevice` or
+//! an `embassy_sync::mutex::Mutex` shared bus) the bus is only held for one command at a
+//! time. A [BusyWait] given with [crate::SpiNandDevice::with_busy_wait] is called between
+//! status polls, leaving the bus free for other devices while the NAND is busy.
+//!
+//! The write enable, program load and execute (or erase) commands that start an operation
+//! must not have other transactions between them. The [BusyWait] is locked before the
+//! first of them and unlocked after the last. [NoWait] and [PollDelay] don't lock, so on
+//! a shared bus wrap the wait in a [LockedWait] and the other devices in a
+//! [LockedDevice], all sharing one [BusLock]:
+//!
+//! ```ignore
+//! let lock = BusLock::new();
+//! let mut sensor = LockedDevice::new(RefCellDevice::new(&bus, sensor_cs, NoDelay)?, &lock);
+//! let mut flash = SpiNandDevice::new(RefCellDevice::new(&bus, flash_cs, NoDelay)?, W25N01GV)
+//!     .with_busy_wait(LockedWait::new(&lock, PollDelay::new(Delay, 100)));
+//! ```
+
+use core::{
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use embedded_hal::spi::{self, Operation};
+
+/// Called between polls of the busy flag during long operations (erase and program)
+pub trait BusyWait {
+    /// Wait before the next poll
+    fn wait(&mut self);
+
+    /// Take the bus before sending the commands that start an erase or program.
+    /// Does nothing by default
+    fn lock(&mut self) {}
+
+    /// Release the bus taken with [BusyWait::lock]. Does nothing by default
+    fn unlock(&mut self) {}
+}
+
+/// Async version of [BusyWait]
+#[allow(async_fn_in_trait)]
+pub trait BusyWaitAsync {
+    /// Wait before the next poll, letting other tasks use the bus
+    async fn wait(&mut self);
+
+    /// Take the bus before sending the commands that start an erase or program,
+    /// waiting until it is free. Does nothing by default
+    async fn lock(&mut self) {}
+
+    /// Release the bus taken with [BusyWaitAsync::lock]. Does nothing by default
+    fn unlock(&mut self) {}
+}
+
+/// Poll the busy flag continuously. This is the default
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NoWait;
+
+impl BusyWait for NoWait {
+    fn wait(&mut self) {}
+}
+
+impl BusyWaitAsync for NoWait {
+    async fn wait(&mut self) {}
+}
+
+/// Wait a fixed time between polls of the busy flag with a delay
+#[derive(Debug, Clone, Copy)]
+pub struct PollDelay<DELAY> {
+    delay: DELAY,
+    interval_us: u32,
+}
+
+impl<DELAY> PollDelay<DELAY> {
+    /// Poll every `interval_us` microseconds using `delay`
+    pub fn new(delay: DELAY, interval_us: u32) -> Self {
+        Self { delay, interval_us }
+    }
+}
+
+impl<DELAY: embedded_hal::delay::DelayNs> BusyWait for PollDelay<DELAY> {
+    fn wait(&mut self) {
+        self.delay.delay_us(self.interval_us);
+    }
+}
+
+impl<DELAY: embedded_hal_async::delay::DelayNs> BusyWaitAsync for PollDelay<DELAY> {
+    async fn wait(&mut self) {
+        self.delay.delay_us(self.interval_us).await;
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<DELAY> defmt::Format for PollDelay<DELAY> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "PollDelay({}us)", self.interval_us);
+    }
+}
+
+/// Lock shared by the devices on a bus, held by the NAND while it starts an erase or
+/// program and by the other devices for each transaction.
+///
+/// Like `RefCellDevice` and a `NoopRawMutex` shared bus it is only for use within one
+/// thread or executor, not from interrupts.
+#[derive(Debug, Default)]
+pub struct BusLock {
+    held: Cell<bool>,
+}
+
+impl BusLock {
+    /// Create an unlocked lock
+    pub const fn new() -> Self {
+        Self {
+            held: Cell::new(false),
+        }
+    }
+
+    /// Returns true if the lock is held
+    pub fn is_held(&self) -> bool {
+        self.held.get()
+    }
+
+    /// Take the lock, returns false if it is already held
+    pub fn try_lock(&self) -> bool {
+        !self.held.replace(true)
+    }
+
+    /// Release the lock
+    pub fn unlock(&self) {
+        self.held.set(false);
+    }
+
+    /// Take the lock, waiting for it to be released
+    async fn lock(&self) {
+        while !self.try_lock() {
+            YieldNow(false).await;
+        }
+    }
+}
+
+/// Yield to the executor once
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// [BusyWait] that holds a [BusLock] while an erase or program is started, waiting
+/// with `W` between polls
+#[derive(Debug)]
+pub struct LockedWait<'a, W> {
+    lock: &'a BusLock,
+    wait: W,
+}
+
+impl<'a, W> LockedWait<'a, W> {
+    /// Hold `lock` while starting operations and wait with `wait`
+    pub fn new(lock: &'a BusLock, wait: W) -> Self {
+        Self { lock, wait }
+    }
+}
+
+impl<W: BusyWait> BusyWait for LockedWait<'_, W> {
+    fn wait(&mut self) {
+        self.wait.wait();
+    }
+
+    /// Panics if the lock is held, which can only happen if a [LockedDevice] is used from
+    /// an interrupt
+    fn lock(&mut self) {
+        assert!(self.lock.try_lock(), "Bus lock already held");
+    }
+
+    fn unlock(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+impl<W: BusyWaitAsync> BusyWaitAsync for LockedWait<'_, W> {
+    async fn wait(&mut self) {
+        self.wait.wait().await;
+    }
+
+    async fn lock(&mut self) {
+        self.lock.lock().await;
+    }
+
+    fn unlock(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+/// Error returned by a [LockedDevice]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LockedError<E> {
+    /// Error from the wrapped device
+    Spi(E),
+    /// The [BusLock] is held by the NAND, only returned by the blocking transaction
+    Locked,
+}
+
+impl<E: spi::Error> spi::Error for LockedError<E> {
+    fn kind(&self) -> spi::ErrorKind {
+        match self {
+            LockedError::Spi(e) => e.kind(),
+            LockedError::Locked => spi::ErrorKind::Other,
+        }
+    }
+}
+
+/// [spi::SpiDevice] for the other devices on a bus shared with the NAND, holding a
+/// [BusLock] for each transaction so none run between the commands that start an erase
+/// or program.
+///
+/// The blocking transaction fails with [LockedError::Locked] if the lock is held, the
+/// async transaction waits for it.
+#[derive(Debug)]
+pub struct LockedDevice<'a, SPI> {
+    spi: SPI,
+    lock: &'a BusLock,
+}
+
+impl<'a, SPI> LockedDevice<'a, SPI> {
+    /// Wrap `spi`, sharing `lock` with the NAND
+    pub fn new(spi: SPI, lock: &'a BusLock) -> Self {
+        Self { spi, lock }
+    }
+
+    /// Release the wrapped device
+    pub fn release(self) -> SPI {
+        self.spi
+    }
+}
+
+impl<SPI: spi::ErrorType> spi::ErrorType for LockedDevice<'_, SPI> {
+    type Error = LockedError<SPI::Error>;
+}
+
+impl<SPI: spi::SpiDevice> spi::SpiDevice for LockedDevice<'_, SPI> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        if !self.lock.try_lock() {
+            return Err(LockedError::Locked);
+        }
+        let result = self.spi.transaction(operations);
+        self.lock.unlock();
+        result.map_err(LockedError::Spi)
+    }
+}
+
+impl<SPI: embedded_hal_async::spi::SpiDevice> embedded_hal_async::spi::SpiDevice
+    for LockedDevice<'_, SPI>
+{
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        self.lock.lock().await;
+        let result = self.spi.transaction(operations).await;
+        self.lock.unlock();
+        result.map_err(LockedError::Spi)
+    }
+}
@@ -0,0 +1,859 @@
+//! Sans-IO command frames.
+//!
+//! The bytes sent for each command, and the decoding of the bytes read back, are built
+//! here without touching the SPI bus. [crate::cmd_blocking::SpiNandBlocking] and
+//! [crate::cmd_async::SpiNandAsync] only send the frames, so the wire format is written
+//! once and the blocking and async commands can't drift apart. The traits themselves are
+//! generated from a single definition, see `spi_nand_traits!`.
+//!
+//! ```
+//! use embedded_nand::{ColumnAddress, PageIndex};
+//! use spi_nand::cmd;
+//!
+//! // Page read (0x13) of page 0x012345, a 24 bit row address
+//! assert_eq!(cmd::row_frame(0x13, PageIndex::new(0x012345)), [0x13, 0x01, 0x23, 0x45]);
+//! // Read from the buffer (0x03) at column 0x0804, followed by a dummy byte
+//! assert_eq!(
+//!     cmd::read_buffer_frame(0x03, ColumnAddress::new(0x0804)),
+//!     [0x03, 0x08, 0x04, 0x00]
+//! );
+//! ```
+
+use embedded_nand::{BlockStatus, ColumnAddress, PageIndex};
+
+use crate::{error::SpiFlashError, JedecID};
+
+/// Command with a 24 bit row (page) address, e.g. page read, program execute and block erase
+pub fn row_frame(command: u8, page: PageIndex) -> [u8; 4] {
+    let pa = page.as_u32();
+    [command, (pa >> 16) as u8, (pa >> 8) as u8, pa as u8]
+}
+
+/// Command with a 16 bit column address, e.g. program load. The data follows the frame
+pub fn column_frame(command: u8, ca: ColumnAddress) -> [u8; 3] {
+    let ca = ca.as_u16();
+    [command, (ca >> 8) as u8, ca as u8]
+}
+
+/// Read from the device buffer at a column address, with the dummy byte before the data
+pub fn read_buffer_frame(command: u8, ca: ColumnAddress) -> [u8; 4] {
+    let [command, high, low] = column_frame(command, ca);
+    [command, high, low, 0]
+}
+
+/// Get feature frame, transferred in place. The value is read back with [register_value]
+pub fn register_read_frame(command: u8, register: u8) -> [u8; 3] {
+    [command, register, 0]
+}
+
+/// Value of the register from a transferred [register_read_frame]
+pub fn register_value(frame: &[u8; 3]) -> u8 {
+    frame[2]
+}
+
+/// Set feature frame
+pub fn register_write_frame(command: u8, register: u8, data: u8) -> [u8; 3] {
+    [command, register, data]
+}
+
+/// Read JEDEC ID frame: the command, a dummy byte then the manufacturer and 2 device ID
+/// bytes. Transferred in place and decoded with [jedec_id]
+pub fn jedec_frame(command: u8) -> [u8; 5] {
+    [command, 0, 0, 0, 0]
+}
+
+/// JEDEC ID from a transferred [jedec_frame]
+pub fn jedec_id(frame: &[u8; 5]) -> JedecID {
+    JedecID::new(frame[2], ((frame[3] as u16) << 8) + frame[4] as u16)
+}
+
+/// Status of a block from the first bytes of the spare area of its first page, None if
+/// the block is good.
+///
+/// `runtime_marker` is reported as [BlockStatus::Worn] and any other marker as
+/// [BlockStatus::FactoryBad]
+pub fn bad_block_status(marker: &[u8; 4], runtime_marker: &[u8; 4]) -> Option<BlockStatus> {
+    if marker == runtime_marker {
+        Some(BlockStatus::Worn)
+    } else if marker[0] != 0xFF || marker[1] != 0xFF {
+        Some(BlockStatus::FactoryBad)
+    } else {
+        None
+    }
+}
+
+/// Error for a failed program or erase of `page`, given the protection register read
+/// after the failure.
+///
+/// [SpiFlashError::WriteProtected] if any of the `block_protect_mask` bits are set,
+/// otherwise `error`
+pub fn write_failed_error<SE>(
+    protection: u8,
+    block_protect_mask: u8,
+    page: PageIndex,
+    error: SpiFlashError<SE>,
+) -> SpiFlashError<SE> {
+    if protection & block_protect_mask != 0 {
+        SpiFlashError::WriteProtected(page)
+    } else {
+        error
+    }
+}
+
+/// Declare the command traits and the `utils` SPI wrappers in the invoking module.
+///
+/// [crate::cmd_blocking] and [crate::cmd_async] are both generated from this one
+/// definition, so the compound commands (reads with their ECC check, programs and erases
+/// with their failure check, bad block marking) can't drift apart either. `async` is put
+/// before every function and `await` after every SPI and command call, both empty for the
+/// blocking traits. `SpiDevice` and `Operation` must be in scope from the matching
+/// embedded-hal crate.
+macro_rules! spi_nand_traits {
+    (
+        $(#[$nand_attr:meta])*
+        pub trait $nand:ident;
+        $(#[$lut_attr:meta])*
+        pub trait $lut:ident;
+        $(#[$oob_attr:meta])*
+        pub trait $oob:ident;
+        async: [$($async:tt)*],
+        await: [$($await:tt)*] $(,)?
+    ) => {
+        use embedded_nand::{BlockIndex, BlockStatus, ColumnAddress, PageIndex};
+        use utils::{spi_transaction, spi_transfer_in_place, spi_write};
+
+        use $crate::{
+            cmd,
+            error::SpiFlashError,
+            register::{Register, StatusReg, WritableRegister},
+            ECCStatus, JedecID, SpiNand,
+        };
+
+        $(#[$nand_attr])*
+        pub trait $nand<SPI: SpiDevice, const N: usize>: SpiNand<N> {
+            // ============= Commands =============
+
+            /// Issue a reset command to the flash device
+            $($async)* fn reset_cmd(&self, spi: &mut SPI) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi_write(spi, &[Self::RESET_COMMAND])$($await)*
+            }
+
+            /// Issue a hard reset command to the flash device
+            $($async)* fn hard_reset_cmd(
+                &self,
+                spi: &mut SPI,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi_write(spi, &[Self::RESET_ENABLE_COMMAND])$($await)*?;
+                spi_write(spi, &[Self::HARD_RESET_COMMAND])$($await)*
+            }
+
+            /// Read the JEDEC ID of the flash device
+            /// command then byte then ID then 2 device ID bytes
+            $($async)* fn read_jedec_id_cmd(
+                &self,
+                spi: &mut SPI,
+            ) -> Result<JedecID, SpiFlashError<SPI::Error>> {
+                let mut frame = cmd::jedec_frame(Self::JEDEC_COMMAND);
+                spi_transfer_in_place(spi, &mut frame)$($await)*?;
+                Ok(cmd::jedec_id(&frame))
+            }
+
+            /// Read a register
+            /// Warning: does not check if the register is valid
+            $($async)* fn read_register_cmd(
+                &self,
+                spi: &mut SPI,
+                register: u8,
+            ) -> Result<u8, SpiFlashError<SPI::Error>> {
+                let mut frame =
+                    cmd::register_read_frame(Self::STATUS_REGISTER_READ_COMMAND, register);
+                spi_transfer_in_place(spi, &mut frame)$($await)*?;
+                Ok(cmd::register_value(&frame))
+            }
+
+            /// Write a register
+            /// Warning: does not check if the register is valid
+            /// Warning: Some registers / bits are not writable
+            $($async)* fn write_register_cmd(
+                &self,
+                spi: &mut SPI,
+                register: u8,
+                data: u8,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi_write(
+                    spi,
+                    &cmd::register_write_frame(Self::STATUS_REGISTER_WRITE_COMMAND, register, data),
+                )$($await)*
+            }
+
+            /// Read a typed register, see [crate::register]
+            $($async)* fn read_reg<R: Register>(
+                &self,
+                spi: &mut SPI,
+            ) -> Result<R, SpiFlashError<SPI::Error>> {
+                Ok(R::from(self.read_register_cmd(spi, R::ADDRESS)$($await)*?))
+            }
+
+            /// Write a typed register, see [crate::register]
+            $($async)* fn write_reg<R: WritableRegister>(
+                &self,
+                spi: &mut SPI,
+                register: R,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                self.write_register_cmd(spi, R::ADDRESS, register.into())$($await)*
+            }
+
+            /// Read a typed register, change it with `f` and write it back
+            $($async)* fn modify_reg<R: WritableRegister>(
+                &self,
+                spi: &mut SPI,
+                f: impl FnOnce(&mut R),
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                let mut register = self.read_reg::<R>(spi)$($await)*?;
+                f(&mut register);
+                self.write_reg(spi, register)$($await)*
+            }
+
+            /// Set bits in a register
+            $($async)* fn set_register_cmd(
+                &self,
+                spi: &mut SPI,
+                register: u8,
+                mask: u8,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                let data = self.read_register_cmd(spi, register)$($await)*? | mask;
+                self.write_register_cmd(spi, register, data)$($await)*
+            }
+
+            /// Clear bits in a register
+            $($async)* fn clear_register_cmd(
+                &self,
+                spi: &mut SPI,
+                register: u8,
+                mask: u8,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                let data = self.read_register_cmd(spi, register)$($await)*? & !mask;
+                self.write_register_cmd(spi, register, data)$($await)*
+            }
+
+            /// Read a page into the device buffer/register
+            $($async)* fn page_read_cmd(
+                &self,
+                spi: &mut SPI,
+                address: PageIndex,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi_write(spi, &cmd::row_frame(Self::PAGE_READ_COMMAND, address))$($await)*
+            }
+
+            /// Read bytes of a page from the device buffer/register starting from column address
+            $($async)* fn page_read_buffer_cmd(
+                &self,
+                spi: &mut SPI,
+                ca: ColumnAddress,
+                buf: &mut [u8],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                let frame = cmd::read_buffer_frame(Self::PAGE_READ_BUFFER_COMMAND, ca);
+                let operations = &mut [Operation::Write(&frame), Operation::Read(buf)];
+                spi_transaction(spi, operations)$($await)*
+            }
+
+            /// Enable writing to the flash device
+            $($async)* fn write_enable_cmd(
+                &self,
+                spi: &mut SPI,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi_write(spi, &[Self::WRITE_ENABLE_COMMAND])$($await)*
+            }
+
+            /// Disable writing to the flash device
+            $($async)* fn write_disable_cmd(
+                &self,
+                spi: &mut SPI,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi_write(spi, &[Self::WRITE_DISABLE_COMMAND])$($await)*
+            }
+
+            /// Write to status register 1
+            /// This is used to set the block protection bits and status protection bits
+            $($async)* fn write_status_register_1_cmd(
+                &self,
+                spi: &mut SPI,
+                data: u8,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi_write(
+                    spi,
+                    &cmd::register_write_frame(Self::STATUS_REGISTER_WRITE_COMMAND, 0xA0, data),
+                )$($await)*
+            }
+
+            /// Erase a block of flash memory
+            $($async)* fn erase_block_cmd(
+                &self,
+                spi: &mut SPI,
+                block_address: BlockIndex,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                let address = PageIndex::from_block_address(block_address, Self::PAGES_PER_BLOCK);
+                spi_write(spi, &cmd::row_frame(Self::BLOCK_ERASE_COMMAND, address))$($await)*
+            }
+
+            /// Write bytes to the device buffer/register
+            ///
+            /// This will reset the buffer/register to 0xFF
+            ///
+            /// Use [Self::write_enable_cmd] to enable writing before this command
+            ///
+            /// Use [Self::program_random_load_cmd] to write without resetting
+            ///
+            /// Use [Self::program_execute_cmd] to write the buffer/register to a page
+            ///
+            $($async)* fn program_load_cmd(
+                &self,
+                spi: &mut SPI,
+                ca: ColumnAddress,
+                buf: &[u8],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                let data = cmd::column_frame(Self::PROGRAM_LOAD_COMMAND, ca);
+                let operations = &mut [Operation::Write(&data), Operation::Write(buf)];
+                spi_transaction(spi, operations)$($await)*
+            }
+
+            /// Write bytes to the device buffer/register without resetting
+            ///
+            /// Use [Self::write_enable_cmd] to enable writing before this command
+            ///
+            /// Use [Self::program_execute_cmd] to write the buffer/register to a page
+            ///
+            /// Use [Self::program_load_cmd] to write with resetting
+            $($async)* fn program_random_load_cmd(
+                &self,
+                spi: &mut SPI,
+                ca: ColumnAddress,
+                buf: &[u8],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                let data = cmd::column_frame(Self::PROGRAM_RANDOM_LOAD_COMMAND, ca);
+                let operations = &mut [Operation::Write(&data), Operation::Write(buf)];
+                spi_transaction(spi, operations)$($await)*
+            }
+
+            /// Write the device buffer/register to a page
+            ///
+            /// Use [Self::program_load_cmd] or [Self::program_random_load_cmd] to write to the
+            /// buffer/register
+            ///
+            /// Use [Self::is_busy] to check when the write is complete
+            ///
+            /// Check [Self::program_failed] to see if the write failed
+            $($async)* fn program_execute_cmd(
+                &self,
+                spi: &mut SPI,
+                address: PageIndex,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi_write(spi, &cmd::row_frame(Self::PROGRAM_EXECUTE_COMMAND, address))$($await)*
+            }
+
+            /// Put the device in deep power down mode
+            /// Requires callling [Self::deep_power_down_exit_cmd] to exit
+            $($async)* fn deep_power_down_cmd(
+                &self,
+                spi: &mut SPI,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi_write(spi, &[Self::DEEP_POWER_DOWN_COMMAND])$($await)*
+            }
+
+            /// Exit deep power down mode
+            $($async)* fn deep_power_down_exit_cmd(
+                &self,
+                spi: &mut SPI,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi_write(spi, &[Self::DEEP_POWER_DOWN_EXIT_COMMAND])$($await)*
+            }
+
+            /// Select the active die on stacked die devices.
+            /// Die 0 is active after power up.
+            $($async)* fn die_select_cmd(
+                &self,
+                spi: &mut SPI,
+                die: u8,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi_write(spi, &[Self::DIE_SELECT_COMMAND, die])$($await)*
+            }
+
+            // ============= Status functions ============
+
+            /// Check if write protection is enabled
+            $($async)* fn is_write_enabled(
+                &self,
+                spi: &mut SPI,
+            ) -> Result<bool, SpiFlashError<SPI::Error>> {
+                let status = self.read_register_cmd(spi, Self::STATUS_REGISTER)$($await)*?;
+                Ok(StatusReg::from(status).write_enabled())
+            }
+
+            /// Check if programming/writing failed
+            $($async)* fn program_failed(
+                &self,
+                spi: &mut SPI,
+            ) -> Result<bool, SpiFlashError<SPI::Error>> {
+                let status = self.read_register_cmd(spi, Self::STATUS_REGISTER)$($await)*?;
+                Ok(StatusReg::from(status).program_failed())
+            }
+
+            /// Check if erase failed
+            $($async)* fn erase_failed(
+                &self,
+                spi: &mut SPI,
+            ) -> Result<bool, SpiFlashError<SPI::Error>> {
+                let status = self.read_register_cmd(spi, Self::STATUS_REGISTER)$($await)*?;
+                Ok(StatusReg::from(status).erase_failed())
+            }
+
+            /// Check if busy flag is set
+            $($async)* fn is_busy(&self, spi: &mut SPI) -> Result<bool, SpiFlashError<SPI::Error>> {
+                let status = self.read_register_cmd(spi, Self::STATUS_REGISTER)$($await)*?;
+                Ok(StatusReg::from(status).busy())
+            }
+
+            /// Read the status register, returning it if the busy flag is clear
+            $($async)* fn ready_status(
+                &self,
+                spi: &mut SPI,
+            ) -> Result<Option<u8>, SpiFlashError<SPI::Error>> {
+                let status = self.read_register_cmd(spi, Self::STATUS_REGISTER)$($await)*?;
+                Ok((!StatusReg::from(status).busy()).then_some(status))
+            }
+
+            /// Wait until the busy flag is clear, returning the status register.
+            ///
+            /// Fails with [SpiFlashError::Timeout] after [SpiNand::BUSY_POLL_LIMIT] reads
+            $($async)* fn wait_ready(
+                &self,
+                spi: &mut SPI,
+            ) -> Result<u8, SpiFlashError<SPI::Error>> {
+                for _ in 0..Self::BUSY_POLL_LIMIT {
+                    if let Some(status) = self.ready_status(spi)$($await)*? {
+                        return Ok(status);
+                    }
+                }
+                warn!("Device busy for {} status reads", Self::BUSY_POLL_LIMIT);
+                Err(SpiFlashError::Timeout)
+            }
+
+            /// Check the ECC result of reading a page into the device buffer, from the status
+            /// register read after the page read.
+            ///
+            /// Override to include the corrected bit count on devices that report it.
+            $($async)* fn check_read_ecc(
+                &self,
+                _spi: &mut SPI,
+                page_address: PageIndex,
+                status: u8,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                ECCStatus::from_status_register(status).check(page_address, None)
+            }
+
+            /// Error to return when a program or erase of a page failed.
+            ///
+            /// [SpiFlashError::WriteProtected] if block protection is enabled, otherwise `error`
+            $($async)* fn write_failed_error(
+                &self,
+                spi: &mut SPI,
+                page_address: PageIndex,
+                error: SpiFlashError<SPI::Error>,
+            ) -> SpiFlashError<SPI::Error> {
+                match self.read_register_cmd(spi, Self::CONFIGURATION_REGISTER)$($await)* {
+                    Ok(protection) => {
+                        let mask = Self::BLOCK_PROTECT_MASK;
+                        cmd::write_failed_error(protection, mask, page_address, error)
+                    }
+                    Err(e) => e,
+                }
+            }
+
+            /// Disable block protection
+            /// Sets bits 3 to 6 as 0 in status register 1
+            $($async)* fn disable_block_protection(
+                &self,
+                spi: &mut SPI,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                let mask = Self::BLOCK_PROTECT_MASK;
+                self.clear_register_cmd(spi, Self::CONFIGURATION_REGISTER, mask)$($await)*
+            }
+
+            // ============ Bad Block functions ============
+            /// Check if the block is marked as bad
+            $($async)* fn block_marked_bad(
+                &self,
+                spi: &mut SPI,
+                block_address: BlockIndex,
+            ) -> Result<bool, SpiFlashError<SPI::Error>> {
+                // Read the first 2 bytes of the extra data
+                let mut buf = [0; 2];
+                // The marker is not covered by ECC, so ECC errors of the page don't matter
+                match self.read_page_slice(
+                    spi,
+                    PageIndex::from_block_address(block_address, Self::PAGES_PER_BLOCK),
+                    ColumnAddress::new(Self::PAGE_SIZE as u16),
+                    &mut buf,
+                )$($await)* {
+                    Ok(())
+                    | Err(SpiFlashError::ReadFailed(_) | SpiFlashError::EccError { .. }) => {}
+                    Err(e) => return Err(e),
+                }
+                Ok(buf[0] != 0xFF || buf[1] != 0xFF)
+            }
+
+            /// Read the status of a block from the bad block marker of its first page.
+            ///
+            /// [SpiNand::RUNTIME_BAD_BLOCK_MARKER] is reported as [BlockStatus::Worn], any other
+            /// marker as [BlockStatus::FactoryBad] and good blocks as [BlockStatus::Ok]. Only the
+            /// marker is read, see [Self::block_erased] to check whether a good block is erased.
+            $($async)* fn block_status(
+                &self,
+                spi: &mut SPI,
+                block_address: BlockIndex,
+            ) -> Result<BlockStatus, SpiFlashError<SPI::Error>> {
+                let mut marker = [0; 4];
+                // The marker is not covered by ECC, so ECC errors of the page don't matter
+                match self.read_page_slice(
+                    spi,
+                    PageIndex::from_block_address(block_address, Self::PAGES_PER_BLOCK),
+                    ColumnAddress::new(Self::PAGE_SIZE as u16),
+                    &mut marker,
+                )$($await)* {
+                    Ok(())
+                    | Err(SpiFlashError::ReadFailed(_) | SpiFlashError::EccError { .. }) => {}
+                    Err(e) => return Err(e),
+                }
+                Ok(
+                    cmd::bad_block_status(&marker, &Self::RUNTIME_BAD_BLOCK_MARKER)
+                        .unwrap_or(BlockStatus::Ok),
+                )
+            }
+
+            /// Return true if the data of the first page of a block is all 0xFF, as after an erase.
+            ///
+            /// The page is read into `buf` in one transaction, so this is kept separate from
+            /// [Self::block_status] for callers that need it.
+            $($async)* fn block_erased(
+                &self,
+                spi: &mut SPI,
+                block_address: BlockIndex,
+                buf: &mut [u8; N],
+            ) -> Result<bool, SpiFlashError<SPI::Error>> {
+                let page_address =
+                    PageIndex::from_block_address(block_address, Self::PAGES_PER_BLOCK);
+                // An erased page can't fail ECC
+                match self.read_page(spi, page_address, buf)$($await)* {
+                    Ok(()) => Ok(buf.iter().all(|&byte| byte == 0xFF)),
+                    Err(SpiFlashError::ReadFailed(_) | SpiFlashError::EccError { .. }) => Ok(false),
+                    Err(e) => Err(e),
+                }
+            }
+
+            /// Mark a block as bad
+            /// This will write [SpiNand::RUNTIME_BAD_BLOCK_MARKER] to the start of the extra data.
+            ///
+            /// Returns true if sucessful
+            $($async)* fn mark_block_bad(
+                &self,
+                spi: &mut SPI,
+                block_address: BlockIndex,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                let pa = PageIndex::from_block_address(block_address, Self::PAGES_PER_BLOCK);
+                // Erase the block
+                self.erase_block(spi, block_address)$($await)*?;
+                // Write the marker to the start of the extra data
+                self.write_page_slice(
+                    spi,
+                    pa,
+                    ColumnAddress::new(Self::PAGE_SIZE as u16),
+                    &Self::RUNTIME_BAD_BLOCK_MARKER,
+                )$($await)*
+            }
+
+            // ============= RWE functions =============
+            /// Start erasing a block, without waiting for the erase to finish.
+            ///
+            /// Finish with [Self::erase_block_finish] once the device is ready.
+            $($async)* fn erase_block_start(
+                &self,
+                spi: &mut SPI,
+                block_address: BlockIndex,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                // Enable writing
+                self.write_enable_cmd(spi)$($await)*?;
+                // Erase the block
+                self.erase_block_cmd(spi, block_address)$($await)*
+            }
+
+            /// Check the result of an erase started with [Self::erase_block_start]
+            $($async)* fn erase_block_finish(
+                &self,
+                spi: &mut SPI,
+                block_address: BlockIndex,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                if self.erase_failed(spi)$($await)*? {
+                    let page = PageIndex::from_block_address(block_address, Self::PAGES_PER_BLOCK);
+                    let error = SpiFlashError::EraseFailed(page);
+                    return Err(self.write_failed_error(spi, page, error)$($await)*);
+                }
+                Ok(())
+            }
+
+            /// Erase a block
+            $($async)* fn erase_block(
+                &self,
+                spi: &mut SPI,
+                block_address: BlockIndex,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                self.erase_block_start(spi, block_address)$($await)*?;
+                // Wait for the erase to complete
+                self.wait_ready(spi)$($await)*?;
+                self.erase_block_finish(spi, block_address)$($await)*
+            }
+
+            /// Read a page from the device
+            $($async)* fn read_page(
+                &self,
+                spi: &mut SPI,
+                page_address: PageIndex,
+                buf: &mut [u8; N],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                // Read page into device buffer
+                self.page_read_cmd(spi, page_address)$($await)*?;
+                // Wait for the read to complete
+                let status = self.wait_ready(spi)$($await)*?;
+                // Read the page from the device buffer
+                self.page_read_buffer_cmd(spi, ColumnAddress::new(0), buf)$($await)*?;
+                // Corrected data is still read on failing
+                self.check_read_ecc(spi, page_address, status)$($await)*
+            }
+
+            /// Read a slice from a page
+            $($async)* fn read_page_slice(
+                &self,
+                spi: &mut SPI,
+                page_address: PageIndex,
+                column_address: ColumnAddress,
+                buf: &mut [u8],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                // Read page into device buffer
+                self.page_read_cmd(spi, page_address)$($await)*?;
+                // Wait for the read to complete
+                let status = self.wait_ready(spi)$($await)*?;
+                // Read the page from the device buffer
+                self.page_read_buffer_cmd(spi, column_address, buf)$($await)*?;
+                // Corrected data is still read on failing
+                self.check_read_ecc(spi, page_address, status)$($await)*
+            }
+
+            /// Start programming a page: write enable, load `buf` into the device buffer at
+            /// `column_address` and execute the program, without waiting for it to finish.
+            ///
+            /// The commands are sent back to back. Finish with [Self::program_finish] once the
+            /// device is ready.
+            $($async)* fn program_start(
+                &self,
+                spi: &mut SPI,
+                page_address: PageIndex,
+                column_address: ColumnAddress,
+                buf: &[u8],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                // Enable writing
+                self.write_enable_cmd(spi)$($await)*?;
+                // Write to the device buffer
+                self.program_load_cmd(spi, column_address, buf)$($await)*?;
+                // Write the buffer to the page
+                self.program_execute_cmd(spi, page_address)$($await)*
+            }
+
+            /// Check the result of a program started with [Self::program_start]
+            $($async)* fn program_finish(
+                &self,
+                spi: &mut SPI,
+                page_address: PageIndex,
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                if self.program_failed(spi)$($await)*? {
+                    return Err(self.write_failed_error(
+                        spi,
+                        page_address,
+                        SpiFlashError::ProgramFailed(page_address),
+                    )$($await)*);
+                }
+                Ok(())
+            }
+
+            /// Write a page to the device.
+            ///
+            /// Must use [Self::erase_block] first
+            $($async)* fn write_page(
+                &self,
+                spi: &mut SPI,
+                page_address: PageIndex,
+                buf: &[u8; N],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                self.write_page_slice(spi, page_address, ColumnAddress::new(0), buf)$($await)*
+            }
+
+            /// Write a slice to a page
+            ///
+            /// Must use [Self::erase_block] first
+            $($async)* fn write_page_slice(
+                &self,
+                spi: &mut SPI,
+                page_address: PageIndex,
+                column_address: ColumnAddress,
+                buf: &[u8],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                self.program_start(spi, page_address, column_address, buf)$($await)*?;
+                // Wait for the write to complete
+                self.wait_ready(spi)$($await)*?;
+                self.program_finish(spi, page_address)$($await)*
+            }
+        }
+
+        $(#[$lut_attr])*
+        pub trait $lut<SPI: SpiDevice, const N: usize>: $nand<SPI, N> {
+            /// Add an entry to the lookup table, replacing `bad` with `replacement`.
+            ///
+            /// Returns false if the lookup table is full.
+            $($async)* fn lut_swap_block(
+                &self,
+                spi: &mut SPI,
+                bad: BlockIndex,
+                replacement: BlockIndex,
+            ) -> Result<bool, SpiFlashError<SPI::Error>>;
+        }
+
+        $(#[$oob_attr])*
+        pub trait $oob<SPI: SpiDevice, const N: usize>: $nand<SPI, N> {
+            /// Free regions of the spare area, as offset from the end of the page and length
+            const OOB_FREE: &'static [(u16, u16)];
+
+            /// Total length of the free regions
+            const OOB_SIZE: usize = {
+                let mut size = 0;
+                let mut i = 0;
+                while i < Self::OOB_FREE.len() {
+                    size += Self::OOB_FREE[i].1 as usize;
+                    i += 1;
+                }
+                size
+            };
+
+            /// Read the free spare bytes of a page, in order of [Self::OOB_FREE]
+            $($async)* fn read_oob(
+                &self,
+                spi: &mut SPI,
+                page_address: PageIndex,
+                oob: &mut [u8],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                // Read page into device buffer
+                self.page_read_cmd(spi, page_address)$($await)*?;
+                // Wait for the read to complete
+                let status = self.wait_ready(spi)$($await)*?;
+                let mut oob = oob;
+                for &(offset, length) in Self::OOB_FREE {
+                    let (region, rest) = oob.split_at_mut(oob.len().min(length as usize));
+                    if !region.is_empty() {
+                        let column = ColumnAddress::new(N as u16 + offset);
+                        self.page_read_buffer_cmd(spi, column, region)$($await)*?;
+                    }
+                    oob = rest;
+                }
+                self.check_read_ecc(spi, page_address, status)$($await)*
+            }
+
+            #[doc = concat!(
+                "Start programming a page and free spare bytes, like [",
+                stringify!($nand),
+                "::program_start]"
+            )]
+            $($async)* fn program_oob_start(
+                &self,
+                spi: &mut SPI,
+                page_address: PageIndex,
+                buf: &[u8; N],
+                oob: &[u8],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                // Enable writing
+                self.write_enable_cmd(spi)$($await)*?;
+                // Write the page to the device buffer, which resets the spare area
+                self.program_load_cmd(spi, ColumnAddress::new(0), buf)$($await)*?;
+                let mut oob = oob;
+                for &(offset, length) in Self::OOB_FREE {
+                    let (region, rest) = oob.split_at(oob.len().min(length as usize));
+                    if !region.is_empty() {
+                        let column = ColumnAddress::new(N as u16 + offset);
+                        self.program_random_load_cmd(spi, column, region)$($await)*?;
+                    }
+                    oob = rest;
+                }
+                // Write the buffer to the page
+                self.program_execute_cmd(spi, page_address)$($await)*
+            }
+
+            /// Write a page and free spare bytes, in order of [Self::OOB_FREE], with one program
+            ///
+            #[doc = concat!("Must use [", stringify!($nand), "::erase_block] first")]
+            $($async)* fn write_page_oob(
+                &self,
+                spi: &mut SPI,
+                page_address: PageIndex,
+                buf: &[u8; N],
+                oob: &[u8],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                self.program_oob_start(spi, page_address, buf, oob)$($await)*?;
+                // Wait for the write to complete
+                self.wait_ready(spi)$($await)*?;
+                self.program_finish(spi, page_address)$($await)*
+            }
+        }
+
+        pub mod utils {
+            use super::{Operation, SpiDevice, SpiFlashError};
+
+            /// Wrapper around [SpiDevice::write] that maps errors
+            pub $($async)* fn spi_write<SPI: SpiDevice>(
+                spi: &mut SPI,
+                buf: &[u8],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi.write(buf)$($await)*.map_err(SpiFlashError::SPI)
+            }
+
+            /// Wrapper around [SpiDevice::read] that maps errors
+            pub $($async)* fn spi_read<SPI: SpiDevice>(
+                spi: &mut SPI,
+                buf: &mut [u8],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi.read(buf)$($await)*.map_err(SpiFlashError::SPI)
+            }
+
+            /// Wrapper around [SpiDevice::transfer] that maps errors
+            pub $($async)* fn spi_transfer<SPI: SpiDevice>(
+                spi: &mut SPI,
+                read: &mut [u8],
+                write: &[u8],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi.transfer(read, write)$($await)*.map_err(SpiFlashError::SPI)
+            }
+
+            /// Wrapper around [SpiDevice::transfer_in_place] that maps errors
+            pub $($async)* fn spi_transfer_in_place<SPI: SpiDevice>(
+                spi: &mut SPI,
+                buf: &mut [u8],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi.transfer_in_place(buf)$($await)*.map_err(SpiFlashError::SPI)
+            }
+
+            /// Wrapper around [SpiDevice::transaction] that maps errors
+            pub $($async)* fn spi_transaction<SPI: SpiDevice>(
+                spi: &mut SPI,
+                operations: &mut [Operation<'_, u8>],
+            ) -> Result<(), SpiFlashError<SPI::Error>> {
+                spi.transaction(operations)$($await)*.map_err(SpiFlashError::SPI)
+            }
+        }
+    };
+}
+pub(crate) use spi_nand_traits;
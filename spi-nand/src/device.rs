@@ -1,12 +1,19 @@
 use core::fmt::Debug;
 
-use embedded_hal::spi::SpiDevice;
+use embedded_hal::spi::{self, SpiDevice};
 use embedded_nand::{
     check_erase, check_read, check_slice, check_write, AddressConversions, BlockIndex, BlockStatus,
-    ByteAddress, ColumnAddress, ErrorType, NandFlash, PageIndex,
+    BlockSwap, ByteAddress, ColumnAddress, ErrorType, NandFlash, NandFlashOob, PageIndex,
+    SplitProgram,
 };
 
-use crate::{cmd_async::SpiNandAsync, cmd_blocking::SpiNandBlocking, error::SpiFlashError};
+use crate::{
+    busy::{BusyWait, BusyWaitAsync, NoWait},
+    cmd_async::{SpiNandAsync, SpiNandLutAsync, SpiNandOobAsync},
+    cmd_blocking::{SpiNandBlocking, SpiNandLutBlocking, SpiNandOobBlocking},
+    error::SpiFlashError,
+    SpiNand,
+};
 
 use super::JedecID;
 
@@ -30,14 +37,27 @@ use super::JedecID;
 ///
 /// To use the async interface, the device D must implement [SpiNandAsync]
 /// and SPI must implement [embedded_hal_async::spi::SpiDevice].
+///
+/// For stacked die devices ([crate::SpiNand::DIE_COUNT] > 1) the block and page
+/// addresses passed to the methods here span all dies. The die containing the
+/// address is selected before each operation.
+///
+/// `W` is called between polls of the busy flag while erasing or programming, see
+/// [crate::busy] for use on a shared SPI bus.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-pub struct SpiNandDevice<SPI, D, const N: usize> {
+pub struct SpiNandDevice<SPI, D, const N: usize, W = NoWait> {
     pub spi: SPI,
     pub device: D,
+    /// The die last selected, None if unknown
+    active_die: Option<u8>,
+    /// Called between busy polls of long operations
+    busy_wait: W,
+    /// The device is in deep power down
+    sleeping: bool,
 }
 // Manually implement Debug to avoid bounds on SPI
 // D must implement Debug, which should be fine as its just data
-impl<SPI, D, const N: usize> Debug for SpiNandDevice<SPI, D, N>
+impl<SPI, D, const N: usize, W> Debug for SpiNandDevice<SPI, D, N, W>
 where
     D: Debug,
 {
@@ -51,13 +71,61 @@ where
 impl<SPI, D, const N: usize> SpiNandDevice<SPI, D, N> {
     /// Create a new [SpiNandDevice] with the given SPI peripheral and flash device.
     pub fn new(spi: SPI, device: D) -> Self {
-        SpiNandDevice { spi, device }
+        SpiNandDevice {
+            spi,
+            device,
+            active_die: None,
+            busy_wait: NoWait,
+            sleeping: false,
+        }
+    }
+}
+
+impl<SPI, D, const N: usize, W> SpiNandDevice<SPI, D, N, W> {
+    /// Call `busy_wait` between polls of the busy flag while erasing or programming,
+    /// instead of polling continuously. Leaves a shared bus free for other devices.
+    pub fn with_busy_wait<W2>(self, busy_wait: W2) -> SpiNandDevice<SPI, D, N, W2> {
+        SpiNandDevice {
+            spi: self.spi,
+            device: self.device,
+            active_die: self.active_die,
+            busy_wait,
+            sleeping: self.sleeping,
+        }
+    }
+
+    /// Returns true if the device was put in deep power down and not woken since
+    pub fn is_sleeping(&self) -> bool {
+        self.sleeping
+    }
+}
+
+impl<SPI, D: SpiNand<N>, const N: usize, W> SpiNandDevice<SPI, D, N, W> {
+    /// Convert the die local page of an error from the active die to one spanning all dies
+    fn die_error<SE>(&self, error: SpiFlashError<SE>) -> SpiFlashError<SE> {
+        match self.active_die {
+            Some(die) if D::DIE_COUNT > 1 => {
+                error.offset_page(die as u32 * D::BLOCKS_PER_DIE * D::PAGES_PER_BLOCK)
+            }
+            _ => error,
+        }
+    }
+
+    /// Error if the device is in deep power down, as it would ignore the command
+    fn check_awake<SE>(&self) -> Result<(), SpiFlashError<SE>> {
+        if self.sleeping {
+            return Err(SpiFlashError::Sleeping);
+        }
+        Ok(())
     }
 }
 
-impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize> SpiNandDevice<SPI, D, N> {
+impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize, W: BusyWait>
+    SpiNandDevice<SPI, D, N, W>
+{
     /// Get the Jedec ID of the flash device using blocking SPI
     pub fn jedec_blocking(&mut self) -> Result<JedecID, SpiFlashError<SPI::Error>> {
+        self.check_awake()?;
         self.device.read_jedec_id_cmd(&mut self.spi)
     }
 
@@ -68,14 +136,142 @@ impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize> SpiNandDevice<S
 
     /// Reset the flash device using blocking SPI
     pub fn reset_blocking(&mut self) -> Result<(), SpiFlashError<SPI::Error>> {
+        self.check_awake()?;
+        self.active_die = None;
         self.device.hard_reset_cmd(&mut self.spi)
     }
+
+    /// Put the device in deep power down using blocking SPI.
+    ///
+    /// Waits tDP ([SpiNand::DEEP_POWER_DOWN_TIME_US]) with `delay` before returning.
+    /// The device ignores all commands until [Self::wake_blocking] is called, so until then
+    /// operations return [SpiFlashError::Sleeping].
+    pub fn sleep_blocking(
+        &mut self,
+        delay: &mut impl embedded_hal::delay::DelayNs,
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        if self.sleeping {
+            return Ok(());
+        }
+        debug!("Entering deep power down");
+        self.device.deep_power_down_cmd(&mut self.spi)?;
+        delay.delay_us(D::DEEP_POWER_DOWN_TIME_US);
+        self.sleeping = true;
+        Ok(())
+    }
+
+    /// Wake the device from deep power down using blocking SPI.
+    ///
+    /// Waits tRDP ([SpiNand::DEEP_POWER_DOWN_EXIT_TIME_US]) with `delay` before returning,
+    /// after which the device is ready for commands.
+    pub fn wake_blocking(
+        &mut self,
+        delay: &mut impl embedded_hal::delay::DelayNs,
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        if !self.sleeping {
+            return Ok(());
+        }
+        debug!("Exiting deep power down");
+        self.device.deep_power_down_exit_cmd(&mut self.spi)?;
+        delay.delay_us(D::DEEP_POWER_DOWN_EXIT_TIME_US);
+        self.sleeping = false;
+        // Stacked die devices return to die 0
+        self.active_die = None;
+        Ok(())
+    }
+
+    /// Select the die containing the block using blocking SPI.
+    /// Returns the index of the block within the die.
+    fn select_die_blocking(
+        &mut self,
+        block: BlockIndex,
+    ) -> Result<BlockIndex, SpiFlashError<SPI::Error>> {
+        self.check_awake()?;
+        if D::DIE_COUNT == 1 {
+            return Ok(block);
+        }
+        let die = (block.as_u32() / D::BLOCKS_PER_DIE) as u8;
+        if self.active_die != Some(die) {
+            trace!("Selecting die {}", die);
+            self.device.die_select_cmd(&mut self.spi, die)?;
+            self.active_die = Some(die);
+        }
+        Ok(BlockIndex::new(block.as_u32() % D::BLOCKS_PER_DIE))
+    }
+
+    /// Select the die containing the page using blocking SPI.
+    /// Returns the index of the page within the die.
+    fn select_die_page_blocking(
+        &mut self,
+        page_address: PageIndex,
+    ) -> Result<PageIndex, SpiFlashError<SPI::Error>> {
+        self.check_awake()?;
+        if D::DIE_COUNT == 1 {
+            return Ok(page_address);
+        }
+        let block =
+            self.select_die_blocking(BlockIndex::new(page_address.as_u32() / D::PAGES_PER_BLOCK))?;
+        Ok(PageIndex::new(
+            block.as_u32() * D::PAGES_PER_BLOCK + page_address.as_u32() % D::PAGES_PER_BLOCK,
+        ))
+    }
+
     /// Erase a block of flash memory using blocking SPI
     pub fn erase_block_blocking(
         &mut self,
         block: BlockIndex,
     ) -> Result<(), SpiFlashError<SPI::Error>> {
-        self.device.erase_block(&mut self.spi, block)
+        let block = self.select_die_blocking(block)?;
+        self.erase_in_die_blocking(block)
+            .map_err(|e| self.die_error(e))
+    }
+
+    /// Wait for an erase or program to finish, calling the busy wait between polls
+    fn wait_ready_blocking(&mut self) -> Result<u8, SpiFlashError<SPI::Error>> {
+        for _ in 0..D::BUSY_POLL_LIMIT {
+            if let Some(status) = self.device.ready_status(&mut self.spi)? {
+                return Ok(status);
+            }
+            self.busy_wait.wait();
+        }
+        warn!("Device busy for {} status reads", D::BUSY_POLL_LIMIT);
+        Err(SpiFlashError::Timeout)
+    }
+
+    /// Send the commands that start an erase or program with the busy wait locked, so no
+    /// other transactions run between them
+    fn locked_blocking<T>(
+        &mut self,
+        start: impl FnOnce(&D, &mut SPI) -> Result<T, SpiFlashError<SPI::Error>>,
+    ) -> Result<T, SpiFlashError<SPI::Error>> {
+        self.busy_wait.lock();
+        let result = start(&self.device, &mut self.spi);
+        self.busy_wait.unlock();
+        result
+    }
+
+    /// Erase a block within the active die
+    fn erase_in_die_blocking(
+        &mut self,
+        block: BlockIndex,
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        self.locked_blocking(|device, spi| device.erase_block_start(spi, block))?;
+        self.wait_ready_blocking()?;
+        self.device.erase_block_finish(&mut self.spi, block)
+    }
+
+    /// Program a slice of a page within the active die
+    fn program_in_die_blocking(
+        &mut self,
+        page_address: PageIndex,
+        column_address: ColumnAddress,
+        buf: &[u8],
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        self.locked_blocking(|device, spi| {
+            device.program_start(spi, page_address, column_address, buf)
+        })?;
+        self.wait_ready_blocking()?;
+        self.device.program_finish(&mut self.spi, page_address)
     }
     /// Read a page into the buffer using blocking SPI
     /// Checks for ECC errors
@@ -84,9 +280,11 @@ impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize> SpiNandDevice<S
         page_address: PageIndex,
         buf: &mut [u8; N],
     ) -> Result<(), SpiFlashError<SPI::Error>> {
+        let page_address = self.select_die_page_blocking(page_address)?;
         // Read page
-        self.device.read_page(&mut self.spi, page_address, buf)
-        // Check ECC if enabled
+        self.device
+            .read_page(&mut self.spi, page_address, buf)
+            .map_err(|e| self.die_error(e))
     }
 
     /// Read a slice of a page using blocking SPI
@@ -97,10 +295,11 @@ impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize> SpiNandDevice<S
         column_address: ColumnAddress,
         buf: &mut [u8],
     ) -> Result<(), SpiFlashError<SPI::Error>> {
+        let page_address = self.select_die_page_blocking(page_address)?;
         // Read page
         self.device
             .read_page_slice(&mut self.spi, page_address, column_address, buf)
-        // Check ECC if enabled
+            .map_err(|e| self.die_error(e))
     }
 
     /// Write a page to the device using blocking SPI
@@ -111,8 +310,10 @@ impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize> SpiNandDevice<S
         page_address: PageIndex,
         buf: &[u8; N],
     ) -> Result<(), SpiFlashError<SPI::Error>> {
+        let page_address = self.select_die_page_blocking(page_address)?;
         // Write page
-        self.device.write_page(&mut self.spi, page_address, buf)
+        self.program_in_die_blocking(page_address, ColumnAddress::new(0), buf)
+            .map_err(|e| self.die_error(e))
     }
 
     /// Write a slice of a page to the device using blocking SPI
@@ -124,30 +325,56 @@ impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize> SpiNandDevice<S
         column_address: ColumnAddress,
         buf: &[u8],
     ) -> Result<(), SpiFlashError<SPI::Error>> {
+        let page_address = self.select_die_page_blocking(page_address)?;
         // Write page
-        self.device
-            .write_page_slice(&mut self.spi, page_address, column_address, buf)
+        self.program_in_die_blocking(page_address, column_address, buf)
+            .map_err(|e| self.die_error(e))
     }
 
     /// Copy a page to another using the device buffer
     /// TODO: This might not be supported by all devices
+    ///
+    /// Pages on different dies are copied through a page buffer on the stack.
     pub fn copy_page_blocking(
         &mut self,
         src_page_address: PageIndex,
         dest_page_address: PageIndex,
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        if D::DIE_COUNT > 1
+            && src_page_address.as_u32() / (D::PAGES_PER_BLOCK * D::BLOCKS_PER_DIE)
+                != dest_page_address.as_u32() / (D::PAGES_PER_BLOCK * D::BLOCKS_PER_DIE)
+        {
+            let mut buf = [0; N];
+            self.read_page_blocking(src_page_address, &mut buf)?;
+            return self.write_page_blocking(dest_page_address, &buf);
+        }
+        let src_page_address = self.select_die_page_blocking(src_page_address)?;
+        let dest_page_address = self.select_die_page_blocking(dest_page_address)?;
+        self.copy_page_buffer_blocking(src_page_address, dest_page_address)
+            .map_err(|e| self.die_error(e))
+    }
+
+    /// Copy a page within the active die through the device buffer
+    fn copy_page_buffer_blocking(
+        &mut self,
+        src_page_address: PageIndex,
+        dest_page_address: PageIndex,
     ) -> Result<(), SpiFlashError<SPI::Error>> {
         // Load the page into the device buffer
         self.device.page_read_cmd(&mut self.spi, src_page_address)?;
-        // Write the page to the destination address
+        let status = self.device.wait_ready(&mut self.spi)?;
+        // Don't copy uncorrectable data
         self.device
-            .program_execute_cmd(&mut self.spi, dest_page_address)?;
+            .check_read_ecc(&mut self.spi, src_page_address, status)?;
+        // Write the page to the destination address
+        self.locked_blocking(|device, spi| {
+            device.write_enable_cmd(spi)?;
+            device.program_execute_cmd(spi, dest_page_address)
+        })?;
         // Wait until the device is ready
-        while self.device.is_busy(&mut self.spi)? {}
+        self.wait_ready_blocking()?;
         // Return the status of the operation
-        if self.device.program_failed(&mut self.spi)? {
-            return Err(SpiFlashError::ProgramFailed);
-        }
-        Ok(())
+        self.device.program_finish(&mut self.spi, dest_page_address)
     }
 
     /// Mark a block as bad using blocking SPI
@@ -157,15 +384,58 @@ impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize> SpiNandDevice<S
         &mut self,
         block: BlockIndex,
     ) -> Result<(), SpiFlashError<SPI::Error>> {
-        self.device.mark_block_bad(&mut self.spi, block)
+        let block = self.select_die_blocking(block)?;
+        self.locked_blocking(|device, spi| device.mark_block_bad(spi, block))
+            .map_err(|e| self.die_error(e))
+    }
+
+    /// Check if a block is marked bad using blocking SPI
+    pub fn block_marked_bad_blocking(
+        &mut self,
+        block: BlockIndex,
+    ) -> Result<bool, SpiFlashError<SPI::Error>> {
+        let block = self.select_die_blocking(block)?;
+        self.device
+            .block_marked_bad(&mut self.spi, block)
+            .map_err(|e| self.die_error(e))
+    }
+
+    /// Read the status of a block from its bad block marker using blocking SPI
+    pub fn block_status_blocking(
+        &mut self,
+        block: BlockIndex,
+    ) -> Result<BlockStatus, SpiFlashError<SPI::Error>> {
+        let block = self.select_die_blocking(block)?;
+        self.device
+            .block_status(&mut self.spi, block)
+            .map_err(|e| self.die_error(e))
+    }
+
+    /// Return true if the first page of a good block is erased using blocking SPI.
+    ///
+    /// The page is read into `buf`, see [SpiNandBlocking::block_erased]
+    pub fn block_erased_blocking(
+        &mut self,
+        block: BlockIndex,
+        buf: &mut [u8; N],
+    ) -> Result<bool, SpiFlashError<SPI::Error>> {
+        let block = self.select_die_blocking(block)?;
+        self.device
+            .block_erased(&mut self.spi, block, buf)
+            .map_err(|e| self.die_error(e))
     }
 }
 
-impl<SPI: embedded_hal_async::spi::SpiDevice, D: SpiNandAsync<SPI, N>, const N: usize>
-    SpiNandDevice<SPI, D, N>
+impl<
+        SPI: embedded_hal_async::spi::SpiDevice,
+        D: SpiNandAsync<SPI, N>,
+        const N: usize,
+        W: BusyWaitAsync,
+    > SpiNandDevice<SPI, D, N, W>
 {
     /// Get the Jedec ID of the flash device using blocking SPI
     pub async fn jedec_async(&mut self) -> Result<JedecID, SpiFlashError<SPI::Error>> {
+        self.check_awake()?;
         self.device.read_jedec_id_cmd(&mut self.spi).await
     }
 
@@ -174,16 +444,143 @@ impl<SPI: embedded_hal_async::spi::SpiDevice, D: SpiNandAsync<SPI, N>, const N:
         Ok(self.jedec_async().await? == JedecID::new(D::JEDEC_MANUFACTURER_ID, D::JEDEC_DEVICE_ID))
     }
 
+    /// Put the device in deep power down using async SPI.
+    ///
+    /// Waits tDP ([SpiNand::DEEP_POWER_DOWN_TIME_US]) with `delay` before returning.
+    /// The device ignores all commands until [Self::wake_async] is called, so until then
+    /// operations return [SpiFlashError::Sleeping].
+    pub async fn sleep_async(
+        &mut self,
+        delay: &mut impl embedded_hal_async::delay::DelayNs,
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        if self.sleeping {
+            return Ok(());
+        }
+        debug!("Entering deep power down");
+        self.device.deep_power_down_cmd(&mut self.spi).await?;
+        delay.delay_us(D::DEEP_POWER_DOWN_TIME_US).await;
+        self.sleeping = true;
+        Ok(())
+    }
+
+    /// Wake the device from deep power down using async SPI.
+    ///
+    /// Waits tRDP ([SpiNand::DEEP_POWER_DOWN_EXIT_TIME_US]) with `delay` before returning,
+    /// after which the device is ready for commands.
+    pub async fn wake_async(
+        &mut self,
+        delay: &mut impl embedded_hal_async::delay::DelayNs,
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        if !self.sleeping {
+            return Ok(());
+        }
+        debug!("Exiting deep power down");
+        self.device.deep_power_down_exit_cmd(&mut self.spi).await?;
+        delay.delay_us(D::DEEP_POWER_DOWN_EXIT_TIME_US).await;
+        self.sleeping = false;
+        // Stacked die devices return to die 0
+        self.active_die = None;
+        Ok(())
+    }
+
     /// Reset the flash device using blocking SPI
     pub async fn reset_async(&mut self) -> Result<(), SpiFlashError<SPI::Error>> {
+        self.check_awake()?;
+        self.active_die = None;
         self.device.reset_cmd(&mut self.spi).await
     }
+
+    /// Select the die containing the block using async SPI.
+    /// Returns the index of the block within the die.
+    async fn select_die_async(
+        &mut self,
+        block: BlockIndex,
+    ) -> Result<BlockIndex, SpiFlashError<SPI::Error>> {
+        self.check_awake()?;
+        if D::DIE_COUNT == 1 {
+            return Ok(block);
+        }
+        let die = (block.as_u32() / D::BLOCKS_PER_DIE) as u8;
+        if self.active_die != Some(die) {
+            trace!("Selecting die {}", die);
+            self.device.die_select_cmd(&mut self.spi, die).await?;
+            self.active_die = Some(die);
+        }
+        Ok(BlockIndex::new(block.as_u32() % D::BLOCKS_PER_DIE))
+    }
+
+    /// Select the die containing the page using async SPI.
+    /// Returns the index of the page within the die.
+    async fn select_die_page_async(
+        &mut self,
+        page_address: PageIndex,
+    ) -> Result<PageIndex, SpiFlashError<SPI::Error>> {
+        self.check_awake()?;
+        if D::DIE_COUNT == 1 {
+            return Ok(page_address);
+        }
+        let block = self
+            .select_die_async(BlockIndex::new(page_address.as_u32() / D::PAGES_PER_BLOCK))
+            .await?;
+        Ok(PageIndex::new(
+            block.as_u32() * D::PAGES_PER_BLOCK + page_address.as_u32() % D::PAGES_PER_BLOCK,
+        ))
+    }
+
     /// Erase a block of flash memory using blocking SPI
     pub async fn erase_block_async(
         &mut self,
         block: BlockIndex,
     ) -> Result<(), SpiFlashError<SPI::Error>> {
-        self.device.erase_block(&mut self.spi, block).await
+        let block = self.select_die_async(block).await?;
+        self.erase_in_die_async(block)
+            .await
+            .map_err(|e| self.die_error(e))
+    }
+
+    /// Wait for an erase or program to finish, calling the busy wait between polls
+    async fn wait_ready_async(&mut self) -> Result<u8, SpiFlashError<SPI::Error>> {
+        for _ in 0..D::BUSY_POLL_LIMIT {
+            if let Some(status) = self.device.ready_status(&mut self.spi).await? {
+                return Ok(status);
+            }
+            self.busy_wait.wait().await;
+        }
+        warn!("Device busy for {} status reads", D::BUSY_POLL_LIMIT);
+        Err(SpiFlashError::Timeout)
+    }
+
+    /// Erase a block within the active die
+    async fn erase_in_die_async(
+        &mut self,
+        block: BlockIndex,
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        self.busy_wait.lock().await;
+        let started = self.device.erase_block_start(&mut self.spi, block).await;
+        self.busy_wait.unlock();
+        started?;
+        self.wait_ready_async().await?;
+        self.device.erase_block_finish(&mut self.spi, block).await
+    }
+
+    /// Program a slice of a page within the active die
+    async fn program_in_die_async(
+        &mut self,
+        page_address: PageIndex,
+        column_address: ColumnAddress,
+        buf: &[u8],
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        self.busy_wait.lock().await;
+        let started = self
+            .device
+            .program_start(&mut self.spi, page_address, column_address, buf)
+            .await;
+        self.busy_wait.unlock();
+        started?;
+        self.wait_ready_async().await?;
+        self.device
+            .program_finish(&mut self.spi, page_address)
+            .await
     }
     /// Read a page into the buffer using blocking SPI
     /// Checks for ECC errors
@@ -192,11 +589,12 @@ impl<SPI: embedded_hal_async::spi::SpiDevice, D: SpiNandAsync<SPI, N>, const N:
         page_address: PageIndex,
         buf: &mut [u8; N],
     ) -> Result<(), SpiFlashError<SPI::Error>> {
+        let page_address = self.select_die_page_async(page_address).await?;
         // Read page
         self.device
             .read_page(&mut self.spi, page_address, buf)
             .await
-        // Check ECC if enabled
+            .map_err(|e| self.die_error(e))
     }
 
     /// Read a slice of a page using blocking SPI
@@ -207,11 +605,12 @@ impl<SPI: embedded_hal_async::spi::SpiDevice, D: SpiNandAsync<SPI, N>, const N:
         column_address: ColumnAddress,
         buf: &mut [u8],
     ) -> Result<(), SpiFlashError<SPI::Error>> {
+        let page_address = self.select_die_page_async(page_address).await?;
         // Read page
         self.device
             .read_page_slice(&mut self.spi, page_address, column_address, buf)
             .await
-        // Check ECC if enabled
+            .map_err(|e| self.die_error(e))
     }
 
     /// Write a page to the device using blocking SPI
@@ -222,10 +621,11 @@ impl<SPI: embedded_hal_async::spi::SpiDevice, D: SpiNandAsync<SPI, N>, const N:
         page_address: PageIndex,
         buf: &[u8; N],
     ) -> Result<(), SpiFlashError<SPI::Error>> {
+        let page_address = self.select_die_page_async(page_address).await?;
         // Write page
-        self.device
-            .write_page(&mut self.spi, page_address, buf)
+        self.program_in_die_async(page_address, ColumnAddress::new(0), buf)
             .await
+            .map_err(|e| self.die_error(e))
     }
 
     /// Write a slice of a page to the device using blocking SPI
@@ -237,34 +637,69 @@ impl<SPI: embedded_hal_async::spi::SpiDevice, D: SpiNandAsync<SPI, N>, const N:
         column_address: ColumnAddress,
         buf: &[u8],
     ) -> Result<(), SpiFlashError<SPI::Error>> {
+        let page_address = self.select_die_page_async(page_address).await?;
         // Write page
-        self.device
-            .write_page_slice(&mut self.spi, page_address, column_address, buf)
+        self.program_in_die_async(page_address, column_address, buf)
             .await
+            .map_err(|e| self.die_error(e))
     }
 
     /// Copy a page to another using the device buffer
     /// TODO: This might not be supported by all devices
+    ///
+    /// Pages on different dies are copied through a page buffer on the stack.
     pub async fn copy_page_async(
         &mut self,
         src_page_address: PageIndex,
         dest_page_address: PageIndex,
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        if D::DIE_COUNT > 1
+            && src_page_address.as_u32() / (D::PAGES_PER_BLOCK * D::BLOCKS_PER_DIE)
+                != dest_page_address.as_u32() / (D::PAGES_PER_BLOCK * D::BLOCKS_PER_DIE)
+        {
+            let mut buf = [0; N];
+            self.read_page_async(src_page_address, &mut buf).await?;
+            return self.write_page_async(dest_page_address, &buf).await;
+        }
+        let src_page_address = self.select_die_page_async(src_page_address).await?;
+        let dest_page_address = self.select_die_page_async(dest_page_address).await?;
+        self.copy_page_buffer_async(src_page_address, dest_page_address)
+            .await
+            .map_err(|e| self.die_error(e))
+    }
+
+    /// Copy a page within the active die through the device buffer
+    async fn copy_page_buffer_async(
+        &mut self,
+        src_page_address: PageIndex,
+        dest_page_address: PageIndex,
     ) -> Result<(), SpiFlashError<SPI::Error>> {
         // Load the page into the device buffer
         self.device
             .page_read_cmd(&mut self.spi, src_page_address)
             .await?;
-        // Write the page to the destination address
+        let status = self.device.wait_ready(&mut self.spi).await?;
+        // Don't copy uncorrectable data
         self.device
-            .program_execute_cmd(&mut self.spi, dest_page_address)
+            .check_read_ecc(&mut self.spi, src_page_address, status)
             .await?;
+        // Write the page to the destination address
+        self.busy_wait.lock().await;
+        let mut started = self.device.write_enable_cmd(&mut self.spi).await;
+        if started.is_ok() {
+            started = self
+                .device
+                .program_execute_cmd(&mut self.spi, dest_page_address)
+                .await;
+        }
+        self.busy_wait.unlock();
+        started?;
         // Wait until the device is ready
-        while self.device.is_busy(&mut self.spi).await? {}
+        self.wait_ready_async().await?;
         // Return the status of the operation
-        if self.device.program_failed(&mut self.spi).await? {
-            return Err(SpiFlashError::ProgramFailed);
-        }
-        Ok(())
+        self.device
+            .program_finish(&mut self.spi, dest_page_address)
+            .await
     }
 
     /// Mark a block as bad using blocking SPI
@@ -274,16 +709,165 @@ impl<SPI: embedded_hal_async::spi::SpiDevice, D: SpiNandAsync<SPI, N>, const N:
         &mut self,
         block: BlockIndex,
     ) -> Result<(), SpiFlashError<SPI::Error>> {
-        self.device.mark_block_bad(&mut self.spi, block).await
+        let block = self.select_die_async(block).await?;
+        self.busy_wait.lock().await;
+        let result = self.device.mark_block_bad(&mut self.spi, block).await;
+        self.busy_wait.unlock();
+        result.map_err(|e| self.die_error(e))
+    }
+
+    /// Check if a block is marked bad using async SPI
+    pub async fn block_marked_bad_async(
+        &mut self,
+        block: BlockIndex,
+    ) -> Result<bool, SpiFlashError<SPI::Error>> {
+        let block = self.select_die_async(block).await?;
+        self.device
+            .block_marked_bad(&mut self.spi, block)
+            .await
+            .map_err(|e| self.die_error(e))
+    }
+
+    /// Read the status of a block from its bad block marker using async SPI
+    pub async fn block_status_async(
+        &mut self,
+        block: BlockIndex,
+    ) -> Result<BlockStatus, SpiFlashError<SPI::Error>> {
+        let block = self.select_die_async(block).await?;
+        self.device
+            .block_status(&mut self.spi, block)
+            .await
+            .map_err(|e| self.die_error(e))
+    }
+
+    /// Return true if the first page of a good block is erased using async SPI.
+    ///
+    /// The page is read into `buf`, see [SpiNandAsync::block_erased]
+    pub async fn block_erased_async(
+        &mut self,
+        block: BlockIndex,
+        buf: &mut [u8; N],
+    ) -> Result<bool, SpiFlashError<SPI::Error>> {
+        let block = self.select_die_async(block).await?;
+        self.device
+            .block_erased(&mut self.spi, block, buf)
+            .await
+            .map_err(|e| self.die_error(e))
     }
 }
 
-impl<SPI: SpiDevice, D, const N: usize> ErrorType for SpiNandDevice<SPI, D, N> {
+impl<
+        SPI: embedded_hal_async::spi::SpiDevice,
+        D: SpiNandLutAsync<SPI, N>,
+        const N: usize,
+        W: BusyWaitAsync,
+    > SpiNandDevice<SPI, D, N, W>
+{
+    /// Add the blocks to the device lookup table using async SPI, like
+    /// [embedded_nand::BlockSwap::swap_block].
+    ///
+    /// On stacked die devices both blocks must be on the same die, otherwise false is returned.
+    pub async fn swap_block_async(
+        &mut self,
+        bad: BlockIndex,
+        replacement: BlockIndex,
+    ) -> Result<bool, SpiFlashError<SPI::Error>> {
+        if bad.as_u32() >= D::BLOCK_COUNT || replacement.as_u32() >= D::BLOCK_COUNT {
+            return Err(SpiFlashError::OutOfBounds);
+        }
+        if bad.as_u32() / D::BLOCKS_PER_DIE != replacement.as_u32() / D::BLOCKS_PER_DIE {
+            return Ok(false);
+        }
+        debug!(
+            "Swapping block {} for {}",
+            bad.as_u32(),
+            replacement.as_u32()
+        );
+        let bad = self.select_die_async(bad).await?;
+        let replacement = BlockIndex::new(replacement.as_u32() % D::BLOCKS_PER_DIE);
+        self.busy_wait.lock().await;
+        let result = self
+            .device
+            .lut_swap_block(&mut self.spi, bad, replacement)
+            .await;
+        self.busy_wait.unlock();
+        result
+    }
+}
+
+impl<
+        SPI: embedded_hal_async::spi::SpiDevice,
+        D: SpiNandOobAsync<SPI, N>,
+        const N: usize,
+        W: BusyWaitAsync,
+    > SpiNandDevice<SPI, D, N, W>
+{
+    /// Read the free spare bytes of a page using async SPI, like
+    /// [embedded_nand::NandFlashOob::read_oob]
+    pub async fn read_oob_async(
+        &mut self,
+        page: PageIndex,
+        oob: &mut [u8],
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        if page.as_u32() >= D::BLOCK_COUNT * D::PAGES_PER_BLOCK || oob.len() > D::OOB_SIZE {
+            return Err(SpiFlashError::OutOfBounds);
+        }
+        let page = self.select_die_page_async(page).await?;
+        self.device
+            .read_oob(&mut self.spi, page, oob)
+            .await
+            .map_err(|e| self.die_error(e))
+    }
+
+    /// Write a page and free spare bytes with one program using async SPI, like
+    /// [embedded_nand::NandFlashOob::write_page_oob]
+    pub async fn write_page_oob_async(
+        &mut self,
+        page: PageIndex,
+        data: &[u8],
+        oob: &[u8],
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        if page.as_u32() >= D::BLOCK_COUNT * D::PAGES_PER_BLOCK || oob.len() > D::OOB_SIZE {
+            return Err(SpiFlashError::OutOfBounds);
+        }
+        let data = data.try_into().map_err(|_| SpiFlashError::NotAligned)?;
+        trace!(
+            "Writing page {} with {} spare bytes",
+            page.as_u32(),
+            oob.len()
+        );
+        let page = self.select_die_page_async(page).await?;
+        self.program_oob_in_die_async(page, data, oob)
+            .await
+            .map_err(|e| self.die_error(e))
+    }
+
+    /// Program a page and free spare bytes within the active die
+    async fn program_oob_in_die_async(
+        &mut self,
+        page: PageIndex,
+        data: &[u8; N],
+        oob: &[u8],
+    ) -> Result<(), SpiFlashError<SPI::Error>> {
+        self.busy_wait.lock().await;
+        let started = self
+            .device
+            .program_oob_start(&mut self.spi, page, data, oob)
+            .await;
+        self.busy_wait.unlock();
+        started?;
+        self.wait_ready_async().await?;
+        self.device.program_finish(&mut self.spi, page).await
+    }
+}
+
+// Shared by the blocking and async NandFlash traits
+impl<SPI: spi::ErrorType, D, const N: usize, W> ErrorType for SpiNandDevice<SPI, D, N, W> {
     type Error = SpiFlashError<SPI::Error>;
 }
 
-impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize> NandFlash
-    for SpiNandDevice<SPI, D, N>
+impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize, W: BusyWait> NandFlash
+    for SpiNandDevice<SPI, D, N, W>
 {
     const READ_SIZE: usize = D::READ_SIZE as usize;
     const PAGE_SIZE: usize = D::PAGE_SIZE as usize;
@@ -292,7 +876,7 @@ impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize> NandFlash
     const PAGES_PER_BLOCK: usize = D::PAGES_PER_BLOCK as usize;
     const WRITE_SIZE: usize = 1;
 
-    fn read(&mut self, offset: u32, mut bytes: &mut [u8]) -> Result<(), Self::Error> {
+    fn read(&mut self, offset: u64, mut bytes: &mut [u8]) -> Result<(), Self::Error> {
         trace!("Reading {} bytes from offset {}", bytes.len(), offset);
         // Check that the requested read is aligned and within bounds
         check_read(self, offset, bytes.len())?;
@@ -336,34 +920,33 @@ impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize> NandFlash
         Ok(())
     }
 
-    fn capacity(&self) -> u32 {
+    fn capacity(&self) -> u64 {
         D::CAPACITY
     }
 
     fn block_status(&mut self, block: BlockIndex) -> Result<BlockStatus, Self::Error> {
-        if self.device.block_marked_bad(&mut self.spi, block)? {
-            Ok(BlockStatus::Failed)
-        } else {
-            Ok(BlockStatus::Ok)
+        if block.as_u32() >= D::BLOCK_COUNT {
+            return Err(SpiFlashError::OutOfBounds);
         }
+        self.block_status_blocking(block)
     }
 
-    fn erase(&mut self, offset: u32, length: u32) -> Result<(), Self::Error> {
-        trace!("Erasing {} bytes from offset {}", length, offset);
+    fn erase(&mut self, from: u64, to: u64) -> Result<(), Self::Error> {
+        trace!("Erasing from {} to {}", from, to);
         // Check that the requested erase is aligned and within bounds
-        check_erase(self, offset, offset + length)?;
+        check_erase(self, from, to)?;
 
-        let start_block = Self::raw_byte_to_block_index(offset);
-        let end_block = Self::raw_byte_to_block_index(offset + length);
+        let start_block = Self::raw_byte_to_block_index(from);
+        let end_block = Self::raw_byte_to_block_index(to);
 
         // Be nice to use an iterator here, but custom range is nightly only
-        for block in (start_block.as_u16())..(end_block.as_u16()) {
+        for block in (start_block.as_u32())..(end_block.as_u32()) {
             self.erase_block(BlockIndex::new(block))?;
         }
         Ok(())
     }
 
-    fn write(&mut self, offset: u32, mut bytes: &[u8]) -> Result<(), Self::Error> {
+    fn write(&mut self, offset: u64, mut bytes: &[u8]) -> Result<(), Self::Error> {
         trace!("Writing {} bytes to offset {}", bytes.len(), offset);
         // Check that the requested write is aligned and within bounds
         check_write(self, offset, bytes.len())?;
@@ -401,22 +984,22 @@ impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize> NandFlash
     }
 
     fn erase_block(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
-        trace!("Erasing block {}", block.as_u16());
+        trace!("Erasing block {}", block.as_u32());
         // check range
-        if block.as_u16() >= Self::BLOCK_COUNT as u16 {
+        if block.as_u32() >= Self::BLOCK_COUNT as u32 {
             return Err(SpiFlashError::OutOfBounds);
         }
         // erase
         self.erase_block_blocking(block)
     }
 
-    fn copy(&mut self, src_offset: u32, dest_offset: u32, length: u32) -> Result<(), Self::Error> {
+    fn copy(&mut self, src_offset: u64, dest_offset: u64, length: u64) -> Result<(), Self::Error> {
         // Check that both read and write are aligned with pages and within bounds
-        check_slice(self, Self::PAGE_SIZE, src_offset, length as usize)?;
-        check_slice(self, Self::PAGE_SIZE, dest_offset, length as usize)?;
+        check_slice(self, Self::PAGE_SIZE, src_offset, length)?;
+        check_slice(self, Self::PAGE_SIZE, dest_offset, length)?;
 
         // Iterate over pages
-        let n_pages = length / Self::PAGE_SIZE as u32;
+        let n_pages = length / Self::PAGE_SIZE as u64;
         let mut src_page = Self::byte_to_page_index(ByteAddress::new(src_offset));
         let mut dest_page = Self::byte_to_page_index(ByteAddress::new(dest_offset));
         for _ in 0..n_pages {
@@ -430,9 +1013,9 @@ impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize> NandFlash
     }
 
     fn mark_block_bad(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
-        debug!("Marking block {} as bad", block.as_u16());
+        debug!("Marking block {} as bad", block.as_u32());
         // check range
-        if block.as_u16() >= Self::BLOCK_COUNT as u16 {
+        if block.as_u32() >= Self::BLOCK_COUNT as u32 {
             return Err(SpiFlashError::OutOfBounds);
         }
         // mark bad
@@ -440,22 +1023,114 @@ impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize> NandFlash
     }
 }
 
+impl<SPI: SpiDevice, D: SpiNandLutBlocking<SPI, N>, const N: usize, W: BusyWait> BlockSwap
+    for SpiNandDevice<SPI, D, N, W>
+{
+    /// Add the blocks to the device lookup table.
+    ///
+    /// On stacked die devices both blocks must be on the same die, otherwise false is returned.
+    fn swap_block(
+        &mut self,
+        bad: BlockIndex,
+        replacement: BlockIndex,
+    ) -> Result<bool, Self::Error> {
+        if bad.as_u32() >= D::BLOCK_COUNT || replacement.as_u32() >= D::BLOCK_COUNT {
+            return Err(SpiFlashError::OutOfBounds);
+        }
+        if bad.as_u32() / D::BLOCKS_PER_DIE != replacement.as_u32() / D::BLOCKS_PER_DIE {
+            return Ok(false);
+        }
+        debug!(
+            "Swapping block {} for {}",
+            bad.as_u32(),
+            replacement.as_u32()
+        );
+        let bad = self.select_die_blocking(bad)?;
+        let replacement = BlockIndex::new(replacement.as_u32() % D::BLOCKS_PER_DIE);
+        self.locked_blocking(|device, spi| device.lut_swap_block(spi, bad, replacement))
+    }
+}
+
+impl<SPI: SpiDevice, D: SpiNandOobBlocking<SPI, N>, const N: usize, W: BusyWait> NandFlashOob
+    for SpiNandDevice<SPI, D, N, W>
+{
+    const OOB_SIZE: usize = D::OOB_SIZE;
+
+    fn read_oob(&mut self, page: PageIndex, oob: &mut [u8]) -> Result<(), Self::Error> {
+        if page.as_u32() >= D::BLOCK_COUNT * D::PAGES_PER_BLOCK || oob.len() > D::OOB_SIZE {
+            return Err(SpiFlashError::OutOfBounds);
+        }
+        let page = self.select_die_page_blocking(page)?;
+        self.device
+            .read_oob(&mut self.spi, page, oob)
+            .map_err(|e| self.die_error(e))
+    }
+
+    fn write_page_oob(
+        &mut self,
+        page: PageIndex,
+        data: &[u8],
+        oob: &[u8],
+    ) -> Result<(), Self::Error> {
+        if page.as_u32() >= D::BLOCK_COUNT * D::PAGES_PER_BLOCK || oob.len() > D::OOB_SIZE {
+            return Err(SpiFlashError::OutOfBounds);
+        }
+        let data = data.try_into().map_err(|_| SpiFlashError::NotAligned)?;
+        trace!(
+            "Writing page {} with {} spare bytes",
+            page.as_u32(),
+            oob.len()
+        );
+        let page = self.select_die_page_blocking(page)?;
+        self.locked_blocking(|device, spi| device.program_oob_start(spi, page, data, oob))
+            .and_then(|()| self.wait_ready_blocking())
+            .and_then(|_| self.device.program_finish(&mut self.spi, page))
+            .map_err(|e| self.die_error(e))
+    }
+}
+
+impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize, W: BusyWait> SplitProgram
+    for SpiNandDevice<SPI, D, N, W>
+{
+    fn program_start(&mut self, page: PageIndex, data: &[u8]) -> Result<(), Self::Error> {
+        if page.as_u32() >= D::BLOCK_COUNT * D::PAGES_PER_BLOCK {
+            return Err(SpiFlashError::OutOfBounds);
+        }
+        if data.len() != N {
+            return Err(SpiFlashError::NotAligned);
+        }
+        let page = self.select_die_page_blocking(page)?;
+        self.locked_blocking(|device, spi| {
+            device.program_start(spi, page, ColumnAddress::new(0), data)
+        })
+        .map_err(|e| self.die_error(e))
+    }
+
+    fn program_finish(&mut self, page: PageIndex) -> Result<(), Self::Error> {
+        if page.as_u32() >= D::BLOCK_COUNT * D::PAGES_PER_BLOCK {
+            return Err(SpiFlashError::OutOfBounds);
+        }
+        // The die was selected by program_start
+        let page = self.select_die_page_blocking(page)?;
+        self.wait_ready_blocking()
+            .and_then(|_| self.device.program_finish(&mut self.spi, page))
+            .map_err(|e| self.die_error(e))
+    }
+}
+
 mod asyn {
     use embedded_hal_async::spi::SpiDevice;
     use embedded_nand::{BlockIndex, BlockStatus, ByteAddress, ColumnAddress};
-    use embedded_nand_async::AddressConversions;
-    use embedded_nand_async::{check_erase, check_read, check_slice, check_write, ErrorType};
+    use embedded_nand_async::{
+        check_erase, check_read, check_slice, check_write, AddressConversions,
+    };
 
-    use crate::{cmd_async::SpiNandAsync, error::SpiFlashError};
+    use crate::{busy::BusyWaitAsync, cmd_async::SpiNandAsync, error::SpiFlashError};
 
     use super::SpiNandDevice;
 
-    impl<SPI: SpiDevice, D, const N: usize> ErrorType for SpiNandDevice<SPI, D, N> {
-        type Error = SpiFlashError<SPI::Error>;
-    }
-
-    impl<SPI: embedded_hal_async::spi::SpiDevice, D: SpiNandAsync<SPI, N>, const N: usize>
-        embedded_nand_async::NandFlash for SpiNandDevice<SPI, D, N>
+    impl<SPI: SpiDevice, D: SpiNandAsync<SPI, N>, const N: usize, W: BusyWaitAsync>
+        embedded_nand_async::NandFlash for SpiNandDevice<SPI, D, N, W>
     {
         const READ_SIZE: usize = D::READ_SIZE as usize;
         const PAGE_SIZE: usize = D::PAGE_SIZE as usize;
@@ -464,7 +1139,7 @@ mod asyn {
         const PAGES_PER_BLOCK: usize = D::PAGES_PER_BLOCK as usize;
         const WRITE_SIZE: usize = 1;
 
-        async fn read(&mut self, offset: u32, mut bytes: &mut [u8]) -> Result<(), Self::Error> {
+        async fn read(&mut self, offset: u64, mut bytes: &mut [u8]) -> Result<(), Self::Error> {
             trace!("Reading {} bytes from offset {}", bytes.len(), offset);
             // Check that the requested read is aligned and within bounds
             check_read(self, offset, bytes.len())?;
@@ -510,34 +1185,33 @@ mod asyn {
             Ok(())
         }
 
-        fn capacity(&self) -> u32 {
+        fn capacity(&self) -> u64 {
             D::CAPACITY
         }
 
         async fn block_status(&mut self, block: BlockIndex) -> Result<BlockStatus, Self::Error> {
-            if self.device.block_marked_bad(&mut self.spi, block).await? {
-                Ok(BlockStatus::Failed)
-            } else {
-                Ok(BlockStatus::Ok)
+            if block.as_u32() >= D::BLOCK_COUNT {
+                return Err(SpiFlashError::OutOfBounds);
             }
+            self.block_status_async(block).await
         }
 
-        async fn erase(&mut self, offset: u32, length: u32) -> Result<(), Self::Error> {
-            trace!("Erasing {} bytes from offset {}", length, offset);
+        async fn erase(&mut self, from: u64, to: u64) -> Result<(), Self::Error> {
+            trace!("Erasing from {} to {}", from, to);
             // Check that the requested erase is aligned and within bounds
-            check_erase(self, offset, offset + length)?;
+            check_erase(self, from, to)?;
 
-            let start_block = Self::raw_byte_to_block_index(offset);
-            let end_block = Self::raw_byte_to_block_index(offset + length);
+            let start_block = Self::raw_byte_to_block_index(from);
+            let end_block = Self::raw_byte_to_block_index(to);
 
             // Be nice to use an iterator here, but custom range is nightly only
-            for block in (start_block.as_u16())..(end_block.as_u16()) {
+            for block in (start_block.as_u32())..(end_block.as_u32()) {
                 self.erase_block(BlockIndex::new(block)).await?;
             }
             Ok(())
         }
 
-        async fn write(&mut self, offset: u32, mut bytes: &[u8]) -> Result<(), Self::Error> {
+        async fn write(&mut self, offset: u64, mut bytes: &[u8]) -> Result<(), Self::Error> {
             trace!("Writing {} bytes to offset {}", bytes.len(), offset);
             // Check that the requested write is aligned and within bounds
             check_write(self, offset, bytes.len())?;
@@ -577,9 +1251,9 @@ mod asyn {
         }
 
         async fn erase_block(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
-            trace!("Erasing block {}", block.as_u16());
+            trace!("Erasing block {}", block.as_u32());
             // check range
-            if block.as_u16() >= Self::BLOCK_COUNT as u16 {
+            if block.as_u32() >= Self::BLOCK_COUNT as u32 {
                 return Err(SpiFlashError::OutOfBounds);
             }
             // erase
@@ -588,16 +1262,16 @@ mod asyn {
 
         async fn copy(
             &mut self,
-            src_offset: u32,
-            dest_offset: u32,
-            length: u32,
+            src_offset: u64,
+            dest_offset: u64,
+            length: u64,
         ) -> Result<(), Self::Error> {
             // Check that both read and write are aligned with pages and within bounds
-            check_slice(self, Self::PAGE_SIZE, src_offset, length as usize)?;
-            check_slice(self, Self::PAGE_SIZE, dest_offset, length as usize)?;
+            check_slice(self, Self::PAGE_SIZE, src_offset, length)?;
+            check_slice(self, Self::PAGE_SIZE, dest_offset, length)?;
 
             // Iterate over pages
-            let n_pages = length / Self::PAGE_SIZE as u32;
+            let n_pages = length / Self::PAGE_SIZE as u64;
             let mut src_page = Self::byte_to_page_index(ByteAddress::new(src_offset));
             let mut dest_page = Self::byte_to_page_index(ByteAddress::new(dest_offset));
             for _ in 0..n_pages {
@@ -611,9 +1285,9 @@ mod asyn {
         }
 
         async fn mark_block_bad(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
-            debug!("Marking block {} as bad", block.as_u16());
+            debug!("Marking block {} as bad", block.as_u32());
             // check range
-            if block.as_u16() >= Self::BLOCK_COUNT as u16 {
+            if block.as_u32() >= Self::BLOCK_COUNT as u32 {
                 return Err(SpiFlashError::OutOfBounds);
             }
             // mark bad
@@ -1,5 +1,5 @@
 use core::fmt::Debug;
-use embedded_nand::{NandFlashError, NandFlashErrorKind};
+use embedded_nand::{BlockFailure, NandFlashError, NandFlashErrorKind, NandOperation, PageIndex};
 
 /// Error type for the SPI flash driver.
 ///
@@ -16,21 +16,35 @@ pub enum SpiFlashError<SE> {
     #[error("SpiDevice error: {0}")]
     SPI(SE),
     /// Block Erase failed.
-    /// This can happen if the block is protected, write is disabled or block has failed.
-    #[error("Erase failed")]
-    EraseFailed,
+    /// This can happen if write is disabled or the block has failed.
+    /// Contains the first page of the block.
+    #[error("Erase failed at page {0:?}")]
+    EraseFailed(PageIndex),
     /// Program failed.
-    /// This can happen if the write is disabled, block is protected or the block has failed.
-    #[error("Program failed")]
-    ProgramFailed,
+    /// This can happen if the write is disabled or the block has failed.
+    #[error("Program failed at page {0:?}")]
+    ProgramFailed(PageIndex),
     /// Read failed
-    /// This can happen due to an ECC error
-    #[error("Read failed")]
-    ReadFailed,
-    /// Read was successful, but ECC error was detected
+    /// This can happen due to an uncorrectable ECC error
+    #[error("Read failed at page {0:?}")]
+    ReadFailed(PageIndex),
+    /// Read was successful, but ECC corrected enough bits to reach the failing threshold
     /// This marks the block as failing and requires remapping
-    #[error("Read was successful, but ECC error was detected")]
-    EccError,
+    #[error("Read was successful, but ECC error was detected at page {page:?}")]
+    EccError {
+        page: PageIndex,
+        /// Most bits corrected in one ECC sector, if the device reports it
+        corrected_bits: Option<u8>,
+    },
+    /// The device stayed busy for longer than [crate::SpiNand::BUSY_POLL_LIMIT] status reads
+    #[error("Timed out waiting for the device")]
+    Timeout,
+    /// Program or erase failed and block protection is enabled
+    #[error("Page {0:?} is write protected")]
+    WriteProtected(PageIndex),
+    /// The device is in deep power down and must be woken first
+    #[error("Device is in deep power down")]
+    Sleeping,
     /// Requested bytes out of bounds
     #[error("Requested bytes out of bounds")]
     OutOfBounds,
@@ -42,6 +56,56 @@ pub enum SpiFlashError<SE> {
     Other,
 }
 
+impl<SE> SpiFlashError<SE> {
+    /// The page the error happened at, if known
+    pub fn page(&self) -> Option<PageIndex> {
+        match self {
+            SpiFlashError::EraseFailed(page)
+            | SpiFlashError::ProgramFailed(page)
+            | SpiFlashError::ReadFailed(page)
+            | SpiFlashError::EccError { page, .. }
+            | SpiFlashError::WriteProtected(page) => Some(*page),
+            _ => None,
+        }
+    }
+
+    /// Offset the page of the error, to convert a page within a die to one spanning all dies
+    pub(crate) fn offset_page(mut self, pages: u32) -> Self {
+        match &mut self {
+            SpiFlashError::EraseFailed(page)
+            | SpiFlashError::ProgramFailed(page)
+            | SpiFlashError::ReadFailed(page)
+            | SpiFlashError::EccError { page, .. }
+            | SpiFlashError::WriteProtected(page) => *page = PageIndex::new(page.as_u32() + pages),
+            _ => {}
+        }
+        self
+    }
+
+    /// Details of a failed or failing block
+    fn block_failure(&self) -> Option<BlockFailure> {
+        Some(match self {
+            SpiFlashError::EraseFailed(page) => {
+                BlockFailure::new(NandOperation::Erase).with_page(*page)
+            }
+            SpiFlashError::ProgramFailed(page) => {
+                BlockFailure::new(NandOperation::Program).with_page(*page)
+            }
+            SpiFlashError::ReadFailed(page) => {
+                BlockFailure::new(NandOperation::Read).with_page(*page)
+            }
+            SpiFlashError::EccError {
+                page,
+                corrected_bits,
+            } => BlockFailure {
+                corrected_bits: *corrected_bits,
+                ..BlockFailure::new(NandOperation::Read).with_page(*page)
+            },
+            _ => return None,
+        })
+    }
+}
+
 // Convert from SPI error to more generic NandFlashError
 impl<SE: Debug> NandFlashError for SpiFlashError<SE> {
     fn kind(&self) -> NandFlashErrorKind {
@@ -49,11 +113,17 @@ impl<SE: Debug> NandFlashError for SpiFlashError<SE> {
             SpiFlashError::NotAligned => NandFlashErrorKind::NotAligned,
             SpiFlashError::OutOfBounds => NandFlashErrorKind::OutOfBounds,
             SpiFlashError::SPI(_) => NandFlashErrorKind::Other,
-            SpiFlashError::EraseFailed => NandFlashErrorKind::BlockFail(None),
-            SpiFlashError::ProgramFailed => NandFlashErrorKind::BlockFail(None),
-            SpiFlashError::ReadFailed => NandFlashErrorKind::BlockFail(None),
-            SpiFlashError::EccError => NandFlashErrorKind::BlockFailing(None),
-            SpiFlashError::Other => NandFlashErrorKind::Other,
+            SpiFlashError::EccError { .. } => {
+                NandFlashErrorKind::BlockFailing(self.block_failure().unwrap_or_default())
+            }
+            SpiFlashError::EraseFailed(_)
+            | SpiFlashError::ProgramFailed(_)
+            | SpiFlashError::ReadFailed(_) => {
+                NandFlashErrorKind::BlockFail(self.block_failure().unwrap_or_default())
+            }
+            SpiFlashError::Timeout => NandFlashErrorKind::Timeout,
+            SpiFlashError::WriteProtected(_) => NandFlashErrorKind::WriteProtected,
+            SpiFlashError::Sleeping | SpiFlashError::Other => NandFlashErrorKind::Other,
         }
     }
 }
@@ -68,36 +138,3 @@ impl<SE> From<NandFlashErrorKind> for SpiFlashError<SE> {
         }
     }
 }
-
-mod asyn {
-    use embedded_nand_async::{NandFlashError, NandFlashErrorKind};
-
-    use super::SpiFlashError;
-
-    // Convert from SPI error to more generic NandFlashError
-    impl<SE: core::fmt::Debug> NandFlashError for SpiFlashError<SE> {
-        fn kind(&self) -> embedded_nand_async::NandFlashErrorKind {
-            match self {
-                SpiFlashError::NotAligned => NandFlashErrorKind::NotAligned,
-                SpiFlashError::OutOfBounds => NandFlashErrorKind::OutOfBounds,
-                SpiFlashError::SPI(_) => NandFlashErrorKind::Other,
-                SpiFlashError::EraseFailed => NandFlashErrorKind::BlockFail(None),
-                SpiFlashError::ProgramFailed => NandFlashErrorKind::BlockFail(None),
-                SpiFlashError::ReadFailed => NandFlashErrorKind::BlockFail(None),
-                SpiFlashError::EccError => NandFlashErrorKind::BlockFailing(None),
-                SpiFlashError::Other => NandFlashErrorKind::Other,
-            }
-        }
-    }
-
-    // This impl is only for the helper check bounds / alignment functions for auto conversion from errors
-    impl<SE> From<NandFlashErrorKind> for SpiFlashError<SE> {
-        fn from(kind: NandFlashErrorKind) -> Self {
-            match kind {
-                NandFlashErrorKind::NotAligned => SpiFlashError::NotAligned,
-                NandFlashErrorKind::OutOfBounds => SpiFlashError::OutOfBounds,
-                _ => SpiFlashError::Other,
-            }
-        }
-    }
-}
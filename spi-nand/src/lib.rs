@@ -2,12 +2,28 @@
 // Must be first to share macros across crate
 pub(crate) mod fmt;
 
+pub mod busy;
+pub mod cmd;
 pub mod cmd_async;
 pub mod cmd_blocking;
 mod device;
 pub mod error;
+pub mod power;
+pub mod register;
 
+// Used by the code generated by [nand_register]
+#[doc(hidden)]
+pub mod __private {
+    #[cfg(feature = "defmt")]
+    pub use defmt;
+}
+
+pub use busy::{
+    BusLock, BusyWait, BusyWaitAsync, LockedDevice, LockedError, LockedWait, NoWait, PollDelay,
+};
 pub use device::SpiNandDevice;
+use embedded_nand::PageIndex;
+pub use power::AutoSleep;
 
 /// Core trait that a NAND flash device must implement.
 ///
@@ -29,10 +45,26 @@ pub trait SpiNand<const N: usize> {
     const BLOCK_COUNT: u32;
     /// The size of a block in bytes
     const BLOCK_SIZE: u32 = Self::PAGE_SIZE * Self::PAGES_PER_BLOCK;
+    /// The number of dies stacked in the package, switched with [SpiNand::DIE_SELECT_COMMAND].
+    /// [SpiNand::BLOCK_COUNT] is the total over all dies.
+    const DIE_COUNT: u32 = 1;
+    /// The number of blocks in each die
+    const BLOCKS_PER_DIE: u32 = Self::BLOCK_COUNT / Self::DIE_COUNT;
     /// The total capacity of the device in bytes
-    const CAPACITY: u32 = Self::PAGE_SIZE * Self::PAGES_PER_BLOCK * Self::BLOCK_COUNT;
+    const CAPACITY: u64 =
+        Self::PAGE_SIZE as u64 * Self::PAGES_PER_BLOCK as u64 * Self::BLOCK_COUNT as u64;
     /// Minimum number of bytes the storage peripheral can read
     const READ_SIZE: u32 = 1;
+    /// Status register reads while waiting for an operation to finish, before giving up
+    /// with [error::SpiFlashError::Timeout]. Stops a device that no longer responds
+    /// (busy bit stuck high) from hanging the driver.
+    const BUSY_POLL_LIMIT: u32 = 1_000_000;
+    /// Time in microseconds from the deep power down command until the device is in
+    /// deep power down (tDP)
+    const DEEP_POWER_DOWN_TIME_US: u32 = 3;
+    /// Time in microseconds from the deep power down exit command until the device
+    /// accepts commands again (tRDP / tRES)
+    const DEEP_POWER_DOWN_EXIT_TIME_US: u32 = 50;
 
     // JEDEC ID
     const JEDEC_MANUFACTURER_ID: u8;
@@ -71,6 +103,8 @@ pub trait SpiNand<const N: usize> {
     const DEEP_POWER_DOWN_COMMAND: u8 = 0xB9;
     /// Command to exit deep power down
     const DEEP_POWER_DOWN_EXIT_COMMAND: u8 = 0xAB;
+    /// Command to select the active die on stacked die devices
+    const DIE_SELECT_COMMAND: u8 = 0xC2;
 
     // Registers
     /// Register (1), standard config, RW
@@ -79,6 +113,14 @@ pub trait SpiNand<const N: usize> {
     const FEATURE_REGISTER: u8 = 0xB0;
     /// Status register (3). Standard readonly status register
     const STATUS_REGISTER: u8 = 0xC0;
+    /// Block protection bits in [SpiNand::CONFIGURATION_REGISTER]
+    const BLOCK_PROTECT_MASK: u8 = 0b0111_1000;
+
+    // Bad block markers
+    /// Spare area bytes written to the first page of a block retired with
+    /// [cmd_blocking::SpiNandBlocking::mark_block_bad]. Byte 1 is cleared so the block still
+    /// fails a factory marker check, bytes 2 and 3 tell it apart from a factory bad block.
+    const RUNTIME_BAD_BLOCK_MARKER: [u8; 4] = [0xFF, 0x00, b'R', b'B'];
 }
 
 /// Possible ECC status values after performing a read operation
@@ -96,6 +138,34 @@ pub enum ECCStatus {
     Failed,
 }
 
+impl ECCStatus {
+    /// Decode the standard ECC status bits (5:4) of [SpiNand::STATUS_REGISTER]
+    pub fn from_status_register(status: u8) -> Self {
+        match register::StatusReg::from(status).ecc() {
+            0b00 => ECCStatus::Ok,
+            0b01 => ECCStatus::Corrected,
+            0b10 => ECCStatus::Failed,
+            _ => ECCStatus::Failing,
+        }
+    }
+
+    /// Result of reading `page`. Errors corrected below the failing threshold are Ok
+    pub fn check<SE>(
+        self,
+        page: PageIndex,
+        corrected_bits: Option<u8>,
+    ) -> Result<(), error::SpiFlashError<SE>> {
+        match self {
+            ECCStatus::Ok | ECCStatus::Corrected => Ok(()),
+            ECCStatus::Failing => Err(error::SpiFlashError::EccError {
+                page,
+                corrected_bits,
+            }),
+            ECCStatus::Failed => Err(error::SpiFlashError::ReadFailed(page)),
+        }
+    }
+}
+
 /// The JEDEC manufacturer ID of a flash device
 /// See https://www.jedec.org/standards-documents/docs/jep-106ab for a list of JEDEC IDs
 ///
@@ -0,0 +1,255 @@
+//! Power management for battery powered devices.
+//!
+//! [crate::SpiNandDevice] can be put in deep power down directly with
+//! [crate::SpiNandDevice::sleep_blocking] and woken with [crate::SpiNandDevice::wake_blocking]
+//! (or the async versions). While asleep the device ignores every command except the wake
+//! command, so the caller must wake it before the next operation. Until then operations
+//! return [crate::error::SpiFlashError::Sleeping] without sending anything.
+//!
+//! [AutoSleep] does this automatically. It wraps a [crate::SpiNandDevice] and implements
+//! [embedded_nand::NandFlash], waking the device before each operation. The application
+//! reports the time spent idle with [AutoSleep::tick_blocking] (or [AutoSleep::tick_async]),
+//! e.g. from its main loop or a timer task, and the device enters deep power down once
+//! it has been idle for the configured timeout.
+
+use embedded_hal::{
+    delay::DelayNs,
+    spi::{self, SpiDevice},
+};
+use embedded_nand::{BlockIndex, BlockStatus, ErrorType, NandFlash};
+
+use crate::{busy::BusyWait, cmd_blocking::SpiNandBlocking, error::SpiFlashError, SpiNandDevice};
+
+/// Enters deep power down after an idle timeout and wakes on the next operation.
+///
+/// Wraps a [SpiNandDevice] and a delay used for the tDP and tRDP timing. All
+/// [NandFlash] calls wake the device first if it is asleep and reset the idle time.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AutoSleep<SPI, D, const N: usize, W, DELAY> {
+    flash: SpiNandDevice<SPI, D, N, W>,
+    delay: DELAY,
+    /// Idle time before entering deep power down
+    timeout_ms: u32,
+    /// Time since the last operation
+    idle_ms: u32,
+}
+
+impl<SPI, D, const N: usize, W, DELAY> AutoSleep<SPI, D, N, W, DELAY> {
+    /// Put `flash` in deep power down after it has been idle for `timeout_ms`.
+    /// `delay` is used to wait for the device to enter and exit deep power down.
+    pub fn new(flash: SpiNandDevice<SPI, D, N, W>, delay: DELAY, timeout_ms: u32) -> Self {
+        Self {
+            flash,
+            delay,
+            timeout_ms,
+            idle_ms: 0,
+        }
+    }
+
+    /// Return the wrapped flash and delay. The flash may still be asleep,
+    /// see [SpiNandDevice::is_sleeping]
+    pub fn into_inner(self) -> (SpiNandDevice<SPI, D, N, W>, DELAY) {
+        (self.flash, self.delay)
+    }
+
+    /// Access the wrapped flash. The flash may be asleep, see [SpiNandDevice::is_sleeping]
+    pub fn inner_mut(&mut self) -> &mut SpiNandDevice<SPI, D, N, W> {
+        &mut self.flash
+    }
+
+    /// Returns true if the device is in deep power down
+    pub fn is_sleeping(&self) -> bool {
+        self.flash.is_sleeping()
+    }
+
+    /// Add `elapsed_ms` to the idle time, returning true if the timeout has been reached
+    fn idle(&mut self, elapsed_ms: u32) -> bool {
+        self.idle_ms = self.idle_ms.saturating_add(elapsed_ms);
+        self.idle_ms >= self.timeout_ms && !self.flash.is_sleeping()
+    }
+}
+
+impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize, W: BusyWait, DELAY: DelayNs>
+    AutoSleep<SPI, D, N, W, DELAY>
+{
+    /// Report `elapsed_ms` of idle time since the last call or operation.
+    /// Puts the device in deep power down once the timeout is reached.
+    pub fn tick_blocking(&mut self, elapsed_ms: u32) -> Result<(), SpiFlashError<SPI::Error>> {
+        if self.idle(elapsed_ms) {
+            self.flash.sleep_blocking(&mut self.delay)?;
+        }
+        Ok(())
+    }
+
+    /// Put the device in deep power down now, without waiting for the timeout
+    pub fn sleep_blocking(&mut self) -> Result<(), SpiFlashError<SPI::Error>> {
+        self.flash.sleep_blocking(&mut self.delay)
+    }
+
+    /// Wake the device if it is asleep and reset the idle time
+    fn wake(&mut self) -> Result<(), SpiFlashError<SPI::Error>> {
+        self.idle_ms = 0;
+        self.flash.wake_blocking(&mut self.delay)
+    }
+}
+
+// Shared by the blocking and async NandFlash traits
+impl<SPI: spi::ErrorType, D, const N: usize, W, DELAY> ErrorType
+    for AutoSleep<SPI, D, N, W, DELAY>
+{
+    type Error = SpiFlashError<SPI::Error>;
+}
+
+impl<SPI: SpiDevice, D: SpiNandBlocking<SPI, N>, const N: usize, W: BusyWait, DELAY: DelayNs>
+    NandFlash for AutoSleep<SPI, D, N, W, DELAY>
+{
+    const READ_SIZE: usize = <SpiNandDevice<SPI, D, N, W> as NandFlash>::READ_SIZE;
+    const PAGE_SIZE: usize = <SpiNandDevice<SPI, D, N, W> as NandFlash>::PAGE_SIZE;
+    const BLOCK_COUNT: usize = <SpiNandDevice<SPI, D, N, W> as NandFlash>::BLOCK_COUNT;
+    const ERASE_SIZE: usize = <SpiNandDevice<SPI, D, N, W> as NandFlash>::ERASE_SIZE;
+    const PAGES_PER_BLOCK: usize = <SpiNandDevice<SPI, D, N, W> as NandFlash>::PAGES_PER_BLOCK;
+    const WRITE_SIZE: usize = <SpiNandDevice<SPI, D, N, W> as NandFlash>::WRITE_SIZE;
+
+    fn read(&mut self, offset: u64, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.wake()?;
+        self.flash.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> u64 {
+        self.flash.capacity()
+    }
+
+    fn mark_block_bad(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+        self.wake()?;
+        self.flash.mark_block_bad(block)
+    }
+
+    fn block_status(&mut self, block: BlockIndex) -> Result<BlockStatus, Self::Error> {
+        self.wake()?;
+        self.flash.block_status(block)
+    }
+
+    fn erase(&mut self, from: u64, to: u64) -> Result<(), Self::Error> {
+        self.wake()?;
+        self.flash.erase(from, to)
+    }
+
+    fn erase_block(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+        self.wake()?;
+        self.flash.erase_block(block)
+    }
+
+    fn write(&mut self, offset: u64, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.wake()?;
+        self.flash.write(offset, bytes)
+    }
+
+    fn copy(&mut self, src_offset: u64, dest_offset: u64, length: u64) -> Result<(), Self::Error> {
+        self.wake()?;
+        self.flash.copy(src_offset, dest_offset, length)
+    }
+}
+
+mod asyn {
+    use embedded_hal_async::{delay::DelayNs, spi::SpiDevice};
+    use embedded_nand::{BlockIndex, BlockStatus};
+    use embedded_nand_async::NandFlash;
+
+    use crate::{
+        busy::BusyWaitAsync, cmd_async::SpiNandAsync, error::SpiFlashError, SpiNandDevice,
+    };
+
+    use super::AutoSleep;
+
+    impl<
+            SPI: SpiDevice,
+            D: SpiNandAsync<SPI, N>,
+            const N: usize,
+            W: BusyWaitAsync,
+            DELAY: DelayNs,
+        > AutoSleep<SPI, D, N, W, DELAY>
+    {
+        /// Report `elapsed_ms` of idle time since the last call or operation.
+        /// Puts the device in deep power down once the timeout is reached.
+        pub async fn tick_async(
+            &mut self,
+            elapsed_ms: u32,
+        ) -> Result<(), SpiFlashError<SPI::Error>> {
+            if self.idle(elapsed_ms) {
+                self.flash.sleep_async(&mut self.delay).await?;
+            }
+            Ok(())
+        }
+
+        /// Put the device in deep power down now, without waiting for the timeout
+        pub async fn sleep_async(&mut self) -> Result<(), SpiFlashError<SPI::Error>> {
+            self.flash.sleep_async(&mut self.delay).await
+        }
+
+        /// Wake the device if it is asleep and reset the idle time
+        async fn wake_async(&mut self) -> Result<(), SpiFlashError<SPI::Error>> {
+            self.idle_ms = 0;
+            self.flash.wake_async(&mut self.delay).await
+        }
+    }
+
+    impl<
+            SPI: SpiDevice,
+            D: SpiNandAsync<SPI, N>,
+            const N: usize,
+            W: BusyWaitAsync,
+            DELAY: DelayNs,
+        > NandFlash for AutoSleep<SPI, D, N, W, DELAY>
+    {
+        const READ_SIZE: usize = <SpiNandDevice<SPI, D, N, W> as NandFlash>::READ_SIZE;
+        const PAGE_SIZE: usize = <SpiNandDevice<SPI, D, N, W> as NandFlash>::PAGE_SIZE;
+        const BLOCK_COUNT: usize = <SpiNandDevice<SPI, D, N, W> as NandFlash>::BLOCK_COUNT;
+        const ERASE_SIZE: usize = <SpiNandDevice<SPI, D, N, W> as NandFlash>::ERASE_SIZE;
+        const PAGES_PER_BLOCK: usize = <SpiNandDevice<SPI, D, N, W> as NandFlash>::PAGES_PER_BLOCK;
+        const WRITE_SIZE: usize = <SpiNandDevice<SPI, D, N, W> as NandFlash>::WRITE_SIZE;
+
+        async fn read(&mut self, offset: u64, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            self.wake_async().await?;
+            self.flash.read(offset, bytes).await
+        }
+
+        fn capacity(&self) -> u64 {
+            NandFlash::capacity(&self.flash)
+        }
+
+        async fn mark_block_bad(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+            self.wake_async().await?;
+            self.flash.mark_block_bad(block).await
+        }
+
+        async fn block_status(&mut self, block: BlockIndex) -> Result<BlockStatus, Self::Error> {
+            self.wake_async().await?;
+            self.flash.block_status(block).await
+        }
+
+        async fn erase(&mut self, from: u64, to: u64) -> Result<(), Self::Error> {
+            self.wake_async().await?;
+            self.flash.erase(from, to).await
+        }
+
+        async fn erase_block(&mut self, block: BlockIndex) -> Result<(), Self::Error> {
+            self.wake_async().await?;
+            self.flash.erase_block(block).await
+        }
+
+        async fn write(&mut self, offset: u64, bytes: &[u8]) -> Result<(), Self::Error> {
+            self.wake_async().await?;
+            self.flash.write(offset, bytes).await
+        }
+
+        async fn copy(
+            &mut self,
+            src_offset: u64,
+            dest_offset: u64,
+            length: u64,
+        ) -> Result<(), Self::Error> {
+            self.wake_async().await?;
+            self.flash.copy(src_offset, dest_offset, length).await
+        }
+    }
+}
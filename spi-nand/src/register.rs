@@ -0,0 +1,240 @@
+//! Typed feature registers.
+//!
+//! The protection, configuration and status registers are read and written with the get
+//! and set feature commands ([crate::cmd_blocking::SpiNandBlocking::read_register_cmd]).
+//! Rather than masking raw bytes, a register is described once with [crate::nand_register]
+//! and accessed through named fields:
+//!
+//! ```
+//! use spi_nand::register::{ConfigReg, Register};
+//!
+//! let mut config = ConfigReg::from(0b0000_0000);
+//! config.set_ecc_enable(true);
+//! assert!(config.ecc_enable());
+//! assert_eq!(u8::from(config), 0b0001_0000);
+//! assert_eq!(ConfigReg::ADDRESS, 0xB0);
+//! ```
+//!
+//! [crate::cmd_blocking::SpiNandBlocking::read_reg], `write_reg` and `modify_reg` (and the
+//! async versions) read and write a typed register at its [Register::ADDRESS].
+//!
+//! The registers here have the layout shared by most SPI NAND devices. Device crates
+//! declare their own registers with [crate::nand_register] for device specific bits.
+
+/// A typed feature register
+pub trait Register: Copy + From<u8> + Into<u8> {
+    /// Address of the register for the get and set feature commands
+    const ADDRESS: u8;
+}
+
+/// A register that can be written with `write_reg` and `modify_reg`.
+///
+/// Implemented by [crate::nand_register] for registers declared with setters
+pub trait WritableRegister: Register {}
+
+/// Declare a typed feature register.
+///
+/// Each field has a getter and a setter and is either a single bit (`bool`) or an
+/// inclusive range of bits, msb first (`u8`, shifted down to bit 0).
+/// Values given to a setter are masked to the width of the field.
+///
+/// ```
+/// spi_nand::nand_register! {
+///     /// Example register at address 0xB0
+///     pub struct ExampleReg(0xB0) {
+///         /// Bit 4
+///         enable, set_enable: 4;
+///         /// Bits 2 and 1
+///         strength, set_strength: 2, 1;
+///     }
+/// }
+///
+/// let mut reg = ExampleReg::default();
+/// reg.set_enable(true);
+/// reg.set_strength(0b11);
+/// assert_eq!(reg.0, 0b0001_0110);
+/// ```
+///
+/// A register declared without setters is read only. It doesn't implement
+/// [WritableRegister], so it can't be passed to `write_reg`:
+///
+/// ```
+/// spi_nand::nand_register! {
+///     /// Example read only register at address 0xC0
+///     pub struct ExampleStatus(0xC0) {
+///         /// Bit 0
+///         busy: 0;
+///     }
+/// }
+///
+/// assert!(ExampleStatus::from(0b1).busy());
+/// ```
+///
+/// ```compile_fail
+/// use spi_nand::{cmd_blocking::SpiNandBlocking, register::StatusReg};
+///
+/// fn clear_status<SPI: embedded_hal::spi::SpiDevice, D: SpiNandBlocking<SPI, 2048>>(
+///     device: &D,
+///     spi: &mut SPI,
+/// ) {
+///     let _ = device.write_reg(spi, StatusReg::default());
+/// }
+/// ```
+#[macro_export]
+macro_rules! nand_register {
+    (@get $(#[$meta:meta])* $get:ident: $bit:literal) => {
+        $(#[$meta])*
+        pub fn $get(&self) -> bool {
+            self.0 & (1 << $bit) != 0
+        }
+    };
+    (@get $(#[$meta:meta])* $get:ident: $msb:literal, $lsb:literal) => {
+        $(#[$meta])*
+        pub fn $get(&self) -> u8 {
+            (self.0 >> $lsb) & (0xFF >> (7 - ($msb - $lsb)))
+        }
+    };
+    (@set $get:ident, $set:ident: $bit:literal) => {
+        #[doc = concat!("Set `", stringify!($get), "`")]
+        pub fn $set(&mut self, value: bool) {
+            if value {
+                self.0 |= 1 << $bit;
+            } else {
+                self.0 &= !(1 << $bit);
+            }
+        }
+    };
+    (@set $get:ident, $set:ident: $msb:literal, $lsb:literal) => {
+        #[doc = concat!("Set `", stringify!($get), "`")]
+        pub fn $set(&mut self, value: u8) {
+            let mask: u8 = (0xFF >> (7 - ($msb - $lsb))) << $lsb;
+            self.0 = (self.0 & !mask) | ((value << $lsb) & mask);
+        }
+    };
+    (@struct $(#[$meta:meta])* $vis:vis struct $name:ident($address:literal)) => {
+        $(#[$meta])*
+        #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+        $vis struct $name(pub u8);
+
+        $crate::__nand_register_format!($name);
+
+        impl $crate::register::Register for $name {
+            const ADDRESS: u8 = $address;
+        }
+
+        impl From<u8> for $name {
+            fn from(value: u8) -> Self {
+                Self(value)
+            }
+        }
+
+        impl From<$name> for u8 {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+    };
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident($address:literal) {
+            $(
+                $(#[$field_meta:meta])*
+                $get:ident, $set:ident: $($bit:literal),+;
+            )*
+        }
+    ) => {
+        $crate::nand_register!(@struct $(#[$meta])* $vis struct $name($address));
+
+        impl $crate::register::WritableRegister for $name {}
+
+        impl $name {
+            $(
+                $crate::nand_register!(@get $(#[$field_meta])* $get: $($bit),+);
+                $crate::nand_register!(@set $get, $set: $($bit),+);
+            )*
+        }
+    };
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident($address:literal) {
+            $(
+                $(#[$field_meta:meta])*
+                $get:ident: $($bit:literal),+;
+            )*
+        }
+    ) => {
+        $crate::nand_register!(@struct $(#[$meta])* $vis struct $name($address));
+
+        impl $name {
+            $(
+                $crate::nand_register!(@get $(#[$field_meta])* $get: $($bit),+);
+            )*
+        }
+    };
+}
+
+/// [defmt::Format] for a register declared with [crate::nand_register], implemented
+/// when spi-nand has the `defmt` feature so the declaring crate doesn't need defmt
+#[cfg(feature = "defmt")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __nand_register_format {
+    ($name:ident) => {
+        impl $crate::__private::defmt::Format for $name {
+            fn format(&self, f: $crate::__private::defmt::Formatter<'_>) {
+                $crate::__private::defmt::Format::format(&self.0, f)
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "defmt"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __nand_register_format {
+    ($name:ident) => {};
+}
+
+nand_register! {
+    /// Protection register (1)
+    pub struct ProtectionReg(0xA0) {
+        /// Status register protect 0
+        status_protect_0, set_status_protect_0: 7;
+        /// Block protect bits BP3 to BP0
+        block_protect, set_block_protect: 6, 3;
+        /// Block protect from the top (0) or bottom (1) of the array
+        top_bottom, set_top_bottom: 2;
+        /// Write protect pin enable
+        write_protect_enable, set_write_protect_enable: 1;
+        /// Status register protect 1
+        status_protect_1, set_status_protect_1: 0;
+    }
+}
+
+nand_register! {
+    /// Configuration register (2)
+    pub struct ConfigReg(0xB0) {
+        /// Lock the OTP area
+        otp_lock, set_otp_lock: 7;
+        /// Access the OTP area instead of the array
+        otp_enable, set_otp_enable: 6;
+        /// On die ECC enabled
+        ecc_enable, set_ecc_enable: 4;
+    }
+}
+
+nand_register! {
+    /// Status register (3). Read only
+    pub struct StatusReg(0xC0) {
+        /// ECC status bits, see [crate::ECCStatus::from_status_register]
+        ecc: 5, 4;
+        /// The last program failed
+        program_failed: 3;
+        /// The last erase failed
+        erase_failed: 2;
+        /// Write enable latch
+        write_enabled: 1;
+        /// An operation is in progress
+        busy: 0;
+    }
+}